@@ -0,0 +1,399 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_fs::{FsExt, OpenOptions};
+use tracing::warn;
+
+use crate::export::csv_escape;
+
+const AUDIT_FILE: &str = "audit.log";
+
+/// Header written by `export_csv`
+const AUDIT_CSV_COLUMNS: [&str; 7] = [
+    "timestamp",
+    "operation",
+    "operator",
+    "username",
+    "providerId",
+    "success",
+    "error",
+];
+
+/// Mutating operation recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditOperation {
+    UserCreated,
+    PinGenerated,
+    OtpGenerated,
+    EmailSent,
+    CredentialsCopied,
+    CardUpdated,
+    DetailCleared,
+}
+
+/// One line of the audit log
+///
+/// Entries only say that a credential was generated or sent, never its value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub operation: AuditOperation,
+    /// Operator identifier from settings, if one is configured
+    pub operator: Option<String>,
+    /// SAFEQ username, or the recipient address for sent emails
+    pub username: String,
+    pub provider_id: Option<i64>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Build an entry from a bulk result row (`{ user, success, error? }`)
+    pub fn from_result(operation: AuditOperation, operator: Option<&str>, row: &Value) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            operation,
+            operator: operator.map(str::to_string),
+            username: row["user"]["userName"].as_str().unwrap_or("").to_string(),
+            provider_id: row["user"]["providerId"].as_i64(),
+            success: row["success"] == true,
+            error: row["error"].as_str().map(str::to_string),
+        }
+    }
+
+    /// Build an entry for one email delivery attempt
+    pub fn email(operator: Option<&str>, recipient: &str, error: Option<&str>) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            operation: AuditOperation::EmailSent,
+            operator: operator.map(str::to_string),
+            username: recipient.to_string(),
+            provider_id: None,
+            success: error.is_none(),
+            error: error.map(str::to_string),
+        }
+    }
+}
+
+/// Outcome of `export_csv`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditCsvExport {
+    pub rows: usize,
+    /// Lines that were malformed or written by an older, incompatible version
+    pub skipped: usize,
+}
+
+/// Errors raised while reading or writing the audit log
+#[derive(Debug)]
+pub enum AuditError {
+    DataDir(tauri::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DataDir(err) => write!(f, "Failed to resolve the app data directory: {err}"),
+            Self::Io(err) => write!(f, "Failed to access the audit log: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AuditError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::DataDir(err) => Some(err),
+            Self::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for AuditError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+fn audit_path(app: &AppHandle) -> Result<PathBuf, AuditError> {
+    let dir = app.path().app_data_dir().map_err(AuditError::DataDir)?;
+    Ok(dir.join(AUDIT_FILE))
+}
+
+/// Append entries to the audit log in the app data directory
+///
+/// Failures are logged rather than returned so a full disk never turns a
+/// successful SAFEQ change into a reported error.
+pub fn record(app: &AppHandle, entries: &[AuditEntry]) {
+    if entries.is_empty() {
+        return;
+    }
+
+    if let Err(err) = try_record(app, entries) {
+        warn!(error = %err, "failed to write audit log entries");
+    }
+}
+
+fn try_record(app: &AppHandle, entries: &[AuditEntry]) -> Result<(), AuditError> {
+    let path = audit_path(app)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    let mut file = app.fs().open(path, options)?;
+
+    append_entries(&mut file, entries)
+}
+
+/// Read back every entry in the audit log, oldest first
+pub fn read(app: &AppHandle) -> Result<Vec<AuditEntry>, AuditError> {
+    let path = audit_path(app)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let contents = app.fs().read(path)?;
+    read_entries(contents.as_slice())
+}
+
+/// Write the entries between `from` and `to` (both inclusive) to a CSV file
+///
+/// A missing log or an empty range still writes the header.
+pub fn export_csv_file(
+    app: &AppHandle,
+    path: &Path,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<AuditCsvExport, AuditError> {
+    let log_path = audit_path(app)?;
+    let contents = if log_path.exists() {
+        app.fs().read(log_path)?
+    } else {
+        Vec::new()
+    };
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    let mut out = io::BufWriter::new(app.fs().open(path.to_path_buf(), options)?);
+    export_csv(contents.as_slice(), &mut out, from, to)
+}
+
+/// Copy the audit entries between `from` and `to` from JSON lines to CSV
+pub fn export_csv(
+    reader: impl BufRead,
+    out: &mut impl Write,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<AuditCsvExport, AuditError> {
+    let (entries, skipped) = parse_entries(reader)?;
+    let mut export = AuditCsvExport { rows: 0, skipped };
+
+    writeln!(out, "{}", AUDIT_CSV_COLUMNS.join(","))?;
+    let in_range = |entry: &&AuditEntry| {
+        from.is_none_or(|from| entry.timestamp >= from) && to.is_none_or(|to| entry.timestamp <= to)
+    };
+    for entry in entries.iter().filter(in_range) {
+        writeln!(out, "{}", csv_row(entry))?;
+        export.rows += 1;
+    }
+    out.flush()?;
+    Ok(export)
+}
+
+fn csv_row(entry: &AuditEntry) -> String {
+    let operation = serde_json::to_value(entry.operation)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default();
+
+    [
+        entry.timestamp.to_rfc3339(),
+        operation,
+        entry.operator.clone().unwrap_or_default(),
+        entry.username.clone(),
+        entry
+            .provider_id
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        entry.success.to_string(),
+        entry.error.clone().unwrap_or_default(),
+    ]
+    .iter()
+    .map(|field| csv_escape(field))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// Write entries as JSON lines
+fn append_entries(writer: &mut impl Write, entries: &[AuditEntry]) -> Result<(), AuditError> {
+    for entry in entries {
+        let line = serde_json::to_string(entry).map_err(io::Error::from)?;
+        writeln!(writer, "{line}")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Parse JSON lines, skipping any line that is not a valid entry
+fn read_entries(reader: impl BufRead) -> Result<Vec<AuditEntry>, AuditError> {
+    Ok(parse_entries(reader)?.0)
+}
+
+/// Valid entries and the number of non-blank lines that were skipped
+fn parse_entries(reader: impl BufRead) -> Result<(Vec<AuditEntry>, usize), AuditError> {
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => {
+                warn!(error = %err, "skipping malformed audit log line");
+                skipped += 1;
+            }
+        }
+    }
+    Ok((entries, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn test_entries_are_appended() {
+        let path = std::env::temp_dir().join(format!("sqc-audit-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let created = json!({
+            "user": { "userName": "jdoe", "providerId": 3 },
+            "success": true,
+            "pin": "1234",
+        });
+        let failed = json!({
+            "user": { "userName": "asmith" },
+            "success": false,
+            "error": "SAFEQ request failed",
+        });
+
+        for row in [&created, &failed] {
+            let mut file = fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .unwrap();
+            let entry = AuditEntry::from_result(AuditOperation::UserCreated, Some("desk-1"), row);
+            append_entries(&mut file, &[entry]).unwrap();
+        }
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let entries = read_entries(contents.as_bytes()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].username, "jdoe");
+        assert_eq!(entries[0].provider_id, Some(3));
+        assert_eq!(entries[0].operator.as_deref(), Some("desk-1"));
+        assert!(entries[0].success);
+        assert_eq!(entries[1].username, "asmith");
+        assert_eq!(entries[1].error.as_deref(), Some("SAFEQ request failed"));
+        assert!(!contents.contains("1234"));
+    }
+
+    #[test]
+    fn test_malformed_lines_are_skipped() {
+        let entry = AuditEntry::email(None, "jdoe@example.com", None);
+        let mut buffer = Vec::new();
+        append_entries(&mut buffer, std::slice::from_ref(&entry)).unwrap();
+        buffer.extend_from_slice(b"not json\n");
+
+        let entries = read_entries(buffer.as_slice()).unwrap();
+        assert_eq!(entries, vec![entry]);
+    }
+
+    fn entry_at(timestamp: &str, username: &str, error: Option<&str>) -> AuditEntry {
+        AuditEntry {
+            timestamp: timestamp.parse().unwrap(),
+            error: error.map(str::to_string),
+            success: error.is_none(),
+            ..AuditEntry::email(Some("desk-1"), username, None)
+        }
+    }
+
+    fn log_of(entries: &[AuditEntry]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        append_entries(&mut buffer, entries).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_csv_export_filters_by_date_range() {
+        let mut log = log_of(&[
+            entry_at("2026-01-01T08:00:00Z", "early@example.com", None),
+            entry_at("2026-02-01T08:00:00Z", "middle@example.com", None),
+            entry_at("2026-03-01T08:00:00Z", "late@example.com", None),
+        ]);
+        log.extend_from_slice(b"not json\n{\"time\":\"2020-01-01\",\"op\":\"create\"}\n");
+
+        let from = "2026-02-01T08:00:00Z".parse().ok();
+        let to = "2026-03-01T00:00:00Z".parse().ok();
+        let mut out = Vec::new();
+        let export = export_csv(log.as_slice(), &mut out, from, to).unwrap();
+
+        assert_eq!(
+            export,
+            AuditCsvExport {
+                rows: 1,
+                skipped: 2
+            }
+        );
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "timestamp,operation,operator,username,providerId,success,error",
+                "2026-02-01T08:00:00+00:00,emailSent,desk-1,middle@example.com,,true,",
+            ]
+        );
+
+        let mut out = Vec::new();
+        let export = export_csv(log.as_slice(), &mut out, to, from).unwrap();
+        assert_eq!(export.rows, 0);
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 1);
+
+        let export = export_csv(log.as_slice(), &mut Vec::new(), None, None).unwrap();
+        assert_eq!(export.rows, 3);
+    }
+
+    #[test]
+    fn test_csv_export_escapes_fields() {
+        let log = log_of(&[entry_at(
+            "2026-01-01T08:00:00Z",
+            "jdoe@example.com",
+            Some("Graph said \"no\", twice\nthen gave up"),
+        )]);
+
+        let mut out = Vec::new();
+        export_csv(log.as_slice(), &mut out, None, None).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+
+        assert!(csv.ends_with(
+            ",jdoe@example.com,,false,\"Graph said \"\"no\"\", twice\nthen gave up\"\n"
+        ));
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::HashSet;
+
+use serde_json::{json, Value};
+
+/// Error reported for users skipped because they already appeared earlier in the batch
+pub const DUPLICATE_IN_BATCH: &str = "duplicate in batch";
+
+/// Error reported for rows whose card id already appeared earlier in the batch
+pub const DUPLICATE_CARD_IN_BATCH: &str = "card ID is already used in this batch";
+
+/// Error reported for rows whose username is blank after trimming
+pub const USERNAME_REQUIRED: &str = "Username is required";
+
+/// Error reported for rows whose `providerId` is not one of the account's providers
+pub const UNKNOWN_PROVIDER: &str = "unknown provider id";
+
+/// Error reported for rows whose `providerId` is text rather than a number
+pub const PROVIDER_ID_NOT_A_NUMBER: &str = "Provider ID must be a number";
+
+/// Error reported when every PIN the configured length allows is already used
+/// in the batch
+pub const PINS_EXHAUSTED: &str = "no unused PIN left in this batch; increase the PIN length";
+
+/// Error reported when every OTP the configured length and characters allow is
+/// already used in the batch
+pub const OTPS_EXHAUSTED: &str =
+    "no unused OTP left in this batch; allow a longer OTP or more characters";
+
+/// A local validation failure on one field of an import row
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError {
+    /// Import field name, e.g. `email` or `userName`
+    pub field: &'static str,
+    pub reason: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, reason: impl Into<String>) -> Self {
+        Self {
+            field,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// What happened to the credential email for one created user
+#[derive(Debug, Clone)]
+pub enum NotifyOutcome {
+    Sent,
+    /// Desktop delivery: the rendered message is returned for the UI to open
+    Draft(Value),
+    Failed(String),
+}
+
+/// The identifying fields of an import row, as echoed back in bulk results
+pub fn user_summary(user: &Value) -> Value {
+    json!({
+        "userName": user["userName"].as_str().unwrap_or(""),
+        "fullName": user["fullName"].as_str(),
+        "email": user["email"].as_str(),
+        "providerId": user["providerId"].as_i64(),
+    })
+}
+
+/// A failed bulk result row for `user`
+pub fn failed_row(user: &Value, error: &str) -> Value {
+    json!({
+        "user": user_summary(user),
+        "success": false,
+        "error": error,
+    })
+}
+
+/// A failed bulk result row for a local validation failure
+///
+/// Adds `field` and `reason` so the UI can highlight the offending column;
+/// `error` carries the reason too for callers that only show a message.
+pub fn invalid_row(user: &Value, error: &FieldError) -> Value {
+    let mut row = failed_row(user, &error.reason);
+    row["field"] = json!(error.field);
+    row["reason"] = json!(error.reason);
+    row
+}
+
+/// Merge per-user create results with the email outcome for each created user
+///
+/// `outcomes` is aligned with `results`; rows whose creation failed carry `None`
+/// and get no `email` entry. Creation and notification are counted separately
+/// so a created user whose email failed shows up in both `created` and
+/// `emailFailed`.
+pub fn combine_create_and_notify(
+    mut results: Vec<Value>,
+    outcomes: Vec<Option<NotifyOutcome>>,
+) -> Value {
+    let counts = apply_notify_outcomes(&mut results, outcomes);
+
+    json!({
+        "created": counts.succeeded,
+        "failed": results.len() - counts.succeeded,
+        "emailed": counts.emailed,
+        "emailFailed": counts.email_failed,
+        "drafts": counts.drafts,
+        "results": results,
+    })
+}
+
+/// Merge per-user OTP generation results with the email outcome for each user
+///
+/// Works like `combine_create_and_notify`, but a user whose OTP was set while
+/// the email failed is also flagged `undelivered`: the OTP is live in SAFEQ
+/// and the user has not been told it.
+pub fn combine_generate_and_notify(
+    mut results: Vec<Value>,
+    outcomes: Vec<Option<NotifyOutcome>>,
+) -> Value {
+    let counts = apply_notify_outcomes(&mut results, outcomes);
+
+    let mut undelivered = 0;
+    for result in &mut results {
+        if result["success"] == true && result["email"]["status"] == "failed" {
+            result["undelivered"] = json!(true);
+            undelivered += 1;
+        }
+    }
+
+    json!({
+        "generated": counts.succeeded,
+        "generateFailed": results.len() - counts.succeeded,
+        "emailed": counts.emailed,
+        "emailFailed": counts.email_failed,
+        "drafts": counts.drafts,
+        "undelivered": undelivered,
+        "results": results,
+    })
+}
+
+#[derive(Default)]
+struct NotifyCounts {
+    succeeded: usize,
+    emailed: usize,
+    email_failed: usize,
+    drafts: usize,
+}
+
+/// Attach an `email` entry to every row that has an outcome and count them
+fn apply_notify_outcomes(
+    results: &mut [Value],
+    outcomes: Vec<Option<NotifyOutcome>>,
+) -> NotifyCounts {
+    let mut counts = NotifyCounts::default();
+
+    for (result, outcome) in results.iter_mut().zip(outcomes) {
+        if result["success"] == true {
+            counts.succeeded += 1;
+        }
+
+        let Some(outcome) = outcome else {
+            continue;
+        };
+
+        result["email"] = match outcome {
+            NotifyOutcome::Sent => {
+                counts.emailed += 1;
+                json!({ "status": "sent" })
+            }
+            NotifyOutcome::Draft(message) => {
+                counts.drafts += 1;
+                json!({ "status": "draft", "message": message })
+            }
+            NotifyOutcome::Failed(error) => {
+                counts.email_failed += 1;
+                json!({ "status": "failed", "error": error })
+            }
+        };
+    }
+
+    counts
+}
+
+/// Trim a username and optionally lowercase it
+///
+/// Returns `None` when nothing is left, so callers can fail the row instead of
+/// sending an empty username to SAFEQ.
+pub fn normalize_username(username: &str, lowercase: bool) -> Option<String> {
+    let username = username.trim();
+    if username.is_empty() {
+        return None;
+    }
+
+    Some(if lowercase {
+        username.to_lowercase()
+    } else {
+        username.to_string()
+    })
+}
+
+/// Find users whose `userName` already appeared earlier in the batch
+///
+/// Usernames are compared as `normalize_username` would send them, so case
+/// only matters when `lowercase` is off. They are scoped per `providerId`, so
+/// the same username under two providers is not a duplicate. Only later
+/// occurrences are flagged; the first one is kept.
+pub fn duplicate_user_indices(users: &[Value], lowercase: bool) -> HashSet<usize> {
+    let mut seen: HashSet<(String, Option<i64>)> = HashSet::new();
+    let mut duplicates = HashSet::new();
+
+    for (index, user) in users.iter().enumerate() {
+        let Some(username) = normalize_username(user["userName"].as_str().unwrap_or(""), lowercase)
+        else {
+            continue;
+        };
+
+        if !seen.insert((username, user["providerId"].as_i64())) {
+            duplicates.insert(index);
+        }
+    }
+
+    duplicates
+}
+
+/// Find rows whose `providerId` is not in `known`
+///
+/// Rows without a provider id target the local provider and are never flagged.
+pub fn unknown_provider_indices(users: &[Value], known: &HashSet<i64>) -> HashSet<usize> {
+    users
+        .iter()
+        .enumerate()
+        .filter(|(_, user)| {
+            user["providerId"]
+                .as_i64()
+                .is_some_and(|id| !known.contains(&id))
+        })
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Find users whose `cardId` is already used by an earlier row in the batch
+///
+/// Card ids are compared after trimming; rows without a card id are ignored.
+pub fn duplicate_card_indices(users: &[Value]) -> HashSet<usize> {
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut duplicates = HashSet::new();
+
+    for (index, user) in users.iter().enumerate() {
+        let card_id = user["cardId"].as_str().unwrap_or("").trim();
+        if card_id.is_empty() {
+            continue;
+        }
+
+        if !seen.insert(card_id.to_string()) {
+            duplicates.insert(index);
+        }
+    }
+
+    duplicates
+}
+
+/// Basic email sanity check: a single `@` with a non-empty local part and a
+/// dotted domain, and no whitespace
+pub fn is_valid_email(email: &str) -> bool {
+    if email.chars().any(char::is_whitespace) {
+        return false;
+    }
+
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+        }
+        None => false,
+    }
+}
+
+/// Run the per-row checks applied before creating a user
+pub fn validate_user_fields(user: &Value) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if user["userName"].as_str().unwrap_or("").trim().is_empty() {
+        errors.push(FieldError::new("userName", USERNAME_REQUIRED));
+    }
+
+    if let Some(email) = user["email"].as_str() {
+        let email = email.trim();
+        if !email.is_empty() && !is_valid_email(email) {
+            errors.push(FieldError::new(
+                "email",
+                format!("Email address '{email}' is not valid"),
+            ));
+        }
+    }
+
+    if user["providerId"].is_string() {
+        errors.push(FieldError::new("providerId", PROVIDER_ID_NOT_A_NUMBER));
+    } else if user["providerId"].as_i64().is_some_and(|id| id < 0) {
+        errors.push(FieldError::new(
+            "providerId",
+            "Provider ID must be a positive number",
+        ));
+    }
+
+    errors
+}
+
+/// Build a dry-run report for a batch without contacting SAFEQ
+///
+/// Each row gets `{ index, user, valid, errors }`, combining the field checks
+/// with in-batch duplicate username and card id detection. `lowercase_usernames`
+/// is the setting of the same name, see `duplicate_user_indices`.
+pub fn validate_users(users: &[Value], lowercase_usernames: bool) -> Vec<Value> {
+    let duplicate_users = duplicate_user_indices(users, lowercase_usernames);
+    let duplicate_cards = duplicate_card_indices(users);
+
+    users
+        .iter()
+        .enumerate()
+        .map(|(index, user)| {
+            let mut errors: Vec<String> = validate_user_fields(user)
+                .into_iter()
+                .map(|error| error.reason)
+                .collect();
+
+            if duplicate_users.contains(&index) {
+                errors.push(DUPLICATE_IN_BATCH.to_string());
+            }
+            if duplicate_cards.contains(&index) {
+                errors.push(DUPLICATE_CARD_IN_BATCH.to_string());
+            }
+
+            json!({
+                "index": index,
+                "user": user,
+                "valid": errors.is_empty(),
+                "errors": errors,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_username_trims() {
+        assert_eq!(
+            normalize_username("  jsmith \t", false).as_deref(),
+            Some("jsmith")
+        );
+        assert_eq!(
+            normalize_username(" JSmith ", false).as_deref(),
+            Some("JSmith")
+        );
+        assert_eq!(
+            normalize_username(" JSmith ", true).as_deref(),
+            Some("jsmith")
+        );
+    }
+
+    #[test]
+    fn test_normalize_username_rejects_blank() {
+        assert_eq!(normalize_username("", false), None);
+        assert_eq!(normalize_username("   ", true), None);
+    }
+
+    #[test]
+    fn test_duplicate_user_indices_flags_later_occurrences() {
+        let users = vec![
+            json!({ "userName": "jdoe", "providerId": 1 }),
+            json!({ "userName": "asmith", "providerId": 1 }),
+            json!({ "userName": "jdoe", "providerId": 1 }),
+        ];
+
+        let duplicates = duplicate_user_indices(&users, false);
+        assert_eq!(duplicates, HashSet::from([2]));
+    }
+
+    #[test]
+    fn test_duplicate_user_indices_follow_lowercase_setting() {
+        let users = vec![
+            json!({ "userName": "JDoe" }),
+            json!({ "userName": " jdoe " }),
+            json!({ "userName": "jdoe" }),
+        ];
+
+        assert_eq!(duplicate_user_indices(&users, true), HashSet::from([1, 2]));
+        // Without lowercasing, SAFEQ may hold `JDoe` and `jdoe` as two users
+        assert_eq!(duplicate_user_indices(&users, false), HashSet::from([2]));
+    }
+
+    #[test]
+    fn test_duplicate_user_indices_scoped_per_provider() {
+        let users = vec![
+            json!({ "userName": "jdoe", "providerId": 1 }),
+            json!({ "userName": "jdoe", "providerId": 2 }),
+        ];
+
+        assert!(duplicate_user_indices(&users, true).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_provider_indices_flags_stale_ids() {
+        let users = vec![
+            json!({ "userName": "jdoe", "providerId": 1 }),
+            json!({ "userName": "asmith", "providerId": 42 }),
+            json!({ "userName": "local" }),
+        ];
+        let known = HashSet::from([1, 4]);
+        assert_eq!(unknown_provider_indices(&users, &known), HashSet::from([1]));
+    }
+
+    #[test]
+    fn test_validate_users_clean_row() {
+        let report = validate_users(
+            &[json!({
+                "userName": "jdoe",
+                "email": "john@example.com",
+                "cardId": "1234",
+                "providerId": 1
+            })],
+            false,
+        );
+
+        assert_eq!(report[0]["valid"], true);
+        assert!(report[0]["errors"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_validate_users_missing_username() {
+        let report = validate_users(
+            &[json!({ "userName": "  ", "email": "a@example.com" })],
+            false,
+        );
+
+        assert_eq!(report[0]["valid"], false);
+        assert_eq!(report[0]["errors"][0], "Username is required");
+    }
+
+    #[test]
+    fn test_validate_users_invalid_email() {
+        let report = validate_users(
+            &[json!({ "userName": "jdoe", "email": "not-an-email" })],
+            false,
+        );
+
+        assert_eq!(report[0]["valid"], false);
+        assert!(report[0]["errors"][0]
+            .as_str()
+            .unwrap()
+            .contains("not-an-email"));
+    }
+
+    #[test]
+    fn test_validate_users_duplicates() {
+        let report = validate_users(
+            &[
+                json!({ "userName": "jdoe", "cardId": "1234" }),
+                json!({ "userName": "asmith", "cardId": "1234" }),
+                json!({ "userName": "jdoe" }),
+            ],
+            false,
+        );
+
+        assert_eq!(report[0]["valid"], true);
+        assert_eq!(
+            report[1]["errors"][0],
+            "card ID is already used in this batch"
+        );
+        assert_eq!(report[2]["errors"][0], DUPLICATE_IN_BATCH);
+    }
+
+    #[test]
+    fn test_invalid_email_row_names_the_field() {
+        let user = json!({ "userName": "jdoe", "email": "not-an-email" });
+        let errors = validate_user_fields(&user);
+        let row = invalid_row(&user, &errors[0]);
+
+        assert_eq!(row["success"], false);
+        assert_eq!(row["field"], "email");
+        assert!(row["reason"].as_str().unwrap().contains("not-an-email"));
+    }
+
+    #[test]
+    fn test_is_valid_email() {
+        assert!(is_valid_email("john@example.com"));
+        assert!(!is_valid_email("john@example"));
+        assert!(!is_valid_email("@example.com"));
+        assert!(!is_valid_email("john@@example.com"));
+        assert!(!is_valid_email("john doe@example.com"));
+    }
+
+    #[test]
+    fn test_combine_create_and_notify_reports_mixed_outcomes() {
+        let results = vec![
+            json!({ "user": { "userName": "jdoe" }, "success": true, "pin": "1234" }),
+            json!({ "user": { "userName": "asmith" }, "success": true, "pin": "5678" }),
+            failed_row(&json!({ "userName": "bob" }), "SAFEQ request failed"),
+        ];
+        let outcomes = vec![
+            Some(NotifyOutcome::Sent),
+            Some(NotifyOutcome::Failed("Graph returned 403".to_string())),
+            None,
+        ];
+
+        let summary = combine_create_and_notify(results, outcomes);
+        assert_eq!(summary["created"], 2);
+        assert_eq!(summary["failed"], 1);
+        assert_eq!(summary["emailed"], 1);
+        assert_eq!(summary["emailFailed"], 1);
+
+        let rows = summary["results"].as_array().unwrap();
+        assert_eq!(rows[0]["email"]["status"], "sent");
+        assert_eq!(rows[1]["success"], true);
+        assert_eq!(rows[1]["email"]["status"], "failed");
+        assert_eq!(rows[1]["email"]["error"], "Graph returned 403");
+        assert!(rows[2].get("email").is_none());
+    }
+
+    #[test]
+    fn test_combine_generate_and_notify_flags_undelivered_otps() {
+        let results = vec![
+            json!({ "user": { "userName": "jdoe" }, "success": true, "value": "AB12" }),
+            json!({ "user": { "userName": "asmith" }, "success": true, "value": "CD34" }),
+            failed_row(&json!({ "userName": "bob" }), "SAFEQ request failed"),
+            json!({ "user": { "userName": "cdoe" }, "success": true, "value": "EF56" }),
+        ];
+        let outcomes = vec![
+            Some(NotifyOutcome::Sent),
+            Some(NotifyOutcome::Failed("Graph returned 403".to_string())),
+            None,
+            Some(NotifyOutcome::Failed("template has no body".to_string())),
+        ];
+
+        let summary = combine_generate_and_notify(results, outcomes);
+        assert_eq!(summary["generated"], 3);
+        assert_eq!(summary["generateFailed"], 1);
+        assert_eq!(summary["emailed"], 1);
+        assert_eq!(summary["emailFailed"], 2);
+        assert_eq!(summary["undelivered"], 2);
+
+        let rows = summary["results"].as_array().unwrap();
+        assert!(rows[0].get("undelivered").is_none());
+        assert_eq!(rows[1]["undelivered"], true);
+        assert_eq!(rows[1]["email"]["error"], "Graph returned 403");
+        assert!(rows[2].get("undelivered").is_none());
+        assert!(rows[2].get("email").is_none());
+        assert_eq!(rows[3]["undelivered"], true);
+    }
+}
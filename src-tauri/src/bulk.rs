@@ -0,0 +1,682 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::ops::Range;
+use std::time::Duration;
+
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::batch;
+use crate::safeq_api::{SafeQClient, UserDetailType};
+use crate::settings::{SafeQSettings, DEFAULT_MAX_CONCURRENCY, MAX_CONCURRENCY_RANGE};
+
+/// Rows handled per chunk of a large batch when not configured
+pub const DEFAULT_CHUNK_SIZE: usize = 200;
+
+/// Running totals and per-user rows for a bulk command
+///
+/// Holds plain data, so concurrent tasks can share one behind a `Mutex`.
+#[derive(Debug, Default)]
+pub struct BulkSummary {
+    success: usize,
+    failed: usize,
+    results: Vec<Value>,
+}
+
+impl BulkSummary {
+    pub fn record_success(&mut self, row: Value) {
+        self.success += 1;
+        self.results.push(row);
+    }
+
+    pub fn record_failure(&mut self, row: Value) {
+        self.failed += 1;
+        self.results.push(row);
+    }
+
+    /// Record a row, counting it by its own `success` field
+    pub fn record(&mut self, row: Value) {
+        if row["success"] == true {
+            self.record_success(row);
+        } else {
+            self.record_failure(row);
+        }
+    }
+
+    pub fn success(&self) -> usize {
+        self.success
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    pub fn results(&self) -> &[Value] {
+        &self.results
+    }
+
+    /// The `{ success, failed, results }` shape returned to the frontend
+    pub fn to_json(&self) -> Value {
+        json!({
+            "success": self.success,
+            "failed": self.failed,
+            "results": self.results,
+        })
+    }
+}
+
+impl FromIterator<Value> for BulkSummary {
+    fn from_iter<I: IntoIterator<Item = Value>>(rows: I) -> Self {
+        let mut summary = Self::default();
+        for row in rows {
+            summary.record(row);
+        }
+        summary
+    }
+}
+
+/// How a large batch is split up and paced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkPlan {
+    pub size: usize,
+    /// Pause between chunks, so operators can keep load on the tenant down
+    pub delay: Duration,
+}
+
+impl Default for ChunkPlan {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_CHUNK_SIZE,
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+impl ChunkPlan {
+    pub fn from_settings(settings: &SafeQSettings) -> Self {
+        Self {
+            size: settings
+                .batch_chunk_size
+                .filter(|size| *size > 0)
+                .unwrap_or(DEFAULT_CHUNK_SIZE),
+            delay: Duration::from_millis(settings.batch_chunk_delay_ms.unwrap_or(0)),
+        }
+    }
+
+    /// Row ranges for a batch of `len` rows; the last one may be short
+    pub fn ranges(&self, len: usize) -> Vec<Range<usize>> {
+        let size = self.size.max(1);
+        (0..len)
+            .step_by(size)
+            .map(|start| start..(start + size).min(len))
+            .collect()
+    }
+}
+
+/// Requests a bulk operation may keep in flight, from `max_concurrency`
+pub fn max_concurrency(settings: &SafeQSettings) -> usize {
+    settings
+        .max_concurrency
+        .unwrap_or(DEFAULT_MAX_CONCURRENCY)
+        .clamp(*MAX_CONCURRENCY_RANGE.start(), *MAX_CONCURRENCY_RANGE.end())
+}
+
+/// Run `task` for every index in `0..len` with at most `limit` running at once
+///
+/// Outputs come back in index order whatever order the tasks finish in. A
+/// `limit` of 1 runs the tasks strictly one after another.
+pub async fn run_concurrent<T, F, Fut>(len: usize, limit: usize, task: F) -> Vec<T>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = T>,
+{
+    stream::iter(0..len)
+        .map(task)
+        .buffered(limit.max(1))
+        .collect()
+        .await
+}
+
+/// Progress reported after each chunk of a batch finishes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkProgress {
+    /// 1-based number of the chunk that just finished
+    pub chunk: usize,
+    pub chunks: usize,
+    pub processed: usize,
+    pub total: usize,
+}
+
+/// Run `process` over a batch one chunk at a time and total up its rows
+///
+/// `process` receives the row range of each chunk and returns one result row
+/// per input row. `on_progress` is called after every chunk.
+pub async fn run_chunked<F, Fut>(
+    len: usize,
+    plan: ChunkPlan,
+    mut process: F,
+    mut on_progress: impl FnMut(ChunkProgress),
+) -> BulkSummary
+where
+    F: FnMut(Range<usize>) -> Fut,
+    Fut: Future<Output = Vec<Value>>,
+{
+    let ranges = plan.ranges(len);
+    let chunks = ranges.len();
+
+    let mut summary = BulkSummary::default();
+    for (index, range) in ranges.into_iter().enumerate() {
+        if index > 0 && !plan.delay.is_zero() {
+            tokio::time::sleep(plan.delay).await;
+        }
+
+        let processed = range.end;
+        for row in process(range).await {
+            summary.record(row);
+        }
+        on_progress(ChunkProgress {
+            chunk: index + 1,
+            chunks,
+            processed,
+            total: len,
+        });
+    }
+
+    summary
+}
+
+/// Assign card ids from `{ userName, providerId, cardId }` rows
+///
+/// Up to `concurrency` rows are sent at once and reported in input order. A
+/// card id that repeats within the batch fails locally; one SAFEQ already has
+/// on another user fails with the server's error for that row.
+pub async fn update_cards(client: &SafeQClient, rows: &[Value], concurrency: usize) -> BulkSummary {
+    let duplicates = batch::duplicate_card_indices(rows);
+
+    run_concurrent(rows.len(), concurrency, |index| {
+        update_card_row(client, &rows[index], duplicates.contains(&index))
+    })
+    .await
+    .into_iter()
+    .collect()
+}
+
+async fn update_card_row(client: &SafeQClient, row: &Value, duplicate: bool) -> Value {
+    let card_id = row["cardId"].as_str().unwrap_or("").trim();
+    let outcome = match batch::normalize_username(row["userName"].as_str().unwrap_or(""), false) {
+        None => Err(batch::USERNAME_REQUIRED.to_string()),
+        Some(_) if card_id.is_empty() => Err("Card ID is required".to_string()),
+        Some(_) if duplicate => Err(batch::DUPLICATE_CARD_IN_BATCH.to_string()),
+        Some(username) => client
+            .update_user_detail(
+                &username,
+                row["providerId"].as_i64(),
+                UserDetailType::CardId,
+                Some(card_id),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+    };
+
+    let mut result = match outcome {
+        Ok(()) => json!({ "user": batch::user_summary(row), "success": true }),
+        Err(error) => batch::failed_row(row, &error),
+    };
+    result["cardId"] = json!(card_id);
+    result
+}
+
+/// Give every `{ userName, providerId }` row a new PIN and OTP
+///
+/// Each user gets both in one request, so a row never ends up with only one
+/// of them. Rows carry the generated `pin` and `otp` on success. `pins`, when
+/// given, holds each row's PIN in place of a freshly generated one, and rows in
+/// `unknown_providers` fail without contacting SAFEQ.
+pub async fn generate_credentials(
+    client: &SafeQClient,
+    settings: &SafeQSettings,
+    rows: &[Value],
+    pins: Option<&[String]>,
+    unknown_providers: &HashSet<usize>,
+    concurrency: usize,
+) -> BulkSummary {
+    run_concurrent(rows.len(), concurrency, |index| {
+        let row = &rows[index];
+        let pin = pins.map(|pins| pins[index].as_str());
+        let unknown_provider = unknown_providers.contains(&index);
+        async move {
+            if unknown_provider {
+                let error = batch::FieldError::new("providerId", batch::UNKNOWN_PROVIDER);
+                return batch::invalid_row(row, &error);
+            }
+            generate_credentials_row(client, settings, row, pin).await
+        }
+    })
+    .await
+    .into_iter()
+    .collect()
+}
+
+async fn generate_credentials_row(
+    client: &SafeQClient,
+    settings: &SafeQSettings,
+    row: &Value,
+    pin: Option<&str>,
+) -> Value {
+    let Some(username) = batch::normalize_username(row["userName"].as_str().unwrap_or(""), false)
+    else {
+        return batch::failed_row(row, batch::USERNAME_REQUIRED);
+    };
+
+    match client
+        .generate_credentials(&username, row["providerId"].as_i64(), pin, settings)
+        .await
+    {
+        Ok(values) => json!({
+            "user": batch::user_summary(row),
+            "success": true,
+            "pin": values["pin"],
+            "otp": values["otp"],
+        }),
+        Err(err) => batch::failed_row(row, &err.to_string()),
+    }
+}
+
+/// Remove `detail` from every `{ userName, providerId }` row
+///
+/// Up to `concurrency` rows are sent at once and reported in input order.
+/// Callers check `UserDetailType::is_clearable` first.
+pub async fn clear_detail(
+    client: &SafeQClient,
+    rows: &[Value],
+    detail: UserDetailType,
+    concurrency: usize,
+) -> BulkSummary {
+    run_concurrent(rows.len(), concurrency, |index| {
+        clear_detail_row(client, &rows[index], detail)
+    })
+    .await
+    .into_iter()
+    .collect()
+}
+
+async fn clear_detail_row(client: &SafeQClient, row: &Value, detail: UserDetailType) -> Value {
+    let Some(username) = batch::normalize_username(row["userName"].as_str().unwrap_or(""), false)
+    else {
+        return batch::failed_row(row, batch::USERNAME_REQUIRED);
+    };
+
+    match client
+        .update_user_detail(&username, row["providerId"].as_i64(), detail, None)
+        .await
+    {
+        Ok(_) => json!({ "user": batch::user_summary(row), "success": true }),
+        Err(err) => batch::failed_row(row, &err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_string, body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn settings_for(server: &MockServer) -> SafeQSettings {
+        SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "test-api-key".to_string(),
+            ..SafeQSettings::default()
+        }
+    }
+
+    fn client_for(server: &MockServer) -> SafeQClient {
+        SafeQClient::from_settings(settings_for(server)).unwrap()
+    }
+
+    #[test]
+    fn test_summary_counts_rows() {
+        let mut summary = BulkSummary::default();
+        summary.record_success(json!({ "user": "jdoe", "success": true }));
+        summary.record_failure(json!({ "user": "asmith", "success": false }));
+        summary.record_success(json!({ "user": "bkim", "success": true }));
+
+        assert_eq!(summary.success(), 2);
+        assert_eq!(summary.failed(), 1);
+        assert_eq!(
+            summary.to_json(),
+            json!({
+                "success": 2,
+                "failed": 1,
+                "results": [
+                    { "user": "jdoe", "success": true },
+                    { "user": "asmith", "success": false },
+                    { "user": "bkim", "success": true },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_summary_from_rows_uses_success_field() {
+        let summary: BulkSummary = vec![
+            json!({ "success": true }),
+            json!({ "success": false, "error": "boom" }),
+            json!({ "error": "no success field" }),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(summary.success(), 1);
+        assert_eq!(summary.failed(), 2);
+        assert_eq!(summary.results().len(), 3);
+    }
+
+    #[test]
+    fn test_summary_shared_across_threads() {
+        use std::sync::{Arc, Mutex};
+
+        let summary = Arc::new(Mutex::new(BulkSummary::default()));
+        let handles: Vec<_> = (0..8)
+            .map(|index| {
+                let summary = summary.clone();
+                std::thread::spawn(move || {
+                    let row = json!({ "index": index, "success": index % 2 == 0 });
+                    summary.lock().unwrap().record(row);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let summary = summary.lock().unwrap();
+        assert_eq!(summary.success(), 4);
+        assert_eq!(summary.failed(), 4);
+    }
+
+    #[test]
+    fn test_chunk_ranges_cover_uneven_batch() {
+        let plan = ChunkPlan {
+            size: 2,
+            ..ChunkPlan::default()
+        };
+
+        assert_eq!(plan.ranges(5), vec![0..2, 2..4, 4..5]);
+        assert_eq!(plan.ranges(4), vec![0..2, 2..4]);
+        assert!(plan.ranges(0).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_plan_from_settings() {
+        assert_eq!(
+            ChunkPlan::from_settings(&SafeQSettings::default()),
+            ChunkPlan::default()
+        );
+
+        let settings = SafeQSettings {
+            batch_chunk_size: Some(50),
+            batch_chunk_delay_ms: Some(250),
+            ..SafeQSettings::default()
+        };
+        assert_eq!(
+            ChunkPlan::from_settings(&settings),
+            ChunkPlan {
+                size: 50,
+                delay: Duration::from_millis(250),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_chunked_respects_boundaries_and_totals() {
+        let plan = ChunkPlan {
+            size: 3,
+            delay: Duration::from_millis(1),
+        };
+        let mut seen = Vec::new();
+        let mut progress = Vec::new();
+
+        let summary = run_chunked(
+            7,
+            plan,
+            |range| {
+                seen.push(range.clone());
+                async move {
+                    range
+                        .map(|index| json!({ "index": index, "success": index != 4 }))
+                        .collect()
+                }
+            },
+            |update| progress.push(update),
+        )
+        .await;
+
+        assert_eq!(seen, vec![0..3, 3..6, 6..7]);
+        assert_eq!(
+            progress.iter().map(|p| p.processed).collect::<Vec<_>>(),
+            vec![3, 6, 7]
+        );
+        assert!(progress.iter().all(|p| p.chunks == 3 && p.total == 7));
+        assert_eq!(summary.success(), 6);
+        assert_eq!(summary.failed(), 1);
+        assert_eq!(summary.results().len(), 7);
+        assert_eq!(summary.results()[6]["index"], 6);
+    }
+
+    #[tokio::test]
+    async fn test_update_cards_summary_shape() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/asmith"))
+            .respond_with(ResponseTemplate::new(409).set_body_json(json!({
+                "errorCode": "CARD_EXISTS",
+                "message": "Card is already assigned"
+            })))
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server);
+        let rows = vec![
+            json!({ "userName": "jdoe", "providerId": 1, "cardId": "1111" }),
+            json!({ "userName": "asmith", "providerId": 1, "cardId": "2222" }),
+            json!({ "userName": "bkim", "providerId": 1, "cardId": "1111" }),
+            json!({ "userName": " ", "cardId": "3333" }),
+        ];
+
+        let summary = update_cards(&client, &rows, 4).await.to_json();
+
+        assert_eq!(summary["success"], 1);
+        assert_eq!(summary["failed"], 3);
+        let results = summary["results"].as_array().unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0]["user"]["userName"], "jdoe");
+        assert_eq!(results[0]["cardId"], "1111");
+        assert_eq!(results[0]["success"], true);
+        assert!(results[1]["error"]
+            .as_str()
+            .unwrap()
+            .contains("CARD_EXISTS"));
+        assert_eq!(results[2]["error"], batch::DUPLICATE_CARD_IN_BATCH);
+        assert_eq!(results[3]["error"], batch::USERNAME_REQUIRED);
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_clear_detail_omits_data_and_summarizes() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .and(body_string("detailtype=10&providerid=1"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/gone"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server);
+        let rows = vec![
+            json!({ "userName": "jdoe", "providerId": 1 }),
+            json!({ "userName": "gone", "providerId": 1 }),
+            json!({ "userName": "" }),
+        ];
+
+        let summary = clear_detail(&client, &rows, UserDetailType::Otp, 2)
+            .await
+            .to_json();
+
+        assert_eq!(summary["success"], 1);
+        assert_eq!(summary["failed"], 2);
+        let results = summary["results"].as_array().unwrap();
+        assert_eq!(results[0]["user"]["userName"], "jdoe");
+        assert_eq!(results[1]["error"], "user gone does not exist");
+        assert_eq!(results[2]["error"], batch::USERNAME_REQUIRED);
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_generate_credentials_sends_pin_and_otp_together() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .and(body_string_contains(
+                "providerid=1&detailtype=5&detaildata=",
+            ))
+            .and(body_string_contains("&detailtype=10&detaildata="))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/gone"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let settings = SafeQSettings {
+            pin_length: Some(6),
+            ..settings_for(&server)
+        };
+        let client = SafeQClient::from_settings(settings.clone()).unwrap();
+        let rows = vec![
+            json!({ "userName": "jdoe", "providerId": 1 }),
+            json!({ "userName": "gone", "providerId": 1 }),
+        ];
+
+        let summary = generate_credentials(&client, &settings, &rows, None, &HashSet::new(), 2)
+            .await
+            .to_json();
+
+        assert_eq!(summary["success"], 1);
+        assert_eq!(summary["failed"], 1);
+        let jdoe = &summary["results"][0];
+        assert_eq!(jdoe["user"]["userName"], "jdoe");
+        let pin = jdoe["pin"].as_str().unwrap();
+        let otp = jdoe["otp"].as_str().unwrap();
+        assert_eq!(pin.len(), 6);
+        assert_eq!(otp.len(), crate::generator::DEFAULT_OTP_LENGTH);
+        assert!(summary["results"][1].get("pin").is_none());
+
+        let requests = server.received_requests().await.unwrap();
+        let body = String::from_utf8(requests[0].body.clone()).unwrap();
+        let pairs: Vec<(String, String)> = url::form_urlencoded::parse(body.as_bytes())
+            .into_owned()
+            .collect();
+        assert!(pairs.contains(&("detaildata".to_string(), pin.to_string())));
+        assert!(pairs.contains(&("detaildata".to_string(), otp.to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_generate_credentials_uses_given_pins_and_skips_unknown_providers() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .and(body_string_contains("detailtype=5&detaildata=4821&"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let settings = settings_for(&server);
+        let client = client_for(&server);
+        let rows = vec![
+            json!({ "userName": "jdoe", "providerId": 1 }),
+            json!({ "userName": "asmith", "providerId": 99 }),
+        ];
+        let pins = ["4821".to_string(), "5930".to_string()];
+
+        let summary = generate_credentials(
+            &client,
+            &settings,
+            &rows,
+            Some(&pins),
+            &HashSet::from([1]),
+            2,
+        )
+        .await
+        .to_json();
+
+        assert_eq!(summary["results"][0]["pin"], "4821");
+        assert_eq!(summary["results"][1]["field"], "providerId");
+        assert_eq!(summary["results"][1]["error"], batch::UNKNOWN_PROVIDER);
+    }
+
+    /// Largest number of tasks that were running at the same time
+    async fn peak_in_flight(limit: usize) -> (usize, Vec<usize>) {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let outputs = run_concurrent(6, limit, |index| {
+            let (running, peak) = (&running, &peak);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                // Later rows finish first, so ordering is not an accident
+                tokio::time::sleep(Duration::from_millis(10 - index as u64)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                index
+            }
+        })
+        .await;
+
+        (peak.load(Ordering::SeqCst), outputs)
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_of_one_runs_sequentially() {
+        let (peak, outputs) = peak_in_flight(1).await;
+        assert_eq!(peak, 1);
+        assert_eq!(outputs, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_caps_tasks_in_flight() {
+        let (peak, outputs) = peak_in_flight(3).await;
+        assert_eq!(peak, 3);
+        assert_eq!(outputs, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_max_concurrency_defaults_to_eight() {
+        assert_eq!(max_concurrency(&SafeQSettings::default()), 8);
+        assert_eq!(
+            max_concurrency(&SafeQSettings {
+                max_concurrency: Some(1),
+                ..SafeQSettings::default()
+            }),
+            1
+        );
+    }
+}
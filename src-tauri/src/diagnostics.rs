@@ -0,0 +1,353 @@
+use serde::Serialize;
+
+use crate::email::test_graph_token_at;
+use crate::safeq_api::SafeQClient;
+use crate::secrets::SecretStore;
+use crate::settings::{SafeQSettings, SettingsLoadError, DEFAULT_API_PORT};
+use crate::url_utils::UrlUtils;
+
+/// Result of one diagnostics check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    /// Not run, because an earlier check failed or the feature is not set up
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+/// Everything support needs to triage a connection problem
+///
+/// Messages never contain the API key, proxy password or Graph secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsReport {
+    /// True when no check failed
+    pub passed: bool,
+    pub checks: Vec<DiagnosticCheck>,
+}
+
+#[derive(Default)]
+struct Checks {
+    checks: Vec<DiagnosticCheck>,
+    secrets: Vec<String>,
+}
+
+impl Checks {
+    fn push(&mut self, name: &'static str, status: CheckStatus, message: impl Into<String>) {
+        let mut message = message.into();
+        for secret in &self.secrets {
+            message = message.replace(secret.as_str(), "[redacted]");
+        }
+        self.checks.push(DiagnosticCheck {
+            name,
+            status,
+            message,
+        });
+    }
+
+    fn result<T, E: std::fmt::Display>(
+        &mut self,
+        name: &'static str,
+        result: &Result<T, E>,
+        passed: impl FnOnce(&T) -> String,
+    ) {
+        match result {
+            Ok(value) => self.push(name, CheckStatus::Pass, passed(value)),
+            Err(err) => self.push(name, CheckStatus::Fail, err.to_string()),
+        }
+    }
+
+    fn skip(&mut self, names: &[&'static str], reason: &str) {
+        for name in names {
+            self.push(name, CheckStatus::Skipped, reason);
+        }
+    }
+
+    fn into_report(self) -> DiagnosticsReport {
+        DiagnosticsReport {
+            passed: self
+                .checks
+                .iter()
+                .all(|check| check.status != CheckStatus::Fail),
+            checks: self.checks,
+        }
+    }
+}
+
+/// Run every check against `settings`, as loaded from the store
+///
+/// A check that later ones depend on skips them when it fails; the account
+/// and provider checks both run so a partial outage shows which call broke.
+pub async fn run_diagnostics(
+    settings: Result<Option<SafeQSettings>, SettingsLoadError>,
+    graph_authority: &str,
+    secrets: &dyn SecretStore,
+) -> DiagnosticsReport {
+    const SAFEQ_CHECKS: [&str; 4] = ["tenantUrl", "baseUrl", "account", "providers"];
+    let mut checks = Checks::default();
+
+    let settings = match settings {
+        Ok(Some(settings)) => settings,
+        Ok(None) => {
+            checks.push(
+                "settings",
+                CheckStatus::Fail,
+                "SAFEQ settings are not configured",
+            );
+            checks.skip(&SAFEQ_CHECKS, "settings are not configured");
+            checks.skip(&["graphToken"], "settings are not configured");
+            return checks.into_report();
+        }
+        Err(err) => {
+            checks.push("settings", CheckStatus::Fail, err.to_string());
+            checks.skip(&SAFEQ_CHECKS, "settings could not be loaded");
+            checks.skip(&["graphToken"], "settings could not be loaded");
+            return checks.into_report();
+        }
+    };
+
+    checks.secrets = [
+        Some(settings.api_key.clone()),
+        settings.proxy_password.clone(),
+        settings.email_settings.graph_client_secret.clone(),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|secret| !secret.is_empty())
+    .collect();
+    checks.push(
+        "settings",
+        CheckStatus::Pass,
+        format!(
+            "settings loaded{}",
+            if settings.mock_mode {
+                " (mock mode)"
+            } else {
+                ""
+            }
+        ),
+    );
+
+    if settings.mock_mode {
+        checks.skip(&SAFEQ_CHECKS[..2], "mock mode sends no requests");
+    } else {
+        let normalized = UrlUtils::normalize_tenant_url(&settings.tenant_url);
+        if normalized.is_empty() {
+            checks.push("tenantUrl", CheckStatus::Fail, "tenant URL is empty");
+        } else {
+            checks.push("tenantUrl", CheckStatus::Pass, normalized.as_str());
+        }
+
+        let base_url =
+            UrlUtils::build_base_url(&normalized, settings.api_port.unwrap_or(DEFAULT_API_PORT));
+        checks.result("baseUrl", &base_url, String::clone);
+        if base_url.is_err() {
+            checks.skip(&SAFEQ_CHECKS[2..], "no usable base URL");
+            return finish_with_graph(checks, &settings, graph_authority, secrets).await;
+        }
+    }
+
+    match SafeQClient::from_settings(settings.clone()) {
+        Ok(client) => {
+            let account = client.get_account().await;
+            checks.result("account", &account, |account| {
+                format!("account {} is reachable", account["id"])
+            });
+            let providers = client.list_auth_providers().await;
+            checks.result("providers", &providers, |providers| {
+                let count = providers.as_array().map_or(0, Vec::len);
+                format!("{count} auth provider(s) listed")
+            });
+        }
+        Err(err) => {
+            checks.push("account", CheckStatus::Fail, err.to_string());
+            checks.skip(&SAFEQ_CHECKS[3..], "the SAFEQ client could not be built");
+        }
+    }
+
+    finish_with_graph(checks, &settings, graph_authority, secrets).await
+}
+
+/// Token check for Microsoft Graph, skipped unless tenant and client ID are set
+async fn finish_with_graph(
+    mut checks: Checks,
+    settings: &SafeQSettings,
+    graph_authority: &str,
+    secrets: &dyn SecretStore,
+) -> DiagnosticsReport {
+    let email = &settings.email_settings;
+    let configured = [&email.graph_tenant_id, &email.graph_client_id]
+        .iter()
+        .all(|value| {
+            value
+                .as_deref()
+                .is_some_and(|value| !value.trim().is_empty())
+        });
+
+    if configured {
+        let token = test_graph_token_at(settings, graph_authority, secrets).await;
+        checks.result("graphToken", &token, |_| "Graph token acquired".to_string());
+    } else {
+        checks.skip(&["graphToken"], "Microsoft Graph is not configured");
+    }
+
+    checks.into_report()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::MockSecretStore;
+    use serde_json::json;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    impl DiagnosticsReport {
+        fn check(&self, name: &str) -> Option<&DiagnosticCheck> {
+            self.checks.iter().find(|check| check.name == name)
+        }
+    }
+
+    fn statuses(report: &DiagnosticsReport) -> Vec<(&'static str, CheckStatus)> {
+        report
+            .checks
+            .iter()
+            .map(|check| (check.name, check.status))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_healthy_tenant_passes_every_check() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 42 })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/authproviders"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!([{ "id": 1 }, { "id": 2 }])),
+            )
+            .mount(&server)
+            .await;
+
+        let settings = SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "top-secret-key".to_string(),
+            ..SafeQSettings::default()
+        };
+        let report = run_diagnostics(Ok(Some(settings)), "", &MockSecretStore::default()).await;
+
+        assert!(report.passed);
+        assert_eq!(
+            statuses(&report),
+            vec![
+                ("settings", CheckStatus::Pass),
+                ("tenantUrl", CheckStatus::Pass),
+                ("baseUrl", CheckStatus::Pass),
+                ("account", CheckStatus::Pass),
+                ("providers", CheckStatus::Pass),
+                ("graphToken", CheckStatus::Skipped),
+            ]
+        );
+        assert_eq!(
+            report.check("account").unwrap().message,
+            "account 42 is reachable"
+        );
+        assert_eq!(
+            report.check("providers").unwrap().message,
+            "2 auth provider(s) listed"
+        );
+        assert!(!serde_json::to_string(&report)
+            .unwrap()
+            .contains("top-secret-key"));
+    }
+
+    #[tokio::test]
+    async fn test_failing_checks_are_reported_without_secrets() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("bad key top-secret-key"))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/tenant-1/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("invalid_client"))
+            .mount(&server)
+            .await;
+
+        let mut settings = SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "top-secret-key".to_string(),
+            ..SafeQSettings::default()
+        };
+        settings.email_settings.graph_tenant_id = Some("tenant-1".to_string());
+        settings.email_settings.graph_client_id = Some("client-1".to_string());
+        settings.email_settings.graph_client_secret = Some("graph-secret".to_string());
+
+        let report = run_diagnostics(
+            Ok(Some(settings)),
+            &server.uri(),
+            &MockSecretStore::default(),
+        )
+        .await;
+
+        assert!(!report.passed);
+        assert_eq!(report.check("account").unwrap().status, CheckStatus::Fail);
+        assert_eq!(report.check("providers").unwrap().status, CheckStatus::Fail);
+        assert_eq!(
+            report.check("graphToken").unwrap().status,
+            CheckStatus::Fail
+        );
+        let serialized = serde_json::to_string(&report).unwrap();
+        assert!(serialized.contains("[redacted]"));
+        assert!(!serialized.contains("top-secret-key"));
+        assert!(!serialized.contains("graph-secret"));
+    }
+
+    #[tokio::test]
+    async fn test_bad_url_and_missing_settings_skip_dependent_checks() {
+        let settings = SafeQSettings {
+            tenant_url: "https://tenant.example.com:99999".to_string(),
+            api_key: "key".to_string(),
+            ..SafeQSettings::default()
+        };
+        let report = run_diagnostics(Ok(Some(settings)), "", &MockSecretStore::default()).await;
+        assert_eq!(report.check("baseUrl").unwrap().status, CheckStatus::Fail);
+        assert_eq!(
+            report.check("account").unwrap().status,
+            CheckStatus::Skipped
+        );
+        assert_eq!(
+            report.check("providers").unwrap().status,
+            CheckStatus::Skipped
+        );
+
+        let report = run_diagnostics(Ok(None), "", &MockSecretStore::default()).await;
+        assert!(!report.passed);
+        assert_eq!(report.checks[0].status, CheckStatus::Fail);
+        assert!(report.checks[1..]
+            .iter()
+            .all(|check| check.status == CheckStatus::Skipped));
+
+        let report = run_diagnostics(
+            Err(SettingsLoadError::MissingTenantUrl),
+            "",
+            &MockSecretStore::default(),
+        )
+        .await;
+        assert_eq!(report.checks.len(), 6);
+        assert_eq!(report.checks[0].status, CheckStatus::Fail);
+    }
+}
@@ -1,16 +1,30 @@
 use std::fmt;
 
-use reqwest::{Client, StatusCode};
-use serde::Deserialize;
-use serde_json::json;
+use futures::lock::Mutex;
+use reqwest::{Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
 use url::form_urlencoded;
 
-use crate::settings::{EmailDeliveryMethod, EmailSettings};
+use crate::bulk::{max_concurrency, run_concurrent};
+use crate::http_client::{apply_min_tls, apply_proxy, is_tls_version_error, user_agent};
+use crate::rate_limit::{self, Backend, RateLimiter};
+use crate::secrets::{default_store, SecretKey, SecretStore, SecretStoreError};
+use crate::settings::{
+    EmailDeliveryMethod, EmailSettings, EmailTemplateSettings, GraphAuthFlow, SafeQSettings,
+};
+use crate::template::{is_html_content, render_html_template, render_template, TemplateTokens};
+use crate::text::{error_body_limit, truncate};
 
 const GRAPH_SCOPE: &str = "https://graph.microsoft.com/.default";
+/// Scopes requested for delegated sends; `offline_access` yields a refresh token
+const GRAPH_DELEGATED_SCOPE: &str = "offline_access https://graph.microsoft.com/Mail.Send";
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
 const GRAPH_BASE_URL: &str = "https://graph.microsoft.com/v1.0";
+pub(crate) const GRAPH_AUTHORITY_URL: &str = "https://login.microsoftonline.com";
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PreparedEmailPayload {
     pub to: String,
@@ -18,21 +32,32 @@ pub struct PreparedEmailPayload {
     pub body: String,
     #[serde(default)]
     pub content_type: EmailContentType,
+    #[serde(default)]
+    pub importance: Option<EmailImportance>,
+    #[serde(default)]
+    pub attachments: Vec<EmailAttachment>,
+}
+
+/// File sent along with a message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailAttachment {
+    pub name: String,
+    /// MIME type; `application/octet-stream` when unset
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// File contents, base64 encoded
+    pub content_bytes: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum EmailContentType {
+    #[default]
     Text,
     Html,
 }
 
-impl Default for EmailContentType {
-    fn default() -> Self {
-        Self::Text
-    }
-}
-
 impl EmailContentType {
     fn graph_value(&self) -> &'static str {
         match self {
@@ -42,11 +67,64 @@ impl EmailContentType {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailImportance {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl EmailImportance {
+    fn graph_value(&self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+        }
+    }
+}
+
+/// Which credential an email template delivers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialType {
+    Pin,
+    Otp,
+}
+
 #[derive(Debug, Default)]
 pub struct EmailSendSummary {
     pub success: usize,
     pub failed: usize,
     pub errors: Vec<String>,
+    /// Per-message outcome in input order: `None` when sent, otherwise the error
+    pub outcomes: Vec<Option<String>>,
+    /// Input positions of the messages that were not sent
+    pub failed_indices: Vec<usize>,
+}
+
+impl EmailSendSummary {
+    fn record_success(&mut self) {
+        self.success += 1;
+        self.outcomes.push(None);
+    }
+
+    fn record_failure(&mut self, error: String) {
+        self.failed += 1;
+        self.failed_indices.push(self.outcomes.len());
+        self.errors.push(error.clone());
+        self.outcomes.push(Some(error));
+    }
+
+    /// The messages that were not sent, ready to pass back for a resend
+    pub fn failed_messages(&self, messages: &[PreparedEmailPayload]) -> Vec<PreparedEmailPayload> {
+        self.failed_indices
+            .iter()
+            .filter_map(|index| messages.get(*index).cloned())
+            .collect()
+    }
 }
 
 #[derive(Debug)]
@@ -57,6 +135,11 @@ pub enum EmailDeliveryError {
     TokenStatus(StatusCode, String),
     TokenParse(serde_json::Error),
     HttpClient(reqwest::Error),
+    InvalidProxy(reqwest::Error),
+    GraphRequest(reqwest::Error),
+    /// Delegated auth is configured but nobody has signed in yet
+    NotSignedIn,
+    Secrets(SecretStoreError),
 }
 
 impl fmt::Display for EmailDeliveryError {
@@ -66,12 +149,43 @@ impl fmt::Display for EmailDeliveryError {
             Self::MissingGraphField(field) => {
                 write!(f, "Email delivery via Microsoft Graph is missing the required setting: {field}")
             }
+            Self::TokenRequest(error) | Self::GraphRequest(error) if is_tls_version_error(error) => write!(
+                f,
+                "Microsoft Graph does not support the minimum TLS version required by the settings: {error}"
+            ),
             Self::TokenRequest(error) => write!(f, "Unable to request Microsoft Graph token: {error}"),
             Self::TokenStatus(status, body) => {
                 write!(f, "Microsoft Graph token endpoint returned {}: {}", status.as_u16(), body)
             }
             Self::TokenParse(error) => write!(f, "Unable to parse Microsoft Graph token response: {error}"),
             Self::HttpClient(error) => write!(f, "Unable to build HTTP client for Microsoft Graph: {error}"),
+            Self::InvalidProxy(error) => write!(f, "Proxy URL is invalid: {error}"),
+            Self::GraphRequest(error) => write!(f, "Unable to reach Microsoft Graph: {error}"),
+            Self::NotSignedIn => write!(f, "Microsoft Graph is set to send as a signed-in user, but nobody has signed in. Sign in with a device code first."),
+            Self::Secrets(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl EmailDeliveryError {
+    /// Key of this error's message in the `i18n` tables
+    pub fn message_key(&self) -> &'static str {
+        match self {
+            Self::MethodNotGraph => "email.method_not_graph",
+            Self::MissingGraphField(_) => "email.missing_graph_field",
+            Self::TokenRequest(error) | Self::GraphRequest(error)
+                if is_tls_version_error(error) =>
+            {
+                "email.tls_version"
+            }
+            Self::TokenRequest(_) => "email.token_request",
+            Self::TokenStatus(_, _) => "email.token_status",
+            Self::TokenParse(_) => "email.token_parse",
+            Self::HttpClient(_) => "email.http_client",
+            Self::InvalidProxy(_) => "proxy.invalid",
+            Self::GraphRequest(_) => "email.graph_request",
+            Self::NotSignedIn => "email.not_signed_in",
+            Self::Secrets(_) => "secrets.unavailable",
         }
     }
 }
@@ -79,17 +193,55 @@ impl fmt::Display for EmailDeliveryError {
 impl std::error::Error for EmailDeliveryError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Self::TokenRequest(error) | Self::HttpClient(error) => Some(error),
+            Self::TokenRequest(error)
+            | Self::HttpClient(error)
+            | Self::InvalidProxy(error)
+            | Self::GraphRequest(error) => Some(error),
             Self::TokenParse(error) => Some(error),
-            Self::TokenStatus(_, _) | Self::MethodNotGraph | Self::MissingGraphField(_) => None,
+            Self::Secrets(error) => Some(error),
+            Self::TokenStatus(_, _)
+            | Self::MethodNotGraph
+            | Self::MissingGraphField(_)
+            | Self::NotSignedIn => None,
         }
     }
 }
 
+impl From<SecretStoreError> for EmailDeliveryError {
+    fn from(error: SecretStoreError) -> Self {
+        Self::Secrets(error)
+    }
+}
+
+/// Graph error code returned when the app registration lacks `Mail.Send`
+const GRAPH_ACCESS_DENIED: &str = "ErrorAccessDenied";
+
+/// Token authority and Graph API roots, overridden in tests
+struct GraphEndpoints<'a> {
+    authority: &'a str,
+    graph_base: &'a str,
+}
+
+const GRAPH_ENDPOINTS: GraphEndpoints<'static> = GraphEndpoints {
+    authority: GRAPH_AUTHORITY_URL,
+    graph_base: GRAPH_BASE_URL,
+};
+
+#[tracing::instrument(skip_all, fields(count = messages.len()))]
 pub async fn send_graph_emails(
-    settings: &EmailSettings,
+    app_settings: &SafeQSettings,
+    messages: &[PreparedEmailPayload],
+) -> Result<EmailSendSummary, EmailDeliveryError> {
+    send_graph_emails_to(app_settings, messages, &GRAPH_ENDPOINTS, default_store()).await
+}
+
+async fn send_graph_emails_to(
+    app_settings: &SafeQSettings,
     messages: &[PreparedEmailPayload],
+    endpoints: &GraphEndpoints<'_>,
+    secrets: &dyn SecretStore,
 ) -> Result<EmailSendSummary, EmailDeliveryError> {
+    let settings = &app_settings.email_settings;
     if settings.method != EmailDeliveryMethod::Graph {
         return Err(EmailDeliveryError::MethodNotGraph);
     }
@@ -98,149 +250,1570 @@ pub async fn send_graph_emails(
         return Ok(EmailSendSummary::default());
     }
 
-    let tenant_id = settings
-        .graph_tenant_id
-        .as_deref()
-        .ok_or(EmailDeliveryError::MissingGraphField("graphTenantId"))?;
-    let client_id = settings
-        .graph_client_id
-        .as_deref()
-        .ok_or(EmailDeliveryError::MissingGraphField("graphClientId"))?;
-    let client_secret = settings
-        .graph_client_secret
-        .as_deref()
-        .ok_or(EmailDeliveryError::MissingGraphField("graphClientSecret"))?;
-    let sender_address = settings
-        .graph_sender_address
-        .as_deref()
-        .ok_or(EmailDeliveryError::MissingGraphField("graphSenderAddress"))?;
+    let credentials = GraphCredentials::from_settings(settings, secrets)?;
+    let graph_base = endpoints.graph_base.trim_end_matches('/');
+    // Delegated sends go out as the signed-in user rather than a named mailbox
+    let send_url = match settings.auth_flow {
+        GraphAuthFlow::DeviceCode => format!("{graph_base}/me/sendMail"),
+        GraphAuthFlow::ClientCredentials => {
+            let sender_address = settings
+                .graph_sender_address
+                .as_deref()
+                .ok_or(EmailDeliveryError::MissingGraphField("graphSenderAddress"))?;
+            let encoded_sender: String =
+                form_urlencoded::byte_serialize(sender_address.as_bytes()).collect();
+            format!("{graph_base}/users/{encoded_sender}/sendMail")
+        }
+    };
 
-    let http_client = Client::builder()
-        .user_agent("SQC-User-Manager/0.1")
-        .build()
-        .map_err(EmailDeliveryError::HttpClient)?;
+    let http_client = graph_http_client(app_settings)?;
+    let token = BatchToken::fetch(&http_client, endpoints.authority, &credentials).await?;
+    let rate_limit = rate_limit::shared(Backend::Graph, app_settings.graph_requests_per_second);
 
-    let token = fetch_access_token(&http_client, tenant_id, client_id, client_secret).await?;
-    let encoded_sender: String =
-        form_urlencoded::byte_serialize(sender_address.as_bytes()).collect();
-    let send_url = format!("{GRAPH_BASE_URL}/users/{encoded_sender}/sendMail");
+    let outcomes = run_concurrent(messages.len(), max_concurrency(app_settings), |index| {
+        send_one(
+            &http_client,
+            &send_url,
+            &token,
+            rate_limit.as_deref(),
+            settings,
+            &messages[index],
+        )
+    })
+    .await;
 
     let mut summary = EmailSendSummary::default();
+    for outcome in outcomes {
+        match outcome {
+            Ok(()) => summary.record_success(),
+            Err(error) => summary.record_failure(error),
+        }
+    }
 
-    for message in messages {
-        if message.to.trim().is_empty() {
-            summary.failed += 1;
-            summary
-                .errors
-                .push("Recipient address is required for every email".to_string());
-            continue;
-        }
-
-        let payload = json!({
-            "message": {
-                "subject": message.subject,
-                "body": {
-                    "contentType": message.content_type.graph_value(),
-                    "content": message.body,
-                },
-                "toRecipients": [
-                    {
-                        "emailAddress": {
-                            "address": message.to
-                        }
-                    }
-                ]
-            },
-            "saveToSentItems": false
-        });
+    info!(
+        success = summary.success,
+        failed = summary.failed,
+        "Graph email batch finished"
+    );
+    Ok(summary)
+}
 
-        match http_client
-            .post(&send_url)
-            .bearer_auth(&token)
-            .json(&payload)
-            .send()
+/// Graph access token shared by the sends of one batch
+///
+/// When Graph answers 401 the token has expired mid-batch; the first send to
+/// notice fetches a new one and concurrent sends that failed with the same
+/// token reuse it instead of fetching again.
+struct BatchToken<'a> {
+    http_client: &'a Client,
+    authority: &'a str,
+    credentials: &'a GraphCredentials<'a>,
+    /// Current token and how many times it has been refreshed
+    current: Mutex<(u32, String)>,
+}
+
+impl<'a> BatchToken<'a> {
+    async fn fetch(
+        http_client: &'a Client,
+        authority: &'a str,
+        credentials: &'a GraphCredentials<'a>,
+    ) -> Result<Self, EmailDeliveryError> {
+        let token = fetch_access_token(http_client, authority, credentials).await?;
+        Ok(Self {
+            http_client,
+            authority,
+            credentials,
+            current: Mutex::new((0, token)),
+        })
+    }
+
+    async fn get(&self) -> (u32, String) {
+        self.current.lock().await.clone()
+    }
+
+    /// Replace the token that Graph rejected, unless another send already did
+    async fn refresh(&self, rejected: u32) -> Result<String, EmailDeliveryError> {
+        let mut current = self.current.lock().await;
+        if current.0 == rejected {
+            let token =
+                fetch_access_token(self.http_client, self.authority, self.credentials).await?;
+            *current = (rejected + 1, token);
+        }
+        Ok(current.1.clone())
+    }
+}
+
+/// Send one message through Graph, describing any failure for the summary
+///
+/// A 401 is retried once with a refreshed token. Each attempt waits for
+/// `rate_limit`, when one is configured.
+async fn send_one(
+    http_client: &Client,
+    send_url: &str,
+    token: &BatchToken<'_>,
+    rate_limit: Option<&RateLimiter>,
+    settings: &EmailSettings,
+    message: &PreparedEmailPayload,
+) -> Result<(), String> {
+    if message.to.trim().is_empty() {
+        warn!("skipping email without a recipient address");
+        return Err("Recipient address is required for every email".to_string());
+    }
+
+    let payload = build_send_mail_payload(settings, message);
+    let post = |token: String| {
+        let request = http_client.post(send_url).bearer_auth(token).json(&payload);
+        async move {
+            if let Some(limit) = rate_limit {
+                limit.acquire().await;
+            }
+            request.send().await
+        }
+    };
+
+    let (generation, current) = token.get().await;
+    let mut result = post(current).await;
+    if matches!(&result, Ok(response) if response.status() == StatusCode::UNAUTHORIZED) {
+        warn!("Graph rejected the access token; refreshing it and retrying");
+        let fresh = token
+            .refresh(generation)
             .await
-        {
-            Ok(response) => {
-                let status = response.status();
-
-                if status.is_success() {
-                    summary.success += 1;
-                } else {
-                    summary.failed += 1;
-                    let body = response
-                        .text()
-                        .await
-                        .unwrap_or_else(|_| "(no details)".to_string());
-                    summary.errors.push(format!(
-                        "{}: Graph returned {} {}",
-                        message.to,
-                        status.as_u16(),
-                        truncate_for_log(&body)
-                    ));
-                }
+            .map_err(|error| format!("{}: {error}", message.to))?;
+        result = post(fresh).await;
+    }
+
+    describe_send_result(send_url, message, result).await
+}
+
+/// Turn a sendMail response into the outcome recorded for `message`
+async fn describe_send_result(
+    send_url: &str,
+    message: &PreparedEmailPayload,
+    result: Result<Response, reqwest::Error>,
+) -> Result<(), String> {
+    match result {
+        Ok(response) => {
+            let status = response.status();
+            debug!(url = %send_url, status = status.as_u16(), "Graph sendMail responded");
+
+            if status.is_success() {
+                Ok(())
+            } else {
+                let body = response
+                    .text()
+                    .await
+                    .unwrap_or_else(|_| "(no details)".to_string());
+                warn!(status = status.as_u16(), "Graph rejected an email");
+                Err(format!(
+                    "{}: {}",
+                    message.to,
+                    describe_graph_error(status, &body)
+                ))
             }
-            Err(error) => {
-                summary.failed += 1;
-                summary
-                    .errors
-                    .push(format!("{}: failed to send email ({error})", message.to));
+        }
+        Err(error) => {
+            warn!(%error, "failed to send email via Graph");
+            Err(format!("{}: failed to send email ({error})", message.to))
+        }
+    }
+}
+
+/// Check that the configured app registration can obtain a Graph token
+///
+/// Nothing is sent, so a failure here points at the tenant, client ID or
+/// secret rather than at the sender mailbox. The delivery method is not
+/// checked, letting admins verify credentials before switching to Graph.
+#[tracing::instrument(skip_all)]
+pub async fn test_graph_token(app_settings: &SafeQSettings) -> Result<(), EmailDeliveryError> {
+    test_graph_token_at(app_settings, GRAPH_AUTHORITY_URL, default_store()).await
+}
+
+pub(crate) async fn test_graph_token_at(
+    app_settings: &SafeQSettings,
+    authority: &str,
+    secrets: &dyn SecretStore,
+) -> Result<(), EmailDeliveryError> {
+    let credentials = GraphCredentials::from_settings(&app_settings.email_settings, secrets)?;
+    let http_client = graph_http_client(app_settings)?;
+    fetch_access_token(&http_client, authority, &credentials).await?;
+    info!("Microsoft Graph token acquired");
+    Ok(())
+}
+
+/// Whether Graph can resolve the configured sender mailbox
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "status")]
+pub enum SenderMailboxStatus {
+    Found,
+    /// 404: no user or shared mailbox with that address in the tenant
+    NotFound,
+    /// 403: the app registration may not read the mailbox
+    Forbidden {
+        message: String,
+    },
+    Unexpected {
+        #[serde(rename = "httpStatus")]
+        http_status: u16,
+        message: String,
+    },
+}
+
+/// Look up the sender mailbox with the app's Graph token
+///
+/// Sending fails when the address does not exist or the app has no rights to
+/// it even though a token can be obtained; this tells the two apart. Token
+/// failures are returned as errors, like `test_graph_token`.
+#[tracing::instrument(skip_all)]
+pub async fn verify_sender_mailbox(
+    app_settings: &SafeQSettings,
+) -> Result<SenderMailboxStatus, EmailDeliveryError> {
+    verify_sender_mailbox_at(app_settings, &GRAPH_ENDPOINTS, default_store()).await
+}
+
+async fn verify_sender_mailbox_at(
+    app_settings: &SafeQSettings,
+    endpoints: &GraphEndpoints<'_>,
+    secrets: &dyn SecretStore,
+) -> Result<SenderMailboxStatus, EmailDeliveryError> {
+    let settings = &app_settings.email_settings;
+    let credentials = GraphCredentials::from_settings(settings, secrets)?;
+    let sender_address = non_empty(settings.graph_sender_address.as_deref())
+        .ok_or(EmailDeliveryError::MissingGraphField("graphSenderAddress"))?;
+
+    let http_client = graph_http_client(app_settings)?;
+    let token = fetch_access_token(&http_client, endpoints.authority, &credentials).await?;
+    let encoded_sender: String =
+        form_urlencoded::byte_serialize(sender_address.as_bytes()).collect();
+    let user_url = format!(
+        "{}/users/{encoded_sender}",
+        endpoints.graph_base.trim_end_matches('/')
+    );
+
+    if let Some(limit) = rate_limit::shared(Backend::Graph, app_settings.graph_requests_per_second)
+    {
+        limit.acquire().await;
+    }
+    let response = http_client
+        .get(&user_url)
+        .bearer_auth(&token)
+        .send()
+        .await
+        .map_err(EmailDeliveryError::GraphRequest)?;
+    let status = response.status();
+    debug!(url = %user_url, status = status.as_u16(), "Graph mailbox lookup responded");
+
+    let mailbox = match status {
+        status if status.is_success() => SenderMailboxStatus::Found,
+        StatusCode::NOT_FOUND => SenderMailboxStatus::NotFound,
+        status => {
+            let body = response.text().await.unwrap_or_default();
+            let message = describe_graph_error(status, &body);
+            if status == StatusCode::FORBIDDEN {
+                SenderMailboxStatus::Forbidden { message }
+            } else {
+                SenderMailboxStatus::Unexpected {
+                    http_status: status.as_u16(),
+                    message,
+                }
             }
         }
+    };
+    info!(?mailbox, "Graph sender mailbox checked");
+    Ok(mailbox)
+}
+
+/// Render the configured PIN or OTP template for one user
+///
+/// `template_key` picks a named template from `EmailSettings::templates`.
+/// Fails with a user-facing message when the user has no email address, the
+/// credential value is missing, or the template renders to nothing.
+pub fn prepare_credential_email(
+    settings: &EmailSettings,
+    credential: CredentialType,
+    template_key: Option<&str>,
+    tokens: &TemplateTokens,
+) -> Result<PreparedEmailPayload, String> {
+    if tokens.email.is_empty() {
+        return Err(format!(
+            "{}: user is missing an email address.",
+            tokens.user_name
+        ));
     }
 
-    Ok(summary)
+    let template = select_template(settings, credential, template_key);
+    let (value, label) = match credential {
+        CredentialType::Pin => (&tokens.pin, "PIN"),
+        CredentialType::Otp => (&tokens.otp, "OTP"),
+    };
+
+    if value.is_empty() {
+        return Err(format!(
+            "{}: no {label} value is available to send.",
+            tokens.user_name
+        ));
+    }
+
+    let EmailPreview {
+        subject,
+        body,
+        content_type,
+    } = render_email(template, tokens);
+
+    if subject.is_empty() || body.is_empty() {
+        return Err(format!(
+            "{}: template subject or body is empty after rendering.",
+            tokens.user_name
+        ));
+    }
+
+    Ok(PreparedEmailPayload {
+        to: tokens.email.clone(),
+        subject,
+        body,
+        content_type,
+        importance: None,
+        attachments: Vec::new(),
+    })
+}
+
+/// A rendered subject and body, as shown before sending
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailPreview {
+    pub subject: String,
+    pub body: String,
+    pub content_type: EmailContentType,
+}
+
+/// Render a template against a sample user (`userName`, `fullName`, `email`,
+/// `pin`, `otp`) so admins can check it before saving
+pub fn preview_email(template: &EmailTemplateSettings, sample: &Value) -> EmailPreview {
+    let tokens = TemplateTokens::from_user(sample, sample["pin"].as_str(), None);
+    render_email(template, &tokens)
+}
+
+/// Render subject and body, escaping values when the template body is HTML
+fn render_email(template: &EmailTemplateSettings, tokens: &TemplateTokens) -> EmailPreview {
+    // Decide on the template itself so user data can never switch the format
+    let content_type = template.content_type.clone().unwrap_or_else(|| {
+        if is_html_content(&template.body) {
+            EmailContentType::Html
+        } else {
+            EmailContentType::Text
+        }
+    });
+
+    let subject = render_template(&template.subject, tokens)
+        .trim()
+        .to_string();
+    let body = match content_type {
+        EmailContentType::Html => render_html_template(&template.body, tokens),
+        EmailContentType::Text => render_template(&template.body, tokens),
+    }
+    .trim()
+    .to_string();
+
+    EmailPreview {
+        subject,
+        body,
+        content_type,
+    }
+}
+
+/// Build the Graph `sendMail` request body for a single message
+fn build_send_mail_payload(settings: &EmailSettings, message: &PreparedEmailPayload) -> Value {
+    let mut payload = json!({
+        "message": {
+            "subject": message.subject,
+            "body": {
+                "contentType": message.content_type.graph_value(),
+                "content": message.body,
+            },
+            "importance": message.importance.unwrap_or_default().graph_value(),
+            "toRecipients": [
+                {
+                    "emailAddress": {
+                        "address": message.to
+                    }
+                }
+            ]
+        },
+        "saveToSentItems": settings.graph_save_to_sent_items.unwrap_or(false)
+    });
+
+    if !message.attachments.is_empty() {
+        payload["message"]["attachments"] = message
+            .attachments
+            .iter()
+            .map(|attachment| {
+                json!({
+                    "@odata.type": "#microsoft.graph.fileAttachment",
+                    "name": attachment.name,
+                    "contentType": attachment.content_type.as_deref().unwrap_or("application/octet-stream"),
+                    "contentBytes": attachment.content_bytes,
+                })
+            })
+            .collect();
+    }
+
+    if let Some(reply_to) = non_empty(settings.graph_reply_to.as_deref()) {
+        payload["message"]["replyTo"] = json!([{ "emailAddress": { "address": reply_to } }]);
+    }
+
+    if let Some(display_name) = non_empty(settings.graph_from_display_name.as_deref()) {
+        let mut from = json!({ "name": display_name });
+        if let Some(sender) = non_empty(settings.graph_sender_address.as_deref()) {
+            from["address"] = json!(sender);
+        }
+        payload["message"]["from"] = json!({ "emailAddress": from });
+    }
+
+    payload
 }
 
+/// Turn a failed Graph response into a message an admin can act on
+///
+/// Uses `error.code`/`error.message` from the Graph error body when present
+/// and falls back to the raw (truncated) body otherwise.
+fn describe_graph_error(status: StatusCode, body: &str) -> String {
+    #[derive(Deserialize)]
+    struct GraphErrorBody {
+        error: GraphError,
+    }
+
+    #[derive(Deserialize)]
+    struct GraphError {
+        code: String,
+        #[serde(default)]
+        message: String,
+    }
+
+    let Ok(GraphErrorBody { error }) = serde_json::from_str::<GraphErrorBody>(body) else {
+        return format!(
+            "Graph returned {} {}",
+            status.as_u16(),
+            truncate_for_log(body)
+        );
+    };
+
+    if status == StatusCode::FORBIDDEN && error.code == GRAPH_ACCESS_DENIED {
+        return format!(
+            "The app registration is missing the Mail.Send application permission, or it has not been granted admin consent ({})",
+            truncate_for_log(&error.message)
+        );
+    }
+
+    format!(
+        "Graph returned {} {}: {}",
+        status.as_u16(),
+        error.code,
+        truncate_for_log(&error.message)
+    )
+}
+
+/// The named template for `key`, or the default one when the key is unknown
+/// or has no template for this credential
+fn select_template<'a>(
+    settings: &'a EmailSettings,
+    credential: CredentialType,
+    key: Option<&str>,
+) -> &'a EmailTemplateSettings {
+    let named = key
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .and_then(|key| match settings.templates.get(key) {
+            Some(named) => Some(named),
+            None => {
+                warn!(
+                    key,
+                    "unknown email template key; using the default template"
+                );
+                None
+            }
+        });
+
+    match credential {
+        CredentialType::Pin => named
+            .and_then(|named| named.pin_template.as_ref())
+            .unwrap_or(&settings.pin_template),
+        CredentialType::Otp => named
+            .and_then(|named| named.otp_template.as_ref())
+            .unwrap_or(&settings.otp_template),
+    }
+}
+
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    value.map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// App registration and sign-in flow used to obtain Graph tokens
+struct GraphCredentials<'a> {
+    flow: GraphAuthFlow,
+    tenant_id: &'a str,
+    client_id: &'a str,
+    /// Only sent with client credentials; delegated sign-in uses a public client
+    client_secret: Option<&'a str>,
+    /// Holds the refresh token of the delegated sign-in
+    secrets: &'a dyn SecretStore,
+}
+
+impl<'a> GraphCredentials<'a> {
+    fn from_settings(
+        settings: &'a EmailSettings,
+        secrets: &'a dyn SecretStore,
+    ) -> Result<Self, EmailDeliveryError> {
+        let client_secret = match settings.auth_flow {
+            GraphAuthFlow::ClientCredentials => Some(
+                settings
+                    .graph_client_secret
+                    .as_deref()
+                    .ok_or(EmailDeliveryError::MissingGraphField("graphClientSecret"))?,
+            ),
+            GraphAuthFlow::DeviceCode => None,
+        };
+
+        Ok(Self {
+            flow: settings.auth_flow,
+            tenant_id: settings
+                .graph_tenant_id
+                .as_deref()
+                .ok_or(EmailDeliveryError::MissingGraphField("graphTenantId"))?,
+            client_id: settings
+                .graph_client_id
+                .as_deref()
+                .ok_or(EmailDeliveryError::MissingGraphField("graphClientId"))?,
+            client_secret,
+            secrets,
+        })
+    }
+
+    /// `{authority}/{tenant}/oauth2/v2.0/{endpoint}`
+    fn oauth_url(&self, authority: &str, endpoint: &str) -> String {
+        format!(
+            "{}/{}/oauth2/v2.0/{endpoint}",
+            authority.trim_end_matches('/'),
+            self.tenant_id
+        )
+    }
+}
+
+fn graph_http_client(app_settings: &SafeQSettings) -> Result<Client, EmailDeliveryError> {
+    let builder = apply_min_tls(
+        Client::builder().user_agent(user_agent(app_settings)),
+        app_settings,
+    );
+    apply_proxy(builder, app_settings)
+        .map_err(EmailDeliveryError::InvalidProxy)?
+        .build()
+        .map_err(EmailDeliveryError::HttpClient)
+}
+
+/// Get an access token for the configured flow
+///
+/// The delegated flow redeems the stored refresh token and stores the rotated
+/// one Entra ID sends back.
 async fn fetch_access_token(
     client: &Client,
-    tenant_id: &str,
-    client_id: &str,
-    client_secret: &str,
+    authority: &str,
+    credentials: &GraphCredentials<'_>,
 ) -> Result<String, EmailDeliveryError> {
-    let token_url = format!("https://login.microsoftonline.com/{tenant_id}/oauth2/v2.0/token");
-    let params = [
-        ("client_id", client_id),
-        ("scope", GRAPH_SCOPE),
-        ("client_secret", client_secret),
-        ("grant_type", "client_credentials"),
-    ];
+    let token_url = credentials.oauth_url(authority, "token");
+    let refresh_token;
+    let params = match credentials.flow {
+        GraphAuthFlow::ClientCredentials => [
+            ("client_id", credentials.client_id),
+            ("scope", GRAPH_SCOPE),
+            (
+                "client_secret",
+                credentials.client_secret.unwrap_or_default(),
+            ),
+            ("grant_type", "client_credentials"),
+        ],
+        GraphAuthFlow::DeviceCode => {
+            refresh_token = credentials
+                .secrets
+                .get(SecretKey::GraphRefreshToken)?
+                .ok_or(EmailDeliveryError::NotSignedIn)?;
+            [
+                ("client_id", credentials.client_id),
+                ("scope", GRAPH_DELEGATED_SCOPE),
+                ("refresh_token", refresh_token.as_str()),
+                ("grant_type", "refresh_token"),
+            ]
+        }
+    };
+
+    let (status, body) = post_token_form(client, &token_url, &params).await?;
+    if !status.is_success() {
+        warn!(
+            status = status.as_u16(),
+            "Microsoft Graph token request was rejected"
+        );
+        return Err(EmailDeliveryError::TokenStatus(
+            status,
+            truncate_for_log(&body),
+        ));
+    }
+
+    let parsed = parse_token_response(&body)?;
+    if let Some(refresh_token) = &parsed.refresh_token {
+        credentials
+            .secrets
+            .set(SecretKey::GraphRefreshToken, refresh_token)?;
+    }
+    Ok(parsed.access_token)
+}
 
+/// POST a form to an Entra ID OAuth endpoint and return the status and body
+async fn post_token_form(
+    client: &Client,
+    url: &str,
+    params: &[(&str, &str)],
+) -> Result<(StatusCode, String), EmailDeliveryError> {
+    debug!(%url, "requesting Microsoft Graph token");
     let response = client
-        .post(token_url)
-        .form(&params)
+        .post(url)
+        .form(params)
         .send()
         .await
-        .map_err(EmailDeliveryError::TokenRequest)?;
+        .map_err(|error| {
+            tracing::error!(%error, "Microsoft Graph token request failed");
+            EmailDeliveryError::TokenRequest(error)
+        })?;
 
     let status = response.status();
+    debug!(
+        status = status.as_u16(),
+        "Microsoft Graph token endpoint responded"
+    );
     let body = response
         .text()
         .await
         .map_err(EmailDeliveryError::TokenRequest)?;
+    Ok((status, body))
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// Only issued to delegated sign-ins that asked for `offline_access`
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+fn parse_token_response(body: &str) -> Result<TokenResponse, EmailDeliveryError> {
+    serde_json::from_str(body).map_err(EmailDeliveryError::TokenParse)
+}
+
+/// Code and instructions shown to the user starting a device code sign-in
+///
+/// Read from Entra ID's snake_case response and sent to the UI in camelCase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct DeviceCodePrompt {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    /// Seconds until the code expires
+    pub expires_in: u64,
+    /// Seconds to wait between polls
+    #[serde(default = "DeviceCodePrompt::default_interval")]
+    pub interval: u64,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl DeviceCodePrompt {
+    fn default_interval() -> u64 {
+        5
+    }
+}
+
+/// Progress of a device code sign-in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase", tag = "status")]
+pub enum DeviceLoginStatus {
+    /// The user has not finished signing in yet
+    Pending,
+    /// Polling too fast; wait longer before the next poll
+    SlowDown,
+    SignedIn,
+}
+
+/// Start a delegated sign-in; the user enters the returned code in a browser
+#[tracing::instrument(skip_all)]
+pub async fn start_device_login(
+    app_settings: &SafeQSettings,
+) -> Result<DeviceCodePrompt, EmailDeliveryError> {
+    let credentials =
+        GraphCredentials::from_settings(&app_settings.email_settings, default_store())?;
+    start_device_login_at(app_settings, &credentials, GRAPH_AUTHORITY_URL).await
+}
+
+async fn start_device_login_at(
+    app_settings: &SafeQSettings,
+    credentials: &GraphCredentials<'_>,
+    authority: &str,
+) -> Result<DeviceCodePrompt, EmailDeliveryError> {
+    let http_client = graph_http_client(app_settings)?;
+    let params = [
+        ("client_id", credentials.client_id),
+        ("scope", GRAPH_DELEGATED_SCOPE),
+    ];
 
+    let (status, body) = post_token_form(
+        &http_client,
+        &credentials.oauth_url(authority, "devicecode"),
+        &params,
+    )
+    .await?;
     if !status.is_success() {
         return Err(EmailDeliveryError::TokenStatus(
             status,
             truncate_for_log(&body),
         ));
     }
+    serde_json::from_str(&body).map_err(EmailDeliveryError::TokenParse)
+}
+
+/// Check once whether the user finished the device code sign-in
+///
+/// On success the refresh token goes to the OS keychain and later sends
+/// redeem it. The UI polls at the prompt's `interval` until this returns
+/// `SignedIn` or an error such as an expired or declined code.
+#[tracing::instrument(skip_all)]
+pub async fn poll_device_login(
+    app_settings: &SafeQSettings,
+    device_code: &str,
+) -> Result<DeviceLoginStatus, EmailDeliveryError> {
+    let credentials =
+        GraphCredentials::from_settings(&app_settings.email_settings, default_store())?;
+    poll_device_login_at(app_settings, &credentials, GRAPH_AUTHORITY_URL, device_code).await
+}
+
+async fn poll_device_login_at(
+    app_settings: &SafeQSettings,
+    credentials: &GraphCredentials<'_>,
+    authority: &str,
+    device_code: &str,
+) -> Result<DeviceLoginStatus, EmailDeliveryError> {
+    let http_client = graph_http_client(app_settings)?;
+    let params = [
+        ("client_id", credentials.client_id),
+        ("device_code", device_code),
+        ("grant_type", DEVICE_CODE_GRANT),
+    ];
+
+    let (status, body) = post_token_form(
+        &http_client,
+        &credentials.oauth_url(authority, "token"),
+        &params,
+    )
+    .await?;
+
+    match parse_device_poll(status, &body)? {
+        DevicePoll::Pending => Ok(DeviceLoginStatus::Pending),
+        DevicePoll::SlowDown => Ok(DeviceLoginStatus::SlowDown),
+        DevicePoll::SignedIn(tokens) => {
+            let refresh_token = tokens.refresh_token.ok_or_else(|| {
+                EmailDeliveryError::TokenStatus(
+                    status,
+                    "sign-in returned no refresh token; grant the offline_access permission"
+                        .to_string(),
+                )
+            })?;
+            credentials
+                .secrets
+                .set(SecretKey::GraphRefreshToken, &refresh_token)?;
+            info!("signed in to Microsoft Graph for delegated sends");
+            Ok(DeviceLoginStatus::SignedIn)
+        }
+    }
+}
+
+enum DevicePoll {
+    Pending,
+    SlowDown,
+    SignedIn(TokenResponse),
+}
+
+/// Interpret a token endpoint answer to a device code poll
+///
+/// Entra ID reports an unfinished sign-in as a 400 with an OAuth error code,
+/// so only unknown codes are errors.
+fn parse_device_poll(status: StatusCode, body: &str) -> Result<DevicePoll, EmailDeliveryError> {
+    if status.is_success() {
+        return parse_token_response(body).map(DevicePoll::SignedIn);
+    }
 
     #[derive(Deserialize)]
-    struct GraphTokenResponse {
-        access_token: String,
+    struct OAuthError {
+        error: String,
+        #[serde(default)]
+        error_description: Option<String>,
     }
 
-    let parsed: GraphTokenResponse =
-        serde_json::from_str(&body).map_err(EmailDeliveryError::TokenParse)?;
-    Ok(parsed.access_token)
+    match serde_json::from_str::<OAuthError>(body) {
+        Ok(error) if error.error == "authorization_pending" => Ok(DevicePoll::Pending),
+        Ok(error) if error.error == "slow_down" => Ok(DevicePoll::SlowDown),
+        Ok(error) => Err(EmailDeliveryError::TokenStatus(
+            status,
+            truncate_for_log(error.error_description.as_deref().unwrap_or(&error.error)),
+        )),
+        Err(_) => Err(EmailDeliveryError::TokenStatus(
+            status,
+            truncate_for_log(body),
+        )),
+    }
 }
 
 fn truncate_for_log(input: &str) -> String {
-    const MAX_LEN: usize = 180;
-    if input.len() <= MAX_LEN {
-        input.to_string()
-    } else {
-        format!("{}…", &input[..MAX_LEN])
+    const DEFAULT_LIMIT: usize = 180;
+    truncate(input, error_body_limit(DEFAULT_LIMIT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::MockSecretStore;
+
+    fn sample_message() -> PreparedEmailPayload {
+        PreparedEmailPayload {
+            to: "jdoe@example.com".to_string(),
+            subject: "Your SAFEQ PIN".to_string(),
+            body: "Your PIN is 1234".to_string(),
+            content_type: EmailContentType::Text,
+            importance: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_payload_does_not_save_to_sent_items_by_default() {
+        let payload = build_send_mail_payload(&EmailSettings::default(), &sample_message());
+        assert_eq!(payload["saveToSentItems"], false);
+        assert_eq!(
+            payload["message"]["toRecipients"][0]["emailAddress"]["address"],
+            "jdoe@example.com"
+        );
+    }
+
+    #[test]
+    fn test_payload_saves_to_sent_items_when_enabled() {
+        let settings = EmailSettings {
+            graph_save_to_sent_items: Some(true),
+            ..EmailSettings::default()
+        };
+        let payload = build_send_mail_payload(&settings, &sample_message());
+        assert_eq!(payload["saveToSentItems"], true);
+    }
+
+    #[test]
+    fn test_payload_includes_reply_to_and_display_name() {
+        let settings = EmailSettings {
+            graph_sender_address: Some("noreply@example.com".to_string()),
+            graph_reply_to: Some("helpdesk@example.com".to_string()),
+            graph_from_display_name: Some("SAFEQ Administrator".to_string()),
+            ..EmailSettings::default()
+        };
+        let payload = build_send_mail_payload(&settings, &sample_message());
+        assert_eq!(
+            payload["message"]["replyTo"][0]["emailAddress"]["address"],
+            "helpdesk@example.com"
+        );
+        assert_eq!(
+            payload["message"]["from"]["emailAddress"]["name"],
+            "SAFEQ Administrator"
+        );
+        assert_eq!(
+            payload["message"]["from"]["emailAddress"]["address"],
+            "noreply@example.com"
+        );
+    }
+
+    #[test]
+    fn test_payload_omits_reply_to_and_from_when_unset() {
+        let settings = EmailSettings {
+            graph_reply_to: Some("   ".to_string()),
+            ..EmailSettings::default()
+        };
+        let payload = build_send_mail_payload(&settings, &sample_message());
+        assert!(payload["message"].get("replyTo").is_none());
+        assert!(payload["message"].get("from").is_none());
+    }
+
+    #[test]
+    fn test_payload_importance_defaults_to_normal() {
+        let payload = build_send_mail_payload(&EmailSettings::default(), &sample_message());
+        assert_eq!(payload["message"]["importance"], "normal");
+    }
+
+    #[test]
+    fn test_payload_importance_from_message() {
+        let message = PreparedEmailPayload {
+            importance: Some(EmailImportance::High),
+            ..sample_message()
+        };
+        let payload = build_send_mail_payload(&EmailSettings::default(), &message);
+        assert_eq!(payload["message"]["importance"], "high");
+    }
+
+    #[test]
+    fn test_importance_deserializes_from_frontend_value() {
+        let message: PreparedEmailPayload = serde_json::from_value(json!({
+            "to": "jdoe@example.com",
+            "subject": "Your OTP",
+            "body": "Your OTP is abc",
+            "importance": "low"
+        }))
+        .unwrap();
+        assert_eq!(message.importance, Some(EmailImportance::Low));
+    }
+
+    #[test]
+    fn test_prepare_credential_email_renders_template() {
+        let tokens = TemplateTokens {
+            user_name: "jdoe".to_string(),
+            email: "jdoe@example.com".to_string(),
+            pin: "1234".to_string(),
+            ..TemplateTokens::default()
+        };
+        let message = prepare_credential_email(
+            &EmailSettings::default(),
+            CredentialType::Pin,
+            None,
+            &tokens,
+        )
+        .unwrap();
+        assert_eq!(message.to, "jdoe@example.com");
+        assert!(message.body.contains("1234"));
+        assert!(message.body.contains("Hello jdoe"));
+    }
+
+    #[test]
+    fn test_declared_template_content_type_reaches_graph_payload() {
+        let tokens = TemplateTokens {
+            user_name: "jdoe".to_string(),
+            full_name: "A & B".to_string(),
+            email: "jdoe@example.com".to_string(),
+            pin: "1234".to_string(),
+            ..TemplateTokens::default()
+        };
+        let mut settings = EmailSettings::default();
+        // No tags, so only the declaration makes this HTML
+        settings.pin_template.body = "Hi {{fullName}}, your PIN is {{pin}}".to_string();
+        settings.pin_template.content_type = Some(EmailContentType::Html);
+
+        let message =
+            prepare_credential_email(&settings, CredentialType::Pin, None, &tokens).unwrap();
+        assert_eq!(message.body, "Hi A &amp; B, your PIN is 1234");
+        let payload = build_send_mail_payload(&settings, &message);
+        assert_eq!(payload["message"]["body"]["contentType"], "HTML");
+
+        settings.pin_template.body = "<p>PIN {{pin}}</p>".to_string();
+        settings.pin_template.content_type = Some(EmailContentType::Text);
+        let message =
+            prepare_credential_email(&settings, CredentialType::Pin, None, &tokens).unwrap();
+        let payload = build_send_mail_payload(&settings, &message);
+        assert_eq!(payload["message"]["body"]["contentType"], "Text");
+    }
+
+    #[test]
+    fn test_html_template_escapes_user_values() {
+        let mut settings = EmailSettings::default();
+        settings.pin_template.body = "<p>Hello {{fullName}}</p><div>{{pin}}</div>".to_string();
+        let tokens = TemplateTokens {
+            user_name: "obrien".to_string(),
+            full_name: "O'Brien & <Co>".to_string(),
+            email: "obrien@example.com".to_string(),
+            pin: "1234".to_string(),
+            ..TemplateTokens::default()
+        };
+
+        let message =
+            prepare_credential_email(&settings, CredentialType::Pin, None, &tokens).unwrap();
+        assert_eq!(message.content_type, EmailContentType::Html);
+        assert_eq!(
+            message.body,
+            "<p>Hello O&#39;Brien &amp; &lt;Co&gt;</p><div>1234</div>"
+        );
+    }
+
+    #[test]
+    fn test_markup_in_values_does_not_make_text_template_html() {
+        let tokens = TemplateTokens {
+            user_name: "jdoe".to_string(),
+            full_name: "<p>Injected</p>".to_string(),
+            email: "jdoe@example.com".to_string(),
+            pin: "1234".to_string(),
+            ..TemplateTokens::default()
+        };
+        let message = prepare_credential_email(
+            &EmailSettings::default(),
+            CredentialType::Pin,
+            None,
+            &tokens,
+        )
+        .unwrap();
+        assert_eq!(message.content_type, EmailContentType::Text);
+    }
+
+    #[test]
+    fn test_preview_default_pin_template() {
+        let preview = preview_email(
+            &EmailTemplateSettings::default_pin_template(),
+            &json!({ "userName": "jdoe", "fullName": "John Doe", "pin": "4821" }),
+        );
+        assert_eq!(preview.subject, "Your SAFEQ PIN");
+        assert!(preview.body.starts_with("Hello John Doe,"));
+        assert!(preview.body.contains("Your new SAFEQ PIN is 4821."));
+        assert_eq!(preview.content_type, EmailContentType::Text);
+    }
+
+    #[test]
+    fn test_preview_uses_fallbacks_and_otp() {
+        let template = EmailTemplateSettings {
+            subject: "OTP for {{fullName || userName}}".to_string(),
+            body: "<p>Code: <strong>{{otp}}</strong></p>".to_string(),
+            content_type: None,
+        };
+        let preview = preview_email(&template, &json!({ "userName": "jdoe", "otp": "X7-K2" }));
+        assert_eq!(preview.subject, "OTP for jdoe");
+        assert_eq!(preview.body, "<p>Code: <strong>X7-K2</strong></p>");
+        assert_eq!(preview.content_type, EmailContentType::Html);
+    }
+
+    #[test]
+    fn test_preview_escapes_sample_values_in_html() {
+        let template = EmailTemplateSettings {
+            subject: "Hi {{fullName}}".to_string(),
+            body: "<p>Hi {{fullName}}</p>".to_string(),
+            content_type: None,
+        };
+        let preview = preview_email(&template, &json!({ "fullName": "A & B" }));
+        assert_eq!(preview.subject, "Hi A & B");
+        assert_eq!(preview.body, "<p>Hi A &amp; B</p>");
+    }
+
+    #[test]
+    fn test_template_key_selects_named_template() {
+        let mut settings = EmailSettings::default();
+        settings.templates.insert(
+            "finance".to_string(),
+            crate::settings::NamedEmailTemplates {
+                pin_template: Some(EmailTemplateSettings {
+                    subject: "Finance printing PIN".to_string(),
+                    body: "Your finance PIN is {{pin}}".to_string(),
+                    content_type: None,
+                }),
+                otp_template: None,
+            },
+        );
+
+        let pin = select_template(&settings, CredentialType::Pin, Some("finance"));
+        assert_eq!(pin.subject, "Finance printing PIN");
+
+        // No OTP override for this key, so the default OTP template is used
+        let otp = select_template(&settings, CredentialType::Otp, Some("finance"));
+        assert_eq!(otp.subject, settings.otp_template.subject);
+    }
+
+    #[test]
+    fn test_unknown_template_key_falls_back_to_default() {
+        let settings = EmailSettings::default();
+        for key in [Some("marketing"), Some(" "), None] {
+            let template = select_template(&settings, CredentialType::Pin, key);
+            assert_eq!(template.subject, settings.pin_template.subject);
+        }
+
+        let tokens = TemplateTokens {
+            user_name: "jdoe".to_string(),
+            email: "jdoe@example.com".to_string(),
+            pin: "1234".to_string(),
+            ..TemplateTokens::default()
+        };
+        let message =
+            prepare_credential_email(&settings, CredentialType::Pin, Some("marketing"), &tokens)
+                .unwrap();
+        assert!(message.body.contains("1234"));
+    }
+
+    #[test]
+    fn test_prepare_credential_email_requires_value_and_address() {
+        let tokens = TemplateTokens {
+            user_name: "jdoe".to_string(),
+            email: "jdoe@example.com".to_string(),
+            ..TemplateTokens::default()
+        };
+        let error = prepare_credential_email(
+            &EmailSettings::default(),
+            CredentialType::Otp,
+            None,
+            &tokens,
+        )
+        .unwrap_err();
+        assert!(error.contains("no OTP value"));
+
+        let no_email = TemplateTokens {
+            email: String::new(),
+            pin: "1234".to_string(),
+            ..tokens
+        };
+        let error = prepare_credential_email(
+            &EmailSettings::default(),
+            CredentialType::Pin,
+            None,
+            &no_email,
+        )
+        .unwrap_err();
+        assert!(error.contains("missing an email address"));
+    }
+
+    #[test]
+    fn test_truncate_for_log_handles_emoji() {
+        // The ASCII prefix puts byte 180 inside a four-byte emoji
+        let input = format!("x{}", "👍".repeat(200));
+        let truncated = truncate_for_log(&input);
+        assert!(truncated.ends_with('…'));
+        assert_eq!(truncated.chars().count(), 181);
+        assert!(truncated.starts_with("x👍"));
+    }
+
+    #[test]
+    fn test_truncate_for_log_never_cuts_mid_character() {
+        // 179 ASCII bytes followed by two-byte characters, so byte 180 falls
+        // inside the first "é"
+        let input = format!("{}{}b", "a".repeat(179), "é".repeat(10));
+        assert_eq!(input.len(), 200);
+        assert!(!input.is_char_boundary(180));
+
+        let truncated = truncate_for_log(&input);
+        assert_eq!(truncated, format!("{}é…", "a".repeat(179)));
+    }
+
+    #[tokio::test]
+    async fn test_graph_token_reports_rejected_credentials() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tenant-1/oauth2/v2.0/token"))
+            .respond_with(
+                ResponseTemplate::new(401).set_body_string(r#"{"error":"invalid_client"}"#),
+            )
+            .mount(&server)
+            .await;
+
+        let mut settings = SafeQSettings::default();
+        settings.email_settings.graph_tenant_id = Some("tenant-1".to_string());
+        settings.email_settings.graph_client_id = Some("client-1".to_string());
+        settings.email_settings.graph_client_secret = Some("wrong".to_string());
+
+        match test_graph_token_at(&settings, &server.uri(), &MockSecretStore::default()).await {
+            Err(EmailDeliveryError::TokenStatus(status, body)) => {
+                assert_eq!(status, StatusCode::UNAUTHORIZED);
+                assert!(body.contains("invalid_client"));
+            }
+            other => panic!("expected a token status error, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_graph_client_enforces_min_tls_and_names_version_errors() {
+        use crate::http_client::tls_probe;
+        use crate::settings::MinTlsVersion;
+
+        let (url, hellos) = tls_probe::serve(tls_probe::PROTOCOL_VERSION_ALERT);
+        let mut settings = SafeQSettings {
+            min_tls_version: MinTlsVersion::Tls13,
+            ..SafeQSettings::default()
+        };
+        settings.email_settings.graph_tenant_id = Some("tenant-1".to_string());
+        settings.email_settings.graph_client_id = Some("client-1".to_string());
+        settings.email_settings.graph_client_secret = Some("secret".to_string());
+
+        let error = test_graph_token_at(&settings, &url, &MockSecretStore::default())
+            .await
+            .unwrap_err();
+        assert_eq!(hellos.recv().unwrap(), [tls_probe::TLS_1_3]);
+        assert_eq!(error.message_key(), "email.tls_version");
+        assert!(error.to_string().contains("minimum TLS version"));
+    }
+
+    #[tokio::test]
+    async fn test_graph_token_requires_client_secret() {
+        let mut settings = SafeQSettings::default();
+        settings.email_settings.graph_tenant_id = Some("tenant-1".to_string());
+        settings.email_settings.graph_client_id = Some("client-1".to_string());
+
+        assert!(matches!(
+            test_graph_token_at(&settings, GRAPH_AUTHORITY_URL, &MockSecretStore::default()).await,
+            Err(EmailDeliveryError::MissingGraphField("graphClientSecret"))
+        ));
+    }
+
+    fn graph_settings() -> SafeQSettings {
+        let mut settings = SafeQSettings::default();
+        settings.email_settings.method = EmailDeliveryMethod::Graph;
+        settings.email_settings.graph_tenant_id = Some("tenant-1".to_string());
+        settings.email_settings.graph_client_id = Some("client-1".to_string());
+        settings.email_settings.graph_client_secret = Some("secret".to_string());
+        settings.email_settings.graph_sender_address = Some("noreply@example.com".to_string());
+        settings
+    }
+
+    #[tokio::test]
+    async fn test_send_reports_missing_mail_send_permission() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tenant-1/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"access_token":"token"}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/users/noreply%40example.com/sendMail"))
+            .respond_with(ResponseTemplate::new(403).set_body_string(
+                r#"{"error":{"code":"ErrorAccessDenied","message":"Access is denied. Check credentials and try again."}}"#,
+            ))
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        let endpoints = GraphEndpoints {
+            authority: &uri,
+            graph_base: &uri,
+        };
+        let summary = send_graph_emails_to(
+            &graph_settings(),
+            &[sample_message()],
+            &endpoints,
+            &MockSecretStore::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.failed, 1);
+        assert!(summary.errors[0].starts_with("jdoe@example.com: "));
+        assert!(summary.errors[0].contains("missing the Mail.Send application permission"));
+    }
+
+    #[tokio::test]
+    async fn test_send_refreshes_expired_token_and_retries() {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tenant-1/oauth2/v2.0/token"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"{"access_token":"expired"}"#),
+            )
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/tenant-1/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"access_token":"fresh"}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/users/noreply%40example.com/sendMail"))
+            .and(header("authorization", "Bearer expired"))
+            .respond_with(ResponseTemplate::new(401))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/users/noreply%40example.com/sendMail"))
+            .and(header("authorization", "Bearer fresh"))
+            .respond_with(ResponseTemplate::new(202))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        let endpoints = GraphEndpoints {
+            authority: &uri,
+            graph_base: &uri,
+        };
+        let summary = send_graph_emails_to(
+            &graph_settings(),
+            &[sample_message()],
+            &endpoints,
+            &MockSecretStore::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.success, 1);
+        assert_eq!(summary.failed, 0);
+    }
+
+    #[tokio::test]
+    async fn test_send_lists_failed_messages_for_resend() {
+        use wiremock::matchers::{body_string_contains, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tenant-1/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"access_token":"token"}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/users/noreply%40example.com/sendMail"))
+            .and(body_string_contains("bounce@example.com"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(
+                r#"{"error":{"code":"ErrorInvalidRecipients","message":"Invalid recipient"}}"#,
+            ))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/users/noreply%40example.com/sendMail"))
+            .respond_with(ResponseTemplate::new(202))
+            .mount(&server)
+            .await;
+
+        let message_to = |to: &str| PreparedEmailPayload {
+            to: to.to_string(),
+            ..sample_message()
+        };
+        let messages = vec![
+            message_to("jdoe@example.com"),
+            message_to("bounce@example.com"),
+            message_to("asmith@example.com"),
+            message_to(" "),
+        ];
+
+        let uri = server.uri();
+        let endpoints = GraphEndpoints {
+            authority: &uri,
+            graph_base: &uri,
+        };
+        let summary = send_graph_emails_to(
+            &graph_settings(),
+            &messages,
+            &endpoints,
+            &MockSecretStore::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.failed_indices, vec![1, 3]);
+        let failed: Vec<String> = summary
+            .failed_messages(&messages)
+            .into_iter()
+            .map(|message| message.to)
+            .collect();
+        assert_eq!(failed, vec!["bounce@example.com", " "]);
+        assert!(summary.errors[0].starts_with("bounce@example.com: "));
+    }
+
+    #[test]
+    fn test_describe_graph_error_uses_code_and_message() {
+        let message = describe_graph_error(
+            StatusCode::BAD_REQUEST,
+            r#"{"error":{"code":"ErrorInvalidRecipients","message":"Invalid recipient"}}"#,
+        );
+        assert_eq!(
+            message,
+            "Graph returned 400 ErrorInvalidRecipients: Invalid recipient"
+        );
+    }
+
+    #[test]
+    fn test_describe_graph_error_falls_back_to_raw_body() {
+        assert_eq!(
+            describe_graph_error(StatusCode::FORBIDDEN, "<html>Forbidden</html>"),
+            "Graph returned 403 <html>Forbidden</html>"
+        );
+    }
+
+    async fn mailbox_lookup(status: u16, body: &str) -> SenderMailboxStatus {
+        use wiremock::matchers::{header, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tenant-1/oauth2/v2.0/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"access_token":"token"}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/users/noreply%40example.com"))
+            .and(header("authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(status).set_body_string(body))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        let endpoints = GraphEndpoints {
+            authority: &uri,
+            graph_base: &uri,
+        };
+        verify_sender_mailbox_at(&graph_settings(), &endpoints, &MockSecretStore::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify_sender_mailbox_found() {
+        let status = mailbox_lookup(200, r#"{"mail":"noreply@example.com"}"#).await;
+        assert_eq!(status, SenderMailboxStatus::Found);
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            json!({ "status": "found" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_sender_mailbox_not_found() {
+        let status = mailbox_lookup(
+            404,
+            r#"{"error":{"code":"Request_ResourceNotFound","message":"Resource does not exist."}}"#,
+        )
+        .await;
+        assert_eq!(status, SenderMailboxStatus::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_verify_sender_mailbox_forbidden() {
+        let status = mailbox_lookup(
+            403,
+            r#"{"error":{"code":"Authorization_RequestDenied","message":"Insufficient privileges."}}"#,
+        )
+        .await;
+        assert_eq!(
+            status,
+            SenderMailboxStatus::Forbidden {
+                message: "Graph returned 403 Authorization_RequestDenied: Insufficient privileges."
+                    .to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_sender_mailbox_unexpected_status() {
+        let status = mailbox_lookup(503, "Service Unavailable").await;
+        assert!(matches!(
+            status,
+            SenderMailboxStatus::Unexpected {
+                http_status: 503,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_device_code_prompt_parses_entra_response() {
+        let prompt: DeviceCodePrompt = serde_json::from_str(
+            r#"{"device_code":"DAQABAAEAAAD","user_code":"F7RW9QKZ2","verification_uri":"https://microsoft.com/devicelogin","expires_in":900,"interval":5,"message":"To sign in, use a web browser"}"#,
+        )
+        .unwrap();
+        assert_eq!(prompt.user_code, "F7RW9QKZ2");
+        assert_eq!(prompt.expires_in, 900);
+
+        let value = serde_json::to_value(&prompt).unwrap();
+        assert_eq!(
+            value["verificationUri"],
+            "https://microsoft.com/devicelogin"
+        );
+        assert_eq!(value["deviceCode"], "DAQABAAEAAAD");
+    }
+
+    #[test]
+    fn test_device_poll_reports_pending_and_slow_down() {
+        let pending = parse_device_poll(
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"authorization_pending","error_description":"AADSTS70016: pending"}"#,
+        );
+        assert!(matches!(pending, Ok(DevicePoll::Pending)));
+
+        let slow = parse_device_poll(StatusCode::BAD_REQUEST, r#"{"error":"slow_down"}"#);
+        assert!(matches!(slow, Ok(DevicePoll::SlowDown)));
+    }
+
+    #[test]
+    fn test_device_poll_returns_tokens_on_sign_in() {
+        let poll = parse_device_poll(
+            StatusCode::OK,
+            r#"{"token_type":"Bearer","access_token":"access","refresh_token":"refresh","expires_in":3599}"#,
+        );
+        match poll {
+            Ok(DevicePoll::SignedIn(tokens)) => {
+                assert_eq!(tokens.access_token, "access");
+                assert_eq!(tokens.refresh_token.as_deref(), Some("refresh"));
+            }
+            _ => panic!("expected a signed-in poll"),
+        }
+    }
+
+    #[test]
+    fn test_device_poll_surfaces_declined_sign_in() {
+        let error = parse_device_poll(
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"authorization_declined","error_description":"AADSTS70000: the user declined"}"#,
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("the user declined"));
+    }
+
+    #[tokio::test]
+    async fn test_delegated_token_redeems_and_rotates_refresh_token() {
+        use wiremock::matchers::{body_string_contains, method, path};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/tenant-1/oauth2/v2.0/token"))
+            .and(body_string_contains("grant_type=refresh_token"))
+            .and(body_string_contains("refresh_token=old-refresh"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"{"access_token":"access","refresh_token":"new-refresh"}"#),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut settings = graph_settings();
+        settings.email_settings.auth_flow = GraphAuthFlow::DeviceCode;
+        let store = MockSecretStore::default();
+        store
+            .set(SecretKey::GraphRefreshToken, "old-refresh")
+            .unwrap();
+        let credentials =
+            GraphCredentials::from_settings(&settings.email_settings, &store).unwrap();
+        assert_eq!(credentials.client_secret, None);
+
+        let token = fetch_access_token(&Client::new(), &server.uri(), &credentials)
+            .await
+            .unwrap();
+        assert_eq!(token, "access");
+        assert_eq!(
+            store.get(SecretKey::GraphRefreshToken).unwrap().as_deref(),
+            Some("new-refresh")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delegated_token_requires_sign_in() {
+        let mut settings = graph_settings();
+        settings.email_settings.auth_flow = GraphAuthFlow::DeviceCode;
+        let store = MockSecretStore::default();
+        let credentials =
+            GraphCredentials::from_settings(&settings.email_settings, &store).unwrap();
+
+        let error = fetch_access_token(&Client::new(), "http://127.0.0.1:9", &credentials)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EmailDeliveryError::NotSignedIn));
     }
 }
@@ -0,0 +1,305 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use tauri::AppHandle;
+use tauri_plugin_fs::{FsExt, OpenOptions};
+
+use crate::email::{EmailAttachment, EmailContentType, EmailImportance, PreparedEmailPayload};
+
+/// Longest encoded line allowed by RFC 2045
+const LINE_LENGTH: usize = 76;
+
+/// Render a message as an RFC 822 draft that mail clients open for editing
+///
+/// `X-Unsent` makes Outlook open the file as an unsent draft. Without a `from`
+/// the mail client uses its default account. Bodies are base64 encoded so any
+/// UTF-8 text survives; attachments turn the message into `multipart/mixed`.
+pub fn render_eml(
+    message: &PreparedEmailPayload,
+    from: Option<&str>,
+    date: DateTime<Utc>,
+    boundary: &str,
+) -> Result<String, base64::DecodeError> {
+    let mut eml = String::new();
+    eml.push_str("X-Unsent: 1\r\n");
+    if let Some(from) = from.map(str::trim).filter(|from| !from.is_empty()) {
+        push_header(&mut eml, "From", &encode_header(from));
+    }
+    push_header(&mut eml, "To", &single_line(&message.to));
+    push_header(&mut eml, "Subject", &encode_header(&message.subject));
+    push_header(&mut eml, "Date", &date.to_rfc2822());
+    match message.importance {
+        Some(EmailImportance::High) => push_header(&mut eml, "Importance", "High"),
+        Some(EmailImportance::Low) => push_header(&mut eml, "Importance", "Low"),
+        _ => {}
+    }
+    push_header(&mut eml, "MIME-Version", "1.0");
+
+    if message.attachments.is_empty() {
+        push_body(&mut eml, message);
+        return Ok(eml);
+    }
+
+    push_header(
+        &mut eml,
+        "Content-Type",
+        &format!("multipart/mixed; boundary=\"{boundary}\""),
+    );
+    eml.push_str("\r\n");
+    eml.push_str(&format!("--{boundary}\r\n"));
+    push_body(&mut eml, message);
+    for attachment in &message.attachments {
+        eml.push_str(&format!("--{boundary}\r\n"));
+        push_attachment(&mut eml, attachment)?;
+    }
+    eml.push_str(&format!("--{boundary}--\r\n"));
+    Ok(eml)
+}
+
+/// File name for the `index`th draft, e.g. `001-jdoe_example.com.eml`
+pub fn draft_file_name(index: usize, message: &PreparedEmailPayload) -> String {
+    let recipient: String = message
+        .to
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{:03}-{recipient}.eml", index + 1)
+}
+
+/// Write one `.eml` draft per message into `dir`, returning the file paths
+pub fn write_drafts(
+    app: &AppHandle,
+    dir: &Path,
+    messages: &[PreparedEmailPayload],
+    from: Option<&str>,
+) -> io::Result<Vec<PathBuf>> {
+    let now = Utc::now();
+    let mut paths = Vec::with_capacity(messages.len());
+
+    for (index, message) in messages.iter().enumerate() {
+        let boundary = format!("sqc-{}-{index}", now.timestamp_millis());
+        let eml = render_eml(message, from, now, &boundary).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("attachment for {} is not valid base64: {err}", message.to),
+            )
+        })?;
+
+        let path = dir.join(draft_file_name(index, message));
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        let mut file = app.fs().open(path.clone(), options)?;
+        file.write_all(eml.as_bytes())?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+fn push_header(eml: &mut String, name: &str, value: &str) {
+    eml.push_str(&format!("{name}: {value}\r\n"));
+}
+
+fn push_body(eml: &mut String, message: &PreparedEmailPayload) {
+    let subtype = match message.content_type {
+        EmailContentType::Text => "plain",
+        EmailContentType::Html => "html",
+    };
+    push_header(
+        eml,
+        "Content-Type",
+        &format!("text/{subtype}; charset=UTF-8"),
+    );
+    push_header(eml, "Content-Transfer-Encoding", "base64");
+    eml.push_str("\r\n");
+    push_wrapped(eml, &STANDARD.encode(message.body.as_bytes()));
+}
+
+fn push_attachment(
+    eml: &mut String,
+    attachment: &EmailAttachment,
+) -> Result<(), base64::DecodeError> {
+    let compact: String = attachment
+        .content_bytes
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    // Decode to reject bad input, then re-encode with standard padding
+    let content = STANDARD.encode(STANDARD.decode(compact)?);
+
+    let name = encode_header(&attachment.name).replace('"', "'");
+    let content_type = attachment
+        .content_type
+        .as_deref()
+        .map(single_line)
+        .filter(|content_type| !content_type.is_empty())
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    push_header(
+        eml,
+        "Content-Type",
+        &format!("{content_type}; name=\"{name}\""),
+    );
+    push_header(eml, "Content-Transfer-Encoding", "base64");
+    push_header(
+        eml,
+        "Content-Disposition",
+        &format!("attachment; filename=\"{name}\""),
+    );
+    eml.push_str("\r\n");
+    push_wrapped(eml, &content);
+    Ok(())
+}
+
+fn push_wrapped(eml: &mut String, encoded: &str) {
+    // Base64 output is ASCII, so byte chunks are whole characters
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        eml.push_str(std::str::from_utf8(line).unwrap_or_default());
+        eml.push_str("\r\n");
+    }
+}
+
+/// Header value on one line; CR and LF would let input add headers
+fn single_line(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if matches!(c, '\r' | '\n') { ' ' } else { c })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// RFC 2047 encoded word for non-ASCII header text, plain text otherwise
+fn encode_header(value: &str) -> String {
+    let value = single_line(value);
+    if value.is_ascii() {
+        value
+    } else {
+        format!("=?UTF-8?B?{}?=", STANDARD.encode(value.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn message() -> PreparedEmailPayload {
+        PreparedEmailPayload {
+            to: "jdoe@example.com".to_string(),
+            subject: "Your SAFEQ PIN".to_string(),
+            body: "Your PIN is 1234".to_string(),
+            content_type: EmailContentType::Text,
+            importance: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    fn date() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 3, 2, 9, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn test_plain_draft_headers_and_body() {
+        let eml = render_eml(&message(), Some("IT Desk <it@example.com>"), date(), "b").unwrap();
+
+        assert_eq!(
+            eml,
+            "X-Unsent: 1\r\n\
+             From: IT Desk <it@example.com>\r\n\
+             To: jdoe@example.com\r\n\
+             Subject: Your SAFEQ PIN\r\n\
+             Date: Mon, 2 Mar 2026 09:30:00 +0000\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=UTF-8\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             \r\n\
+             WW91ciBQSU4gaXMgMTIzNA==\r\n"
+        );
+    }
+
+    #[test]
+    fn test_non_ascii_subject_is_encoded_and_newlines_dropped() {
+        let message = PreparedEmailPayload {
+            to: "jdoe@example.com\r\nBcc: evil@example.com".to_string(),
+            subject: "Käyttäjätunnus".to_string(),
+            content_type: EmailContentType::Html,
+            importance: Some(EmailImportance::High),
+            ..message()
+        };
+        let eml = render_eml(&message, None, date(), "b").unwrap();
+
+        assert!(eml.contains("Subject: =?UTF-8?B?S8OkeXR0w6Rqw6R0dW5udXM=?=\r\n"));
+        assert!(eml.contains("To: jdoe@example.com  Bcc: evil@example.com\r\n"));
+        assert!(!eml.contains("\r\nBcc:"));
+        assert!(!eml.contains("From:"));
+        assert!(eml.contains("Importance: High\r\n"));
+        assert!(eml.contains("Content-Type: text/html; charset=UTF-8\r\n"));
+    }
+
+    #[test]
+    fn test_attachments_make_a_multipart_draft() {
+        let message = PreparedEmailPayload {
+            attachments: vec![EmailAttachment {
+                name: "guide.pdf".to_string(),
+                content_type: Some("application/pdf".to_string()),
+                content_bytes: "JVBERi0x\nLjQ=".to_string(),
+            }],
+            ..message()
+        };
+        let eml = render_eml(&message, None, date(), "sqc-boundary").unwrap();
+
+        assert!(eml.contains("Content-Type: multipart/mixed; boundary=\"sqc-boundary\"\r\n"));
+        assert!(eml.contains(
+            "--sqc-boundary\r\n\
+             Content-Type: application/pdf; name=\"guide.pdf\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             Content-Disposition: attachment; filename=\"guide.pdf\"\r\n\
+             \r\n\
+             JVBERi0xLjQ=\r\n\
+             --sqc-boundary--\r\n"
+        ));
+
+        let broken = PreparedEmailPayload {
+            attachments: vec![EmailAttachment {
+                content_bytes: "not base64!".to_string(),
+                ..message.attachments[0].clone()
+            }],
+            ..message
+        };
+        assert!(render_eml(&broken, None, date(), "b").is_err());
+    }
+
+    #[test]
+    fn test_long_bodies_are_wrapped() {
+        let message = PreparedEmailPayload {
+            body: "x".repeat(200),
+            ..message()
+        };
+        let eml = render_eml(&message, None, date(), "b").unwrap();
+        let body = eml.split("\r\n\r\n").nth(1).unwrap();
+        assert!(body.lines().all(|line| line.len() <= LINE_LENGTH));
+        assert!(body.lines().count() > 1);
+    }
+
+    #[test]
+    fn test_draft_file_names_are_safe() {
+        let message = PreparedEmailPayload {
+            to: "j doe/../x@example.com".to_string(),
+            ..message()
+        };
+        assert_eq!(
+            draft_file_name(0, &message),
+            "001-j_doe_.._x_example.com.eml"
+        );
+    }
+}
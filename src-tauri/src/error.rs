@@ -0,0 +1,268 @@
+use std::fmt;
+
+use reqwest::StatusCode;
+use serde::Serialize;
+
+use crate::audit::AuditError;
+use crate::email::EmailDeliveryError;
+use crate::export::ExportError;
+use crate::generator::GeneratorConfigError;
+use crate::import::ImportError;
+use crate::safeq_api::SafeQApiError;
+use crate::settings::{SettingsFileError, SettingsLoadError};
+
+/// Error returned from Tauri commands so the frontend can branch on `kind`
+///
+/// Known kinds: `configuration`, `validation`, `unauthorized`, `forbidden`,
+/// `not_found`, `rate_limited`, `timeout`, `network`, `server`, `http`,
+/// `parse`, `unexpected_response` and `internal`. `message` is always English;
+/// `message_key` names the translated text in the `i18n` tables when there is one.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppError {
+    pub kind: String,
+    pub message: String,
+    pub status: Option<u16>,
+    /// Error code from a structured SAFEQ error body, e.g. `USER_EXISTS`
+    pub code: Option<String>,
+    pub message_key: Option<String>,
+}
+
+impl AppError {
+    pub fn new(kind: &str, message: impl Into<String>) -> Self {
+        Self {
+            kind: kind.to_string(),
+            message: message.into(),
+            status: None,
+            code: None,
+            message_key: None,
+        }
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::new("validation", message)
+    }
+
+    fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = Some(status.as_u16());
+        self
+    }
+
+    fn with_message_key(mut self, key: &str) -> Self {
+        self.message_key = Some(key.to_string());
+        self
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AppError {}
+
+/// Classify an HTTP status code into an error kind
+fn status_kind(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        408 | 504 => "timeout",
+        429 => "rate_limited",
+        400 | 422 => "validation",
+        500..=599 => "server",
+        _ => "http",
+    }
+}
+
+/// Classify a transport-level reqwest error into an error kind
+fn request_kind(error: &reqwest::Error) -> &'static str {
+    if error.is_timeout() {
+        "timeout"
+    } else {
+        "network"
+    }
+}
+
+impl From<SafeQApiError> for AppError {
+    fn from(error: SafeQApiError) -> Self {
+        let message = error.to_string();
+        let app_error = match &error {
+            SafeQApiError::Settings(_)
+            | SafeQApiError::MissingSettings
+            | SafeQApiError::HttpClient(_)
+            | SafeQApiError::InvalidProxy(_)
+            | SafeQApiError::CaCertRead { .. }
+            | SafeQApiError::CaCertParse { .. } => Self::new("configuration", message),
+            SafeQApiError::InvalidBaseUrl(_) => Self::validation(message),
+            SafeQApiError::Request(err) => Self::new(request_kind(err), message),
+            SafeQApiError::Timeout { .. } => Self::new("timeout", message),
+            SafeQApiError::HttpStatus { status, detail, .. } => {
+                let mut error = Self::new(status_kind(*status), message).with_status(*status);
+                error.code = detail.as_ref().and_then(|detail| detail.error_code.clone());
+                error
+            }
+            SafeQApiError::JsonParse(_) => Self::new("parse", message),
+            SafeQApiError::MissingField(_) => Self::new("unexpected_response", message),
+            SafeQApiError::CardAlreadyAssigned { .. } => Self::validation(message),
+            SafeQApiError::UserNotFound(_) => Self::new("not_found", message),
+        };
+        app_error.with_message_key(error.message_key())
+    }
+}
+
+impl From<EmailDeliveryError> for AppError {
+    fn from(error: EmailDeliveryError) -> Self {
+        let message = error.to_string();
+        let app_error = match &error {
+            EmailDeliveryError::MethodNotGraph
+            | EmailDeliveryError::MissingGraphField(_)
+            | EmailDeliveryError::HttpClient(_)
+            | EmailDeliveryError::InvalidProxy(_) => Self::new("configuration", message),
+            EmailDeliveryError::TokenRequest(err) | EmailDeliveryError::GraphRequest(err) => {
+                Self::new(request_kind(err), message)
+            }
+            // Entra ID answers bad client credentials with 400 or 401
+            EmailDeliveryError::TokenStatus(status, _) => {
+                let kind = match status.as_u16() {
+                    400 | 401 => "unauthorized",
+                    _ => status_kind(*status),
+                };
+                Self::new(kind, message).with_status(*status)
+            }
+            EmailDeliveryError::TokenParse(_) => Self::new("parse", message),
+            EmailDeliveryError::NotSignedIn => Self::new("configuration", message),
+            EmailDeliveryError::Secrets(_) => Self::new("internal", message),
+        };
+        app_error.with_message_key(error.message_key())
+    }
+}
+
+impl From<ExportError> for AppError {
+    fn from(error: ExportError) -> Self {
+        match error {
+            ExportError::Api(err) => err.into(),
+            ExportError::Io(_) => Self::new("internal", error.to_string()),
+        }
+    }
+}
+
+impl From<SettingsLoadError> for AppError {
+    fn from(error: SettingsLoadError) -> Self {
+        let app_error = match error {
+            SettingsLoadError::InvalidTenantUrl(_)
+            | SettingsLoadError::InsecureTenantUrl
+            | SettingsLoadError::InvalidMaxConcurrency(_)
+            | SettingsLoadError::InvalidPinLength { .. } => Self::validation(error.to_string()),
+            _ => Self::new("configuration", error.to_string()),
+        };
+        app_error.with_message_key(error.message_key())
+    }
+}
+
+impl From<SettingsFileError> for AppError {
+    fn from(error: SettingsFileError) -> Self {
+        let message = error.to_string();
+        match &error {
+            SettingsFileError::Load(_) | SettingsFileError::NotConfigured => {
+                Self::new("configuration", message)
+            }
+            SettingsFileError::Invalid(_) => Self::validation(message),
+            SettingsFileError::Read { .. } | SettingsFileError::Write { .. } => {
+                Self::new("internal", message)
+            }
+        }
+    }
+}
+
+impl From<GeneratorConfigError> for AppError {
+    fn from(error: GeneratorConfigError) -> Self {
+        Self::validation(error.to_string())
+    }
+}
+
+impl From<ImportError> for AppError {
+    fn from(error: ImportError) -> Self {
+        let message = error.to_string();
+        match error {
+            ImportError::Read { .. } => Self::new("internal", message),
+            _ => Self::validation(message),
+        }
+    }
+}
+
+impl From<AuditError> for AppError {
+    fn from(error: AuditError) -> Self {
+        Self::new("internal", error.to_string())
+    }
+}
+
+impl From<tauri::Error> for AppError {
+    fn from(error: tauri::Error) -> Self {
+        Self::new("internal", error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn http_status(code: u16) -> SafeQApiError {
+        SafeQApiError::HttpStatus {
+            status: StatusCode::from_u16(code).unwrap(),
+            body: String::new(),
+            detail: None,
+            url: "https://tenant.example.com:7300/api/v1/account".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_unauthorized_status_maps_to_unauthorized() {
+        let error = AppError::from(http_status(401));
+        assert_eq!(error.kind, "unauthorized");
+        assert_eq!(error.status, Some(401));
+    }
+
+    #[test]
+    fn test_status_kinds() {
+        assert_eq!(AppError::from(http_status(429)).kind, "rate_limited");
+        assert_eq!(AppError::from(http_status(504)).kind, "timeout");
+        assert_eq!(AppError::from(http_status(422)).kind, "validation");
+        assert_eq!(AppError::from(http_status(503)).kind, "server");
+    }
+
+    #[test]
+    fn test_missing_settings_maps_to_configuration() {
+        let error = AppError::from(SafeQApiError::MissingSettings);
+        assert_eq!(error.kind, "configuration");
+        assert_eq!(error.status, None);
+    }
+
+    #[test]
+    fn test_graph_token_rejection_maps_to_unauthorized() {
+        let error = AppError::from(EmailDeliveryError::TokenStatus(
+            StatusCode::BAD_REQUEST,
+            "AADSTS7000215: Invalid client secret".to_string(),
+        ));
+        assert_eq!(error.kind, "unauthorized");
+        assert_eq!(error.status, Some(400));
+    }
+
+    #[test]
+    fn test_serializes_for_frontend() {
+        let value = serde_json::to_value(AppError::from(http_status(401))).unwrap();
+        assert_eq!(value["kind"], "unauthorized");
+        assert_eq!(value["status"], 401);
+        assert!(value["message"].as_str().unwrap().contains("401"));
+    }
+
+    #[test]
+    fn test_message_key_is_serialized() {
+        let value = serde_json::to_value(AppError::from(SafeQApiError::MissingSettings)).unwrap();
+        assert_eq!(value["messageKey"], "settings.not_configured");
+
+        let value = serde_json::to_value(AppError::validation("bad row")).unwrap();
+        assert_eq!(value["messageKey"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use serde_json::Value;
+use tracing::debug;
+
+use crate::safeq_api::{next_page_token, users_in, SafeQApiError, SafeQClient};
+
+/// Columns written by `export_users_csv`, as SAFEQ user field names
+const USER_CSV_COLUMNS: [&str; 7] = [
+    "userName",
+    "fullName",
+    "email",
+    "department",
+    "providerId",
+    "cards",
+    "isExpired",
+];
+
+/// Errors raised while exporting users to CSV
+#[derive(Debug)]
+pub enum ExportError {
+    Api(SafeQApiError),
+    Io(io::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Api(err) => write!(f, "Failed to fetch users for export: {err}"),
+            Self::Io(err) => write!(f, "Failed to write the export file: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Api(err) => Some(err),
+            Self::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<SafeQApiError> for ExportError {
+    fn from(error: SafeQApiError) -> Self {
+        Self::Api(error)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Write a provider's users to `out` as CSV, one page at a time
+///
+/// Each page is written and flushed before the next is requested, so only one
+/// page of users is held in memory. Returns the number of rows written.
+pub async fn export_users_csv<W: Write>(
+    client: &SafeQClient,
+    provider_id: i64,
+    out: &mut W,
+) -> Result<usize, ExportError> {
+    writeln!(out, "{}", USER_CSV_COLUMNS.join(","))?;
+
+    let mut rows = 0;
+    let mut page_token: Option<String> = None;
+    loop {
+        let page = client
+            .list_users_page(provider_id, page_token.as_deref())
+            .await?;
+
+        for user in users_in(&page) {
+            writeln!(out, "{}", user_csv_row(user))?;
+            rows += 1;
+        }
+        out.flush()?;
+        debug!(rows, "exported page of users");
+
+        page_token = next_page_token(&page);
+        if page_token.is_none() {
+            return Ok(rows);
+        }
+    }
+}
+
+fn user_csv_row(user: &Value) -> String {
+    USER_CSV_COLUMNS
+        .iter()
+        .map(|column| csv_field(&user[*column]))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Format one value as a CSV field, quoting it when needed
+///
+/// Card lists are joined with `;` so a user stays on one row.
+fn csv_field(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| {
+                item.as_str()
+                    .map_or_else(|| item.to_string(), str::to_string)
+            })
+            .collect::<Vec<_>>()
+            .join(";"),
+        other => other.to_string(),
+    };
+
+    csv_escape(&text)
+}
+
+/// Quote `text` for CSV when it contains a delimiter, quote or line break
+pub(crate) fn csv_escape(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::SafeQSettings;
+    use serde_json::json;
+    use wiremock::matchers::{method, path, query_param, query_param_is_missing};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn client_for(server: &MockServer) -> SafeQClient {
+        let settings = SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "test-key".to_string(),
+            api_port: Some(server.address().port()),
+            ..Default::default()
+        };
+        SafeQClient::from_settings(settings).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_multi_page_export_writes_every_user() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param("providerid", "7"))
+            .and(query_param_is_missing("pagetoken"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [
+                    { "userName": "jdoe", "fullName": "Doe, John", "email": "jdoe@example.com", "providerId": 7, "cards": ["1111", "2222"], "isExpired": false },
+                ],
+                "nextPageToken": "page-2",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param("pagetoken", "page-2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [
+                    { "userName": "asmith", "fullName": "Anna \"Ann\" Smith", "providerId": 7, "cards": [], "isExpired": true },
+                ],
+                "nextPageToken": null,
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut out = Vec::new();
+        let rows = export_users_csv(&client_for(&server), 7, &mut out)
+            .await
+            .unwrap();
+
+        assert_eq!(rows, 2);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "userName,fullName,email,department,providerId,cards,isExpired\n\
+             jdoe,\"Doe, John\",jdoe@example.com,,7,1111;2222,false\n\
+             asmith,\"Anna \"\"Ann\"\" Smith\",,,7,,true\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bare_array_page_is_exported() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param("providerid", "7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "userName": "jdoe", "providerId": 7, "cards": ["1111"], "isExpired": false },
+            ])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut out = Vec::new();
+        let rows = export_users_csv(&client_for(&server), 7, &mut out)
+            .await
+            .unwrap();
+
+        assert_eq!(rows, 1);
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("\njdoe,,,,7,1111,false\n"));
+    }
+}
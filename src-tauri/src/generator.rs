@@ -1,4 +1,19 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Mutex;
+
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Characters excluded from OTPs by default because they are easy to confuse
+pub const DEFAULT_EXCLUDE_CHARACTERS: &str = "1lI0Oo";
+
+/// Curated look-alike characters removed by the `exclude_ambiguous` setting
+pub const AMBIGUOUS_CHARACTERS: &str = "0Oo1lI|5S";
+
+/// Entropy in bits below which generated credentials should be flagged as weak
+pub const MIN_RECOMMENDED_ENTROPY_BITS: f64 = 30.0;
 
 /// Settings for PIN generation
 #[derive(Debug, Clone)]
@@ -12,9 +27,18 @@ impl Default for PinSettings {
     }
 }
 
+/// Longest value `generate_value` will produce
+pub const MAX_GENERATED_LENGTH: usize = 256;
+
+/// OTP length used when `otp_length` is not set
+pub const DEFAULT_OTP_LENGTH: usize = 8;
+
+/// Short ID length used when `short_id_length` is not set
+pub const DEFAULT_SHORT_ID_LENGTH: usize = 6;
+
 /// Settings for Short ID (One Time Password) generation
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ShortIdSettings {
     pub length: usize,
     pub use_uppercase: bool,
@@ -27,59 +51,325 @@ pub struct ShortIdSettings {
 impl Default for ShortIdSettings {
     fn default() -> Self {
         Self {
-            length: 6,
+            length: DEFAULT_SHORT_ID_LENGTH,
             use_uppercase: true,
             use_lowercase: true,
             use_numbers: true,
             use_special: false,
-            exclude_characters: String::from("1lI0Oo"),
+            exclude_characters: String::from(DEFAULT_EXCLUDE_CHARACTERS),
         }
     }
 }
 
-/// Generate a random numeric PIN
-pub fn generate_pin(settings: &PinSettings) -> String {
-    let mut rng = rand::thread_rng();
-    (0..settings.length)
-        .map(|_| rng.gen_range(0..10).to_string())
+/// Generator options sent with a one-off `generate_value` request
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeneratorConfig {
+    pub length: usize,
+    #[serde(default)]
+    pub use_uppercase: bool,
+    #[serde(default)]
+    pub use_lowercase: bool,
+    #[serde(default)]
+    pub use_numbers: bool,
+    #[serde(default)]
+    pub use_special: bool,
+    #[serde(default)]
+    pub exclude_characters: String,
+}
+
+/// Why a `GeneratorConfig` was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeneratorConfigError {
+    InvalidLength(usize),
+    NoCharacterClass,
+    EverythingExcluded,
+}
+
+impl fmt::Display for GeneratorConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLength(length) => write!(
+                f,
+                "length must be between 1 and {MAX_GENERATED_LENGTH}, got {length}"
+            ),
+            Self::NoCharacterClass => write!(f, "select at least one character class"),
+            Self::EverythingExcluded => {
+                write!(f, "the excluded characters remove every selected character")
+            }
+        }
+    }
+}
+
+impl std::error::Error for GeneratorConfigError {}
+
+impl GeneratorConfig {
+    /// Check the config and turn it into generator settings
+    ///
+    /// Unlike stored settings, nothing falls back silently: a config with no
+    /// class, or one whose exclusions remove every character, is rejected.
+    pub fn validate(self) -> Result<ShortIdSettings, GeneratorConfigError> {
+        if self.length == 0 || self.length > MAX_GENERATED_LENGTH {
+            return Err(GeneratorConfigError::InvalidLength(self.length));
+        }
+        if !(self.use_uppercase || self.use_lowercase || self.use_numbers || self.use_special) {
+            return Err(GeneratorConfigError::NoCharacterClass);
+        }
+
+        let mut settings = ShortIdSettings {
+            length: self.length,
+            use_uppercase: self.use_uppercase,
+            use_lowercase: self.use_lowercase,
+            use_numbers: self.use_numbers,
+            use_special: self.use_special,
+            exclude_characters: String::new(),
+        };
+        // Check the selected classes before `charset` would fall back to digits
+        let excluded_all = settings
+            .charset()
+            .iter()
+            .all(|c| self.exclude_characters.contains(*c));
+        if excluded_all {
+            return Err(GeneratorConfigError::EverythingExcluded);
+        }
+
+        settings.exclude_characters = self.exclude_characters;
+        Ok(settings)
+    }
+}
+
+/// Why a Short ID is drawn from digits instead of the configured characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CharsetWarning {
+    NoCharacterClass,
+    EverythingExcluded,
+}
+
+impl fmt::Display for CharsetWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoCharacterClass => {
+                write!(f, "no character classes enabled; falling back to numeric")
+            }
+            Self::EverythingExcluded => {
+                write!(
+                    f,
+                    "exclusion removes entire charset; using numeric fallback"
+                )
+            }
+        }
+    }
+}
+
+impl PinSettings {
+    /// Entropy of a PIN of the configured length
+    pub fn entropy_bits(&self) -> f64 {
+        entropy_bits(self.length, 10)
+    }
+
+    /// Number of distinct PINs of the configured length
+    pub fn value_space(&self) -> u128 {
+        value_space(self.length, 10)
+    }
+}
+
+impl ShortIdSettings {
+    /// Characters a Short ID is drawn from after exclusions and fallbacks
+    pub fn charset(&self) -> Vec<char> {
+        let mut charset = String::new();
+
+        if self.use_uppercase {
+            charset.push_str("ABCDEFGHIJKLMNOPQRSTUVWXYZ");
+        }
+        if self.use_lowercase {
+            charset.push_str("abcdefghijklmnopqrstuvwxyz");
+        }
+        if self.use_numbers {
+            charset.push_str("0123456789");
+        }
+        if self.use_special {
+            charset.push_str("!@#$%^&*-_+=");
+        }
+
+        // Fallback to numbers if no character set is selected
+        if charset.is_empty() {
+            charset.push_str("0123456789");
+        }
+
+        // Filter out excluded characters
+        let excluded: Vec<char> = self.exclude_characters.chars().collect();
+        let chars: Vec<char> = charset.chars().filter(|c| !excluded.contains(c)).collect();
+
+        // Fallback to all numbers if filtering removed everything
+        if chars.is_empty() {
+            vec!['0', '1', '2', '3', '4', '5', '6', '7', '8', '9']
+        } else {
+            chars
+        }
+    }
+
+    /// The fallback `charset` silently applies to these settings, if any
+    pub fn charset_warning(&self) -> Option<CharsetWarning> {
+        if !(self.use_uppercase || self.use_lowercase || self.use_numbers || self.use_special) {
+            return Some(CharsetWarning::NoCharacterClass);
+        }
+        let selected = ShortIdSettings {
+            exclude_characters: String::new(),
+            ..self.clone()
+        };
+        selected
+            .charset()
+            .iter()
+            .all(|c| self.exclude_characters.contains(*c))
+            .then_some(CharsetWarning::EverythingExcluded)
+    }
+
+    /// Entropy of a Short ID generated with these settings
+    pub fn entropy_bits(&self) -> f64 {
+        entropy_bits(self.length, self.charset().len())
+    }
+
+    /// Number of distinct Short IDs these settings can produce
+    pub fn value_space(&self) -> u128 {
+        value_space(self.length, self.charset().len())
+    }
+}
+
+/// Entropy in bits of `length` symbols drawn uniformly from `charset_size` options
+pub fn entropy_bits(length: usize, charset_size: usize) -> f64 {
+    if charset_size == 0 {
+        return 0.0;
+    }
+    length as f64 * (charset_size as f64).log2()
+}
+
+/// Number of distinct values of `length` symbols from `charset_size` options,
+/// saturating at `u128::MAX`
+pub fn value_space(length: usize, charset_size: usize) -> u128 {
+    (charset_size as u128).saturating_pow(length.try_into().unwrap_or(u32::MAX))
+}
+
+/// Generate `count` distinct values, regenerating on collision
+///
+/// Returns `None` without generating anything when `space`, the number of
+/// values `generate` can produce, is smaller than `count`.
+pub fn generate_unique(
+    count: usize,
+    space: u128,
+    mut generate: impl FnMut() -> String,
+) -> Option<Vec<String>> {
+    if (count as u128) > space {
+        return None;
+    }
+
+    let mut seen = HashSet::with_capacity(count);
+    (0..count)
+        .map(|_| draw_unused(&mut seen, space, &mut generate))
         .collect()
 }
 
-/// Generate a random Short ID (One Time Password) with UTF-8 characters
-#[allow(dead_code)]
-pub fn generate_short_id(settings: &ShortIdSettings) -> String {
-    let mut charset = String::new();
+/// Draw from `generate` until it yields a value missing from `seen`, and
+/// record it there
+///
+/// Returns `None` once `seen` holds `space` values, rather than looping forever.
+fn draw_unused(
+    seen: &mut HashSet<String>,
+    space: u128,
+    generate: &mut impl FnMut() -> String,
+) -> Option<String> {
+    if seen.len() as u128 >= space {
+        return None;
+    }
 
-    if settings.use_uppercase {
-        charset.push_str("ABCDEFGHIJKLMNOPQRSTUVWXYZ");
+    loop {
+        let value = generate();
+        if seen.insert(value.clone()) {
+            return Some(value);
+        }
     }
-    if settings.use_lowercase {
-        charset.push_str("abcdefghijklmnopqrstuvwxyz");
+}
+
+/// Values used so far in one batch, so generated credentials don't repeat
+///
+/// Shared by the concurrent rows of a batch.
+#[derive(Debug, Default)]
+pub struct UniqueTracker {
+    seen: Mutex<HashSet<String>>,
+}
+
+impl UniqueTracker {
+    /// A tracker that treats `values`, e.g. ones a batch supplies explicitly,
+    /// as already used
+    pub fn with_used<I, S>(values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            seen: Mutex::new(values.into_iter().map(Into::into).collect()),
+        }
     }
-    if settings.use_numbers {
-        charset.push_str("0123456789");
+
+    /// A value from `generate` that this tracker has not seen yet
+    ///
+    /// Returns `None` once all `space` values are used.
+    pub fn next(&self, space: u128, mut generate: impl FnMut() -> String) -> Option<String> {
+        let mut seen = self.seen.lock().unwrap_or_else(|err| err.into_inner());
+        draw_unused(&mut seen, space, &mut generate)
     }
-    if settings.use_special {
-        charset.push_str("!@#$%^&*-_+=");
+}
+
+/// Generate one value per entry of `groups`, distinct among entries in the
+/// same group
+///
+/// Used to keep PINs unique within a provider while still letting two
+/// providers share one. When a group has more entries than `space` allows,
+/// returns the size of that group as the error.
+pub fn generate_unique_per_group<K: Eq + Hash>(
+    groups: &[K],
+    space: u128,
+    mut generate: impl FnMut() -> String,
+) -> Result<Vec<String>, usize> {
+    let mut members: HashMap<&K, Vec<usize>> = HashMap::new();
+    for (index, group) in groups.iter().enumerate() {
+        members.entry(group).or_default().push(index);
     }
 
-    // Fallback to numbers if no character set is selected
-    if charset.is_empty() {
-        charset.push_str("0123456789");
+    let mut values = vec![String::new(); groups.len()];
+    for indices in members.values() {
+        let generated =
+            generate_unique(indices.len(), space, &mut generate).ok_or(indices.len())?;
+        for (index, value) in indices.iter().zip(generated) {
+            values[*index] = value;
+        }
     }
+    Ok(values)
+}
+
+/// Generate a random numeric PIN
+pub fn generate_pin(settings: &PinSettings) -> String {
+    generate_pin_with_rng(settings, &mut rand::thread_rng())
+}
 
-    // Filter out excluded characters
-    let excluded: Vec<char> = settings.exclude_characters.chars().collect();
-    let chars: Vec<char> = charset.chars().filter(|c| !excluded.contains(c)).collect();
+/// Generate a numeric PIN from the given RNG, e.g. a seeded `StdRng` in tests
+pub fn generate_pin_with_rng(settings: &PinSettings, rng: &mut impl Rng) -> String {
+    (0..settings.length)
+        .map(|_| rng.gen_range(0..10).to_string())
+        .collect()
+}
 
-    // Fallback to all numbers if filtering removed everything
-    let final_chars = if chars.is_empty() {
-        vec!['0', '1', '2', '3', '4', '5', '6', '7', '8', '9']
-    } else {
-        chars
-    };
+/// Generate a random Short ID (One Time Password)
+///
+/// `length` counts characters, not bytes; every built-in charset is ASCII, so
+/// each character is also a single grapheme.
+pub fn generate_short_id(settings: &ShortIdSettings) -> String {
+    generate_short_id_with_rng(settings, &mut rand::thread_rng())
+}
 
-    let mut rng = rand::thread_rng();
+/// Generate a Short ID from the given RNG, e.g. a seeded `StdRng` in tests
+pub fn generate_short_id_with_rng(settings: &ShortIdSettings, rng: &mut impl Rng) -> String {
+    let final_chars = settings.charset();
 
     (0..settings.length)
         .map(|_| final_chars[rng.gen_range(0..final_chars.len())])
@@ -89,6 +379,8 @@ pub fn generate_short_id(settings: &ShortIdSettings) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
 
     #[test]
     fn test_generate_pin() {
@@ -119,4 +411,285 @@ mod tests {
         assert_eq!(short_id.len(), 8);
         assert!(short_id.chars().all(|c| c.is_ascii_digit()));
     }
+
+    #[test]
+    fn test_generate_short_id_never_uses_excluded_characters() {
+        let settings = ShortIdSettings {
+            length: 64,
+            ..ShortIdSettings::default()
+        };
+        for _ in 0..50 {
+            let short_id = generate_short_id(&settings);
+            assert!(!short_id
+                .chars()
+                .any(|c| DEFAULT_EXCLUDE_CHARACTERS.contains(c)));
+        }
+    }
+
+    #[test]
+    fn test_generate_short_id_falls_back_when_everything_is_excluded() {
+        let settings = ShortIdSettings {
+            length: 10,
+            use_uppercase: false,
+            use_lowercase: false,
+            use_numbers: true,
+            use_special: false,
+            exclude_characters: String::from("0123456789"),
+        };
+        let short_id = generate_short_id(&settings);
+        assert_eq!(short_id.len(), 10);
+        assert!(short_id.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn test_generate_unique_fills_small_space_without_repeats() {
+        let settings = PinSettings { length: 2 };
+        let pins =
+            generate_unique(100, settings.value_space(), || generate_pin(&settings)).unwrap();
+
+        assert_eq!(pins.len(), 100);
+        assert_eq!(pins.iter().collect::<HashSet<_>>().len(), 100);
+        assert!(pins.iter().all(|pin| pin.len() == 2));
+    }
+
+    #[test]
+    fn test_generate_unique_rejects_count_beyond_space() {
+        let settings = PinSettings { length: 2 };
+        assert!(generate_unique(101, settings.value_space(), || generate_pin(&settings)).is_none());
+        assert_eq!(
+            generate_unique(0, settings.value_space(), || generate_pin(&settings)),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_generate_unique_follows_short_id_settings() {
+        let settings = ShortIdSettings {
+            length: 5,
+            use_uppercase: true,
+            use_lowercase: false,
+            use_numbers: false,
+            use_special: false,
+            exclude_characters: String::from("O"),
+        };
+        let values =
+            generate_unique(50, settings.value_space(), || generate_short_id(&settings)).unwrap();
+
+        assert_eq!(values.len(), 50);
+        assert!(values
+            .iter()
+            .all(|value| value.len() == 5
+                && value.chars().all(|c| c.is_ascii_uppercase() && c != 'O')));
+    }
+
+    #[test]
+    fn test_unique_per_group_never_repeats_within_a_group() {
+        let settings = PinSettings { length: 2 };
+        let groups: Vec<Option<i64>> = (0..150)
+            .map(|index| if index < 100 { Some(1) } else { Some(2) })
+            .collect();
+
+        let pins =
+            generate_unique_per_group(&groups, settings.value_space(), || generate_pin(&settings))
+                .unwrap();
+
+        assert_eq!(pins[..100].iter().collect::<HashSet<_>>().len(), 100);
+        assert_eq!(pins[100..].iter().collect::<HashSet<_>>().len(), 50);
+    }
+
+    #[test]
+    fn test_unique_per_group_reports_exhausted_space() {
+        let settings = PinSettings { length: 1 };
+        let groups = vec![1; 11];
+
+        assert_eq!(
+            generate_unique_per_group(&groups, settings.value_space(), || generate_pin(&settings)),
+            Err(11)
+        );
+    }
+
+    #[test]
+    fn test_generator_config_validation() {
+        let config = |length, use_numbers, exclude: &str| GeneratorConfig {
+            length,
+            use_numbers,
+            exclude_characters: exclude.to_string(),
+            ..GeneratorConfig::default()
+        };
+
+        assert_eq!(
+            config(0, true, "").validate().unwrap_err(),
+            GeneratorConfigError::InvalidLength(0)
+        );
+        assert_eq!(
+            config(MAX_GENERATED_LENGTH + 1, true, "")
+                .validate()
+                .unwrap_err(),
+            GeneratorConfigError::InvalidLength(MAX_GENERATED_LENGTH + 1)
+        );
+        assert_eq!(
+            config(8, false, "").validate().unwrap_err(),
+            GeneratorConfigError::NoCharacterClass
+        );
+        assert_eq!(
+            config(8, true, "0123456789").validate().unwrap_err(),
+            GeneratorConfigError::EverythingExcluded
+        );
+        let uppercase_only = GeneratorConfig {
+            length: 8,
+            use_uppercase: true,
+            exclude_characters: "ABCDEFGHIJKLMNOPQRSTUVWXYZ".to_string(),
+            ..GeneratorConfig::default()
+        };
+        assert_eq!(
+            uppercase_only.validate().unwrap_err(),
+            GeneratorConfigError::EverythingExcluded
+        );
+        assert!(config(MAX_GENERATED_LENGTH, true, "").validate().is_ok());
+    }
+
+    #[test]
+    fn test_generator_config_output_invariants() {
+        let settings = GeneratorConfig {
+            length: 32,
+            use_lowercase: true,
+            use_special: true,
+            exclude_characters: "lo!".to_string(),
+            ..GeneratorConfig::default()
+        }
+        .validate()
+        .unwrap();
+
+        for _ in 0..20 {
+            let value = generate_short_id(&settings);
+            assert_eq!(value.chars().count(), 32);
+            assert!(value.chars().all(
+                |c| (c.is_ascii_lowercase() || "@#$%^&*-_+=".contains(c)) && !"lo!".contains(c)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_value_space() {
+        assert_eq!(PinSettings { length: 4 }.value_space(), 10_000);
+        assert_eq!(value_space(3, 0), 0);
+        assert_eq!(value_space(200, 62), u128::MAX);
+    }
+
+    #[test]
+    fn test_entropy_bits_weak_config() {
+        let settings = ShortIdSettings {
+            length: 3,
+            use_uppercase: false,
+            use_lowercase: false,
+            use_numbers: true,
+            use_special: false,
+            exclude_characters: String::new(),
+        };
+        let bits = settings.entropy_bits();
+        assert!((9.9..10.0).contains(&bits));
+        assert!(bits < MIN_RECOMMENDED_ENTROPY_BITS);
+        assert!(PinSettings { length: 4 }.entropy_bits() < MIN_RECOMMENDED_ENTROPY_BITS);
+    }
+
+    #[test]
+    fn test_entropy_bits_strong_config() {
+        let settings = ShortIdSettings {
+            length: 12,
+            ..ShortIdSettings::default()
+        };
+        // 62 letters and digits minus the 6 default exclusions leaves 56
+        assert_eq!(settings.charset().len(), 56);
+        let bits = settings.entropy_bits();
+        assert!((69.0..70.0).contains(&bits));
+        assert!(bits >= MIN_RECOMMENDED_ENTROPY_BITS);
+    }
+
+    #[test]
+    fn test_entropy_bits_counts_exclusions() {
+        let settings = ShortIdSettings {
+            length: 1,
+            use_uppercase: false,
+            use_lowercase: false,
+            use_numbers: true,
+            use_special: false,
+            exclude_characters: "012345".to_string(),
+        };
+        assert_eq!(settings.entropy_bits(), 2.0);
+    }
+
+    #[test]
+    fn test_same_seed_yields_same_values() {
+        let pin_settings = PinSettings { length: 8 };
+        let short_id_settings = ShortIdSettings {
+            length: 16,
+            ..ShortIdSettings::default()
+        };
+
+        let pin_a = generate_pin_with_rng(&pin_settings, &mut StdRng::seed_from_u64(42));
+        let pin_b = generate_pin_with_rng(&pin_settings, &mut StdRng::seed_from_u64(42));
+        assert_eq!(pin_a, pin_b);
+
+        let id_a = generate_short_id_with_rng(&short_id_settings, &mut StdRng::seed_from_u64(42));
+        let id_b = generate_short_id_with_rng(&short_id_settings, &mut StdRng::seed_from_u64(42));
+        assert_eq!(id_a, id_b);
+    }
+
+    #[test]
+    fn test_different_seeds_yield_different_values() {
+        let pin_settings = PinSettings { length: 16 };
+        let short_id_settings = ShortIdSettings {
+            length: 16,
+            ..ShortIdSettings::default()
+        };
+
+        assert_ne!(
+            generate_pin_with_rng(&pin_settings, &mut StdRng::seed_from_u64(1)),
+            generate_pin_with_rng(&pin_settings, &mut StdRng::seed_from_u64(2))
+        );
+        assert_ne!(
+            generate_short_id_with_rng(&short_id_settings, &mut StdRng::seed_from_u64(1)),
+            generate_short_id_with_rng(&short_id_settings, &mut StdRng::seed_from_u64(2))
+        );
+    }
+
+    #[test]
+    fn test_short_id_length_counts_characters() {
+        let settings = ShortIdSettings {
+            length: 20,
+            use_special: true,
+            ..ShortIdSettings::default()
+        };
+        let short_id = generate_short_id(&settings);
+        assert_eq!(short_id.chars().count(), 20);
+        assert_eq!(short_id.len(), 20);
+    }
+
+    #[test]
+    fn test_tracker_never_repeats_within_a_batch() {
+        let settings = PinSettings { length: 3 };
+        let tracker = UniqueTracker::default();
+
+        let pins: Vec<String> = (0..600)
+            .map(|_| {
+                tracker
+                    .next(settings.value_space(), || generate_pin(&settings))
+                    .unwrap()
+            })
+            .collect();
+
+        let distinct: HashSet<_> = pins.iter().collect();
+        assert_eq!(distinct.len(), pins.len());
+    }
+
+    #[test]
+    fn test_tracker_fails_once_space_is_used() {
+        let tracker = UniqueTracker::with_used(["a"]);
+        let mut values = ["a", "b", "c"].into_iter();
+        let mut generate = || values.next().unwrap().to_string();
+
+        assert_eq!(tracker.next(3, &mut generate).as_deref(), Some("b"));
+        assert_eq!(tracker.next(3, &mut generate).as_deref(), Some("c"));
+        assert_eq!(tracker.next(3, &mut generate), None);
+    }
 }
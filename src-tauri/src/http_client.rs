@@ -0,0 +1,424 @@
+use std::time::Duration;
+
+use reqwest::tls::Version;
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+
+use crate::settings::{MinTlsVersion, SafeQSettings};
+
+/// Product token sent with every SAFEQ and Graph request
+const USER_AGENT_PRODUCT: &str = concat!("SQC-User-Manager/", env!("CARGO_PKG_VERSION"));
+
+/// Hosts that always bypass the proxy when `NO_PROXY` is not set
+const DEFAULT_NO_PROXY: &str = "localhost,127.0.0.1,::1";
+
+/// Build the User-Agent, appending the configured operator identifier if any
+///
+/// Characters that are not allowed in a header value are dropped so a stray
+/// newline in settings cannot break request construction.
+pub fn user_agent(settings: &SafeQSettings) -> String {
+    let operator = settings
+        .operator_id
+        .as_deref()
+        .map(|id| {
+            id.chars()
+                .filter(|c| c.is_ascii_graphic() || *c == ' ')
+                .filter(|c| !matches!(c, '(' | ')'))
+                .collect::<String>()
+        })
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty());
+
+    match operator {
+        Some(operator) => format!("{USER_AGENT_PRODUCT} ({operator})"),
+        None => USER_AGENT_PRODUCT.to_string(),
+    }
+}
+
+/// Connection pool tuning for bulk runs; `None` keeps reqwest's default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolSettings {
+    pub max_idle_per_host: Option<usize>,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl PoolSettings {
+    pub fn from_settings(settings: &SafeQSettings) -> Self {
+        Self {
+            max_idle_per_host: settings.pool_max_idle_per_host,
+            idle_timeout: settings.pool_idle_timeout_secs.map(Duration::from_secs),
+        }
+    }
+
+    pub fn apply(self, mut builder: ClientBuilder) -> ClientBuilder {
+        if let Some(max) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max);
+        }
+        if let Some(timeout) = self.idle_timeout {
+            builder = builder.pool_idle_timeout(timeout);
+        }
+        builder
+    }
+}
+
+/// Refuse to negotiate a TLS version older than the configured minimum
+pub fn apply_min_tls(builder: ClientBuilder, settings: &SafeQSettings) -> ClientBuilder {
+    builder.min_tls_version(match settings.min_tls_version {
+        MinTlsVersion::Tls12 => Version::TLS_1_2,
+        MinTlsVersion::Tls13 => Version::TLS_1_3,
+    })
+}
+
+/// Whether a request failed because the server only offers an older TLS version
+///
+/// rustls reports this deep in the error chain, so every source is checked.
+pub fn is_tls_version_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        let text = error.to_string();
+        if text.contains("ProtocolVersion") || text.contains("DoesNotSupportTls") {
+            return true;
+        }
+        current = error.source();
+    }
+    false
+}
+
+/// Route all requests through the configured proxy, if any
+///
+/// Hosts listed in `NO_PROXY` (or localhost when it is unset) are contacted
+/// directly so the dev server keeps working behind a corporate proxy.
+pub fn apply_proxy(
+    builder: ClientBuilder,
+    settings: &SafeQSettings,
+) -> Result<ClientBuilder, reqwest::Error> {
+    let Some(proxy_url) = settings.proxy_url.as_deref() else {
+        return Ok(builder);
+    };
+
+    let mut proxy = Proxy::all(proxy_url)?
+        .no_proxy(NoProxy::from_env().or_else(|| NoProxy::from_string(DEFAULT_NO_PROXY)));
+
+    if let Some(username) = settings.proxy_username.as_deref() {
+        proxy = proxy.basic_auth(username, settings.proxy_password.as_deref().unwrap_or(""));
+    }
+
+    Ok(builder.proxy(proxy))
+}
+
+/// A TLS endpoint that records what clients offer, for testing TLS settings
+#[cfg(test)]
+pub(crate) mod tls_probe {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Receiver};
+
+    pub const TLS_1_2: u16 = 0x0303;
+    pub const TLS_1_3: u16 = 0x0304;
+
+    /// Fatal `protocol_version` alert, as sent by a server that only speaks
+    /// older TLS versions
+    pub const PROTOCOL_VERSION_ALERT: &[u8] = &[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x46];
+
+    /// Serve `https://127.0.0.1:<port>`, answering every ClientHello with
+    /// `reply` and closing the connection
+    ///
+    /// The receiver yields the versions each hello listed in its
+    /// `supported_versions` extension.
+    pub fn serve(reply: &'static [u8]) -> (String, Receiver<Vec<u16>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("https://{}", listener.local_addr().unwrap());
+        let (sender, hellos) = channel();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    break;
+                };
+                if let Some(versions) = read_client_hello(&mut stream) {
+                    let _ = sender.send(versions);
+                }
+                let _ = stream.write_all(reply);
+            }
+        });
+
+        (url, hellos)
+    }
+
+    fn read_client_hello(stream: &mut TcpStream) -> Option<Vec<u16>> {
+        let mut header = [0u8; 5];
+        stream.read_exact(&mut header).ok()?;
+        let mut hello = vec![0u8; usize::from(u16::from_be_bytes([header[3], header[4]]))];
+        stream.read_exact(&mut hello).ok()?;
+
+        // Handshake header, client version and random, then the
+        // variable-length session id, cipher suites and compression methods
+        let mut pos = 4 + 2 + 32;
+        pos += 1 + usize::from(*hello.get(pos)?);
+        pos += 2 + usize::from(u16::from_be_bytes([*hello.get(pos)?, *hello.get(pos + 1)?]));
+        pos += 1 + usize::from(*hello.get(pos)?);
+        pos += 2;
+
+        while pos + 4 <= hello.len() {
+            let kind = u16::from_be_bytes([hello[pos], hello[pos + 1]]);
+            let len = usize::from(u16::from_be_bytes([hello[pos + 2], hello[pos + 3]]));
+            let data = hello.get(pos + 4..pos + 4 + len)?;
+            // supported_versions: a one-byte list length, then two bytes per version
+            if kind == 0x002b {
+                return Some(
+                    data.get(1..)?
+                        .chunks_exact(2)
+                        .map(|version| u16::from_be_bytes([version[0], version[1]]))
+                        .collect(),
+                );
+            }
+            pos += 4 + len;
+        }
+        Some(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Client;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_user_agent_contains_crate_version() {
+        let settings = SafeQSettings::default();
+        assert_eq!(
+            user_agent(&settings),
+            format!("SQC-User-Manager/{}", env!("CARGO_PKG_VERSION"))
+        );
+    }
+
+    #[test]
+    fn test_user_agent_appends_operator_id() {
+        let settings = SafeQSettings {
+            operator_id: Some(" helpdesk-01 (\nfloor 2)".to_string()),
+            ..SafeQSettings::default()
+        };
+        let agent = user_agent(&settings);
+        assert!(agent.starts_with(&format!("SQC-User-Manager/{}", env!("CARGO_PKG_VERSION"))));
+        assert!(agent.ends_with(" (helpdesk-01 floor 2)"));
+    }
+
+    #[test]
+    fn test_pool_settings_default_to_reqwest() {
+        let pool = PoolSettings::from_settings(&SafeQSettings::default());
+        assert_eq!(pool, PoolSettings::default());
+    }
+
+    /// Serve empty keep-alive HTTP/1.1 responses, counting accepted connections
+    fn serve_counting_connections() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let accepted = Arc::clone(&connections);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    break;
+                };
+                accepted.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    let mut reader = BufReader::new(&stream);
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).is_ok_and(|read| read > 0) {
+                        if line == "\r\n" {
+                            let reply = b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+                            if (&stream).write_all(reply).is_err() {
+                                break;
+                            }
+                        }
+                        line.clear();
+                    }
+                });
+            }
+        });
+
+        (url, connections)
+    }
+
+    /// Send two requests `pause` apart and return how many connections they used
+    async fn connections_for_two_requests(pool: PoolSettings, pause: Duration) -> usize {
+        let (url, connections) = serve_counting_connections();
+        let client = pool.apply(Client::builder()).build().unwrap();
+
+        client.get(&url).send().await.unwrap();
+        tokio::time::sleep(pause).await;
+        client.get(&url).send().await.unwrap();
+
+        connections.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_pool_settings_from_settings() {
+        let settings = SafeQSettings {
+            pool_max_idle_per_host: Some(4),
+            pool_idle_timeout_secs: Some(30),
+            ..SafeQSettings::default()
+        };
+        let pool = PoolSettings::from_settings(&settings);
+        assert_eq!(pool.max_idle_per_host, Some(4));
+        assert_eq!(pool.idle_timeout, Some(Duration::from_secs(30)));
+    }
+
+    #[tokio::test]
+    async fn test_pool_reuses_idle_connections_by_default() {
+        let pool = PoolSettings::from_settings(&SafeQSettings::default());
+        assert_eq!(connections_for_two_requests(pool, Duration::ZERO).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_pool_max_idle_per_host_zero_disables_reuse() {
+        let settings = SafeQSettings {
+            pool_max_idle_per_host: Some(0),
+            ..SafeQSettings::default()
+        };
+        let pool = PoolSettings::from_settings(&settings);
+        assert_eq!(connections_for_two_requests(pool, Duration::ZERO).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_pool_idle_timeout_closes_idle_connections() {
+        let pool = PoolSettings {
+            idle_timeout: Some(Duration::from_millis(50)),
+            ..PoolSettings::default()
+        };
+        assert_eq!(
+            connections_for_two_requests(pool, Duration::from_millis(300)).await,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_min_tls_defaults_to_1_2() {
+        let (url, hellos) = tls_probe::serve(&[]);
+        let client = apply_min_tls(Client::builder(), &SafeQSettings::default())
+            .build()
+            .unwrap();
+
+        assert!(client.get(&url).send().await.is_err());
+        let mut offered = hellos.recv().unwrap();
+        offered.sort_unstable();
+        assert_eq!(offered, [tls_probe::TLS_1_2, tls_probe::TLS_1_3]);
+    }
+
+    #[tokio::test]
+    async fn test_min_tls_from_settings() {
+        let (url, hellos) = tls_probe::serve(&[]);
+        let settings = SafeQSettings {
+            min_tls_version: MinTlsVersion::Tls13,
+            ..SafeQSettings::default()
+        };
+        let client = apply_min_tls(Client::builder(), &settings).build().unwrap();
+
+        assert!(client.get(&url).send().await.is_err());
+        assert_eq!(hellos.recv().unwrap(), [tls_probe::TLS_1_3]);
+    }
+
+    #[tokio::test]
+    async fn test_server_rejecting_tls_version_is_recognised() {
+        let (url, _hellos) = tls_probe::serve(tls_probe::PROTOCOL_VERSION_ALERT);
+        let client = apply_min_tls(Client::builder(), &SafeQSettings::default())
+            .build()
+            .unwrap();
+
+        let error = client.get(&url).send().await.unwrap_err();
+        assert!(is_tls_version_error(&error), "{error:?}");
+    }
+
+    #[test]
+    fn test_is_tls_version_error_checks_sources() {
+        #[derive(Debug)]
+        struct Wrapped(std::io::Error);
+
+        impl std::fmt::Display for Wrapped {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "error sending request")
+            }
+        }
+
+        impl std::error::Error for Wrapped {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let tls = Wrapped(std::io::Error::other(
+            "received fatal alert: ProtocolVersion",
+        ));
+        assert!(is_tls_version_error(&tls));
+
+        let refused = Wrapped(std::io::Error::other("connection refused"));
+        assert!(!is_tls_version_error(&refused));
+    }
+
+    #[tokio::test]
+    async fn test_apply_proxy_without_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = apply_proxy(Client::builder(), &SafeQSettings::default())
+            .unwrap()
+            .build()
+            .unwrap();
+        let response = client
+            .get(format!("{}/api/v1/account", server.uri()))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_apply_proxy_with_url() {
+        // The mock server plays the proxy; the tenant host does not resolve
+        let proxy = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .and(header("proxy-authorization", "Basic dXNlcjpzZWNyZXQ="))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&proxy)
+            .await;
+
+        let settings = SafeQSettings {
+            proxy_url: Some(proxy.uri()),
+            proxy_username: Some("user".to_string()),
+            proxy_password: Some("secret".to_string()),
+            ..SafeQSettings::default()
+        };
+        let client = apply_proxy(Client::builder(), &settings)
+            .unwrap()
+            .build()
+            .unwrap();
+        let response = client
+            .get("http://tenant.example.invalid/api/v1/account")
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    #[test]
+    fn test_apply_proxy_invalid_url() {
+        let settings = SafeQSettings {
+            proxy_url: Some("not a url".to_string()),
+            ..SafeQSettings::default()
+        };
+        assert!(apply_proxy(Client::builder(), &settings).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+
+/// Locale used for any message a translation does not cover
+pub const FALLBACK_LOCALE: &str = "en";
+
+/// Message tables by language code, keyed by each error's `message_key`
+const CATALOGS: [(&str, &str); 3] = [
+    ("en", include_str!("../locales/en.json")),
+    ("fi", include_str!("../locales/fi.json")),
+    ("de", include_str!("../locales/de.json")),
+];
+
+/// Messages for `locale` over the English table
+///
+/// `fi-FI` and `fi_FI` both resolve to `fi`. An unknown locale, or a key the
+/// locale has not translated, gets the English message.
+pub fn catalog(locale: &str) -> BTreeMap<String, String> {
+    let mut messages = table(FALLBACK_LOCALE);
+    messages.extend(table(&language(locale)));
+    messages
+}
+
+fn table(language: &str) -> BTreeMap<String, String> {
+    CATALOGS
+        .iter()
+        .find(|(code, _)| *code == language)
+        .and_then(|(_, json)| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+fn language(locale: &str) -> String {
+    locale
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::EmailDeliveryError;
+    use crate::safeq_api::SafeQApiError;
+    use crate::settings::SettingsLoadError;
+
+    fn message(locale: &str, key: &str) -> Option<String> {
+        catalog(locale).remove(key)
+    }
+
+    #[test]
+    fn test_every_table_parses_and_matches_english_keys() {
+        let english = table(FALLBACK_LOCALE);
+        assert!(!english.is_empty());
+
+        for (code, json) in CATALOGS {
+            let messages: BTreeMap<String, String> = serde_json::from_str(json).unwrap();
+            assert!(
+                messages.keys().eq(english.keys()),
+                "{code} keys differ from English"
+            );
+        }
+    }
+
+    #[test]
+    fn test_variants_resolve_to_their_keys() {
+        assert_eq!(
+            SafeQApiError::MissingSettings.message_key(),
+            "settings.not_configured"
+        );
+        assert_eq!(
+            SafeQApiError::Settings(SettingsLoadError::MissingApiKey).message_key(),
+            "settings.missing_api_key"
+        );
+        assert_eq!(
+            EmailDeliveryError::NotSignedIn.message_key(),
+            "email.not_signed_in"
+        );
+        assert_eq!(
+            SettingsLoadError::InsecureTenantUrl.message_key(),
+            "settings.insecure_tenant_url"
+        );
+
+        for key in [
+            SafeQApiError::MissingSettings.message_key(),
+            EmailDeliveryError::MethodNotGraph.message_key(),
+            SettingsLoadError::MissingTenantUrl.message_key(),
+        ] {
+            assert!(
+                message(FALLBACK_LOCALE, key).is_some(),
+                "{key} has no message"
+            );
+        }
+    }
+
+    #[test]
+    fn test_locale_lookup_and_fallback() {
+        assert_eq!(
+            message("fi-FI", "safeq.timeout").as_deref(),
+            Some("SAFEQ-palvelin ei vastannut ajoissa.")
+        );
+        assert_eq!(
+            message("de_DE", "safeq.timeout").as_deref(),
+            Some("Der SAFEQ-Server hat nicht rechtzeitig geantwortet.")
+        );
+        assert_eq!(
+            message("sv-SE", "safeq.timeout").as_deref(),
+            Some("The SAFEQ server did not answer in time.")
+        );
+        assert_eq!(message("fi", "no.such.key"), None);
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Data rows returned by `inspect_import` for previewing the mapping
+const SAMPLE_ROWS: usize = 5;
+
+/// Delimiters tried on the header row, in order of preference on a tie
+const DELIMITERS: [char; 4] = [';', ',', '\t', '|'];
+
+/// Every field an import row can carry
+pub const IMPORT_FIELDS: [&str; 10] = [
+    "userName",
+    "fullName",
+    "email",
+    "homeFolder",
+    "password",
+    "cardId",
+    "shortId",
+    "otp",
+    "providerId",
+    "templateKey",
+];
+
+/// Import fields that must be mapped to a column
+pub const REQUIRED_FIELDS: [&str; 1] = ["userName"];
+
+/// Normalized header names recognised for each import field
+///
+/// The frontend parses dropped files through `parse_import_csv`, so this is
+/// the only alias table.
+const COLUMN_ALIASES: [(&str, &str); 21] = [
+    ("upn", "userName"),
+    ("username", "userName"),
+    ("user", "userName"),
+    ("fullname", "fullName"),
+    ("name", "fullName"),
+    ("emailaddress", "email"),
+    ("email", "email"),
+    ("homefolder", "homeFolder"),
+    ("home", "homeFolder"),
+    ("password", "password"),
+    ("cardid", "cardId"),
+    ("card", "cardId"),
+    ("shortid", "shortId"),
+    ("short", "shortId"),
+    ("pin", "shortId"),
+    ("otp", "otp"),
+    ("pid", "providerId"),
+    ("providerid", "providerId"),
+    ("provider", "providerId"),
+    ("template", "templateKey"),
+    ("templatekey", "templateKey"),
+];
+
+/// Errors raised while reading an import file
+#[derive(Debug)]
+pub enum ImportError {
+    Read { path: String, source: io::Error },
+    Empty,
+    UnknownField(String),
+    UnknownColumn { field: String, column: String },
+    UnmappedRequired(Vec<&'static str>),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read { path, source } => {
+                write!(f, "failed to read import file '{path}': {source}")
+            }
+            Self::Empty => write!(f, "import file has no header row"),
+            Self::UnknownField(field) => write!(f, "'{field}' is not an import field"),
+            Self::UnknownColumn { field, column } => {
+                write!(
+                    f,
+                    "column '{column}' mapped to '{field}' is not in the header"
+                )
+            }
+            Self::UnmappedRequired(fields) => {
+                write!(f, "required fields are not mapped: {}", fields.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Read { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// One header column and the import field it was matched to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportColumn {
+    pub index: usize,
+    pub name: String,
+    pub field: Option<&'static str>,
+}
+
+/// What an import file looks like before any rows are imported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportInspection {
+    pub delimiter: String,
+    pub columns: Vec<ImportColumn>,
+    /// Best-guess `{ field: column }` mapping
+    pub mapping: BTreeMap<&'static str, String>,
+    /// Columns that match no field, or a field an earlier column already took
+    pub unmapped_columns: Vec<String>,
+    pub missing_required: Vec<&'static str>,
+    pub sample_rows: Vec<Vec<String>>,
+}
+
+/// Read the header and first rows of `path` and guess the column mapping
+pub fn inspect_import_file(path: &Path) -> Result<ImportInspection, ImportError> {
+    inspect_import(&read_import_file(path)?)
+}
+
+/// Parse `path` into import rows, see [`import_users`]
+pub fn import_users_file(
+    path: &Path,
+    mapping: Option<&HashMap<String, String>>,
+) -> Result<Vec<Value>, ImportError> {
+    import_users(&read_import_file(path)?, mapping)
+}
+
+fn read_import_file(path: &Path) -> Result<String, ImportError> {
+    std::fs::read_to_string(path).map_err(|source| ImportError::Read {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+/// Parse CSV rows into import user objects keyed by field name
+///
+/// `mapping` is `{ field: column }`; without one the header aliases are
+/// used. Columns match case-insensitively and unmapped columns are ignored.
+/// A `providerId` that is not a number is kept as text so validation can
+/// report it; an empty one becomes `null`.
+pub fn import_users(
+    contents: &str,
+    mapping: Option<&HashMap<String, String>>,
+) -> Result<Vec<Value>, ImportError> {
+    let (_, records) = parse_records(contents)?;
+    let mut records = records.into_iter();
+    let columns = records.next().ok_or(ImportError::Empty)?;
+
+    let fields: Vec<(&'static str, usize)> = match mapping {
+        Some(mapping) => mapping
+            .iter()
+            .filter(|(_, column)| !column.trim().is_empty())
+            .map(|(field, column)| {
+                let field = IMPORT_FIELDS
+                    .into_iter()
+                    .find(|known| known == field)
+                    .ok_or_else(|| ImportError::UnknownField(field.clone()))?;
+                let index = columns
+                    .iter()
+                    .position(|name| name.to_lowercase() == column.trim().to_lowercase())
+                    .ok_or_else(|| ImportError::UnknownColumn {
+                        field: field.to_string(),
+                        column: column.clone(),
+                    })?;
+                Ok((field, index))
+            })
+            .collect::<Result<_, ImportError>>()?,
+        None => {
+            let mut fields: Vec<(&'static str, usize)> = Vec::new();
+            for (index, name) in columns.iter().enumerate() {
+                if let Some(field) = field_for_column(name) {
+                    if !fields.iter().any(|(taken, _)| *taken == field) {
+                        fields.push((field, index));
+                    }
+                }
+            }
+            fields
+        }
+    };
+
+    let unmapped: Vec<&'static str> = REQUIRED_FIELDS
+        .into_iter()
+        .filter(|required| !fields.iter().any(|(field, _)| field == required))
+        .collect();
+    if !unmapped.is_empty() {
+        return Err(ImportError::UnmappedRequired(unmapped));
+    }
+
+    Ok(records
+        .map(|values| {
+            let mut user = Map::new();
+            for (field, index) in &fields {
+                let value = values.get(*index).cloned().unwrap_or_default();
+                let value = match *field {
+                    "providerId" if value.is_empty() => Value::Null,
+                    "providerId" => value
+                        .parse::<i64>()
+                        .map(Value::from)
+                        .unwrap_or(Value::String(value)),
+                    _ => Value::String(value),
+                };
+                user.insert(field.to_string(), value);
+            }
+            Value::Object(user)
+        })
+        .collect())
+}
+
+pub fn inspect_import(contents: &str) -> Result<ImportInspection, ImportError> {
+    let (delimiter, records) = parse_records(contents)?;
+    let mut records = records.into_iter();
+    let header = records.next().ok_or(ImportError::Empty)?;
+
+    let mut mapping = BTreeMap::new();
+    let mut unmapped_columns = Vec::new();
+    let columns: Vec<ImportColumn> = header
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| {
+            let field = field_for_column(&name).filter(|field| !mapping.contains_key(field));
+            match field {
+                Some(field) => {
+                    mapping.insert(field, name.clone());
+                }
+                None => unmapped_columns.push(name.clone()),
+            }
+            ImportColumn { index, name, field }
+        })
+        .collect();
+
+    let missing_required = REQUIRED_FIELDS
+        .into_iter()
+        .filter(|field| !mapping.contains_key(field))
+        .collect();
+    let sample_rows = records.take(SAMPLE_ROWS).collect();
+
+    Ok(ImportInspection {
+        delimiter: delimiter.to_string(),
+        columns,
+        mapping,
+        unmapped_columns,
+        missing_required,
+        sample_rows,
+    })
+}
+
+/// Import field a header name matches, ignoring case, spaces, `_` and `-`
+fn field_for_column(name: &str) -> Option<&'static str> {
+    let normalized: String = name
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, '_' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect();
+
+    COLUMN_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == normalized)
+        .map(|(_, field)| *field)
+}
+
+/// The delimiter that splits the header into the most columns
+fn detect_delimiter(header: &str) -> char {
+    DELIMITERS
+        .into_iter()
+        .max_by_key(|delimiter| {
+            // Reversed position so the earlier delimiter wins a tie
+            let rank =
+                DELIMITERS.len() - DELIMITERS.iter().position(|d| d == delimiter).unwrap_or(0);
+            (header.matches(*delimiter).count(), rank)
+        })
+        .unwrap_or(',')
+}
+
+/// The delimiter and records of an import file, header first
+///
+/// The delimiter is detected on the first non-blank line.
+fn parse_records(contents: &str) -> Result<(char, Vec<Vec<String>>), ImportError> {
+    let contents = contents.trim_start_matches('\u{feff}');
+    let header = contents
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .ok_or(ImportError::Empty)?;
+    let delimiter = detect_delimiter(header);
+    Ok((delimiter, read_records(contents, delimiter)))
+}
+
+/// Split CSV text into records of trimmed fields
+///
+/// Quoted fields may hold the delimiter, doubled quotes and line breaks, as in
+/// RFC 4180. Records with no content, such as blank lines, are skipped.
+fn read_records(contents: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => {
+                record.push(field.trim().to_string());
+                field.clear();
+            }
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                record.push(field.trim().to_string());
+                field.clear();
+                push_record(&mut records, std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    record.push(field.trim().to_string());
+    push_record(&mut records, record);
+    records
+}
+
+fn push_record(records: &mut Vec<Vec<String>>, record: Vec<String>) {
+    if record.iter().any(|field| !field.is_empty()) {
+        records.push(record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_well_formed_header_maps_every_column() {
+        let inspection = inspect_import(
+            "\u{feff}UPN;Full Name;Email;CardId;PIN;OTP;ProviderId\n\
+             jdoe@example.com;\"Doe; John\";jdoe@example.com;1111;1234;X7K2;3\n",
+        )
+        .unwrap();
+
+        assert_eq!(inspection.delimiter, ";");
+        assert_eq!(
+            inspection.mapping.keys().copied().collect::<Vec<_>>(),
+            vec![
+                "cardId",
+                "email",
+                "fullName",
+                "otp",
+                "providerId",
+                "shortId",
+                "userName"
+            ]
+        );
+        assert_eq!(inspection.mapping["fullName"], "Full Name");
+        assert!(inspection.unmapped_columns.is_empty());
+        assert!(inspection.missing_required.is_empty());
+        assert_eq!(inspection.sample_rows[0][1], "Doe; John");
+    }
+
+    #[test]
+    fn test_extra_columns_are_reported_unmapped() {
+        let inspection = inspect_import(
+            "username,email,Cost Center,e-mail\n\
+             jdoe,jdoe@example.com,4410,other@example.com\n",
+        )
+        .unwrap();
+
+        assert_eq!(inspection.delimiter, ",");
+        assert_eq!(inspection.mapping["email"], "email");
+        assert_eq!(inspection.unmapped_columns, vec!["Cost Center", "e-mail"]);
+        assert_eq!(inspection.columns[2].field, None);
+        assert_eq!(inspection.columns[0].field, Some("userName"));
+    }
+
+    #[test]
+    fn test_missing_required_column_is_flagged() {
+        let rows: String = (0..10)
+            .map(|i| format!("User {i}\tuser{i}@example.com\n"))
+            .collect();
+        let inspection = inspect_import(&format!("Name\tEmail\n{rows}")).unwrap();
+
+        assert_eq!(inspection.delimiter, "\t");
+        assert_eq!(inspection.missing_required, vec!["userName"]);
+        assert_eq!(inspection.sample_rows.len(), SAMPLE_ROWS);
+
+        assert!(matches!(inspect_import("\n  \n"), Err(ImportError::Empty)));
+    }
+
+    #[test]
+    fn test_custom_mapping_reads_renamed_columns() {
+        let mapping = HashMap::from([
+            ("userName".to_string(), "Employee ID".to_string()),
+            ("email".to_string(), "employee email".to_string()),
+            ("providerId".to_string(), "Directory".to_string()),
+            ("fullName".to_string(), String::new()),
+        ]);
+        let users = import_users(
+            "Employee ID,Employee Email,Name,Directory\n\
+             E100,e100@example.com,Jane Doe,3\n\
+             E101,e101@example.com,John Doe,\n",
+            Some(&mapping),
+        )
+        .unwrap();
+
+        assert_eq!(
+            users[0],
+            serde_json::json!({"userName": "E100", "email": "e100@example.com", "providerId": 3})
+        );
+        assert_eq!(users[1]["providerId"], Value::Null);
+        assert!(users[1].get("fullName").is_none());
+    }
+
+    #[test]
+    fn test_quoted_fields_may_span_lines() {
+        let users = import_users(
+            "username,fullname,email\r\n\
+             jdoe,\"Doe,\r\nJohn \"\"JD\"\"\",jdoe@example.com\r\n\
+             \r\n\
+             asmith,Anna Smith,asmith@example.com",
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0]["fullName"], "Doe,\r\nJohn \"JD\"");
+        assert_eq!(users[0]["email"], "jdoe@example.com");
+        assert_eq!(users[1]["userName"], "asmith");
+    }
+
+    #[test]
+    fn test_non_numeric_provider_id_is_kept_for_validation() {
+        let users = import_users("user;provider\njdoe;Local\nasmith;4\n", None).unwrap();
+
+        assert_eq!(users[0]["providerId"], "Local");
+        assert_eq!(users[1]["providerId"], 4);
+        assert_eq!(
+            crate::batch::validate_user_fields(&users[0]),
+            vec![crate::batch::FieldError::new(
+                "providerId",
+                crate::batch::PROVIDER_ID_NOT_A_NUMBER
+            )]
+        );
+    }
+
+    #[test]
+    fn test_incomplete_mapping_errors() {
+        let contents = "Employee ID,Employee Email\nE100,e100@example.com\n";
+        let mapping = HashMap::from([("email".to_string(), "Employee Email".to_string())]);
+        assert!(matches!(
+            import_users(contents, Some(&mapping)),
+            Err(ImportError::UnmappedRequired(fields)) if fields == vec!["userName"]
+        ));
+
+        let mapping = HashMap::from([("userName".to_string(), "Staff No".to_string())]);
+        assert!(matches!(
+            import_users(contents, Some(&mapping)),
+            Err(ImportError::UnknownColumn { .. })
+        ));
+
+        let mapping = HashMap::from([("department".to_string(), "Employee ID".to_string())]);
+        assert!(matches!(
+            import_users(contents, Some(&mapping)),
+            Err(ImportError::UnknownField(_))
+        ));
+
+        let users = import_users("upn;email\njdoe;jdoe@example.com\n", None).unwrap();
+        assert_eq!(users[0]["userName"], "jdoe");
+    }
+}
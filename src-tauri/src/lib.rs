@@ -1,54 +1,192 @@
+mod audit;
+mod batch;
+mod bulk;
+mod diagnostics;
 mod email;
+mod eml;
+mod error;
+mod export;
 mod generator;
+mod http_client;
+mod i18n;
+mod import;
+mod mock;
+mod rate_limit;
+mod reconcile;
 mod safeq_api;
+mod secrets;
 mod settings;
+mod template;
+mod text;
+mod trace;
 mod url_utils;
+mod window;
 
-use tauri::Manager;
+use std::collections::HashSet;
+
+use error::AppError;
+use safeq_api::SafeQApiError;
+use tauri::{Emitter, Manager};
+use tauri_plugin_fs::FsExt;
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable controlling the log level, e.g. `SQC_LOG=debug`
+const LOG_ENV_VAR: &str = "SQC_LOG";
 
 #[tauri::command]
-fn get_safeq_settings(app: tauri::AppHandle) -> Result<Option<settings::SafeQSettings>, String> {
-    settings::load_safeq_settings(&app).map_err(|error| error.to_string())
+fn get_safeq_settings(app: tauri::AppHandle) -> Result<Option<settings::SafeQSettings>, AppError> {
+    settings::load_safeq_settings(&app).map_err(AppError::from)
 }
 
 #[tauri::command]
-async fn list_safeq_users(app: tauri::AppHandle) -> Result<serde_json::Value, String> {
-    let client = safeq_api::SafeQClient::from_store(&app).map_err(|error| error.to_string())?;
+async fn list_safeq_users(app: tauri::AppHandle) -> Result<serde_json::Value, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
 
-    client.list_users().await.map_err(|error| error.to_string())
+    client.list_users().await.map_err(AppError::from)
 }
 
 #[tauri::command]
-async fn list_auth_providers(app: tauri::AppHandle) -> Result<serde_json::Value, String> {
-    let client = safeq_api::SafeQClient::from_store(&app).map_err(|error| error.to_string())?;
+async fn get_account_info(app: tauri::AppHandle) -> Result<serde_json::Value, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
 
-    client
-        .list_auth_providers()
+    client.get_account().await.map_err(AppError::from)
+}
+
+/// Auth providers for the account, served from cache within the configured TTL
+#[tauri::command]
+async fn list_auth_providers(
+    app: tauri::AppHandle,
+    force_refresh: Option<bool>,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
+
+    app.state::<safeq_api::ProviderCache>()
+        .get_or_fetch(
+            &client,
+            safeq_api::provider_cache_ttl(&settings),
+            force_refresh.unwrap_or(false),
+        )
         .await
-        .map_err(|error| error.to_string())
+        .map_err(AppError::from)
+}
+
+/// `[{ providerId, providerName, count }]` for every auth provider
+#[tauri::command]
+async fn count_users_by_provider(
+    app: tauri::AppHandle,
+) -> Result<Vec<safeq_api::ProviderUserCount>, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
+
+    Ok(client.count_users_by_provider().await?)
 }
 
 #[tauri::command]
 async fn list_users_for_provider(
     app: tauri::AppHandle,
     provider_id: i64,
-) -> Result<serde_json::Value, String> {
-    let client = safeq_api::SafeQClient::from_store(&app).map_err(|error| error.to_string())?;
+) -> Result<serde_json::Value, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
 
     client
         .list_users_for_provider(provider_id)
         .await
-        .map_err(|error| error.to_string())
+        .map_err(AppError::from)
 }
 
+/// A provider's users with only the requested fields, to keep the payload small
+#[tauri::command]
+async fn list_users_projected(
+    app: tauri::AppHandle,
+    provider_id: i64,
+    fields: Vec<String>,
+) -> Result<Vec<serde_json::Value>, AppError> {
+    let fields = safeq_api::projection_fields(&fields).map_err(|unknown| {
+        AppError::validation(format!("unknown user fields: {}", unknown.join(", ")))
+    })?;
+    if fields.is_empty() {
+        return Err(AppError::validation("select at least one user field"));
+    }
+    let client = safeq_api::SafeQClient::from_store(&app)?;
+
+    client
+        .list_users_projected(provider_id, &fields)
+        .await
+        .map_err(AppError::from)
+}
+
+/// Write a provider's users to a CSV file page by page; returns the row count
+#[tauri::command]
+async fn export_users_csv(
+    app: tauri::AppHandle,
+    provider_id: i64,
+    path: String,
+) -> Result<usize, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
+    let mut options = tauri_plugin_fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    let file = app
+        .fs()
+        .open(std::path::PathBuf::from(path), options)
+        .map_err(export::ExportError::Io)?;
+    let mut out = std::io::BufWriter::new(file);
+
+    let rows = export::export_users_csv(&client, provider_id, &mut out).await?;
+    info!(provider_id, rows, "users exported");
+    Ok(rows)
+}
+
+/// Show what a tenant URL normalizes to and the base URL requests would use
+#[tauri::command]
+fn normalize_and_preview_url(input: String, api_port: Option<u16>) -> url_utils::UrlPreview {
+    url_utils::UrlUtils::preview(&input, api_port.unwrap_or(settings::DEFAULT_API_PORT))
+}
+
+#[tauri::command]
+async fn search_users(
+    app: tauri::AppHandle,
+    provider_id: Option<i64>,
+    query: String,
+) -> Result<serde_json::Value, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
+
+    client
+        .search_users(provider_id, &query)
+        .await
+        .map_err(AppError::from)
+}
+
+/// Error messages for `locale` by `messageKey`, with English for anything untranslated
+#[tauri::command]
+fn get_message_catalog(locale: String) -> std::collections::BTreeMap<String, String> {
+    i18n::catalog(&locale)
+}
+
+/// Every SAFEQ user detail type as `[{ id, name }]`
+#[tauri::command]
+fn list_user_detail_types() -> serde_json::Value {
+    safeq_api::UserDetailType::list_json()
+}
+
+/// Set a user's card id; `None` or an empty value removes the card
 #[tauri::command]
 async fn update_user_card(
     app: tauri::AppHandle,
     username: String,
     provider_id: Option<i64>,
     card_id: Option<String>,
-) -> Result<serde_json::Value, String> {
-    let client = safeq_api::SafeQClient::from_store(&app).map_err(|error| error.to_string())?;
+    check_card_uniqueness: Option<bool>,
+) -> Result<serde_json::Value, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
+
+    if let Some(card) = card_id.as_deref().filter(|card| !card.trim().is_empty()) {
+        if check_card_uniqueness.unwrap_or(false) {
+            client
+                .check_card_uniqueness(&username, provider_id, card)
+                .await?;
+        }
+    }
 
     client
         .update_user_detail(
@@ -58,37 +196,139 @@ async fn update_user_card(
             card_id.as_deref(),
         )
         .await
-        .map_err(|error| error.to_string())
+        .map_err(AppError::from)
 }
 
+/// Whether a user has a PIN, OTP, card and so on, without the values
+#[tauri::command]
+async fn get_credential_status(
+    app: tauri::AppHandle,
+    username: String,
+    provider_id: Option<i64>,
+) -> Result<Option<safeq_api::CredentialStatus>, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
+
+    client
+        .get_credential_status(&username, provider_id)
+        .await
+        .map_err(AppError::from)
+}
+
+/// Set one user detail and read it back to confirm SAFEQ stored it
+#[tauri::command]
+async fn set_and_verify_detail(
+    app: tauri::AppHandle,
+    username: String,
+    provider_id: Option<i64>,
+    detail_type: i32,
+    detail_data: Option<String>,
+) -> Result<safeq_api::DetailVerification, AppError> {
+    let detail = safeq_api::UserDetailType::from_id(detail_type)
+        .ok_or_else(|| AppError::validation(format!("unknown detail type {detail_type}")))?;
+    let client = safeq_api::SafeQClient::from_store(&app)?;
+
+    client
+        .set_and_verify_detail(&username, provider_id, detail, detail_data.as_deref())
+        .await
+        .map_err(AppError::from)
+}
+
+/// Assign card ids in bulk from `{ userName, providerId, cardId }` rows
+#[tauri::command]
+async fn bulk_update_cards(
+    app: tauri::AppHandle,
+    rows: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
+
+    let summary = bulk::update_cards(&client, &rows, bulk::max_concurrency(&settings)).await;
+    info!(
+        success = summary.success(),
+        failed = summary.failed(),
+        "bulk card update finished"
+    );
+
+    record_audit(
+        &app,
+        &settings,
+        audit::AuditOperation::CardUpdated,
+        summary.results(),
+    );
+
+    Ok(summary.to_json())
+}
+
+/// Remove one detail type, by `list_user_detail_types` id, from every given user
+#[tauri::command]
+async fn bulk_clear_detail(
+    app: tauri::AppHandle,
+    users: Vec<serde_json::Value>,
+    detail_type: i32,
+) -> Result<serde_json::Value, AppError> {
+    let detail = safeq_api::UserDetailType::from_id(detail_type)
+        .ok_or_else(|| AppError::validation(format!("unknown detail type {detail_type}")))?;
+    if !detail.is_clearable() {
+        return Err(AppError::validation(format!(
+            "{} can't be cleared in bulk",
+            detail.name()
+        )));
+    }
+
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
+
+    let summary =
+        bulk::clear_detail(&client, &users, detail, bulk::max_concurrency(&settings)).await;
+    info!(
+        detail = detail.name(),
+        success = summary.success(),
+        failed = summary.failed(),
+        "bulk detail clear finished"
+    );
+
+    record_audit(
+        &app,
+        &settings,
+        audit::AuditOperation::DetailCleared,
+        summary.results(),
+    );
+
+    Ok(summary.to_json())
+}
+
+/// Set a user's short ID, which SAFEQ keeps as the OTP detail (detailtype=10)
+///
+/// `None` or an empty value removes it.
 #[tauri::command]
 async fn update_user_short_id(
     app: tauri::AppHandle,
     username: String,
     provider_id: Option<i64>,
     short_id: Option<String>,
-) -> Result<serde_json::Value, String> {
-    let client = safeq_api::SafeQClient::from_store(&app).map_err(|error| error.to_string())?;
+) -> Result<serde_json::Value, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
 
     client
         .update_user_detail(
             &username,
             provider_id,
-            safeq_api::UserDetailType::Otp, // Short ID uses detailtype=6 (OTP)
+            safeq_api::UserDetailType::Otp,
             short_id.as_deref(),
         )
         .await
-        .map_err(|error| error.to_string())
+        .map_err(AppError::from)
 }
 
+/// Set a user's PIN; `None` or an empty value revokes it
 #[tauri::command]
 async fn update_user_pin(
     app: tauri::AppHandle,
     username: String,
     provider_id: Option<i64>,
     pin: Option<String>,
-) -> Result<serde_json::Value, String> {
-    let client = safeq_api::SafeQClient::from_store(&app).map_err(|error| error.to_string())?;
+) -> Result<serde_json::Value, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
 
     client
         .update_user_detail(
@@ -98,7 +338,24 @@ async fn update_user_pin(
             pin.as_deref(),
         )
         .await
-        .map_err(|error| error.to_string())
+        .map_err(AppError::from)
+}
+
+/// Suspend (`enabled: false`) or re-enable a user without deleting them
+#[tauri::command]
+async fn set_user_enabled(
+    app: tauri::AppHandle,
+    username: String,
+    provider_id: Option<i64>,
+    enabled: bool,
+) -> Result<serde_json::Value, AppError> {
+    let client = safeq_api::SafeQClient::from_store(&app)?;
+
+    let result = client
+        .set_user_enabled(&username, provider_id, enabled)
+        .await?;
+    info!(%username, enabled, "user enabled state changed");
+    Ok(result)
 }
 
 #[tauri::command]
@@ -106,18 +363,17 @@ async fn generate_user_pin(
     app: tauri::AppHandle,
     username: String,
     provider_id: Option<i64>,
-) -> Result<serde_json::Value, String> {
-    let settings = settings::load_safeq_settings(&app)
-        .map_err(|error| error.to_string())?
-        .ok_or("Settings not configured")?;
+) -> Result<serde_json::Value, AppError> {
+    // Often called once per row from the UI, so skip re-reading the store
+    let settings =
+        settings::load_cached_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
 
-    let client = safeq_api::SafeQClient::from_settings(settings.clone())
-        .map_err(|error| error.to_string())?;
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
 
     client
         .generate_pin(&username, provider_id, &settings)
         .await
-        .map_err(|error| error.to_string())
+        .map_err(AppError::from)
 }
 
 #[tauri::command]
@@ -125,111 +381,190 @@ async fn generate_user_otp(
     app: tauri::AppHandle,
     username: String,
     provider_id: Option<i64>,
-) -> Result<serde_json::Value, String> {
-    let settings = settings::load_safeq_settings(&app)
-        .map_err(|error| error.to_string())?
-        .ok_or("Settings not configured")?;
+) -> Result<serde_json::Value, AppError> {
+    let settings =
+        settings::load_cached_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
 
-    let client = safeq_api::SafeQClient::from_settings(settings.clone())
-        .map_err(|error| error.to_string())?;
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
 
     client
         .generate_otp(&username, provider_id, &settings)
         .await
-        .map_err(|error| error.to_string())
+        .map_err(AppError::from)
 }
 
 #[tauri::command]
 async fn generate_bulk_pins(
     app: tauri::AppHandle,
     users: Vec<serde_json::Value>,
-) -> Result<serde_json::Value, String> {
-    let settings = settings::load_safeq_settings(&app)
-        .map_err(|error| error.to_string())?
-        .ok_or("Settings not configured")?;
-
-    let client = safeq_api::SafeQClient::from_settings(settings.clone())
-        .map_err(|error| error.to_string())?;
-
-    let mut success_count = 0;
-    let mut failed_count = 0;
-    let mut results: Vec<serde_json::Value> = Vec::new();
-
-    for user in users {
-        let username = user["userName"].as_str().unwrap_or("");
-        let provider_id = user["providerId"].as_i64();
-
-        match client.generate_pin(username, provider_id, &settings).await {
-            Ok(result) => {
-                success_count += 1;
-                results.push(serde_json::json!({
-                    "user": user,
-                    "success": true,
-                    "value": result["pin"]
-                }));
-            }
-            Err(e) => {
-                failed_count += 1;
-                results.push(serde_json::json!({
-                    "user": user,
-                    "success": false,
-                    "error": e.to_string()
-                }));
-            }
-        }
-    }
+    validate_providers: Option<bool>,
+    unique_pins: Option<bool>,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
 
-    Ok(serde_json::json!({
-        "success": success_count,
-        "failed": failed_count,
-        "results": results
-    }))
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
+
+    // Drawn up front so a batch that can't get unique PINs fails before any is set
+    let pins = if unique_pins.unwrap_or(false) {
+        Some(unique_pins_per_provider(&settings, &users)?)
+    } else {
+        None
+    };
+
+    let unknown_providers =
+        unknown_provider_rows(&client, &users, validate_providers.unwrap_or(false)).await?;
+    let summary: bulk::BulkSummary =
+        bulk::run_concurrent(users.len(), bulk::max_concurrency(&settings), |index| {
+            generate_pin_row(
+                &client,
+                &settings,
+                &users[index],
+                pins.as_ref().map(|pins| pins[index].as_str()),
+                unknown_providers.contains(&index),
+            )
+        })
+        .await
+        .into_iter()
+        .collect();
+
+    info!(
+        success = summary.success(),
+        failed = summary.failed(),
+        "bulk PIN generation finished"
+    );
+
+    record_audit(
+        &app,
+        &settings,
+        audit::AuditOperation::PinGenerated,
+        summary.results(),
+    );
+
+    Ok(summary.to_json())
 }
 
 #[tauri::command]
 async fn generate_bulk_otps(
     app: tauri::AppHandle,
     users: Vec<serde_json::Value>,
-) -> Result<serde_json::Value, String> {
-    let settings = settings::load_safeq_settings(&app)
-        .map_err(|error| error.to_string())?
-        .ok_or("Settings not configured")?;
-
-    let client = safeq_api::SafeQClient::from_settings(settings.clone())
-        .map_err(|error| error.to_string())?;
-
-    let mut success_count = 0;
-    let mut failed_count = 0;
-    let mut results: Vec<serde_json::Value> = Vec::new();
-
-    for user in users {
-        let username = user["userName"].as_str().unwrap_or("");
-        let provider_id = user["providerId"].as_i64();
-
-        match client.generate_otp(username, provider_id, &settings).await {
-            Ok(result) => {
-                success_count += 1;
-                results.push(serde_json::json!({
-                    "user": user,
-                    "success": true,
-                    "value": result["otp"]
-                }));
-            }
-            Err(e) => {
-                failed_count += 1;
-                results.push(serde_json::json!({
-                    "user": user,
-                    "success": false,
-                    "error": e.to_string()
-                }));
-            }
-        }
+    validate_providers: Option<bool>,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
+
+    let unknown_providers =
+        unknown_provider_rows(&client, &users, validate_providers.unwrap_or(false)).await?;
+    let summary = generate_otp_rows(&client, &settings, &users, &unknown_providers).await;
+
+    info!(
+        success = summary.success(),
+        failed = summary.failed(),
+        "bulk OTP generation finished"
+    );
+
+    record_audit(
+        &app,
+        &settings,
+        audit::AuditOperation::OtpGenerated,
+        summary.results(),
+    );
+
+    Ok(summary.to_json())
+}
+
+/// Give each user a new PIN and OTP, one request per user
+///
+/// Takes the same `validate_providers` and `unique_pins` switches as
+/// `generate_bulk_pins`.
+#[tauri::command]
+async fn generate_bulk_credentials(
+    app: tauri::AppHandle,
+    users: Vec<serde_json::Value>,
+    validate_providers: Option<bool>,
+    unique_pins: Option<bool>,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
+
+    // Drawn up front so a batch that can't get unique PINs fails before any is set
+    let pins = if unique_pins.unwrap_or(false) {
+        Some(unique_pins_per_provider(&settings, &users)?)
+    } else {
+        None
+    };
+
+    let unknown_providers =
+        unknown_provider_rows(&client, &users, validate_providers.unwrap_or(false)).await?;
+    let summary = bulk::generate_credentials(
+        &client,
+        &settings,
+        &users,
+        pins.as_deref(),
+        &unknown_providers,
+        bulk::max_concurrency(&settings),
+    )
+    .await;
+    info!(
+        success = summary.success(),
+        failed = summary.failed(),
+        "bulk PIN and OTP generation finished"
+    );
+
+    for operation in [
+        audit::AuditOperation::PinGenerated,
+        audit::AuditOperation::OtpGenerated,
+    ] {
+        record_audit(&app, &settings, operation, summary.results());
     }
 
+    Ok(summary.to_json())
+}
+
+/// Compare fetched SAFEQ users with a desired roster for reconciliation
+#[tauri::command]
+fn diff_users(current: serde_json::Value, desired: Vec<serde_json::Value>) -> serde_json::Value {
+    reconcile::diff_users(&current, &desired)
+}
+
+/// Read an import file's header and first rows and guess the column mapping
+#[tauri::command]
+fn inspect_import_file(path: String) -> Result<import::ImportInspection, AppError> {
+    Ok(import::inspect_import_file(std::path::Path::new(&path))?)
+}
+
+/// Parse an import file into user rows, optionally with a `{ field: column }` mapping
+#[tauri::command]
+fn import_users_csv(
+    path: String,
+    mapping: Option<std::collections::HashMap<String, String>>,
+) -> Result<Vec<serde_json::Value>, AppError> {
+    Ok(import::import_users_file(
+        std::path::Path::new(&path),
+        mapping.as_ref(),
+    )?)
+}
+
+/// Parse CSV text, e.g. a file dropped on the import page, using the header aliases
+#[tauri::command]
+fn parse_import_csv(contents: String) -> Result<Vec<serde_json::Value>, AppError> {
+    Ok(import::import_users(&contents, None)?)
+}
+
+/// Dry-run the import checks, comparing usernames as `lowercase_usernames` would send them
+#[tauri::command]
+fn validate_users(
+    app: tauri::AppHandle,
+    users: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.unwrap_or_default();
+    let results = batch::validate_users(&users, settings.lowercase_usernames);
+    let valid_count = results.iter().filter(|row| row["valid"] == true).count();
+
     Ok(serde_json::json!({
-        "success": success_count,
-        "failed": failed_count,
-        "results": results
+        "valid": valid_count,
+        "invalid": results.len() - valid_count,
+        "results": results,
     }))
 }
 
@@ -239,192 +574,1286 @@ async fn create_users(
     users: Vec<serde_json::Value>,
     auto_generate_pin: bool,
     auto_generate_otp: bool,
-) -> Result<serde_json::Value, String> {
-    let settings = settings::load_safeq_settings(&app)
-        .map_err(|error| error.to_string())?
-        .ok_or("Settings not configured")?;
-
-    let client = safeq_api::SafeQClient::from_settings(settings.clone())
-        .map_err(|error| error.to_string())?;
-
-    let mut success_count = 0;
-    let mut failed_count = 0;
-    let mut results: Vec<serde_json::Value> = Vec::new();
-
-    for user in users {
-        let username = user["userName"].as_str().unwrap_or("");
-        let provider_id = user["providerId"].as_i64();
-        let full_name = user["fullName"].as_str();
-        let email = user["email"].as_str();
-        let card_id = user["cardId"].as_str();
-        let mut short_id = user["shortId"].as_str().map(|s| s.to_string());
-        let mut otp = user["otp"].as_str().map(|s| s.to_string());
-
-        // Auto-generate PIN if requested and empty
-        if auto_generate_pin && short_id.as_ref().map_or(true, |s| s.is_empty()) {
-            short_id = Some(safeq_api::generate_pin_value(&settings));
-        }
+    rollback_on_partial: Option<bool>,
+    validate_providers: Option<bool>,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
 
-        // Auto-generate OTP if requested and empty
-        if auto_generate_otp && otp.as_ref().map_or(true, |s| s.is_empty()) {
-            otp = Some(safeq_api::generate_otp_value(&settings));
-        }
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
+    let unknown_providers =
+        unknown_provider_rows(&client, &users, validate_providers.unwrap_or(false)).await?;
+
+    let options = CreateOptions {
+        auto_generate_pin,
+        auto_generate_otp,
+        rollback_on_partial: rollback_on_partial.unwrap_or(false),
+    };
+    // Shared across chunks so uniqueness holds for the whole import
+    let generated = GeneratedCredentials::for_batch(&users);
+    let rejections =
+        RowRejections::for_batch(&users, settings.lowercase_usernames, unknown_providers);
+    let summary = bulk::run_chunked(
+        users.len(),
+        bulk::ChunkPlan::from_settings(&settings),
+        |rows| {
+            create_user_rows(
+                &client,
+                &settings,
+                &users,
+                rows,
+                options,
+                &generated,
+                &rejections,
+            )
+        },
+        |progress| {
+            if let Err(err) = app.emit(CREATE_USERS_PROGRESS_EVENT, progress) {
+                tracing::warn!(error = %err, "failed to emit create progress");
+            }
+        },
+    )
+    .await;
+
+    info!(
+        success = summary.success(),
+        failed = summary.failed(),
+        "bulk user creation finished"
+    );
+
+    record_audit(
+        &app,
+        &settings,
+        audit::AuditOperation::UserCreated,
+        summary.results(),
+    );
+
+    Ok(summary.to_json())
+}
+
+/// Rows with a `providerId` the account does not have, when the opt-in check is on
+///
+/// Costs one extra request for the provider list, so it only runs on demand.
+async fn unknown_provider_rows(
+    client: &safeq_api::SafeQClient,
+    users: &[serde_json::Value],
+    validate: bool,
+) -> Result<HashSet<usize>, AppError> {
+    if !validate {
+        return Ok(HashSet::new());
+    }
+
+    let known = client.provider_ids().await?;
+    Ok(batch::unknown_provider_indices(users, &known))
+}
+
+/// Generate and assign an OTP for each row, reporting the value as `value`
+async fn generate_otp_rows(
+    client: &safeq_api::SafeQClient,
+    settings: &settings::SafeQSettings,
+    users: &[serde_json::Value],
+    unknown_providers: &HashSet<usize>,
+) -> bulk::BulkSummary {
+    bulk::run_concurrent(users.len(), bulk::max_concurrency(settings), |index| {
+        generate_otp_row(
+            client,
+            settings,
+            &users[index],
+            unknown_providers.contains(&index),
+        )
+    })
+    .await
+    .into_iter()
+    .collect()
+}
+
+/// One PIN per user, never repeated among users of the same provider
+fn unique_pins_per_provider(
+    settings: &settings::SafeQSettings,
+    users: &[serde_json::Value],
+) -> Result<Vec<String>, AppError> {
+    let pin_settings = safeq_api::pin_settings(settings);
+    let providers: Vec<Option<i64>> = users
+        .iter()
+        .map(|user| user["providerId"].as_i64())
+        .collect();
+
+    generator::generate_unique_per_group(&providers, pin_settings.value_space(), || {
+        safeq_api::generate_pin_value(settings)
+    })
+    .map_err(|count| {
+        AppError::validation(format!(
+            "{count} users of one provider need unique PINs, but {}-digit PINs only allow {}. Increase the PIN length.",
+            pin_settings.length,
+            pin_settings.value_space()
+        ))
+    })
+}
+
+/// Assign `pin` to the user, or a freshly generated one when `None`
+async fn generate_pin_row(
+    client: &safeq_api::SafeQClient,
+    settings: &settings::SafeQSettings,
+    user: &serde_json::Value,
+    pin: Option<&str>,
+    unknown_provider: bool,
+) -> serde_json::Value {
+    if unknown_provider {
+        return serde_json::json!({
+            "user": user,
+            "success": false,
+            "error": batch::UNKNOWN_PROVIDER
+        });
+    }
+
+    let username = user["userName"].as_str().unwrap_or("");
+    let provider_id = user["providerId"].as_i64();
 
-        match client
-            .create_user(
+    let result = match pin {
+        Some(pin) => client
+            .update_user_detail(
                 username,
                 provider_id,
-                full_name,
-                email,
-                card_id,
-                short_id.as_deref(),
-                otp.as_deref(),
+                safeq_api::UserDetailType::Pin,
+                Some(pin),
             )
             .await
-        {
-            Ok(_) => {
-                success_count += 1;
-                let mut result_json = serde_json::json!({
-                    "user": {
-                        "userName": username,
-                        "fullName": full_name,
-                        "email": email,
-                        "providerId": provider_id,
-                    },
-                    "success": true,
+            .map(|_| serde_json::json!({ "pin": pin })),
+        None => client.generate_pin(username, provider_id, settings).await,
+    };
+
+    match result {
+        Ok(result) => serde_json::json!({
+            "user": user,
+            "success": true,
+            "value": result["pin"]
+        }),
+        Err(e) => serde_json::json!({
+            "user": user,
+            "success": false,
+            "error": e.to_string()
+        }),
+    }
+}
+
+async fn generate_otp_row(
+    client: &safeq_api::SafeQClient,
+    settings: &settings::SafeQSettings,
+    user: &serde_json::Value,
+    unknown_provider: bool,
+) -> serde_json::Value {
+    if unknown_provider {
+        return serde_json::json!({
+            "user": user,
+            "success": false,
+            "error": batch::UNKNOWN_PROVIDER
+        });
+    }
+
+    let username = user["userName"].as_str().unwrap_or("");
+    let provider_id = user["providerId"].as_i64();
+
+    match client.generate_otp(username, provider_id, settings).await {
+        Ok(result) => serde_json::json!({
+            "user": user,
+            "success": true,
+            "value": result["otp"]
+        }),
+        Err(e) => serde_json::json!({
+            "user": user,
+            "success": false,
+            "error": e.to_string()
+        }),
+    }
+}
+
+/// Event carrying a `bulk::ChunkProgress` after each chunk of `create_users`
+const CREATE_USERS_PROGRESS_EVENT: &str = "create-users-progress";
+
+/// Credentials used so far in one create batch, so generated ones stay distinct
+#[derive(Debug)]
+struct GeneratedCredentials {
+    pins: generator::UniqueTracker,
+    otps: generator::UniqueTracker,
+}
+
+impl GeneratedCredentials {
+    /// Trackers seeded with the PINs and OTPs that rows of `users` supply
+    /// themselves, so no generated value repeats one of them
+    fn for_batch(users: &[serde_json::Value]) -> Self {
+        let supplied = |field: &str| {
+            users
+                .iter()
+                .filter_map(|user| user[field].as_str())
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        Self {
+            pins: generator::UniqueTracker::with_used(supplied("shortId")),
+            otps: generator::UniqueTracker::with_used(supplied("otp")),
+        }
+    }
+}
+
+/// Rows of a batch that fail locally, without contacting SAFEQ
+///
+/// Found once for the whole batch, so a chunked import neither rescans it per
+/// chunk nor lets a repeat in a later chunk through.
+#[derive(Debug, Default)]
+struct RowRejections {
+    duplicate_users: HashSet<usize>,
+    duplicate_cards: HashSet<usize>,
+    unknown_providers: HashSet<usize>,
+}
+
+impl RowRejections {
+    fn for_batch(
+        users: &[serde_json::Value],
+        lowercase_usernames: bool,
+        unknown_providers: HashSet<usize>,
+    ) -> Self {
+        Self {
+            duplicate_users: batch::duplicate_user_indices(users, lowercase_usernames),
+            duplicate_cards: batch::duplicate_card_indices(users),
+            unknown_providers,
+        }
+    }
+
+    /// Why row `index` is rejected; repeats are reported before unknown providers
+    fn error_for(&self, index: usize) -> Option<batch::FieldError> {
+        if self.duplicate_users.contains(&index) {
+            Some(batch::FieldError::new(
+                "userName",
+                batch::DUPLICATE_IN_BATCH,
+            ))
+        } else if self.duplicate_cards.contains(&index) {
+            Some(batch::FieldError::new(
+                "cardId",
+                batch::DUPLICATE_CARD_IN_BATCH,
+            ))
+        } else if self.unknown_providers.contains(&index) {
+            Some(batch::FieldError::new(
+                "providerId",
+                batch::UNKNOWN_PROVIDER,
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// Per-batch switches for `create_user_rows`
+#[derive(Debug, Clone, Copy, Default)]
+struct CreateOptions {
+    auto_generate_pin: bool,
+    auto_generate_otp: bool,
+    rollback_on_partial: bool,
+}
+
+/// Create the import rows in `rows`, returning one result per row in input order
+///
+/// With `rollback_on_partial`, a user whose creation fails with an error status
+/// is deleted again so SAFEQ never keeps an account with only some of its
+/// details applied. Only users looked up as absent before their create are
+/// rolled back, and never after a transport error, so an account that predates
+/// this batch is never deleted. Rows in `rejections` and rows failing field
+/// validation fail locally without contacting SAFEQ, and name the offending
+/// `field` in their result.
+async fn create_user_rows(
+    client: &safeq_api::SafeQClient,
+    settings: &settings::SafeQSettings,
+    users: &[serde_json::Value],
+    rows: std::ops::Range<usize>,
+    options: CreateOptions,
+    generated: &GeneratedCredentials,
+    rejections: &RowRejections,
+) -> Vec<serde_json::Value> {
+    bulk::run_concurrent(rows.len(), bulk::max_concurrency(settings), |offset| {
+        let index = rows.start + offset;
+        let user = &users[index];
+        let local_error = rejections
+            .error_for(index)
+            .or_else(|| batch::validate_user_fields(user).into_iter().next());
+
+        create_user_row(client, settings, user, options, generated, local_error)
+    })
+    .await
+}
+
+/// Create one import row unless it already failed local validation
+async fn create_user_row(
+    client: &safeq_api::SafeQClient,
+    settings: &settings::SafeQSettings,
+    user: &serde_json::Value,
+    options: CreateOptions,
+    generated: &GeneratedCredentials,
+    local_error: Option<batch::FieldError>,
+) -> serde_json::Value {
+    if let Some(error) = local_error {
+        return batch::invalid_row(user, &error);
+    }
+
+    let Some(username) = batch::normalize_username(
+        user["userName"].as_str().unwrap_or(""),
+        settings.lowercase_usernames,
+    ) else {
+        return batch::invalid_row(
+            user,
+            &batch::FieldError::new("userName", batch::USERNAME_REQUIRED),
+        );
+    };
+    let provider_id = user["providerId"].as_i64();
+    let full_name = user["fullName"].as_str();
+    let email = user["email"].as_str();
+    let home_folder = user["homeFolder"].as_str();
+    let password = user["password"].as_str();
+    let card_id = user["cardId"].as_str();
+    let mut short_id = user["shortId"].as_str().map(|s| s.to_string());
+    let mut otp = user["otp"].as_str().map(|s| s.to_string());
+
+    // Auto-generate PIN if requested and empty, never repeating one from this batch
+    if options.auto_generate_pin && short_id.as_ref().is_none_or(|s| s.is_empty()) {
+        let space = safeq_api::pin_settings(settings).value_space();
+        let Some(pin) = generated
+            .pins
+            .next(space, || safeq_api::generate_pin_value(settings))
+        else {
+            let error = batch::FieldError::new("shortId", batch::PINS_EXHAUSTED);
+            return batch::invalid_row(user, &error);
+        };
+        short_id = Some(pin);
+    }
+
+    // Auto-generate OTP if requested and empty
+    if options.auto_generate_otp && otp.as_ref().is_none_or(|s| s.is_empty()) {
+        let space = safeq_api::otp_settings(settings).value_space();
+        let Some(value) = generated
+            .otps
+            .next(space, || safeq_api::generate_otp_value(settings))
+        else {
+            let error = batch::FieldError::new("otp", batch::OTPS_EXHAUSTED);
+            return batch::invalid_row(user, &error);
+        };
+        otp = Some(value);
+    }
+
+    // Rollback may only remove a user this call creates; when the lookup fails
+    // it's unknown whether the user predates the batch, so nothing is rolled back
+    let may_roll_back = options.rollback_on_partial
+        && matches!(client.get_user(&username, provider_id).await, Ok(None));
+
+    match client
+        .create_user(
+            &username,
+            provider_id,
+            full_name,
+            email,
+            home_folder,
+            password,
+            card_id,
+            short_id.as_deref(),
+            otp.as_deref(),
+        )
+        .await
+    {
+        Ok(_) => {
+            let mut result_json = serde_json::json!({
+                "user": batch::user_summary(user),
+                "success": true,
+            });
+            // Include generated credentials in the result
+            if let Some(pin_value) = &short_id {
+                result_json["pin"] = serde_json::json!(pin_value);
+            }
+            if let Some(otp_value) = &otp {
+                result_json["otp"] = serde_json::json!(otp_value);
+            }
+            result_json
+        }
+        Err(err) => {
+            let mut result_json = batch::failed_row(user, &err.to_string());
+            if may_roll_back && err.may_have_created_user() {
+                let outcome = client.rollback_user(&username, provider_id).await;
+                result_json["rollback"] = outcome.to_json();
+            }
+            result_json
+        }
+    }
+}
+
+/// Write each prepared message as an `.eml` draft in `directory` for desktop delivery
+///
+/// Returns the written file paths in message order.
+#[tauri::command]
+fn generate_email_drafts(
+    app: tauri::AppHandle,
+    messages: Vec<email::PreparedEmailPayload>,
+    directory: String,
+) -> Result<Vec<String>, AppError> {
+    let from = settings::load_safeq_settings(&app)?
+        .and_then(|settings| settings.email_settings.draft_from);
+
+    let paths = eml::write_drafts(
+        &app,
+        std::path::Path::new(&directory),
+        &messages,
+        from.as_deref(),
+    )
+    .map_err(|err| AppError::new("internal", format!("Failed to write email drafts: {err}")))?;
+    info!(drafts = paths.len(), "email drafts written");
+
+    Ok(paths
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Create users and email each one the chosen credential in a single pass
+///
+/// Graph delivery sends directly. Desktop delivery cannot open mail drafts from
+/// the backend, so the rendered messages are returned for the UI to open.
+#[tauri::command]
+async fn create_and_notify_users(
+    app: tauri::AppHandle,
+    users: Vec<serde_json::Value>,
+    auto_generate_pin: bool,
+    auto_generate_otp: bool,
+    credential: email::CredentialType,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
+
+    let options = CreateOptions {
+        auto_generate_pin,
+        auto_generate_otp,
+        rollback_on_partial: false,
+    };
+    let results = create_user_rows(
+        &client,
+        &settings,
+        &users,
+        0..users.len(),
+        options,
+        &GeneratedCredentials::for_batch(&users),
+        &RowRejections::for_batch(&users, settings.lowercase_usernames, HashSet::new()),
+    )
+    .await;
+
+    record_audit(
+        &app,
+        &settings,
+        audit::AuditOperation::UserCreated,
+        &results,
+    );
+
+    // Render an email for every user that was created
+    let tokens: Vec<_> = results
+        .iter()
+        .map(|result| {
+            (result["success"] == true).then(|| {
+                template::TemplateTokens::from_user(
+                    &result["user"],
+                    result["pin"].as_str(),
+                    result["otp"].as_str(),
+                )
+            })
+        })
+        .collect();
+    let outcomes = notify_rows(&app, &settings, &users, tokens, credential).await;
+
+    let summary = batch::combine_create_and_notify(results, outcomes);
+
+    info!(
+        created = summary["created"].as_u64(),
+        failed = summary["failed"].as_u64(),
+        emailed = summary["emailed"].as_u64(),
+        email_failed = summary["emailFailed"].as_u64(),
+        "create and notify finished"
+    );
+
+    Ok(summary)
+}
+
+/// Generate an OTP for each user and email it in the same pass
+///
+/// Generation and delivery failures are counted separately. A user whose OTP
+/// was set but whose email failed is flagged `undelivered` in the results.
+#[tauri::command]
+async fn generate_and_email_otps(
+    app: tauri::AppHandle,
+    users: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+
+    let client = safeq_api::SafeQClient::cached(&app, &settings)?;
+
+    let summary = generate_otp_rows(&client, &settings, &users, &HashSet::new()).await;
+    record_audit(
+        &app,
+        &settings,
+        audit::AuditOperation::OtpGenerated,
+        summary.results(),
+    );
+
+    let tokens: Vec<_> = summary
+        .results()
+        .iter()
+        .map(|result| {
+            (result["success"] == true).then(|| {
+                template::TemplateTokens::from_user(&result["user"], None, result["value"].as_str())
+            })
+        })
+        .collect();
+    let outcomes = notify_rows(&app, &settings, &users, tokens, email::CredentialType::Otp).await;
+
+    let summary = batch::combine_generate_and_notify(summary.results().to_vec(), outcomes);
+
+    info!(
+        generated = summary["generated"].as_u64(),
+        generate_failed = summary["generateFailed"].as_u64(),
+        emailed = summary["emailed"].as_u64(),
+        email_failed = summary["emailFailed"].as_u64(),
+        "generate and email OTPs finished"
+    );
+
+    Ok(summary)
+}
+
+/// Render and deliver the credential email for every row with tokens
+///
+/// `tokens` is aligned with `users`; rows without tokens get no outcome. Graph
+/// messages are sent and audited here, desktop messages come back as drafts.
+async fn notify_rows(
+    app: &tauri::AppHandle,
+    settings: &settings::SafeQSettings,
+    users: &[serde_json::Value],
+    tokens: Vec<Option<template::TemplateTokens>>,
+    credential: email::CredentialType,
+) -> Vec<Option<batch::NotifyOutcome>> {
+    let mut outcomes: Vec<Option<batch::NotifyOutcome>> = vec![None; tokens.len()];
+    let mut messages = Vec::new();
+    let mut message_rows = Vec::new();
+
+    for (index, tokens) in tokens.into_iter().enumerate() {
+        let Some(tokens) = tokens else {
+            continue;
+        };
+
+        match email::prepare_credential_email(
+            &settings.email_settings,
+            credential,
+            users[index]["templateKey"].as_str(),
+            &tokens,
+        ) {
+            Ok(message) => {
+                message_rows.push(index);
+                messages.push(message);
+            }
+            Err(error) => outcomes[index] = Some(batch::NotifyOutcome::Failed(error)),
+        }
+    }
+
+    match settings.email_settings.method {
+        settings::EmailDeliveryMethod::Graph => {
+            let sent: Vec<Option<String>> =
+                match email::send_graph_emails(settings, &messages).await {
+                    Ok(summary) => summary.outcomes,
+                    Err(error) => vec![Some(error.to_string()); messages.len()],
+                };
+
+            let entries: Vec<audit::AuditEntry> = messages
+                .iter()
+                .zip(&sent)
+                .map(|(message, error)| {
+                    audit::AuditEntry::email(
+                        settings.operator_id.as_deref(),
+                        &message.to,
+                        error.as_deref(),
+                    )
+                })
+                .collect();
+            audit::record(app, &entries);
+
+            for (row, error) in message_rows.into_iter().zip(sent) {
+                outcomes[row] = Some(match error {
+                    None => batch::NotifyOutcome::Sent,
+                    Some(error) => batch::NotifyOutcome::Failed(error),
                 });
-                // Include generated credentials in the result
-                if let Some(pin_value) = &short_id {
-                    result_json["pin"] = serde_json::json!(pin_value);
-                }
-                if let Some(otp_value) = &otp {
-                    result_json["otp"] = serde_json::json!(otp_value);
-                }
-                results.push(result_json);
             }
-            Err(err) => {
-                failed_count += 1;
-                results.push(serde_json::json!({
-                    "user": {
-                        "userName": username,
-                        "fullName": full_name,
-                        "email": email,
-                        "providerId": provider_id,
-                    },
-                    "success": false,
-                    "error": err.to_string(),
-                }));
+        }
+        settings::EmailDeliveryMethod::Desktop => {
+            for (row, message) in message_rows.into_iter().zip(messages) {
+                outcomes[row] = Some(batch::NotifyOutcome::Draft(serde_json::json!(message)));
             }
         }
     }
 
-    Ok(serde_json::json!({
-        "success": success_count,
-        "failed": failed_count,
-        "results": results,
-    }))
+    outcomes
 }
 
 #[tauri::command]
 async fn send_graph_emails(
     app: tauri::AppHandle,
     messages: Vec<email::PreparedEmailPayload>,
-) -> Result<serde_json::Value, String> {
-    let settings = settings::load_safeq_settings(&app)
-        .map_err(|error| error.to_string())?
-        .ok_or("Settings not configured")?;
+) -> Result<serde_json::Value, AppError> {
+    send_and_audit(&app, &messages).await
+}
 
-    let summary = email::send_graph_emails(&settings.email_settings, &messages)
-        .await
-        .map_err(|error| error.to_string())?;
+/// Send the `failedMessages` of an earlier batch again
+///
+/// Only the messages Graph did not accept are passed back, so recipients that
+/// already got their email are not mailed twice.
+#[tauri::command]
+async fn resend_failed(
+    app: tauri::AppHandle,
+    messages: Vec<email::PreparedEmailPayload>,
+) -> Result<serde_json::Value, AppError> {
+    info!(count = messages.len(), "resending failed emails");
+    send_and_audit(&app, &messages).await
+}
+
+/// Send messages through Graph and audit each outcome
+///
+/// Returns `{ success, failed, errors, failedIndices, failedMessages }`; every
+/// error starts with its recipient address.
+async fn send_and_audit(
+    app: &tauri::AppHandle,
+    messages: &[email::PreparedEmailPayload],
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(app)?.ok_or(SafeQApiError::MissingSettings)?;
+
+    let summary = email::send_graph_emails(&settings, messages).await?;
+
+    let entries: Vec<audit::AuditEntry> = messages
+        .iter()
+        .zip(&summary.outcomes)
+        .map(|(message, error)| {
+            audit::AuditEntry::email(
+                settings.operator_id.as_deref(),
+                &message.to,
+                error.as_deref(),
+            )
+        })
+        .collect();
+    audit::record(app, &entries);
 
     Ok(serde_json::json!({
         "success": summary.success,
         "failed": summary.failed,
         "errors": summary.errors,
+        "failedIndices": summary.failed_indices,
+        "failedMessages": summary.failed_messages(messages),
     }))
 }
 
 #[tauri::command]
-async fn close_splashscreen(app: tauri::AppHandle) -> Result<(), String> {
-    let main_window = if let Some(main_window) = app.get_webview_window("main") {
-        println!("Main window already exists, showing it");
-        // Main window already exists, just show it
-        main_window.show().map_err(|e| e.to_string())?;
-        main_window
+fn get_secret_settings() -> Result<settings::SecretSettings, AppError> {
+    Ok(settings::load_secret_settings()?)
+}
+
+#[tauri::command]
+fn save_secret_settings(
+    app: tauri::AppHandle,
+    secrets: settings::SecretSettings,
+) -> Result<(), AppError> {
+    settings::save_secret_settings(&secrets)?;
+    settings_changed(&app);
+    Ok(())
+}
+
+/// Drop everything derived from the previous settings
+fn settings_changed(app: &tauri::AppHandle) {
+    app.state::<settings::SettingsCache>().invalidate();
+    app.state::<safeq_api::ClientCache>().invalidate();
+    app.state::<safeq_api::ProviderCache>().invalidate();
+}
+
+/// Validate and store the settings form, returning the settings as saved
+#[tauri::command]
+fn save_safeq_settings(
+    app: tauri::AppHandle,
+    settings: settings::SafeQSettings,
+) -> Result<settings::SafeQSettings, AppError> {
+    let saved = settings::save_safeq_settings(&app, &settings)?;
+    settings_changed(&app);
+    Ok(saved)
+}
+
+/// Replace the API key after SAFEQ has accepted it, returning its account
+#[tauri::command]
+async fn rotate_api_key(
+    app: tauri::AppHandle,
+    new_key: String,
+) -> Result<serde_json::Value, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+
+    let account = safeq_api::rotate_api_key(&settings, &new_key, secrets::default_store()).await?;
+    settings_changed(&app);
+    Ok(account)
+}
+
+/// Clear the stored settings and return the defaults the UI should show
+#[tauri::command]
+fn reset_settings(app: tauri::AppHandle) -> Result<settings::SafeQSettings, AppError> {
+    let defaults = settings::reset_safeq_settings(&app)?;
+    settings_changed(&app);
+    info!("settings reset to defaults");
+    Ok(defaults)
+}
+
+#[tauri::command]
+fn export_settings(
+    app: tauri::AppHandle,
+    path: String,
+    include_secrets: bool,
+) -> Result<(), AppError> {
+    let settings =
+        settings::load_safeq_settings(&app)?.ok_or(settings::SettingsFileError::NotConfigured)?;
+
+    settings::export_settings_file(&settings, std::path::Path::new(&path), include_secrets)?;
+    info!(include_secrets, "settings exported");
+    Ok(())
+}
+
+/// Validate a settings file and replace the stored settings with it
+///
+/// With `merge` only the file's non-secret, non-empty fields are applied on
+/// top of the current settings.
+#[tauri::command]
+fn import_settings(
+    app: tauri::AppHandle,
+    path: String,
+    merge: bool,
+) -> Result<settings::SafeQSettings, AppError> {
+    let path = std::path::Path::new(&path);
+    let settings = if merge {
+        let imported = settings::import_settings_keys(path)?;
+        let current = settings::load_safeq_settings(&app)?.unwrap_or_default();
+        current
+            .merged_with(&imported)
+            .map_err(settings::SettingsFileError::Invalid)?
     } else {
-        // Create the main window
-        let main_url = if cfg!(dev) {
-            tauri::WebviewUrl::External("http://localhost:1420/".parse().unwrap())
-        } else {
-            tauri::WebviewUrl::App("index.html".into())
-        };
+        settings::import_settings_file(path)?
+    };
 
-        let window = tauri::WebviewWindowBuilder::new(&app, "main", main_url)
-            .title("SAFEQ Cloud User Manager")
-            .inner_size(1200.0, 800.0)
-            .center()
-            .build()
-            .map_err(|e| e.to_string())?;
+    let settings = settings::save_safeq_settings(&app, &settings)?;
+    settings_changed(&app);
+    info!(merge, "settings imported");
+    Ok(settings)
+}
 
-        window.show().map_err(|e| e.to_string())?;
-        window
+/// Report how guessable generated PINs and OTPs are
+///
+/// Uses `settings` when given, so the settings screen can check unsaved edits,
+/// and the saved settings otherwise.
+#[tauri::command]
+fn get_credential_strength(
+    app: tauri::AppHandle,
+    settings: Option<settings::SafeQSettings>,
+) -> Result<serde_json::Value, AppError> {
+    let settings = match settings {
+        Some(settings) => settings,
+        None => settings::load_safeq_settings(&app)?.unwrap_or_default(),
+    };
+
+    let strength = |bits: f64| {
+        serde_json::json!({
+            "bits": bits,
+            "weak": bits < generator::MIN_RECOMMENDED_ENTROPY_BITS,
+        })
     };
 
-    // Focus the main window
-    main_window.set_focus().map_err(|e| e.to_string())?;
+    Ok(serde_json::json!({
+        "thresholdBits": generator::MIN_RECOMMENDED_ENTROPY_BITS,
+        "pin": strength(safeq_api::pin_settings(&settings).entropy_bits()),
+        "otp": strength(safeq_api::otp_settings(&settings).entropy_bits()),
+    }))
+}
+
+/// PIN, OTP and short ID rules with defaults applied, for the settings screen
+#[tauri::command]
+fn get_effective_generation_settings(
+    app: tauri::AppHandle,
+) -> Result<settings::GenerationSettings, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.unwrap_or_default();
+    Ok(settings::GenerationSettings::resolve(&settings))
+}
+
+/// Warn about OTP and short ID rules that would silently fall back to digits
+#[tauri::command]
+fn validate_generation_settings(
+    settings: settings::SafeQSettings,
+) -> Vec<settings::GenerationWarning> {
+    settings::GenerationSettings::resolve(&settings).warnings()
+}
+
+/// Generate `count` distinct PINs for printing without assigning them to anyone
+#[tauri::command]
+fn preview_generate_pins(app: tauri::AppHandle, count: usize) -> Result<Vec<String>, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.unwrap_or_default();
+    let space = safeq_api::pin_settings(&settings).value_space();
+
+    generator::generate_unique(count, space, || safeq_api::generate_pin_value(&settings))
+        .ok_or_else(|| {
+            AppError::validation(format!(
+                "cannot generate {count} unique PINs; the configured length only allows {space}"
+            ))
+        })
+}
+
+/// Generate `count` distinct OTPs without assigning them to anyone
+#[tauri::command]
+fn preview_generate_otps(app: tauri::AppHandle, count: usize) -> Result<Vec<String>, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.unwrap_or_default();
+    let space = safeq_api::otp_settings(&settings).value_space();
+
+    generator::generate_unique(count, space, || safeq_api::generate_otp_value(&settings)).ok_or_else(
+        || AppError::validation(format!("cannot generate {count} unique OTPs; the configured length and characters only allow {space}")),
+    )
+}
+
+/// Generate `count` distinct short IDs without assigning them to anyone
+#[tauri::command]
+fn preview_generate_short_ids(
+    app: tauri::AppHandle,
+    count: usize,
+) -> Result<Vec<String>, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.unwrap_or_default();
+    let space = safeq_api::short_id_settings(&settings).value_space();
+
+    generator::generate_unique(count, space, || {
+        safeq_api::generate_short_id_value(&settings)
+    })
+    .ok_or_else(|| {
+        AppError::validation(format!(
+            "cannot generate {count} unique short IDs; the configured length and characters only allow {space}"
+        ))
+    })
+}
+
+/// Generate one throwaway value from `config`, ignoring the stored settings
+#[tauri::command]
+fn generate_value(config: generator::GeneratorConfig) -> Result<String, AppError> {
+    let settings = config.validate()?;
+    Ok(generator::generate_short_id(&settings))
+}
+
+#[tauri::command]
+fn read_audit_log(app: tauri::AppHandle) -> Result<Vec<audit::AuditEntry>, AppError> {
+    Ok(audit::read(&app)?)
+}
+
+/// Write audit entries between `from` and `to` (inclusive, either optional) to a CSV file
+#[tauri::command]
+fn export_audit_csv(
+    app: tauri::AppHandle,
+    path: String,
+    from: Option<chrono::DateTime<chrono::Utc>>,
+    to: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<audit::AuditCsvExport, AppError> {
+    let export = audit::export_csv_file(&app, std::path::Path::new(&path), from, to)?;
+    info!(
+        rows = export.rows,
+        skipped = export.skipped,
+        "audit log exported"
+    );
+    Ok(export)
+}
+
+/// Write one audit entry per bulk result row
+fn record_audit(
+    app: &tauri::AppHandle,
+    settings: &settings::SafeQSettings,
+    operation: audit::AuditOperation,
+    results: &[serde_json::Value],
+) {
+    let entries: Vec<audit::AuditEntry> = results
+        .iter()
+        .map(|row| audit::AuditEntry::from_result(operation, settings.operator_id.as_deref(), row))
+        .collect();
+    audit::record(app, &entries);
+}
+
+/// Format a user's freshly generated credentials for the clipboard
+///
+/// The copy is audited without the credential values.
+#[tauri::command]
+fn format_credentials(
+    app: tauri::AppHandle,
+    user: serde_json::Value,
+    pin: Option<String>,
+    otp: Option<String>,
+) -> Result<String, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.unwrap_or_default();
+
+    let tokens = template::TemplateTokens::from_user(&user, pin.as_deref(), otp.as_deref());
+    let template = settings
+        .clipboard_template
+        .as_deref()
+        .unwrap_or(template::DEFAULT_CLIPBOARD_TEMPLATE);
+    let formatted = template::format_credentials(template, &tokens);
+
+    record_audit(
+        &app,
+        &settings,
+        audit::AuditOperation::CredentialsCopied,
+        &[serde_json::json!({ "user": user, "success": true })],
+    );
+
+    Ok(formatted)
+}
+
+/// Render an email template against sample user data without sending it
+#[tauri::command]
+fn preview_email(
+    template: settings::EmailTemplateSettings,
+    sample: serde_json::Value,
+) -> email::EmailPreview {
+    email::preview_email(&template, &sample)
+}
+
+/// Request a Graph token with the saved credentials without sending any email
+#[tauri::command]
+async fn test_graph_token(app: tauri::AppHandle) -> Result<(), AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+    email::test_graph_token(&settings).await?;
+    Ok(())
+}
+
+/// Run the connectivity, auth and settings checks support asks for
+#[tauri::command]
+async fn run_diagnostics(app: tauri::AppHandle) -> diagnostics::DiagnosticsReport {
+    let report = diagnostics::run_diagnostics(
+        settings::load_safeq_settings(&app),
+        email::GRAPH_AUTHORITY_URL,
+        secrets::default_store(),
+    )
+    .await;
+    info!(passed = report.passed, "diagnostics finished");
+    report
+}
+
+/// Begin a device code sign-in for delegated Graph sends
+#[tauri::command]
+async fn start_graph_device_login(
+    app: tauri::AppHandle,
+) -> Result<email::DeviceCodePrompt, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+    Ok(email::start_device_login(&settings).await?)
+}
+
+/// Poll once for the device code sign-in started by `start_graph_device_login`
+#[tauri::command]
+async fn poll_graph_device_login(
+    app: tauri::AppHandle,
+    device_code: String,
+) -> Result<email::DeviceLoginStatus, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+    Ok(email::poll_device_login(&settings, &device_code).await?)
+}
+
+/// Check that Graph can resolve the configured sender mailbox
+#[tauri::command]
+async fn verify_sender_mailbox(
+    app: tauri::AppHandle,
+) -> Result<email::SenderMailboxStatus, AppError> {
+    let settings = settings::load_safeq_settings(&app)?.ok_or(SafeQApiError::MissingSettings)?;
+    Ok(email::verify_sender_mailbox(&settings).await?)
+}
+
+/// Check settings the way saving would, without saving them
+#[tauri::command]
+fn validate_settings(settings: settings::SafeQSettings) -> Result<(), AppError> {
+    Ok(settings::validate_settings(&settings)?)
+}
 
-    // Close the splashscreen window AFTER main window is shown
-    if let Some(splashscreen) = app.get_webview_window("splashscreen") {
-        splashscreen.close().map_err(|e| e.to_string())?;
+/// Open the main window, unless `require_valid_settings_on_startup` is set and
+/// the settings are invalid; the error is then returned for the splash to show
+#[tauri::command]
+async fn close_splashscreen(app: tauri::AppHandle) -> Result<(), AppError> {
+    if let Some(error) = window::startup_blocker(&app) {
+        warn!(%error, "settings are invalid; keeping the splash screen open");
+        return Err(error.into());
     }
+    window::show_main_window(&app)?;
+    Ok(())
+}
 
+/// Why startup is blocked, if it is, so the main window can open on the settings page
+#[tauri::command]
+fn get_startup_blocker(app: tauri::AppHandle) -> Option<AppError> {
+    window::startup_blocker(&app).map(AppError::from)
+}
+
+/// Open the main window even though startup is blocked, so the settings can be fixed
+#[tauri::command]
+async fn open_settings_despite_blocker(app: tauri::AppHandle) -> Result<(), AppError> {
+    info!("opening the main window to fix settings that block startup");
+    window::show_main_window(&app)?;
     Ok(())
 }
 
+/// Quit from the splash screen when startup is blocked
+#[tauri::command]
+fn quit_app(app: tauri::AppHandle) {
+    app.exit(0);
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_env(LOG_ENV_VAR).unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_store::Builder::default().build())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
+        .manage(window::MainWindowGuard::default())
+        .manage(settings::SettingsCache::default())
+        .manage(safeq_api::ClientCache::default())
+        .manage(safeq_api::ProviderCache::default())
         .setup(|app| {
-            // Create the splash screen window first
-            let splash_url = if cfg!(dev) {
-                tauri::WebviewUrl::External("http://localhost:1420/splash.html".parse().unwrap())
-            } else {
-                tauri::WebviewUrl::App("splash.html".into())
-            };
-
-            tauri::WebviewWindowBuilder::new(app, "splashscreen", splash_url)
-                .title("SAFEQ Cloud User Manager")
-                .inner_size(600.0, 400.0)
-                .resizable(false)
-                .decorations(false)
-                .always_on_top(true)
-                .skip_taskbar(true)
-                .center()
-                .build()?;
+            let handle = app.handle();
+            handle.manage(window::WindowConfig::load(handle));
+
+            // Invalid gated settings don't stop this; the main window then opens on
+            // its settings page, the only place to fix them
+            if window::skip_splash() {
+                info!("splash screen skipped");
+                window::show_main_window(handle)?;
+                return Ok(());
+            }
+
+            window::create_splashscreen(handle)?;
+
+            if let Some(timeout) = window::splash_timeout() {
+                window::schedule_splash_timeout(handle, timeout);
+            }
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             get_safeq_settings,
+            get_secret_settings,
+            save_secret_settings,
+            save_safeq_settings,
+            reset_settings,
+            rotate_api_key,
+            export_settings,
+            import_settings,
             list_safeq_users,
+            get_account_info,
             list_auth_providers,
+            list_user_detail_types,
+            get_message_catalog,
+            bulk_update_cards,
+            set_and_verify_detail,
+            get_credential_status,
+            bulk_clear_detail,
             list_users_for_provider,
+            list_users_projected,
+            count_users_by_provider,
+            search_users,
+            normalize_and_preview_url,
+            export_users_csv,
             update_user_card,
             update_user_short_id,
             update_user_pin,
+            set_user_enabled,
             generate_user_pin,
             generate_user_otp,
             generate_bulk_pins,
             generate_bulk_otps,
+            generate_bulk_credentials,
+            get_credential_strength,
+            get_effective_generation_settings,
+            validate_generation_settings,
+            preview_generate_pins,
+            preview_generate_otps,
+            preview_generate_short_ids,
+            generate_value,
+            validate_users,
+            inspect_import_file,
+            import_users_csv,
+            parse_import_csv,
+            diff_users,
             create_users,
+            create_and_notify_users,
+            generate_and_email_otps,
             send_graph_emails,
-            close_splashscreen
+            generate_email_drafts,
+            resend_failed,
+            test_graph_token,
+            run_diagnostics,
+            start_graph_device_login,
+            poll_graph_device_login,
+            verify_sender_mailbox,
+            preview_email,
+            format_credentials,
+            read_audit_log,
+            export_audit_csv,
+            close_splashscreen,
+            get_startup_blocker,
+            open_settings_despite_blocker,
+            quit_app,
+            validate_settings
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Run `create_user_rows` over every row against `server`
+    async fn create_rows(
+        server: &MockServer,
+        settings: settings::SafeQSettings,
+        users: &[serde_json::Value],
+        options: CreateOptions,
+    ) -> Vec<serde_json::Value> {
+        let settings = settings::SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "test-api-key".to_string(),
+            ..settings
+        };
+        let client = safeq_api::SafeQClient::from_settings(settings.clone()).unwrap();
+
+        create_user_rows(
+            &client,
+            &settings,
+            users,
+            0..users.len(),
+            options,
+            &GeneratedCredentials::for_batch(users),
+            &RowRejections::for_batch(users, settings.lowercase_usernames, HashSet::new()),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_rollback_only_undoes_users_created_by_the_batch() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [{ "userName": "jdoe", "cards": [] }]
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/users"))
+            .and(body_string_contains("username=jdoe"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("email rejected"))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/users"))
+            .and(body_string_contains("username=asmith"))
+            .respond_with(ResponseTemplate::new(409).set_body_string("card already assigned"))
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/v1/users/asmith"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let users = [
+            json!({ "userName": "jdoe", "providerId": 1, "email": "jdoe@example.com" }),
+            json!({ "userName": "asmith", "providerId": 1, "cardId": "1111" }),
+        ];
+        let options = CreateOptions {
+            rollback_on_partial: true,
+            ..CreateOptions::default()
+        };
+        let results = create_rows(&server, Default::default(), &users, options).await;
+
+        assert_eq!(results[0]["success"], false);
+        assert!(results[0].get("rollback").is_none());
+        assert_eq!(results[1]["rollback"]["status"], "deleted");
+    }
+
+    #[tokio::test]
+    async fn test_repeated_card_id_fails_locally() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/users"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let users = [
+            json!({ "userName": "jdoe", "cardId": "1111" }),
+            json!({ "userName": "asmith", "cardId": " 1111 " }),
+        ];
+        let results = create_rows(&server, Default::default(), &users, Default::default()).await;
+
+        assert_eq!(results[0]["success"], true);
+        assert_eq!(results[1]["success"], false);
+        assert_eq!(results[1]["field"], "cardId");
+        assert_eq!(results[1]["reason"], batch::DUPLICATE_CARD_IN_BATCH);
+    }
+
+    #[tokio::test]
+    async fn test_generated_otps_skip_supplied_ones_and_fail_when_exhausted() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/users"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        // Single-digit OTPs from "01" leave only "1" once a row supplies "0"
+        let settings = settings::SafeQSettings {
+            otp_length: Some(1),
+            otp_use_uppercase: Some(false),
+            otp_use_lowercase: Some(false),
+            otp_use_numbers: Some(true),
+            otp_use_special: Some(false),
+            otp_exclude_characters: Some("23456789".to_string()),
+            ..settings::SafeQSettings::default()
+        };
+        let users = [
+            json!({ "userName": "jdoe", "otp": "0" }),
+            json!({ "userName": "asmith" }),
+            json!({ "userName": "bwong" }),
+        ];
+        let options = CreateOptions {
+            auto_generate_otp: true,
+            ..CreateOptions::default()
+        };
+        let results = create_rows(&server, settings, &users, options).await;
+
+        assert_eq!(results[0]["otp"], "0");
+        let generated: Vec<_> = results[1..]
+            .iter()
+            .filter(|row| row["success"] == true)
+            .collect();
+        assert_eq!(generated.len(), 1);
+        assert_eq!(generated[0]["otp"], "1");
+        let exhausted: Vec<_> = results[1..]
+            .iter()
+            .filter(|row| row["field"] == "otp")
+            .collect();
+        assert_eq!(exhausted.len(), 1);
+    }
+}
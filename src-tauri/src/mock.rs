@@ -0,0 +1,139 @@
+use reqwest::Method;
+use serde_json::{json, Value};
+
+use crate::safeq_api::{ACCOUNT_PATH, AUTH_PROVIDERS_PATH, LIST_ALL_USERS_PATH, UPDATE_USER_PATH};
+
+/// Base URL reported by clients in mock mode; it is never contacted
+pub const MOCK_BASE_URL: &str = "https://mock.safeq.invalid";
+
+const MOCK_ACCOUNT_ID: i64 = 1000;
+const LOCAL_PROVIDER_ID: i64 = 1;
+const ENTRA_PROVIDER_ID: i64 = 2;
+
+/// Canned answer to a SAFEQ API call for demos and UI development
+///
+/// Reads return fixed demo data and writes always succeed, echoing the user
+/// they touched. Nothing is stored, so created users don't appear in lists.
+pub fn respond(method: &Method, path: &str, form: Option<&[(&str, String)]>) -> Value {
+    let (route, query) = path.split_once('?').unwrap_or((path, ""));
+
+    match (method.clone(), route) {
+        (Method::GET, ACCOUNT_PATH) => json!({ "id": MOCK_ACCOUNT_ID, "name": "Demo Company" }),
+        (Method::GET, AUTH_PROVIDERS_PATH) => providers(),
+        (Method::GET, LIST_ALL_USERS_PATH) => users(query_value(query, "providerid")),
+        (Method::PUT, UPDATE_USER_PATH) => json!({
+            "userName": form_value(form, "username"),
+            "providerId": form_value(form, "providerid").and_then(|id| id.parse::<i64>().ok()),
+        }),
+        (_, route) => match route
+            .strip_prefix(UPDATE_USER_PATH)
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            Some(username) => json!({ "userName": username }),
+            None => Value::Null,
+        },
+    }
+}
+
+fn providers() -> Value {
+    json!([
+        { "id": LOCAL_PROVIDER_ID, "name": "Local", "type": "local", "accountId": MOCK_ACCOUNT_ID },
+        { "id": ENTRA_PROVIDER_ID, "name": "Entra ID", "type": "azure", "accountId": MOCK_ACCOUNT_ID },
+    ])
+}
+
+fn users(provider_id: Option<&str>) -> Value {
+    let provider_id = provider_id
+        .and_then(|id| id.parse::<i64>().ok())
+        .unwrap_or(LOCAL_PROVIDER_ID);
+
+    let people: &[(&str, &str, &str)] = if provider_id == ENTRA_PROVIDER_ID {
+        &[
+            ("alice.smith@demo.example", "Alice Smith", "Finance"),
+            ("bob.jones@demo.example", "Bob Jones", "Sales"),
+            ("carol.white@demo.example", "Carol White", "IT"),
+        ]
+    } else {
+        &[
+            ("jdoe", "John Doe", "Facilities"),
+            ("reception", "Reception Desk", "Facilities"),
+        ]
+    };
+
+    let items: Vec<Value> = people
+        .iter()
+        .enumerate()
+        .map(|(index, (user_name, full_name, department))| {
+            let email = if user_name.contains('@') {
+                user_name.to_string()
+            } else {
+                format!("{user_name}@demo.example")
+            };
+            json!({
+                "id": provider_id * 100 + index as i64 + 1,
+                "userName": user_name,
+                "fullName": full_name,
+                "email": email,
+                "department": department,
+                "accountId": MOCK_ACCOUNT_ID,
+                "providerId": provider_id,
+                "cards": [format!("DEMO{:04}", provider_id * 100 + index as i64 + 1)],
+                "isExpired": false,
+            })
+        })
+        .collect();
+
+    json!({
+        "recordsOnPage": items.len(),
+        "items": items,
+        "nextPageToken": null,
+    })
+}
+
+fn query_value<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
+
+fn form_value(form: Option<&[(&str, String)]>, key: &str) -> Option<String> {
+    form?
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users_are_listed_per_provider() {
+        let local = respond(&Method::GET, "api/v1/users/all?providerid=1", None);
+        assert_eq!(local["items"][0]["userName"], "jdoe");
+        assert_eq!(local["recordsOnPage"], 2);
+
+        let entra = respond(&Method::GET, "api/v1/users/all?providerid=2", None);
+        assert_eq!(entra["items"].as_array().unwrap().len(), 3);
+        assert!(entra["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|user| user["providerId"] == 2));
+    }
+
+    #[test]
+    fn test_writes_echo_the_user() {
+        let form = [
+            ("username", "newuser".to_string()),
+            ("providerid", "1".to_string()),
+        ];
+        let created = respond(&Method::PUT, "api/v1/users", Some(&form));
+        assert_eq!(created, json!({ "userName": "newuser", "providerId": 1 }));
+
+        let updated = respond(&Method::POST, "api/v1/users/jdoe", None);
+        assert_eq!(updated, json!({ "userName": "jdoe" }));
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Outbound services that are limited independently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    SafeQ,
+    Graph,
+}
+
+/// Limiters shared by every client of a backend, so the cap holds across
+/// commands and concurrent batches
+static LIMITERS: Mutex<Vec<(Backend, Arc<RateLimiter>)>> = Mutex::new(Vec::new());
+
+/// Token bucket allowing `per_second` requests per second
+///
+/// The bucket holds one second's worth of tokens, so an idle backend may see
+/// a short burst before requests are paced. A caller that finds it empty
+/// reserves the next token and sleeps until it is due, so waiting callers are
+/// served in order.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: u32,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while callers are waiting for tokens they already reserved
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32) -> Self {
+        let per_second = per_second.max(1);
+        Self {
+            per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(per_second),
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn per_second(&self) -> u32 {
+        self.per_second
+    }
+
+    /// Wait until one more request may be sent
+    pub async fn acquire(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Take a token and return how long to wait before it is usable
+    fn reserve(&self) -> Duration {
+        let rate = f64::from(self.per_second);
+        let mut bucket = self.bucket.lock().unwrap_or_else(|err| err.into_inner());
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.refilled = now;
+        bucket.tokens -= 1.0;
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+/// The process-wide limiter for `backend`, or `None` when it is unlimited
+///
+/// The limiter is kept while `per_second` stays the same and replaced when
+/// the setting changes.
+pub fn shared(backend: Backend, per_second: Option<u32>) -> Option<Arc<RateLimiter>> {
+    let mut limiters = LIMITERS.lock().unwrap_or_else(|err| err.into_inner());
+    shared_in(&mut limiters, backend, per_second)
+}
+
+fn shared_in(
+    limiters: &mut Vec<(Backend, Arc<RateLimiter>)>,
+    backend: Backend,
+    per_second: Option<u32>,
+) -> Option<Arc<RateLimiter>> {
+    let existing = limiters.iter().position(|(other, _)| *other == backend);
+
+    let Some(per_second) = per_second.filter(|rate| *rate > 0) else {
+        if let Some(index) = existing {
+            limiters.remove(index);
+        }
+        return None;
+    };
+
+    match existing {
+        Some(index) if limiters[index].1.per_second() == per_second => {
+            Some(limiters[index].1.clone())
+        }
+        Some(index) => {
+            let limiter = Arc::new(RateLimiter::new(per_second));
+            limiters[index].1 = limiter.clone();
+            Some(limiter)
+        }
+        None => {
+            let limiter = Arc::new(RateLimiter::new(per_second));
+            limiters.push((backend, limiter.clone()));
+            Some(limiter)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_is_paced_to_the_configured_rate() {
+        let limiter = RateLimiter::new(20);
+        let started = Instant::now();
+
+        // 20 tokens are available at once; the other 10 arrive at 20 per second
+        join_all((0..30).map(|_| limiter.acquire())).await;
+        let elapsed = started.elapsed();
+
+        assert!(elapsed >= Duration::from_millis(499), "{elapsed:?}");
+        assert!(elapsed <= Duration::from_millis(501), "{elapsed:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_bucket_refills_up_to_one_second() {
+        let limiter = RateLimiter::new(5);
+        join_all((0..5).map(|_| limiter.acquire())).await;
+
+        // A long pause refills only one second's worth of tokens
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let started = Instant::now();
+        join_all((0..6).map(|_| limiter.acquire())).await;
+
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(199), "{elapsed:?}");
+        assert!(elapsed <= Duration::from_millis(201), "{elapsed:?}");
+    }
+
+    #[test]
+    fn test_shared_limiter_is_reused_per_backend_until_the_rate_changes() {
+        let mut limiters = Vec::new();
+        let first = shared_in(&mut limiters, Backend::Graph, Some(5)).unwrap();
+        let again = shared_in(&mut limiters, Backend::Graph, Some(5)).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+
+        let safeq = shared_in(&mut limiters, Backend::SafeQ, Some(5)).unwrap();
+        assert!(!Arc::ptr_eq(&first, &safeq));
+
+        let changed = shared_in(&mut limiters, Backend::Graph, Some(7)).unwrap();
+        assert!(!Arc::ptr_eq(&first, &changed));
+        assert_eq!(changed.per_second(), 7);
+
+        assert!(shared_in(&mut limiters, Backend::Graph, None).is_none());
+        assert!(shared_in(&mut limiters, Backend::SafeQ, Some(0)).is_none());
+        assert!(limiters.is_empty());
+    }
+}
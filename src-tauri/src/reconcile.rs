@@ -0,0 +1,177 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{json, Value};
+
+use crate::safeq_api::users_in;
+
+/// Fields compared between a SAFEQ user and its desired row
+const COMPARED_FIELDS: [&str; 3] = ["fullName", "email", "cardId"];
+
+/// Compare SAFEQ users against a desired roster, matching on `userName`
+///
+/// Usernames are matched case-insensitively after trimming. `current` may be a
+/// `{ "items": [...] }` response or a bare array. Returns
+/// `{ toCreate, toUpdate, toDelete }`: desired rows missing from SAFEQ, matched
+/// users whose fields differ along with each `{ field, current, desired }`
+/// change, and SAFEQ users absent from the roster.
+///
+/// A field missing or blank in the desired row is not compared, so a roster
+/// without card ids never reports card changes.
+pub fn diff_users(current: &Value, desired: &[Value]) -> Value {
+    let current_users = users_in(current);
+
+    let by_name: HashMap<String, &Value> = current_users
+        .iter()
+        .filter_map(|user| Some((user_key(user)?, user)))
+        .collect();
+
+    let mut wanted = HashSet::new();
+    let mut to_create = Vec::new();
+    let mut to_update = Vec::new();
+
+    for row in desired {
+        let Some(key) = user_key(row) else {
+            continue;
+        };
+        wanted.insert(key.clone());
+
+        match by_name.get(&key) {
+            None => to_create.push(row.clone()),
+            Some(user) => {
+                let changes = field_changes(user, row);
+                if !changes.is_empty() {
+                    to_update.push(json!({
+                        "userName": user["userName"],
+                        "user": row,
+                        "changes": changes,
+                    }));
+                }
+            }
+        }
+    }
+
+    let to_delete: Vec<&Value> = current_users
+        .iter()
+        .filter(|user| user_key(user).is_some_and(|key| !wanted.contains(&key)))
+        .collect();
+
+    json!({
+        "toCreate": to_create,
+        "toUpdate": to_update,
+        "toDelete": to_delete,
+    })
+}
+
+fn user_key(user: &Value) -> Option<String> {
+    let name = user["userName"].as_str()?.trim();
+    (!name.is_empty()).then(|| name.to_lowercase())
+}
+
+fn field_changes(user: &Value, row: &Value) -> Vec<Value> {
+    COMPARED_FIELDS
+        .iter()
+        .filter_map(|field| {
+            let desired = row[*field]
+                .as_str()
+                .map(str::trim)
+                .filter(|v| !v.is_empty())?;
+            let current = current_value(user, field);
+
+            let same = match (*field, current.as_deref()) {
+                // SAFEQ users can hold several cards; any match counts
+                ("cardId", _) => user_cards(user).any(|card| card == desired),
+                ("email", Some(current)) => current.eq_ignore_ascii_case(desired),
+                (_, Some(current)) => current == desired,
+                (_, None) => false,
+            };
+
+            (!same).then(|| {
+                json!({
+                    "field": field,
+                    "current": current,
+                    "desired": desired,
+                })
+            })
+        })
+        .collect()
+}
+
+fn current_value(user: &Value, field: &str) -> Option<String> {
+    if field == "cardId" {
+        let cards: Vec<&str> = user_cards(user).collect();
+        return (!cards.is_empty()).then(|| cards.join(", "));
+    }
+    user[field]
+        .as_str()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+fn user_cards(user: &Value) -> impl Iterator<Item = &str> {
+    user["cards"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn safeq_users() -> Value {
+        json!({
+            "items": [
+                { "userName": "jdoe", "fullName": "John Doe", "email": "john@example.com", "cards": ["1111"] },
+                { "userName": "asmith", "fullName": "Anna Smith", "email": "anna@example.com", "cards": [] },
+                { "userName": "leaver", "fullName": "Old Employee", "email": "old@example.com" },
+            ]
+        })
+    }
+
+    #[test]
+    fn test_diff_reports_missing_changed_and_extra_users() {
+        let desired = vec![
+            json!({ "userName": "JDoe", "fullName": "John Doe", "email": "JOHN@example.com", "cardId": "1111" }),
+            json!({ "userName": "asmith", "email": "anna.smith@example.com", "cardId": "2222" }),
+            json!({ "userName": "newhire", "fullName": "New Hire" }),
+        ];
+
+        let diff = diff_users(&safeq_users(), &desired);
+
+        assert_eq!(diff["toCreate"], json!([desired[2]]));
+        assert_eq!(diff["toDelete"].as_array().unwrap().len(), 1);
+        assert_eq!(diff["toDelete"][0]["userName"], "leaver");
+
+        let updates = diff["toUpdate"].as_array().unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0]["userName"], "asmith");
+        assert_eq!(
+            updates[0]["changes"],
+            json!([
+                { "field": "email", "current": "anna@example.com", "desired": "anna.smith@example.com" },
+                { "field": "cardId", "current": null, "desired": "2222" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_diff_ignores_fields_missing_from_roster() {
+        let desired = vec![json!({ "userName": "jdoe" })];
+        let diff = diff_users(&safeq_users(), &desired);
+
+        assert!(diff["toUpdate"].as_array().unwrap().is_empty());
+        assert!(diff["toCreate"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff_against_empty_roster_deletes_everyone() {
+        let users = safeq_users()["items"].clone();
+        let diff = diff_users(&users, &[]);
+
+        assert_eq!(diff["toDelete"].as_array().unwrap().len(), 3);
+        assert!(diff["toCreate"].as_array().unwrap().is_empty());
+    }
+}
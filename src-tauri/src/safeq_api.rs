@@ -1,24 +1,66 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 use crate::generator::{
     generate_pin as gen_pin, generate_short_id as gen_short_id, PinSettings, ShortIdSettings,
 };
-use crate::settings::{load_safeq_settings, SafeQSettings, SettingsLoadError};
-use crate::url_utils::UrlUtils;
-use reqwest::{Client, StatusCode};
-use serde_json::Value;
-use tauri::AppHandle;
-
-const USER_AGENT: &str = "SQC-User-Manager/0.1";
-const ACCOUNT_PATH: &str = "api/v1/account";
-const AUTH_PROVIDERS_PATH: &str = "api/v1/authproviders";
-const LIST_ALL_USERS_PATH: &str = "api/v1/users/all";
-const UPDATE_USER_PATH: &str = "api/v1/users";
-const DEFAULT_API_PORT: u16 = 7300;
+use crate::http_client::{
+    apply_min_tls, apply_proxy, is_tls_version_error, user_agent, PoolSettings,
+};
+use crate::mock;
+use crate::rate_limit::{self, Backend, RateLimiter};
+use crate::secrets::{SecretKey, SecretStore};
+use crate::settings::{
+    load_safeq_settings, AuthHeaderStyle, GenerationSettings, SafeQSettings, SettingsLoadError,
+    DEFAULT_API_PORT,
+};
+use crate::text::{error_body_limit, truncate};
+use crate::trace::{TraceEntry, TraceLog, TRACE_FILE};
+use crate::url_utils::{BaseUrlError, UrlUtils};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Certificate, Client, ClientBuilder, Method, StatusCode};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tauri::{AppHandle, Manager};
+use tracing::{debug, error, info, warn};
+
+pub(crate) const ACCOUNT_PATH: &str = "api/v1/account";
+pub(crate) const AUTH_PROVIDERS_PATH: &str = "api/v1/authproviders";
+pub(crate) const LIST_ALL_USERS_PATH: &str = "api/v1/users/all";
+pub(crate) const UPDATE_USER_PATH: &str = "api/v1/users";
+/// Fields of a `users/all` user that `list_users_projected` can return
+pub const USER_FIELDS: [&str; 14] = [
+    "id",
+    "userName",
+    "fullName",
+    "email",
+    "shortId",
+    "otp",
+    "department",
+    "accountId",
+    "providerId",
+    "groupIds",
+    "cards",
+    "token",
+    "isExpired",
+    "createdDate",
+];
+/// Users requested per page by `list_users_page`
+const USERS_PAGE_SIZE: usize = 500;
+/// Expiration written to suspend a user; any date in the past disables sign-in
+const DISABLED_EXPIRATION: &str = "2000-01-01";
+/// How long one SAFEQ request may take when not configured
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a fetched provider list is reused when not configured
+const DEFAULT_PROVIDER_CACHE_TTL: Duration = Duration::from_secs(300);
 
 /// User detail types for SAFEQ Cloud API
-#[derive(Debug, Clone, Copy)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UserDetailType {
     FullName = 0,
     Email = 1,
@@ -32,76 +74,491 @@ pub enum UserDetailType {
     ExternalId = 14,
 }
 
+impl UserDetailType {
+    pub const ALL: [UserDetailType; 10] = [
+        Self::FullName,
+        Self::Email,
+        Self::HomeFolder,
+        Self::Password,
+        Self::CardId,
+        Self::Pin,
+        Self::Otp,
+        Self::Department,
+        Self::Expiration,
+        Self::ExternalId,
+    ];
+
+    /// Field name used for this detail in user objects and import rows
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::FullName => "fullName",
+            Self::Email => "email",
+            Self::HomeFolder => "homeFolder",
+            Self::Password => "password",
+            Self::CardId => "cardId",
+            Self::Pin => "pin",
+            Self::Otp => "otp",
+            Self::Department => "department",
+            Self::Expiration => "expiration",
+            Self::ExternalId => "externalId",
+        }
+    }
+
+    /// Field holding this detail on a `users/all` user, or `None` when listings
+    /// never return it and the detail can't be read back
+    ///
+    /// SAFEQ keeps the PIN in `shortId` and cards in a `cards` array.
+    pub fn user_field(self) -> Option<&'static str> {
+        match self {
+            Self::FullName => Some("fullName"),
+            Self::Email => Some("email"),
+            Self::CardId => Some("cards"),
+            Self::Pin => Some("shortId"),
+            Self::Otp => Some("otp"),
+            Self::Department => Some("department"),
+            Self::Password | Self::HomeFolder | Self::Expiration | Self::ExternalId => None,
+        }
+    }
+
+    /// Detail type for a SAFEQ `detailtype` id, as listed by `list_json`
+    pub fn from_id(id: i32) -> Option<Self> {
+        Self::ALL.into_iter().find(|detail| *detail as i32 == id)
+    }
+
+    /// Whether bulk clearing may remove this detail
+    ///
+    /// Full name and email identify the user and address their notifications,
+    /// so they are only changed one user at a time.
+    pub fn is_clearable(self) -> bool {
+        !matches!(self, Self::FullName | Self::Email)
+    }
+
+    /// Every detail type as `[{ id, name }]` for the frontend
+    pub fn list_json() -> Value {
+        Self::ALL
+            .iter()
+            .map(|detail| json!({ "id": *detail as i32, "name": detail.name() }))
+            .collect()
+    }
+}
+
 pub struct SafeQClient {
     base_url: String,
     api_key: String,
+    auth_header_style: AuthHeaderStyle,
     http: Client,
+    /// Account the API key belongs to, from settings or looked up once per client
+    account_id: OnceLock<i64>,
+    /// Answer every call from `mock` instead of the network
+    mock: bool,
+    /// Limit for each request, so one hung call can't stall a batch
+    request_timeout: Duration,
+    /// Redacted request/response log, when `debug_trace` is on
+    trace: Option<TraceLog>,
+    /// Shared cap from `safeq_requests_per_second`
+    rate_limit: Option<Arc<RateLimiter>>,
+}
+
+/// Content type and body of a successful SAFEQ response
+struct SafeQResponse {
+    content_type: Option<String>,
+    body: String,
+}
+
+/// Keeps the last built client so commands share one warm connection pool
+///
+/// The client is rebuilt whenever the settings it was built from change.
+#[derive(Default)]
+pub struct ClientCache {
+    cached: Mutex<Option<(u64, Arc<SafeQClient>)>>,
+}
+
+impl ClientCache {
+    /// The cached client, or a new one when `settings` changed since it was built
+    ///
+    /// `trace_path` is where requests are traced when `debug_trace` is on.
+    pub fn get_or_build(
+        &self,
+        settings: &SafeQSettings,
+        trace_path: Option<PathBuf>,
+    ) -> Result<Arc<SafeQClient>, SafeQApiError> {
+        let key = settings_hash(settings);
+        let mut cached = self.cached.lock().unwrap_or_else(|err| err.into_inner());
+
+        if let Some((cached_key, client)) = cached.as_ref() {
+            if *cached_key == key {
+                return Ok(client.clone());
+            }
+        }
+
+        debug!("building SAFEQ client for changed settings");
+        let mut client = SafeQClient::from_settings(settings.clone())?;
+        if let Some(path) = trace_path.filter(|_| settings.debug_trace) {
+            info!(path = %path.display(), "tracing raw SAFEQ requests");
+            client = client.with_trace(TraceLog::new(path));
+        }
+        let client = Arc::new(client);
+        *cached = Some((key, client.clone()));
+        Ok(client)
+    }
+
+    /// Drop the cached client, e.g. after the settings were saved
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap_or_else(|err| err.into_inner()) = None;
+    }
+}
+
+/// One row of `count_users_by_provider`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderUserCount {
+    pub provider_id: i64,
+    pub provider_name: String,
+    pub count: usize,
+}
+
+/// Outcome of `set_and_verify_detail`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetailVerification {
+    pub user_name: String,
+    pub detail: &'static str,
+    /// Value sent to SAFEQ; `None` when the detail was cleared
+    pub sent: Option<String>,
+    /// Value read back afterwards; never set for PINs and OTPs
+    pub stored: Option<String>,
+    /// `None` for write-only details that can't be read back
+    pub verified: Option<bool>,
+}
+
+/// Which details a user has set, without their values
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialStatus {
+    pub user_name: String,
+    pub has_full_name: bool,
+    pub has_email: bool,
+    pub has_card: bool,
+    pub has_pin: bool,
+    pub has_otp: bool,
+}
+
+impl CredentialStatus {
+    pub fn from_user(user: &Value) -> Self {
+        Self {
+            user_name: user["userName"].as_str().unwrap_or_default().to_string(),
+            has_full_name: stored_detail(user, UserDetailType::FullName).is_some(),
+            has_email: stored_detail(user, UserDetailType::Email).is_some(),
+            has_card: stored_detail(user, UserDetailType::CardId).is_some(),
+            has_pin: stored_detail(user, UserDetailType::Pin).is_some(),
+            has_otp: stored_detail(user, UserDetailType::Otp).is_some(),
+        }
+    }
+}
+
+/// Auth provider lists by account id, so dropdowns don't refetch on every render
+#[derive(Default)]
+pub struct ProviderCache {
+    entries: Mutex<HashMap<i64, (Instant, Value)>>,
+}
+
+impl ProviderCache {
+    /// Cached providers for the client's account, fetched when missing or older than `ttl`
+    pub async fn get_or_fetch(
+        &self,
+        client: &SafeQClient,
+        ttl: Duration,
+        force_refresh: bool,
+    ) -> Result<Value, SafeQApiError> {
+        let account_id = client.account_id().await?;
+
+        if !force_refresh {
+            let entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some((fetched_at, providers)) = entries.get(&account_id) {
+                if fetched_at.elapsed() < ttl {
+                    return Ok(providers.clone());
+                }
+            }
+        }
+
+        let providers = client.auth_providers_for(account_id).await?;
+        self.entries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(account_id, (Instant::now(), providers.clone()));
+        Ok(providers)
+    }
+
+    /// Forget every cached list, e.g. after the tenant settings changed
+    pub fn invalidate(&self) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clear();
+    }
+}
+
+/// Provider cache TTL from settings, five minutes when unset
+pub fn provider_cache_ttl(settings: &SafeQSettings) -> Duration {
+    settings
+        .provider_cache_ttl_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_PROVIDER_CACHE_TTL)
+}
+
+/// Per-request timeout from settings, 30 seconds when unset or zero
+pub fn request_timeout(settings: &SafeQSettings) -> Duration {
+    settings
+        .request_timeout_ms
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_REQUEST_TIMEOUT)
+}
+
+/// Fingerprint of everything that affects how a client is built
+fn settings_hash(settings: &SafeQSettings) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(settings)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
 }
 
 impl SafeQClient {
-    pub fn from_store(app: &AppHandle) -> Result<Self, SafeQApiError> {
+    /// Client for the saved settings, reused across commands while they are unchanged
+    pub fn from_store(app: &AppHandle) -> Result<Arc<Self>, SafeQApiError> {
         let settings = load_safeq_settings(app)
             .map_err(SafeQApiError::Settings)?
             .ok_or(SafeQApiError::MissingSettings)?;
-        Self::from_settings(settings)
+        Self::cached(app, &settings)
+    }
+
+    /// Client for already loaded settings, shared through the app's `ClientCache`
+    pub fn cached(app: &AppHandle, settings: &SafeQSettings) -> Result<Arc<Self>, SafeQApiError> {
+        let trace_path = app
+            .path()
+            .app_log_dir()
+            .ok()
+            .map(|dir| dir.join(TRACE_FILE));
+        app.state::<ClientCache>()
+            .get_or_build(settings, trace_path)
     }
 
     pub fn from_settings(settings: SafeQSettings) -> Result<Self, SafeQApiError> {
-        let base_url = UrlUtils::build_base_url(&settings.tenant_url, DEFAULT_API_PORT)
-            .map_err(SafeQApiError::InvalidBaseUrl)?;
-        let client = Client::builder()
-            .user_agent(USER_AGENT)
+        let base_url = if settings.mock_mode {
+            info!("SAFEQ client is in mock mode; no requests will be sent");
+            mock::MOCK_BASE_URL.to_string()
+        } else {
+            UrlUtils::build_base_url(
+                &settings.tenant_url,
+                settings.api_port.unwrap_or(DEFAULT_API_PORT),
+            )
+            .map_err(SafeQApiError::InvalidBaseUrl)?
+        };
+        let client = http_client_builder(&settings)?
             .build()
             .map_err(SafeQApiError::HttpClient)?;
 
         Ok(Self {
             base_url,
             api_key: settings.api_key.trim().to_owned(),
+            auth_header_style: settings.auth_header_style,
             http: client,
+            account_id: settings.account_id.map(OnceLock::from).unwrap_or_default(),
+            mock: settings.mock_mode,
+            request_timeout: request_timeout(&settings),
+            trace: None,
+            rate_limit: rate_limit::shared(Backend::SafeQ, settings.safeq_requests_per_second),
         })
     }
 
-    pub async fn list_auth_providers(&self) -> Result<Value, SafeQApiError> {
-        // Step 1: Get account info to retrieve account ID
+    /// Record every request and response to `trace`, with secrets redacted
+    pub fn with_trace(mut self, trace: TraceLog) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Fetch the account the API key belongs to
+    ///
+    /// Returns the account JSON as provided by SAFEQ (id, name, ...). Fails with
+    /// `MissingField("account.id")` if the response has no numeric id.
+    pub async fn get_account(&self) -> Result<Value, SafeQApiError> {
         let account_info = self.get_json(ACCOUNT_PATH).await?;
-        let account_id = account_info
-            .get("id")
-            .and_then(|v| v.as_i64())
-            .ok_or_else(|| SafeQApiError::MissingField("account.id".to_string()))?;
+        account_id_from(&account_info)?;
+        Ok(account_info)
+    }
+
+    pub async fn list_auth_providers(&self) -> Result<Value, SafeQApiError> {
+        // Step 1: Get the account ID, unless the settings already name it
+        let account_id = self.account_id().await?;
 
         // Step 2: Get auth providers using account ID
+        self.auth_providers_for(account_id).await
+    }
+
+    /// Account id for this client's API key
+    ///
+    /// Uses the `accountId` setting when present; otherwise it is fetched on
+    /// first use only.
+    pub async fn account_id(&self) -> Result<i64, SafeQApiError> {
+        if let Some(account_id) = self.account_id.get() {
+            return Ok(*account_id);
+        }
+
+        let account_id = account_id_from(&self.get_account().await?)?;
+        Ok(*self.account_id.get_or_init(|| account_id))
+    }
+
+    async fn auth_providers_for(&self, account_id: i64) -> Result<Value, SafeQApiError> {
         let providers_url = format!("{}?accountid={}", AUTH_PROVIDERS_PATH, account_id);
         self.get_json(&providers_url).await
     }
 
+    /// Ids of every auth provider on the account, for validating rows up front
+    pub async fn provider_ids(&self) -> Result<HashSet<i64>, SafeQApiError> {
+        Ok(provider_ids_from(&self.list_auth_providers().await?))
+    }
+
     pub async fn list_users_for_provider(&self, provider_id: i64) -> Result<Value, SafeQApiError> {
         let users_url = format!("{}?providerid={}", LIST_ALL_USERS_PATH, provider_id);
         self.get_json(&users_url).await
     }
 
+    /// One page of a provider's users; pass the previous `nextPageToken` to continue
+    pub async fn list_users_page(
+        &self,
+        provider_id: i64,
+        page_token: Option<&str>,
+    ) -> Result<Value, SafeQApiError> {
+        let mut users_url = format!(
+            "{}?providerid={}&maxrecords={}",
+            LIST_ALL_USERS_PATH, provider_id, USERS_PAGE_SIZE
+        );
+        if let Some(token) = page_token {
+            let token: String = url::form_urlencoded::byte_serialize(token.as_bytes()).collect();
+            users_url.push_str(&format!("&pagetoken={token}"));
+        }
+        self.get_json(&users_url).await
+    }
+
+    /// Every user of a provider with only `fields` kept, fetched page by page
+    ///
+    /// Each page is trimmed as it arrives, so the full objects never pile up
+    /// or cross the Tauri bridge.
+    pub async fn list_users_projected(
+        &self,
+        provider_id: i64,
+        fields: &[&str],
+    ) -> Result<Vec<Value>, SafeQApiError> {
+        let mut users = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let page = self
+                .list_users_page(provider_id, page_token.as_deref())
+                .await?;
+            users.extend(
+                users_in(&page)
+                    .iter()
+                    .map(|user| project_user(user, fields)),
+            );
+
+            page_token = next_page_token(&page);
+            if page_token.is_none() {
+                return Ok(users);
+            }
+        }
+    }
+
+    /// Every user of a provider, fetched page by page
+    ///
+    /// `None` uses the account's first provider, like `list_users`.
+    pub async fn list_all_users(
+        &self,
+        provider_id: Option<i64>,
+    ) -> Result<Vec<Value>, SafeQApiError> {
+        let provider_id = match provider_id {
+            Some(pid) => pid,
+            None => self.default_provider_id().await?,
+        };
+
+        let mut users = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let page = self
+                .list_users_page(provider_id, page_token.as_deref())
+                .await?;
+            users.extend_from_slice(users_in(&page));
+
+            page_token = next_page_token(&page);
+            if page_token.is_none() {
+                return Ok(users);
+            }
+        }
+    }
+
+    /// Number of users in each auth provider, for dashboard summaries
+    ///
+    /// Each provider's list is fetched in turn, every page of it, and counted
+    /// here, so only the totals reach the frontend.
+    pub async fn count_users_by_provider(&self) -> Result<Vec<ProviderUserCount>, SafeQApiError> {
+        let providers = self.list_auth_providers().await?;
+
+        let mut counts = Vec::new();
+        for provider in providers.as_array().into_iter().flatten() {
+            let Some(provider_id) = provider["id"].as_i64() else {
+                continue;
+            };
+            let users = self.list_all_users(Some(provider_id)).await?;
+            counts.push(ProviderUserCount {
+                provider_id,
+                provider_name: provider["name"].as_str().unwrap_or_default().to_string(),
+                count: users.len(),
+            });
+        }
+
+        Ok(counts)
+    }
+
     pub async fn list_users(&self) -> Result<Value, SafeQApiError> {
-        // Step 1: Get account info to retrieve account ID
-        let account_info = self.get_json(ACCOUNT_PATH).await?;
-        let account_id = account_info
-            .get("id")
-            .and_then(|v| v.as_i64())
-            .ok_or_else(|| SafeQApiError::MissingField("account.id".to_string()))?;
+        let provider_id = self.default_provider_id().await?;
+
+        // Get all users for this provider
+        let users_url = format!("{}?providerid={}", LIST_ALL_USERS_PATH, provider_id);
+        self.get_json(&users_url).await
+    }
+
+    /// Id of the account's first auth provider, used when a call names none
+    async fn default_provider_id(&self) -> Result<i64, SafeQApiError> {
+        // Step 1: Get the account ID, unless the settings already name it
+        let account_id = self.account_id().await?;
 
         // Step 2: Get auth providers using account ID
         let providers_url = format!("{}?accountid={}", AUTH_PROVIDERS_PATH, account_id);
         let providers_info = self.get_json(&providers_url).await?;
 
         // Step 3: Extract first provider ID from the array
-        let provider_id = providers_info
+        providers_info
             .as_array()
             .and_then(|arr| arr.first())
             .and_then(|provider| provider.get("id"))
             .and_then(|v| v.as_i64())
-            .ok_or_else(|| SafeQApiError::MissingField("authprovider.id".to_string()))?;
+            .ok_or_else(|| SafeQApiError::MissingField("authprovider.id".to_string()))
+    }
 
-        // Step 4: Get all users for this provider
-        let users_url = format!("{}?providerid={}", LIST_ALL_USERS_PATH, provider_id);
-        self.get_json(&users_url).await
+    /// Search users by username, full name or email
+    ///
+    /// The SAFEQ API has no search parameter on `users/all`, so every page is
+    /// fetched and filtered here instead of in the UI. Matches come back as
+    /// `{ "items": [...] }`.
+    pub async fn search_users(
+        &self,
+        provider_id: Option<i64>,
+        query: &str,
+    ) -> Result<Value, SafeQApiError> {
+        let users = self.list_all_users(provider_id).await?;
+
+        Ok(filter_users(&json!({ "items": users }), query))
     }
 
     /// Update a user detail in SAFEQ Cloud
@@ -110,7 +567,7 @@ impl SafeQClient {
     /// * `username` - Username of the user to update
     /// * `provider_id` - Optional provider ID (if None, uses local provider)
     /// * `detail_type` - Type of detail to update
-    /// * `detail_data` - Optional detail data (if None, deletes the detail)
+    /// * `detail_data` - Optional detail data (if None or blank, deletes the detail)
     pub async fn update_user_detail(
         &self,
         username: &str,
@@ -126,14 +583,163 @@ impl SafeQClient {
             form.push(("providerid", pid.to_string()));
         }
 
-        if let Some(data) = detail_data {
+        // Without `detaildata` SAFEQ removes the detail; an empty value would be
+        // stored as-is instead
+        if let Some(data) = detail_data.filter(|data| !data.trim().is_empty()) {
+            form.push(("detaildata", data.to_string()));
+        }
+
+        self.post_form(&path, &form)
+            .await
+            .map_err(|err| user_not_found(err, username))
+    }
+
+    /// Set several details of one user in a single request
+    ///
+    /// Sends one `detailtype`/`detaildata` pair per detail, like `create_user`.
+    /// Details can't be cleared this way; use `update_user_detail` for that.
+    pub async fn update_user_details(
+        &self,
+        username: &str,
+        provider_id: Option<i64>,
+        details: &[(UserDetailType, &str)],
+    ) -> Result<Value, SafeQApiError> {
+        let path = format!("{}/{}", UPDATE_USER_PATH, username);
+
+        let mut form = Vec::new();
+        if let Some(pid) = provider_id {
+            form.push(("providerid", pid.to_string()));
+        }
+        for (detail_type, data) in details {
+            form.push(("detailtype", (*detail_type as i32).to_string()));
             form.push(("detaildata", data.to_string()));
         }
 
-        self.post_form(&path, &form).await
+        self.post_form(&path, &form)
+            .await
+            .map_err(|err| user_not_found(err, username))
+    }
+
+    /// Suspend or re-enable a user without deleting them
+    ///
+    /// SAFEQ has no enabled flag, so this uses the expiration detail: disabling
+    /// sets an expiration in the past and enabling removes the expiration.
+    /// Enabling therefore also clears any future expiry the user had.
+    pub async fn set_user_enabled(
+        &self,
+        username: &str,
+        provider_id: Option<i64>,
+        enabled: bool,
+    ) -> Result<Value, SafeQApiError> {
+        let expiration = (!enabled).then_some(DISABLED_EXPIRATION);
+        self.update_user_detail(
+            username,
+            provider_id,
+            UserDetailType::Expiration,
+            expiration,
+        )
+        .await
+    }
+
+    /// Fail when `card_id` is already assigned to a user other than `username`
+    ///
+    /// SAFEQ rejects duplicate cards with an opaque error, so this fetches every
+    /// page of the provider's user list first. Costs one extra request per page.
+    pub async fn check_card_uniqueness(
+        &self,
+        username: &str,
+        provider_id: Option<i64>,
+        card_id: &str,
+    ) -> Result<(), SafeQApiError> {
+        let users = self.list_all_users(provider_id).await?;
+
+        match card_owner(&users, card_id, username) {
+            Some(owner) => Err(SafeQApiError::CardAlreadyAssigned {
+                card_id: card_id.trim().to_string(),
+                owner,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Fetch one user by username, or `None` when the provider has no such user
+    ///
+    /// SAFEQ has no single-user endpoint, so this searches every page of the
+    /// provider's list.
+    pub async fn get_user(
+        &self,
+        username: &str,
+        provider_id: Option<i64>,
+    ) -> Result<Option<Value>, SafeQApiError> {
+        let users = self.list_all_users(provider_id).await?;
+
+        Ok(user_named(&users, username))
+    }
+
+    /// Which credentials a user has, for support views that must not show them
+    ///
+    /// `None` when the provider has no such user.
+    pub async fn get_credential_status(
+        &self,
+        username: &str,
+        provider_id: Option<i64>,
+    ) -> Result<Option<CredentialStatus>, SafeQApiError> {
+        let user = self.get_user(username, provider_id).await?;
+        Ok(user.as_ref().map(CredentialStatus::from_user))
+    }
+
+    /// Update a detail, then read the user back to confirm SAFEQ stored it
+    ///
+    /// Catches updates the API accepts but silently ignores. Details without a
+    /// `user_field` can't be read back and report `verified: null`; PINs and
+    /// OTPs are compared but never echoed in `stored`.
+    pub async fn set_and_verify_detail(
+        &self,
+        username: &str,
+        provider_id: Option<i64>,
+        detail_type: UserDetailType,
+        detail_data: Option<&str>,
+    ) -> Result<DetailVerification, SafeQApiError> {
+        self.update_user_detail(username, provider_id, detail_type, detail_data)
+            .await?;
+        let user = self.get_user(username, provider_id).await?;
+
+        let sent = detail_data.map(str::trim).filter(|data| !data.is_empty());
+        let stored = user
+            .as_ref()
+            .and_then(|user| stored_detail(user, detail_type));
+        let verified = match detail_type {
+            _ if detail_type.user_field().is_none() => None,
+            _ if user.is_none() => Some(false),
+            UserDetailType::CardId => Some(match sent {
+                Some(card) => user.as_ref().is_some_and(|user| has_card(user, card)),
+                None => stored.is_none(),
+            }),
+            UserDetailType::Email => Some(match (sent, stored.as_deref()) {
+                (Some(sent), Some(stored)) => sent.eq_ignore_ascii_case(stored),
+                (sent, stored) => sent == stored,
+            }),
+            _ => Some(sent == stored.as_deref()),
+        };
+
+        let stored = match detail_type {
+            UserDetailType::Pin | UserDetailType::Otp => None,
+            _ => stored,
+        };
+
+        Ok(DetailVerification {
+            user_name: username.to_string(),
+            detail: detail_type.name(),
+            sent: sent.map(str::to_string),
+            stored,
+            verified,
+        })
     }
 
     /// Generate a new PIN for a user
+    ///
+    /// Returns `{ "pin": <value> }` rather than the SAFEQ update response, which
+    /// does not echo the value back.
     pub async fn generate_pin(
         &self,
         username: &str,
@@ -141,10 +747,7 @@ impl SafeQClient {
         settings: &SafeQSettings,
     ) -> Result<Value, SafeQApiError> {
         // Generate a random numeric PIN using settings or defaults
-        let gen_settings = PinSettings {
-            length: settings.pin_length.unwrap_or(4),
-        };
-        let pin = gen_pin(&gen_settings);
+        let pin = generate_pin_value(settings);
 
         // Update the user with the generated PIN (detailtype=5)
         self.update_user_detail(username, provider_id, UserDetailType::Pin, Some(&pin))
@@ -161,18 +764,7 @@ impl SafeQClient {
         settings: &SafeQSettings,
     ) -> Result<Value, SafeQApiError> {
         // Generate a random OTP using OTP-specific settings
-        let gen_settings = ShortIdSettings {
-            length: settings.otp_length.unwrap_or(8),
-            use_uppercase: settings.otp_use_uppercase.unwrap_or(true),
-            use_lowercase: settings.otp_use_lowercase.unwrap_or(true),
-            use_numbers: settings.otp_use_numbers.unwrap_or(true),
-            use_special: settings.otp_use_special.unwrap_or(false),
-            exclude_characters: settings
-                .otp_exclude_characters
-                .clone()
-                .unwrap_or_else(|| String::from("1lI0Oo")),
-        };
-        let otp = gen_short_id(&gen_settings);
+        let otp = generate_otp_value(settings);
 
         // Update the user with the generated OTP (detailtype=10)
         self.update_user_detail(username, provider_id, UserDetailType::Otp, Some(&otp))
@@ -182,15 +774,44 @@ impl SafeQClient {
         Ok(serde_json::json!({ "otp": otp }))
     }
 
+    /// Generate and assign a new PIN and OTP in one request
+    ///
+    /// Returns `{ "pin": <value>, "otp": <value> }`. If the request fails,
+    /// neither value was stored. `pin` is assigned instead of a generated PIN
+    /// when given, e.g. one drawn unique for the whole batch.
+    pub async fn generate_credentials(
+        &self,
+        username: &str,
+        provider_id: Option<i64>,
+        pin: Option<&str>,
+        settings: &SafeQSettings,
+    ) -> Result<Value, SafeQApiError> {
+        let pin = pin.map_or_else(|| generate_pin_value(settings), str::to_string);
+        let otp = generate_otp_value(settings);
+
+        self.update_user_details(
+            username,
+            provider_id,
+            &[(UserDetailType::Pin, &pin), (UserDetailType::Otp, &otp)],
+        )
+        .await?;
+
+        Ok(json!({ "pin": pin, "otp": otp }))
+    }
+
     /// Create a new user in SAFEQ Cloud
     ///
-    /// Creates a user with all details in a single PUT request per the API
+    /// Creates a user with all details in a single PUT request per the API.
+    /// The password is only sent in the form body, never logged.
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_user(
         &self,
         username: &str,
         provider_id: Option<i64>,
         full_name: Option<&str>,
         email: Option<&str>,
+        home_folder: Option<&str>,
+        password: Option<&str>,
         card_id: Option<&str>,
         short_id: Option<&str>,
         otp: Option<&str>,
@@ -219,6 +840,25 @@ impl SafeQClient {
             }
         }
 
+        // Add home folder if provided (detailtype=2)
+        if let Some(folder) = home_folder {
+            if !folder.is_empty() {
+                form.push((
+                    "detailtype",
+                    (UserDetailType::HomeFolder as i32).to_string(),
+                ));
+                form.push(("detaildata", folder.to_string()));
+            }
+        }
+
+        // Add initial password if provided (detailtype=3)
+        if let Some(secret) = password {
+            if !secret.is_empty() {
+                form.push(("detailtype", (UserDetailType::Password as i32).to_string()));
+                form.push(("detaildata", secret.to_string()));
+            }
+        }
+
         // Add card ID if provided (detailtype=4)
         if let Some(card) = card_id {
             if !card.is_empty() {
@@ -243,7 +883,64 @@ impl SafeQClient {
             }
         }
 
-        self.put_form(&path, &form).await
+        match self.put_form(path, &form).await {
+            // The create may have reached SAFEQ before the connection dropped.
+            // SAFEQ takes no idempotency key, so only repeat it when the user
+            // does not exist yet.
+            Err(err @ (SafeQApiError::Request(_) | SafeQApiError::Timeout { .. })) => {
+                warn!(error = %err, "user create lost its response; checking before retrying");
+                match self.get_user(username, provider_id).await {
+                    Ok(Some(user)) => Ok(user),
+                    Ok(None) => self.put_form(path, &form).await,
+                    // The lost create is what the caller needs to hear about
+                    Err(lookup) => {
+                        warn!(error = %lookup, "lookup after lost create failed");
+                        Err(err)
+                    }
+                }
+            }
+            result => result,
+        }
+    }
+
+    /// Delete a user, e.g. to undo a creation that was only partially applied
+    pub async fn delete_user(
+        &self,
+        username: &str,
+        provider_id: Option<i64>,
+    ) -> Result<(), SafeQApiError> {
+        let path = format!("{}/{}", UPDATE_USER_PATH, username);
+
+        let mut form = Vec::new();
+        if let Some(pid) = provider_id {
+            form.push(("providerid", pid.to_string()));
+        }
+
+        if self
+            .mock_response(&Method::DELETE, &path, Some(&form))
+            .is_none()
+        {
+            self.send(Method::DELETE, &path, Some(&form)).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove a user whose creation failed so no half-configured account is left
+    ///
+    /// A 404 from SAFEQ means the user row was never created, which is reported
+    /// separately from a successful cleanup.
+    pub async fn rollback_user(&self, username: &str, provider_id: Option<i64>) -> RollbackOutcome {
+        match self.delete_user(username, provider_id).await {
+            Ok(()) => RollbackOutcome::Deleted,
+            Err(SafeQApiError::HttpStatus {
+                status: StatusCode::NOT_FOUND,
+                ..
+            }) => RollbackOutcome::NotCreated,
+            Err(err) => {
+                warn!(error = %err, "failed to roll back partially created user");
+                RollbackOutcome::Failed(err)
+            }
+        }
     }
 
     async fn put_form(
@@ -251,29 +948,12 @@ impl SafeQClient {
         path: &str,
         form_data: &[(&str, String)],
     ) -> Result<Value, SafeQApiError> {
-        let request_url = self.endpoint(path);
-
-        let response = self
-            .http
-            .put(&request_url)
-            .header("X-Api-Key", &self.api_key)
-            .form(form_data)
-            .send()
-            .await
-            .map_err(SafeQApiError::Request)?;
-
-        let status = response.status();
-
-        if !status.is_success() {
-            let body = response.text().await.unwrap_or_default();
-            return Err(SafeQApiError::HttpStatus {
-                status,
-                url: request_url.clone(),
-                body: truncate_body(&body),
-            });
+        if let Some(value) = self.mock_response(&Method::PUT, path, Some(form_data)) {
+            return Ok(value);
         }
+        let response = self.send(Method::PUT, path, Some(form_data)).await?;
 
-        response.json().await.map_err(SafeQApiError::ResponseJson)
+        read_success_body(response)
     }
 
     async fn post_form(
@@ -281,55 +961,140 @@ impl SafeQClient {
         path: &str,
         form_data: &[(&str, String)],
     ) -> Result<Value, SafeQApiError> {
-        let request_url = self.endpoint(path);
+        if let Some(value) = self.mock_response(&Method::POST, path, Some(form_data)) {
+            return Ok(value);
+        }
+        let response = self.send(Method::POST, path, Some(form_data)).await?;
 
-        let response = self
-            .http
-            .post(&request_url)
-            .header("X-Api-Key", &self.api_key)
-            .form(form_data)
-            .send()
-            .await
-            .map_err(SafeQApiError::Request)?;
+        read_success_body(response)
+    }
 
-        let status = response.status();
+    async fn get_json(&self, path: &str) -> Result<Value, SafeQApiError> {
+        if let Some(value) = self.mock_response(&Method::GET, path, None) {
+            return Ok(value);
+        }
+        let response = self.send(Method::GET, path, None).await?;
 
-        if !status.is_success() {
-            let body = response.text().await.unwrap_or_default();
-            return Err(SafeQApiError::HttpStatus {
-                status,
-                body: truncate_body(&body),
-                url: request_url,
-            });
-        }
-
-        let response_body = response.text().await.map_err(SafeQApiError::Request)?;
+        read_success_body(response)
+    }
 
-        serde_json::from_str(&response_body).map_err(|e| SafeQApiError::JsonParse(e))
+    /// Canned response when the client runs in mock mode, `None` otherwise
+    fn mock_response(
+        &self,
+        method: &Method,
+        path: &str,
+        form_data: Option<&[(&str, String)]>,
+    ) -> Option<Value> {
+        self.mock.then(|| {
+            debug!(%method, path, "answering SAFEQ request from mock data");
+            mock::respond(method, path, form_data)
+        })
     }
 
-    async fn get_json(&self, path: &str) -> Result<Value, SafeQApiError> {
+    /// Send an authenticated request and turn non-success statuses into errors
+    ///
+    /// Only the method, URL and status are logged; the API key and form values
+    /// (which may carry PINs and OTPs) never are. The optional trace log gets
+    /// the full exchange with those values redacted.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        form_data: Option<&[(&str, String)]>,
+    ) -> Result<SafeQResponse, SafeQApiError> {
         let request_url = self.endpoint(path);
+        debug!(%method, url = %request_url, "sending SAFEQ request");
 
-        let response = self
+        let mut request = self
             .http
-            .get(&request_url)
-            .header("X-Api-Key", &self.api_key)
-            .send()
-            .await
-            .map_err(SafeQApiError::Request)?;
+            .request(method.clone(), &request_url)
+            .timeout(self.request_timeout);
+        request = match self.auth_header_style {
+            AuthHeaderStyle::ApiKeyHeader => request.header("X-Api-Key", &self.api_key),
+            AuthHeaderStyle::Bearer => request.bearer_auth(&self.api_key),
+        };
+
+        if let Some(form) = form_data {
+            request = request.form(form);
+        }
+
+        if let Some(limit) = &self.rate_limit {
+            limit.acquire().await;
+        }
+        let response = request.send().await.map_err(|err| {
+            error!(%method, url = %request_url, error = %err, "SAFEQ request failed");
+            self.trace(
+                &method,
+                &request_url,
+                form_data,
+                None,
+                None,
+                Some(&err.to_string()),
+            );
+            if err.is_timeout() {
+                SafeQApiError::Timeout {
+                    url: request_url.clone(),
+                    after: self.request_timeout,
+                }
+            } else {
+                SafeQApiError::Request(err)
+            }
+        })?;
 
         let status = response.status();
+        debug!(%method, url = %request_url, status = status.as_u16(), "received SAFEQ response");
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let body = response.text().await;
+        self.trace(
+            &method,
+            &request_url,
+            form_data,
+            Some(status),
+            body.as_deref().ok(),
+            body.as_ref().err().map(ToString::to_string).as_deref(),
+        );
+
         if !status.is_success() {
-            let body = response.text().await.unwrap_or_default();
+            warn!(%method, url = %request_url, status = status.as_u16(), "SAFEQ request was rejected");
+            let body = body.unwrap_or_default();
             return Err(SafeQApiError::HttpStatus {
                 status,
+                detail: SafeQErrorDetail::parse(&body),
                 body: truncate_body(&body),
                 url: request_url,
             });
         }
 
-        response.json().await.map_err(SafeQApiError::ResponseJson)
+        Ok(SafeQResponse {
+            content_type,
+            body: body.map_err(SafeQApiError::Request)?,
+        })
+    }
+
+    fn trace(
+        &self,
+        method: &Method,
+        url: &str,
+        form_data: Option<&[(&str, String)]>,
+        status: Option<StatusCode>,
+        body: Option<&str>,
+        error: Option<&str>,
+    ) {
+        if let Some(trace) = &self.trace {
+            trace.record(&TraceEntry::new(
+                method.as_str(),
+                url,
+                form_data,
+                &self.api_key,
+                status.map(|status| status.as_u16()),
+                body,
+                error,
+            ));
+        }
     }
 
     fn endpoint(&self, path: &str) -> String {
@@ -338,45 +1103,376 @@ impl SafeQClient {
     }
 }
 
+/// What happened when undoing a failed user creation
+#[derive(Debug)]
+pub enum RollbackOutcome {
+    Deleted,
+    NotCreated,
+    Failed(SafeQApiError),
+}
+
+impl RollbackOutcome {
+    /// The `rollback` entry reported in bulk create results
+    pub fn to_json(&self) -> Value {
+        match self {
+            Self::Deleted => json!({ "status": "deleted" }),
+            Self::NotCreated => json!({ "status": "notCreated" }),
+            Self::Failed(err) => json!({ "status": "failed", "error": err.to_string() }),
+        }
+    }
+}
+
+/// Check `new_key` against `api/v1/account` and store it only if SAFEQ accepts it
+///
+/// Returns the account the new key belongs to. On any failure the previously
+/// stored key is left untouched, so a typo can never lock the app out.
+pub async fn rotate_api_key(
+    settings: &SafeQSettings,
+    new_key: &str,
+    secrets: &dyn SecretStore,
+) -> Result<Value, SafeQApiError> {
+    let new_key = new_key.trim();
+    if new_key.is_empty() {
+        return Err(SafeQApiError::Settings(SettingsLoadError::MissingApiKey));
+    }
+
+    let candidate = SafeQSettings {
+        api_key: new_key.to_string(),
+        ..settings.clone()
+    };
+    let account = SafeQClient::from_settings(candidate)?.get_account().await?;
+
+    secrets
+        .set(SecretKey::ApiKey, new_key)
+        .map_err(|err| SafeQApiError::Settings(SettingsLoadError::Secrets(err)))?;
+    info!("SAFEQ API key rotated");
+    Ok(account)
+}
+
+/// Read the numeric account id from an account response
+fn account_id_from(account_info: &Value) -> Result<i64, SafeQApiError> {
+    account_info
+        .get("id")
+        .and_then(|v| v.as_i64())
+        .ok_or_else(|| SafeQApiError::MissingField("account.id".to_string()))
+}
+
+/// Users in a `users/all` response, which is either `{ items }` or a bare array
+pub(crate) fn users_in(users: &Value) -> &[Value] {
+    users["items"]
+        .as_array()
+        .or_else(|| users.as_array())
+        .map_or(&[], Vec::as_slice)
+}
+
+/// The token for the page after `page`, or `None` on the last page
+pub(crate) fn next_page_token(page: &Value) -> Option<String> {
+    page["nextPageToken"]
+        .as_str()
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+}
+
+/// Read the body of a successful SAFEQ response
+fn read_success_body(response: SafeQResponse) -> Result<Value, SafeQApiError> {
+    parse_success_body(response.content_type.as_deref(), &response.body)
+        .map_err(SafeQApiError::JsonParse)
+}
+
+/// Parse a success body that may be empty or plain text
+///
+/// Some SAFEQ updates answer 200 with no body or a bare `OK`. An empty body
+/// becomes `Value::Null` and plain text a JSON string; only a body that claims
+/// or looks like JSON and fails to parse is an error.
+fn parse_success_body(content_type: Option<&str>, body: &str) -> Result<Value, serde_json::Error> {
+    let trimmed = body.trim();
+    if trimmed.is_empty() {
+        return Ok(Value::Null);
+    }
+
+    let declared_json = content_type.is_some_and(|content_type| content_type.contains("json"));
+    let looks_like_json = trimmed.starts_with('{') || trimmed.starts_with('[');
+    if declared_json || looks_like_json {
+        return serde_json::from_str(trimmed);
+    }
+
+    Ok(Value::String(trimmed.to_string()))
+}
+
+/// Collect the `id` of every provider in an auth providers response
+fn provider_ids_from(providers: &Value) -> HashSet<i64> {
+    providers
+        .as_array()
+        .map(|providers| {
+            providers
+                .iter()
+                .filter_map(|provider| provider["id"].as_i64())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Build the HTTP client configuration for the given settings
+fn http_client_builder(settings: &SafeQSettings) -> Result<ClientBuilder, SafeQApiError> {
+    let mut builder = PoolSettings::from_settings(settings)
+        .apply(Client::builder().user_agent(user_agent(settings)));
+    builder = apply_min_tls(builder, settings);
+
+    if let Some(path) = settings.ca_cert_path.as_deref() {
+        builder = builder.add_root_certificate(load_ca_certificate(path)?);
+    }
+
+    if settings.allow_invalid_certs {
+        warn!(
+            tenant_url = %settings.tenant_url,
+            "TLS certificate verification is DISABLED; only use this for trusted on-prem tenants"
+        );
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    apply_proxy(builder, settings).map_err(SafeQApiError::InvalidProxy)
+}
+
+/// Read a PEM-encoded CA certificate from disk
+fn load_ca_certificate(path: &str) -> Result<Certificate, SafeQApiError> {
+    let pem = std::fs::read(path).map_err(|err| SafeQApiError::CaCertRead {
+        path: path.to_string(),
+        source: err,
+    })?;
+
+    Certificate::from_pem(&pem).map_err(|err| SafeQApiError::CaCertParse {
+        path: path.to_string(),
+        source: err,
+    })
+}
+
+/// PIN generation rules from the app settings, see `GenerationSettings`
+pub fn pin_settings(settings: &SafeQSettings) -> PinSettings {
+    GenerationSettings::resolve(settings).pin()
+}
+
+/// OTP generation rules from the app settings, see `GenerationSettings`
+pub fn otp_settings(settings: &SafeQSettings) -> ShortIdSettings {
+    GenerationSettings::resolve(settings).otp
+}
+
+/// Short ID generation rules from the app settings, see `GenerationSettings`
+pub fn short_id_settings(settings: &SafeQSettings) -> ShortIdSettings {
+    GenerationSettings::resolve(settings).short_id
+}
+
 /// Generate a PIN value using the given settings
 pub fn generate_pin_value(settings: &SafeQSettings) -> String {
-    let gen_settings = PinSettings {
-        length: settings.pin_length.unwrap_or(4),
-    };
-    gen_pin(&gen_settings)
+    gen_pin(&pin_settings(settings))
 }
 
 /// Generate an OTP value using the given settings
 pub fn generate_otp_value(settings: &SafeQSettings) -> String {
-    let gen_settings = ShortIdSettings {
-        length: settings.otp_length.unwrap_or(8),
-        use_uppercase: settings.otp_use_uppercase.unwrap_or(true),
-        use_lowercase: settings.otp_use_lowercase.unwrap_or(true),
-        use_numbers: settings.otp_use_numbers.unwrap_or(true),
-        use_special: settings.otp_use_special.unwrap_or(false),
-        exclude_characters: settings
-            .otp_exclude_characters
-            .clone()
-            .unwrap_or_else(|| String::from("1lI0Oo")),
+    gen_short_id(&otp_settings(settings))
+}
+
+/// Generate a short ID value using the given settings
+pub fn generate_short_id_value(settings: &SafeQSettings) -> String {
+    gen_short_id(&short_id_settings(settings))
+}
+
+/// The user called `username`, compared case-insensitively
+fn user_named(users: &[Value], username: &str) -> Option<Value> {
+    users
+        .iter()
+        .find(|user| {
+            user["userName"]
+                .as_str()
+                .unwrap_or("")
+                .eq_ignore_ascii_case(username.trim())
+        })
+        .cloned()
+}
+
+fn has_card(user: &Value, card_id: &str) -> bool {
+    user["cards"].as_array().is_some_and(|cards| {
+        cards.iter().any(|card| {
+            card.as_str()
+                .is_some_and(|card| card.trim() == card_id.trim())
+        })
+    })
+}
+
+/// A detail's current value on a fetched user; cards are joined with `;`
+fn stored_detail(user: &Value, detail_type: UserDetailType) -> Option<String> {
+    let field = &user[detail_type.user_field()?];
+    let value = match detail_type {
+        UserDetailType::CardId => field
+            .as_array()?
+            .iter()
+            .map(|card| card.as_str().unwrap_or_default().trim())
+            .filter(|card| !card.is_empty())
+            .collect::<Vec<_>>()
+            .join(";"),
+        _ => match field {
+            Value::String(value) => value.trim().to_string(),
+            Value::Null => return None,
+            value => value.to_string(),
+        },
+    };
+
+    (!value.is_empty()).then_some(value)
+}
+
+/// Find the user other than `username` whose `cards` contain `card_id`
+///
+/// Usernames are compared case-insensitively and card ids after trimming.
+fn card_owner(users: &[Value], card_id: &str, username: &str) -> Option<String> {
+    let card_id = card_id.trim();
+    users
+        .iter()
+        .filter(|user| {
+            !user["userName"]
+                .as_str()
+                .unwrap_or("")
+                .eq_ignore_ascii_case(username.trim())
+        })
+        .find(|user| has_card(user, card_id))
+        .map(|user| user["userName"].as_str().unwrap_or("").to_string())
+}
+
+/// Requested projection fields as `USER_FIELDS` entries, or the unknown names
+pub fn projection_fields(requested: &[String]) -> Result<Vec<&'static str>, Vec<String>> {
+    let mut fields = Vec::new();
+    let mut unknown = Vec::new();
+    for name in requested {
+        match USER_FIELDS.iter().find(|field| **field == name.trim()) {
+            Some(field) if !fields.contains(field) => fields.push(*field),
+            Some(_) => {}
+            None => unknown.push(name.clone()),
+        }
+    }
+
+    if unknown.is_empty() {
+        Ok(fields)
+    } else {
+        Err(unknown)
+    }
+}
+
+/// `user` with only `fields` kept; fields the user lacks are left out
+fn project_user(user: &Value, fields: &[&str]) -> Value {
+    fields
+        .iter()
+        .filter_map(|field| Some((field.to_string(), user.get(*field)?.clone())))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+/// Keep only the users whose username, full name or email contains `query`
+///
+/// Matching is case-insensitive. Accepts either a `{ "items": [...] }` response
+/// or a bare array and returns the same shape. An empty query matches everyone.
+pub fn filter_users(users: &Value, query: &str) -> Value {
+    let needle = query.trim().to_lowercase();
+    if needle.is_empty() {
+        return users.clone();
+    }
+
+    let matches = |user: &Value| {
+        ["userName", "fullName", "email"].iter().any(|field| {
+            user.get(field)
+                .and_then(|v| v.as_str())
+                .is_some_and(|v| v.to_lowercase().contains(&needle))
+        })
     };
-    gen_short_id(&gen_settings)
+
+    match users {
+        Value::Array(items) => Value::Array(items.iter().filter(|u| matches(u)).cloned().collect()),
+        Value::Object(map) => {
+            let mut filtered = map.clone();
+            if let Some(Value::Array(items)) = map.get("items") {
+                let kept: Vec<Value> = items.iter().filter(|u| matches(u)).cloned().collect();
+                if filtered.contains_key("recordsOnPage") {
+                    filtered.insert("recordsOnPage".to_string(), serde_json::json!(kept.len()));
+                }
+                filtered.insert("items".to_string(), Value::Array(kept));
+            }
+            Value::Object(filtered)
+        }
+        other => other.clone(),
+    }
+}
+
+/// Error code and message from a JSON SAFEQ error body
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeQErrorDetail {
+    /// Machine-readable code such as `USER_EXISTS`
+    pub error_code: Option<String>,
+    pub message: Option<String>,
+}
+
+impl SafeQErrorDetail {
+    /// Parse an error body, returning `None` for non-JSON or unrecognised bodies
+    fn parse(body: &str) -> Option<Self> {
+        let value: Value = serde_json::from_str(body.trim()).ok()?;
+        let field = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| value.get(name).and_then(Value::as_str))
+                .map(str::to_string)
+        };
+
+        let detail = Self {
+            error_code: field(&["errorCode", "error_code", "code"]),
+            message: field(&["message", "errorMessage", "error"]),
+        };
+        (detail.error_code.is_some() || detail.message.is_some()).then_some(detail)
+    }
+}
+
+/// Turn a 404 from an update into `UserNotFound`
+fn user_not_found(err: SafeQApiError, username: &str) -> SafeQApiError {
+    match &err {
+        SafeQApiError::HttpStatus { status, .. } if *status == StatusCode::NOT_FOUND => {
+            SafeQApiError::UserNotFound(username.to_string())
+        }
+        _ => err,
+    }
 }
 
 #[derive(Debug)]
 pub enum SafeQApiError {
     Settings(SettingsLoadError),
     MissingSettings,
-    InvalidBaseUrl(url::ParseError),
+    InvalidBaseUrl(BaseUrlError),
     HttpClient(reqwest::Error),
+    InvalidProxy(reqwest::Error),
+    CaCertRead {
+        path: String,
+        source: std::io::Error,
+    },
+    CaCertParse {
+        path: String,
+        source: reqwest::Error,
+    },
     Request(reqwest::Error),
+    /// No response within the per-request timeout
+    Timeout {
+        url: String,
+        after: Duration,
+    },
     HttpStatus {
         status: StatusCode,
+        /// Truncated response text, kept for bodies that are not JSON
         body: String,
+        detail: Option<SafeQErrorDetail>,
         url: String,
     },
-    ResponseJson(reqwest::Error),
     JsonParse(serde_json::Error),
     MissingField(String),
+    CardAlreadyAssigned {
+        card_id: String,
+        owner: String,
+    },
+    /// An update targeted a username SAFEQ doesn't know
+    UserNotFound(String),
 }
 
 impl fmt::Display for SafeQApiError {
@@ -386,17 +1482,85 @@ impl fmt::Display for SafeQApiError {
             Self::MissingSettings => write!(f, "SAFEQ settings are not configured"),
             Self::InvalidBaseUrl(err) => write!(f, "tenant URL is invalid: {err}"),
             Self::HttpClient(err) => write!(f, "failed to build HTTP client: {err}"),
+            Self::InvalidProxy(err) => write!(f, "proxy URL is invalid: {err}"),
+            Self::CaCertRead { path, source } => {
+                write!(f, "failed to read CA certificate at {path}: {source}")
+            }
+            Self::CaCertParse { path, source } => {
+                write!(
+                    f,
+                    "CA certificate at {path} is not a valid PEM file: {source}"
+                )
+            }
+            Self::Request(err) if is_tls_version_error(err) => write!(
+                f,
+                "SAFEQ server does not support the minimum TLS version required by the settings: {err}"
+            ),
             Self::Request(err) => write!(f, "SAFEQ request failed: {err}"),
-            Self::HttpStatus { status, body, url } => {
+            Self::Timeout { url, after } => write!(
+                f,
+                "SAFEQ request to {url} timed out after {:.1} seconds",
+                after.as_secs_f64()
+            ),
+            Self::HttpStatus {
+                status,
+                body,
+                detail,
+                url,
+            } => {
                 write!(f, "SAFEQ request to {url} failed with {status}")?;
-                if !body.is_empty() {
-                    write!(f, " (response: {body})")?;
+                match detail {
+                    Some(SafeQErrorDetail {
+                        error_code: Some(code),
+                        message: Some(message),
+                    }) => write!(f, " ({code}: {message})")?,
+                    _ if !body.is_empty() => write!(f, " (response: {body})")?,
+                    _ => {}
                 }
                 Ok(())
             }
-            Self::ResponseJson(err) => write!(f, "failed to parse SAFEQ response: {err}"),
             Self::JsonParse(err) => write!(f, "failed to parse JSON: {err}"),
-            Self::MissingField(field) => write!(f, "required field missing: {field}"),
+            Self::MissingField(field) => {
+                write!(f, "SAFEQ response is missing the required field {field}")
+            }
+            Self::CardAlreadyAssigned { card_id, owner } => {
+                write!(f, "card {card_id} is already assigned to {owner}")
+            }
+            Self::UserNotFound(username) => write!(f, "user {username} does not exist"),
+        }
+    }
+}
+
+impl SafeQApiError {
+    /// Whether a failed create may have left a user behind for rollback to remove
+    ///
+    /// Only an error status qualifies, including a 409 for a conflicting
+    /// detail such as an already assigned card. After a transport error it is
+    /// unknown whether SAFEQ created anything. Callers must still confirm the
+    /// user did not exist before the create, as SAFEQ applies a create to an
+    /// existing user too.
+    pub fn may_have_created_user(&self) -> bool {
+        matches!(self, Self::HttpStatus { .. })
+    }
+
+    /// Key of this error's message in the `i18n` tables
+    pub fn message_key(&self) -> &'static str {
+        match self {
+            Self::Settings(err) => err.message_key(),
+            Self::MissingSettings => "settings.not_configured",
+            Self::InvalidBaseUrl(_) => "safeq.invalid_base_url",
+            Self::HttpClient(_) => "safeq.http_client",
+            Self::InvalidProxy(_) => "proxy.invalid",
+            Self::CaCertRead { .. } => "safeq.ca_cert_read",
+            Self::CaCertParse { .. } => "safeq.ca_cert_parse",
+            Self::Request(err) if is_tls_version_error(err) => "safeq.tls_version",
+            Self::Request(_) => "safeq.request_failed",
+            Self::Timeout { .. } => "safeq.timeout",
+            Self::HttpStatus { .. } => "safeq.http_status",
+            Self::JsonParse(_) => "safeq.json_parse",
+            Self::MissingField(_) => "safeq.missing_field",
+            Self::CardAlreadyAssigned { .. } => "safeq.card_already_assigned",
+            Self::UserNotFound(_) => "safeq.user_not_found",
         }
     }
 }
@@ -407,33 +1571,1589 @@ impl std::error::Error for SafeQApiError {
             Self::Settings(err) => Some(err),
             Self::InvalidBaseUrl(err) => Some(err),
             Self::HttpClient(err) => Some(err),
+            Self::InvalidProxy(err) => Some(err),
+            Self::CaCertRead { source, .. } => Some(source),
+            Self::CaCertParse { source, .. } => Some(source),
             Self::Request(err) => Some(err),
-            Self::ResponseJson(err) => Some(err),
             Self::JsonParse(err) => Some(err),
-            Self::MissingSettings | Self::HttpStatus { .. } | Self::MissingField(_) => None,
+            Self::MissingSettings
+            | Self::Timeout { .. }
+            | Self::HttpStatus { .. }
+            | Self::MissingField(_)
+            | Self::CardAlreadyAssigned { .. }
+            | Self::UserNotFound(_) => None,
         }
     }
 }
 
 fn truncate_body(body: &str) -> String {
-    let trimmed = body.trim();
-    if trimmed.is_empty() {
-        return String::new();
+    const DEFAULT_LIMIT: usize = 400;
+    truncate(body.trim(), error_body_limit(DEFAULT_LIMIT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{self, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use wiremock::matchers::{
+        body_string_contains, method, path, query_param, query_param_is_missing,
+    };
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Collects formatted tracing output so tests can assert on emitted events
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl CapturedLogs {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client_for(server: &MockServer) -> SafeQClient {
+        SafeQClient::from_settings(SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "test-api-key".to_string(),
+            ..SafeQSettings::default()
+        })
+        .unwrap()
+    }
+
+    fn sample_users() -> Value {
+        json!({
+            "items": [
+                { "userName": "jdoe", "fullName": "John Doe", "email": "john@example.com" },
+                { "userName": "asmith", "fullName": "Alice Smith", "email": "alice@contoso.com" },
+                { "userName": "bob", "email": null }
+            ],
+            "recordsOnPage": 3
+        })
+    }
+
+    #[test]
+    fn test_filter_users_matches_each_field() {
+        let by_username = filter_users(&sample_users(), "jdo");
+        assert_eq!(by_username["items"].as_array().unwrap().len(), 1);
+        assert_eq!(by_username["items"][0]["userName"], "jdoe");
+
+        let by_full_name = filter_users(&sample_users(), "smith");
+        assert_eq!(by_full_name["items"][0]["userName"], "asmith");
+
+        let by_email = filter_users(&sample_users(), "contoso");
+        assert_eq!(by_email["items"][0]["userName"], "asmith");
+        assert_eq!(by_email["recordsOnPage"], 1);
+    }
+
+    #[test]
+    fn test_filter_users_is_case_insensitive() {
+        let result = filter_users(&sample_users(), "  JOHN ");
+        assert_eq!(result["items"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_filter_users_empty_query_returns_all() {
+        assert_eq!(filter_users(&sample_users(), ""), sample_users());
+    }
+
+    #[tokio::test]
+    async fn test_request_events_are_logged_without_api_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 1 })))
+            .mount(&server)
+            .await;
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        client_for(&server).get_json(ACCOUNT_PATH).await.unwrap();
+
+        let output = logs.contents();
+        assert!(output.contains("sending SAFEQ request"));
+        assert!(output.contains("/api/v1/account"));
+        assert!(output.contains("status=200"));
+        assert!(!output.contains("test-api-key"));
+    }
+
+    #[tokio::test]
+    async fn test_get_account_returns_account_details() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "id": 42, "name": "Contoso" })),
+            )
+            .mount(&server)
+            .await;
+
+        let account = client_for(&server).get_account().await.unwrap();
+        assert_eq!(account["id"], 42);
+        assert_eq!(account["name"], "Contoso");
+    }
+
+    #[tokio::test]
+    async fn test_get_account_without_id_is_an_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "name": "Contoso" })))
+            .mount(&server)
+            .await;
+
+        let error = client_for(&server).get_account().await.unwrap_err();
+        assert!(matches!(&error, SafeQApiError::MissingField(field) if field == "account.id"));
+        assert!(error.to_string().contains("account.id"));
+    }
+
+    #[tokio::test]
+    async fn test_generate_pin_returns_generated_value() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "ok" })))
+            .mount(&server)
+            .await;
+
+        let settings = SafeQSettings {
+            pin_length: Some(6),
+            ..SafeQSettings::default()
+        };
+        let result = client_for(&server)
+            .generate_pin("jdoe", Some(1), &settings)
+            .await
+            .unwrap();
+
+        let pin = result["pin"].as_str().unwrap();
+        assert_eq!(pin.len(), 6);
+        assert!(pin.chars().all(|c| c.is_ascii_digit()));
+
+        let requests = server.received_requests().await.unwrap();
+        let form = String::from_utf8_lossy(&requests[0].body).to_string();
+        assert!(form.contains("detailtype=5"));
+        assert!(form.contains(&format!("detaildata={pin}")));
+    }
+
+    #[tokio::test]
+    async fn test_card_conflict_on_create_may_have_created_user() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/users"))
+            .respond_with(ResponseTemplate::new(409).set_body_string("card already assigned"))
+            .mount(&server)
+            .await;
+
+        let error = client_for(&server)
+            .create_user(
+                "jdoe",
+                Some(1),
+                None,
+                None,
+                None,
+                None,
+                Some("CARD1"),
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+
+        assert!(error.may_have_created_user());
+    }
+
+    #[tokio::test]
+    async fn test_rollback_deletes_user_from_provider() {
+        let server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let outcome = client_for(&server).rollback_user("jdoe", Some(1)).await;
+        assert!(matches!(outcome, RollbackOutcome::Deleted));
+        assert_eq!(outcome.to_json(), json!({ "status": "deleted" }));
+
+        let requests = server.received_requests().await.unwrap();
+        let delete = requests
+            .iter()
+            .find(|request| request.method.as_str() == "DELETE")
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&delete.body), "providerid=1");
+    }
+
+    #[tokio::test]
+    async fn test_rollback_reports_user_that_was_never_created() {
+        let server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let outcome = client_for(&server).rollback_user("jdoe", None).await;
+        assert!(matches!(outcome, RollbackOutcome::NotCreated));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_failure_is_reported() {
+        let server = MockServer::start().await;
+        Mock::given(method("DELETE"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let outcome = client_for(&server).rollback_user("jdoe", None).await;
+        assert!(matches!(outcome, RollbackOutcome::Failed(_)));
+        assert_eq!(outcome.to_json()["status"], "failed");
+    }
+
+    #[tokio::test]
+    async fn test_generate_otp_returns_generated_value() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "status": "ok" })))
+            .mount(&server)
+            .await;
+
+        let result = client_for(&server)
+            .generate_otp("jdoe", None, &SafeQSettings::default())
+            .await
+            .unwrap();
+
+        assert_eq!(result["otp"].as_str().unwrap().len(), 8);
+        assert!(result.get("status").is_none());
+    }
+
+    #[test]
+    fn test_from_settings_uses_default_api_port() {
+        let settings = SafeQSettings {
+            tenant_url: "https://tenant.example.com".to_string(),
+            ..SafeQSettings::default()
+        };
+        let client = SafeQClient::from_settings(settings).unwrap();
+        assert_eq!(client.base_url, "https://tenant.example.com:7300");
+    }
+
+    #[test]
+    fn test_from_settings_uses_configured_api_port() {
+        let settings = SafeQSettings {
+            tenant_url: "https://tenant.example.com".to_string(),
+            api_port: Some(443),
+            ..SafeQSettings::default()
+        };
+        let client = SafeQClient::from_settings(settings).unwrap();
+        assert_eq!(client.base_url, "https://tenant.example.com:443");
+
+        let explicit = SafeQSettings {
+            tenant_url: "https://tenant.example.com:9443".to_string(),
+            api_port: Some(443),
+            ..SafeQSettings::default()
+        };
+        let client = SafeQClient::from_settings(explicit).unwrap();
+        assert_eq!(client.base_url, "https://tenant.example.com:9443");
+    }
+
+    #[test]
+    fn test_http_client_builder_strict_tls_by_default() {
+        let settings = SafeQSettings::default();
+        let builder = format!("{:?}", http_client_builder(&settings).unwrap());
+        assert!(!builder.contains("danger_accept_invalid_certs"));
+    }
+
+    #[test]
+    fn test_http_client_builder_allows_invalid_certs() {
+        let settings = SafeQSettings {
+            allow_invalid_certs: true,
+            ..SafeQSettings::default()
+        };
+        let builder = format!("{:?}", http_client_builder(&settings).unwrap());
+        assert!(builder.contains("danger_accept_invalid_certs: true"));
+    }
+
+    #[test]
+    fn test_http_client_builder_accepts_custom_ca() {
+        let settings = SafeQSettings {
+            ca_cert_path: Some(
+                concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/test-ca.pem").to_string(),
+            ),
+            ..SafeQSettings::default()
+        };
+        let builder = http_client_builder(&settings).unwrap();
+        assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn test_http_client_builder_missing_ca_file() {
+        let settings = SafeQSettings {
+            ca_cert_path: Some("does-not-exist.pem".to_string()),
+            ..SafeQSettings::default()
+        };
+        assert!(matches!(
+            http_client_builder(&settings),
+            Err(SafeQApiError::CaCertRead { .. })
+        ));
+    }
+
+    #[test]
+    fn test_filter_users_bare_array() {
+        let users = sample_users()["items"].clone();
+        let result = filter_users(&users, "bob");
+        assert_eq!(result.as_array().unwrap().len(), 1);
     }
 
-    const LIMIT: usize = 400;
-    if trimmed.len() <= LIMIT {
-        return trimmed.to_string();
+    #[tokio::test]
+    async fn test_provider_ids_lists_account_providers() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 7 })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/authproviders"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(
+                    json!([{ "id": 1, "name": "Local" }, { "id": 4, "name": "Entra" }]),
+                ),
+            )
+            .mount(&server)
+            .await;
+
+        let ids = client_for(&server).provider_ids().await.unwrap();
+        assert_eq!(ids, HashSet::from([1, 4]));
+        assert!(!ids.contains(&99));
+    }
+
+    #[tokio::test]
+    async fn test_card_uniqueness_rejects_card_held_by_another_user() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [
+                    { "userName": "jdoe", "cards": ["1111"] },
+                    { "userName": "asmith", "cards": ["2222", "3333"] }
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server);
+        let error = client
+            .check_card_uniqueness("jdoe", Some(1), " 3333 ")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            &error,
+            SafeQApiError::CardAlreadyAssigned { owner, .. } if owner == "asmith"
+        ));
+        assert_eq!(error.to_string(), "card 3333 is already assigned to asmith");
+
+        // Re-assigning a user's own card, or a free card, is fine
+        client
+            .check_card_uniqueness("JDOE", Some(1), "1111")
+            .await
+            .unwrap();
+        client
+            .check_card_uniqueness("jdoe", Some(1), "4444")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_user_finds_user_on_a_later_page() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param_is_missing("pagetoken"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [{ "userName": "jdoe" }],
+                "nextPageToken": "page-2"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param("pagetoken", "page-2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [{ "userName": "ASmith", "email": "asmith@example.com" }]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server);
+        let user = client.get_user("asmith", Some(1)).await.unwrap().unwrap();
+        assert_eq!(user["email"], "asmith@example.com");
+        assert!(client.get_user("nobody", Some(1)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_card_uniqueness_checks_later_pages() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param_is_missing("pagetoken"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [{ "userName": "jdoe", "cards": ["1111"] }],
+                "nextPageToken": "page-2"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param("pagetoken", "page-2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [{ "userName": "asmith", "cards": ["2222"] }]
+            })))
+            .mount(&server)
+            .await;
+
+        let error = client_for(&server)
+            .check_card_uniqueness("jdoe", Some(1), "2222")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SafeQApiError::CardAlreadyAssigned { owner, .. } if owner == "asmith"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_json_error_body_is_parsed() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(409).set_body_json(json!({
+                "errorCode": "USER_EXISTS",
+                "message": "User already exists"
+            })))
+            .mount(&server)
+            .await;
+
+        let error = client_for(&server)
+            .update_user_detail("jdoe", None, UserDetailType::Email, Some("a@b.c"))
+            .await
+            .unwrap_err();
+        let SafeQApiError::HttpStatus { detail, .. } = &error else {
+            panic!("expected an HTTP status error, got {error:?}");
+        };
+        assert_eq!(
+            detail,
+            &Some(SafeQErrorDetail {
+                error_code: Some("USER_EXISTS".to_string()),
+                message: Some("User already exists".to_string()),
+            })
+        );
+        assert!(error
+            .to_string()
+            .ends_with("(USER_EXISTS: User already exists)"));
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_error_body_is_kept() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(500).set_body_string("Internal Server Error"))
+            .mount(&server)
+            .await;
+
+        let error = client_for(&server)
+            .update_user_detail("jdoe", None, UserDetailType::Email, Some("a@b.c"))
+            .await
+            .unwrap_err();
+        let SafeQApiError::HttpStatus { detail, body, .. } = &error else {
+            panic!("expected an HTTP status error, got {error:?}");
+        };
+        assert_eq!(detail, &None);
+        assert_eq!(body, "Internal Server Error");
+    }
+
+    #[test]
+    fn test_detail_type_list_matches_enum() {
+        let list = UserDetailType::list_json();
+        let list = list.as_array().unwrap();
+        assert_eq!(list.len(), UserDetailType::ALL.len());
+
+        for (entry, detail) in list.iter().zip(UserDetailType::ALL) {
+            assert_eq!(entry["id"], detail as i32);
+            assert_eq!(entry["name"], detail.name());
+        }
+        assert!(list.contains(&json!({ "id": 4, "name": "cardId" })));
+        assert!(list.contains(&json!({ "id": 10, "name": "otp" })));
+
+        let ids: HashSet<i64> = list
+            .iter()
+            .map(|entry| entry["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(ids.len(), list.len());
+    }
+
+    #[test]
+    fn test_detail_type_from_id_and_clearable() {
+        assert_eq!(UserDetailType::from_id(10), Some(UserDetailType::Otp));
+        assert_eq!(UserDetailType::from_id(4), Some(UserDetailType::CardId));
+        assert_eq!(UserDetailType::from_id(6), None);
+
+        assert!(UserDetailType::Otp.is_clearable());
+        assert!(UserDetailType::CardId.is_clearable());
+        assert!(!UserDetailType::FullName.is_clearable());
+        assert!(!UserDetailType::Email.is_clearable());
+    }
+
+    #[tokio::test]
+    async fn test_clearing_a_detail_omits_detail_data() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server);
+        for card_id in [None, Some(""), Some("  ")] {
+            client
+                .update_user_detail("jdoe", Some(1), UserDetailType::CardId, card_id)
+                .await
+                .unwrap();
+        }
+        client
+            .update_user_detail("jdoe", Some(1), UserDetailType::CardId, Some("CARD1"))
+            .await
+            .unwrap();
+
+        let bodies: Vec<String> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| String::from_utf8_lossy(&request.body).into_owned())
+            .collect();
+        assert_eq!(
+            bodies,
+            vec![
+                "detailtype=4&providerid=1",
+                "detailtype=4&providerid=1",
+                "detailtype=4&providerid=1",
+                "detailtype=4&providerid=1&detaildata=CARD1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_client_cache_reuses_client_for_unchanged_settings() {
+        let settings = SafeQSettings {
+            tenant_url: "https://tenant.example.com".to_string(),
+            api_key: "key-1".to_string(),
+            ..SafeQSettings::default()
+        };
+        let cache = ClientCache::default();
+
+        let first = cache.get_or_build(&settings, None).unwrap();
+        let second = cache.get_or_build(&settings.clone(), None).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let rotated = SafeQSettings {
+            api_key: "key-2".to_string(),
+            ..settings.clone()
+        };
+        let third = cache.get_or_build(&rotated, None).unwrap();
+        assert!(!Arc::ptr_eq(&second, &third));
+        assert_eq!(third.api_key, "key-2");
+
+        cache.invalidate();
+        let fourth = cache.get_or_build(&rotated, None).unwrap();
+        assert!(!Arc::ptr_eq(&third, &fourth));
+    }
+
+    async fn account_server_accepting(key: &str) -> MockServer {
+        use wiremock::matchers::header;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .and(header("X-Api-Key", key))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 7 })))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(401))
+            .with_priority(2)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_rotate_api_key_persists_valid_key() {
+        use crate::secrets::MockSecretStore;
+
+        let server = account_server_accepting("new-key").await;
+        let secrets = MockSecretStore::default();
+        secrets.set(SecretKey::ApiKey, "old-key").unwrap();
+        let settings = SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "old-key".to_string(),
+            ..SafeQSettings::default()
+        };
+
+        let account = rotate_api_key(&settings, " new-key ", &secrets)
+            .await
+            .unwrap();
+        assert_eq!(account["id"], 7);
+        assert_eq!(
+            secrets.get(SecretKey::ApiKey).unwrap().as_deref(),
+            Some("new-key")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rotate_api_key_keeps_old_key_when_rejected() {
+        use crate::secrets::MockSecretStore;
+
+        let server = account_server_accepting("new-key").await;
+        let secrets = MockSecretStore::default();
+        secrets.set(SecretKey::ApiKey, "old-key").unwrap();
+        let settings = SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "old-key".to_string(),
+            ..SafeQSettings::default()
+        };
+
+        let error = rotate_api_key(&settings, "typo-key", &secrets)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SafeQApiError::HttpStatus { status, .. } if status == StatusCode::UNAUTHORIZED
+        ));
+        assert_eq!(
+            secrets.get(SecretKey::ApiKey).unwrap().as_deref(),
+            Some("old-key")
+        );
+    }
+
+    async fn mount_provider_endpoints(server: &MockServer, provider_fetches: u64) {
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 7 })))
+            .expect(1)
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/authproviders"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{ "id": 1 }])))
+            .expect(provider_fetches)
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_provider_cache_hit_skips_requests() {
+        let server = MockServer::start().await;
+        mount_provider_endpoints(&server, 1).await;
+
+        let client = client_for(&server);
+        let cache = ProviderCache::default();
+        let ttl = Duration::from_secs(60);
+
+        let first = cache.get_or_fetch(&client, ttl, false).await.unwrap();
+        let second = cache.get_or_fetch(&client, ttl, false).await.unwrap();
+
+        assert_eq!(first, json!([{ "id": 1 }]));
+        assert_eq!(second, first);
+    }
+
+    #[tokio::test]
+    async fn test_provider_cache_force_refresh_refetches() {
+        let server = MockServer::start().await;
+        mount_provider_endpoints(&server, 2).await;
+
+        let client = client_for(&server);
+        let cache = ProviderCache::default();
+        let ttl = Duration::from_secs(60);
+
+        cache.get_or_fetch(&client, ttl, false).await.unwrap();
+        cache.get_or_fetch(&client, ttl, true).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_provider_cache_expired_entry_refetches() {
+        let server = MockServer::start().await;
+        mount_provider_endpoints(&server, 2).await;
+
+        let client = client_for(&server);
+        let cache = ProviderCache::default();
+
+        cache
+            .get_or_fetch(&client, Duration::ZERO, false)
+            .await
+            .unwrap();
+        cache
+            .get_or_fetch(&client, Duration::ZERO, false)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_create_user_sends_home_folder_and_password() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/users"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client_for(&server)
+            .create_user(
+                "jdoe",
+                Some(1),
+                Some("John Doe"),
+                None,
+                Some("\\\\files\\jdoe"),
+                Some("s3cret!"),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let form: Vec<(String, String)> = url::form_urlencoded::parse(&requests[0].body)
+            .into_owned()
+            .collect();
+        let pair = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(
+            form,
+            vec![
+                pair("username", "jdoe"),
+                pair("providerid", "1"),
+                pair("detailtype", "0"),
+                pair("detaildata", "John Doe"),
+                pair("detailtype", "2"),
+                pair("detaildata", "\\\\files\\jdoe"),
+                pair("detailtype", "3"),
+                pair("detaildata", "s3cret!"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_user_skips_empty_home_folder_and_password() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/users"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&server)
+            .await;
+
+        client_for(&server)
+            .create_user(
+                "jdoe",
+                None,
+                None,
+                None,
+                Some(""),
+                Some(""),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(String::from_utf8_lossy(&requests[0].body), "username=jdoe");
+    }
+
+    #[tokio::test]
+    async fn test_set_user_enabled_maps_to_expiration() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server);
+        client
+            .set_user_enabled("jdoe", Some(1), false)
+            .await
+            .unwrap();
+        client
+            .set_user_enabled("jdoe", Some(1), true)
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&requests[0].body),
+            "detailtype=12&providerid=1&detaildata=2000-01-01"
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&requests[1].body),
+            "detailtype=12&providerid=1"
+        );
+    }
+
+    #[test]
+    fn test_parse_success_body() {
+        assert_eq!(parse_success_body(None, "").unwrap(), Value::Null);
+        assert_eq!(
+            parse_success_body(Some("application/json"), "  \n").unwrap(),
+            Value::Null
+        );
+        assert_eq!(
+            parse_success_body(Some("text/plain"), "OK").unwrap(),
+            json!("OK")
+        );
+        assert_eq!(
+            parse_success_body(None, r#"{"id":1}"#).unwrap(),
+            json!({ "id": 1 })
+        );
+        assert_eq!(
+            parse_success_body(Some("application/json"), "true").unwrap(),
+            json!(true)
+        );
+        assert!(parse_success_body(Some("application/json"), "OK").is_err());
+        assert!(parse_success_body(Some("text/plain"), "{\"id\":").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_tolerates_empty_success_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let result = client_for(&server)
+            .update_user_detail("jdoe", None, UserDetailType::CardId, Some("CARD1"))
+            .await
+            .unwrap();
+        assert_eq!(result, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_times_out_without_blocking_others() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/slow"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/fast"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let client = SafeQClient::from_settings(SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "test-api-key".to_string(),
+            request_timeout_ms: Some(100),
+            ..SafeQSettings::default()
+        })
+        .unwrap();
+
+        let started = Instant::now();
+        let slow = client
+            .update_user_detail("slow", None, UserDetailType::CardId, Some("CARD1"))
+            .await;
+        assert!(matches!(
+            slow,
+            Err(SafeQApiError::Timeout { after, .. }) if after == Duration::from_millis(100)
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let fast = client
+            .update_user_detail("fast", None, UserDetailType::CardId, Some("CARD2"))
+            .await;
+        assert!(fast.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_create_tolerates_plain_text_success_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/v1/users"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("OK"))
+            .mount(&server)
+            .await;
+
+        let result = client_for(&server)
+            .create_user("jdoe", None, None, None, None, None, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(result, json!("OK"));
+    }
+
+    #[tokio::test]
+    async fn test_configured_account_id_skips_account_lookup() {
+        use wiremock::matchers::query_param;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 7 })))
+            .expect(0)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/authproviders"))
+            .and(query_param("accountid", "42"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{ "id": 1 }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = SafeQClient::from_settings(SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "test-api-key".to_string(),
+            account_id: Some(42),
+            ..SafeQSettings::default()
+        })
+        .unwrap();
+
+        let providers = client.list_auth_providers().await.unwrap();
+        assert_eq!(providers, json!([{ "id": 1 }]));
+    }
+
+    #[tokio::test]
+    async fn test_account_id_is_looked_up_when_not_configured() {
+        use wiremock::matchers::query_param;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 7 })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/authproviders"))
+            .and(query_param("accountid", "7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([{ "id": 1 }])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let providers = client_for(&server).list_auth_providers().await.unwrap();
+        assert_eq!(providers, json!([{ "id": 1 }]));
     }
 
-    let mut collected = String::new();
-    for (count, ch) in trimmed.chars().enumerate() {
-        if count >= LIMIT {
-            collected.push_str("...");
-            break;
+    #[tokio::test]
+    async fn test_mock_mode_returns_canned_users_without_network() {
+        let client = SafeQClient::from_settings(SafeQSettings {
+            mock_mode: true,
+            ..SafeQSettings::default()
+        })
+        .unwrap();
+
+        let users = client.list_users().await.unwrap();
+        let items = users["items"].as_array().unwrap();
+        assert!(!items.is_empty());
+        assert!(items.iter().all(|user| user["userName"].is_string()));
+
+        let providers = client.list_auth_providers().await.unwrap();
+        assert_eq!(providers.as_array().unwrap().len(), 2);
+
+        let created = client
+            .create_user("newuser", Some(1), None, None, None, None, None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(created["userName"], "newuser");
+
+        client
+            .update_user_detail("jdoe", Some(1), UserDetailType::CardId, Some("CARD1"))
+            .await
+            .unwrap();
+        client.delete_user("newuser", Some(1)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_count_users_by_provider() {
+        use wiremock::matchers::query_param;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 7 })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/authproviders"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "id": 1, "name": "Local" },
+                { "id": 2, "name": "Entra" },
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param("providerid", "1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(sample_users()))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param("providerid", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "items": [] })))
+            .mount(&server)
+            .await;
+
+        let counts = client_for(&server).count_users_by_provider().await.unwrap();
+        assert_eq!(
+            counts,
+            vec![
+                ProviderUserCount {
+                    provider_id: 1,
+                    provider_name: "Local".to_string(),
+                    count: 3,
+                },
+                ProviderUserCount {
+                    provider_id: 2,
+                    provider_name: "Entra".to_string(),
+                    count: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&counts[0]).unwrap(),
+            json!({ "providerId": 1, "providerName": "Local", "count": 3 })
+        );
+    }
+
+    /// Serve SAFEQ over a raw socket, dropping the connection on the first PUT
+    /// as if the response was lost after SAFEQ had handled the create
+    ///
+    /// User listings answer with `users`, or with a 500 when it is `null`.
+    fn server_dropping_first_create(users: Value) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let creates = Arc::new(AtomicUsize::new(0));
+
+        let counter = creates.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    break;
+                };
+                while let Some(request_line) = read_request(&mut stream) {
+                    if request_line.starts_with("PUT")
+                        && counter.fetch_add(1, Ordering::SeqCst) == 0
+                    {
+                        break;
+                    }
+                    let (status, body) = if request_line.starts_with("PUT") {
+                        ("200 OK", json!({}).to_string())
+                    } else if users.is_null() {
+                        ("500 Internal Server Error", json!({}).to_string())
+                    } else {
+                        ("200 OK", users.to_string())
+                    };
+                    let _ = write!(
+                        stream,
+                        "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+                        body.len()
+                    );
+                }
+            }
+        });
+
+        (url, creates)
+    }
+
+    /// Read one request and return its request line, or `None` once the peer is gone
+    fn read_request(stream: &mut TcpStream) -> Option<String> {
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            if stream.read(&mut byte).ok()? == 0 {
+                return None;
+            }
+            head.push(byte[0]);
+        }
+
+        let head = String::from_utf8_lossy(&head).into_owned();
+        let length = head
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().unwrap_or(0))
+            })
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).ok()?;
+
+        head.lines().next().map(str::to_string)
+    }
+
+    fn client_at(url: &str) -> SafeQClient {
+        SafeQClient::from_settings(SafeQSettings {
+            tenant_url: url.to_string(),
+            api_key: "test-api-key".to_string(),
+            ..SafeQSettings::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_retry_skips_user_that_already_exists() {
+        let (url, creates) = server_dropping_first_create(json!({
+            "items": [{ "userName": "jdoe", "providerId": 1 }]
+        }));
+
+        let result = client_at(&url)
+            .create_user("jdoe", Some(1), None, None, None, None, None, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(result["userName"], "jdoe");
+        assert_eq!(creates.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_retries_when_user_is_missing() {
+        let (url, creates) = server_dropping_first_create(json!({ "items": [] }));
+
+        client_at(&url)
+            .create_user("jdoe", Some(1), None, None, None, None, None, None, None)
+            .await
+            .unwrap();
+
+        assert_eq!(creates.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_create_keeps_lost_response_error_when_lookup_fails() {
+        let (url, creates) = server_dropping_first_create(Value::Null);
+
+        let error = client_at(&url)
+            .create_user("jdoe", Some(1), None, None, None, None, None, None, None)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(error, SafeQApiError::Request(_)), "{error:?}");
+        assert_eq!(creates.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_pin_settings_clamp_length() {
+        let length = |pin_length, min_pin_length| {
+            pin_settings(&SafeQSettings {
+                pin_length,
+                min_pin_length,
+                ..SafeQSettings::default()
+            })
+            .length
+        };
+
+        assert_eq!(length(None, None), 4);
+        assert_eq!(length(Some(1), None), 3);
+        assert_eq!(length(Some(40), None), 12);
+        assert_eq!(length(None, Some(6)), 6);
+        assert_eq!(
+            generate_pin_value(&SafeQSettings {
+                pin_length: Some(1),
+                ..SafeQSettings::default()
+            })
+            .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_short_id_generation_skips_excluded_characters() {
+        let settings = SafeQSettings {
+            short_id_use_uppercase: Some(false),
+            short_id_use_lowercase: Some(false),
+            short_id_exclude_characters: Some("02468".to_string()),
+            ..SafeQSettings::default()
+        };
+
+        for _ in 0..50 {
+            let short_id = generate_short_id_value(&settings);
+            assert_eq!(short_id.len(), 6);
+            assert!(short_id.chars().all(|c| "13579".contains(c)), "{short_id}");
         }
-        collected.push(ch);
     }
 
-    collected
+    async fn verify_email_against(stored_email: &str) -> DetailVerification {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .and(body_string_contains("detaildata=new%40example.com"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [{ "userName": "jdoe", "email": stored_email, "cards": [] }]
+            })))
+            .mount(&server)
+            .await;
+
+        client_for(&server)
+            .set_and_verify_detail(
+                "jdoe",
+                Some(1),
+                UserDetailType::Email,
+                Some("new@example.com"),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_set_and_verify_detail_confirms_echoed_value() {
+        let verification = verify_email_against("New@Example.com").await;
+        assert_eq!(verification.verified, Some(true));
+        assert_eq!(verification.detail, "email");
+        assert_eq!(verification.stored.as_deref(), Some("New@Example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_set_and_verify_detail_checks_pin_without_echoing_it() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [{ "userName": "jdoe", "shortId": "4821" }]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server);
+        let verification = client
+            .set_and_verify_detail("jdoe", Some(1), UserDetailType::Pin, Some("4821"))
+            .await
+            .unwrap();
+        assert_eq!(verification.verified, Some(true));
+        assert_eq!(verification.stored, None);
+
+        let verification = client
+            .set_and_verify_detail("jdoe", Some(1), UserDetailType::Password, Some("secret"))
+            .await
+            .unwrap();
+        assert_eq!(verification.verified, None);
+    }
+
+    #[tokio::test]
+    async fn test_set_and_verify_detail_flags_dropped_value() {
+        let verification = verify_email_against("old@example.com").await;
+        assert_eq!(verification.verified, Some(false));
+        assert_eq!(verification.sent.as_deref(), Some("new@example.com"));
+        assert_eq!(verification.stored.as_deref(), Some("old@example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_trace_log_records_redacted_exchange() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(500).set_body_string("could not store 482193"))
+            .mount(&server)
+            .await;
+
+        let trace_path =
+            std::env::temp_dir().join(format!("sqc-client-trace-{}.log", std::process::id()));
+        let client = client_for(&server).with_trace(TraceLog::new(trace_path.clone()));
+        client
+            .update_user_detail("jdoe", Some(1), UserDetailType::Pin, Some("482193"))
+            .await
+            .unwrap_err();
+
+        let trace = std::fs::read_to_string(&trace_path).unwrap();
+        std::fs::remove_file(&trace_path).unwrap();
+        let entry: Value = serde_json::from_str(trace.trim()).unwrap();
+        assert_eq!(entry["method"], "POST");
+        assert_eq!(entry["status"], 500);
+        assert_eq!(entry["body"], "could not store [redacted]");
+        assert!(!trace.contains("482193"));
+        assert!(!trace.contains("test-api-key"));
+    }
+
+    #[tokio::test]
+    async fn test_credential_status_reports_flags_without_values() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [
+                    {
+                        "userName": "jdoe",
+                        "fullName": "John Doe",
+                        "email": "",
+                        "shortId": "4821",
+                        "otp": null,
+                        "cards": ["  ", "CARD-1"]
+                    },
+                    { "userName": "asmith", "cards": [], "otp": "X7K2P9" }
+                ]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server);
+        let status = client
+            .get_credential_status("JDOE", Some(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            status,
+            CredentialStatus {
+                user_name: "jdoe".to_string(),
+                has_full_name: true,
+                has_email: false,
+                has_card: true,
+                has_pin: true,
+                has_otp: false,
+            }
+        );
+        assert!(!serde_json::to_string(&status).unwrap().contains("4821"));
+
+        let status = client
+            .get_credential_status("asmith", Some(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(status.has_otp && !status.has_card && !status.has_pin);
+
+        assert_eq!(
+            client
+                .get_credential_status("nobody", Some(1))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_projected_users_keep_only_requested_fields() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param("providerid", "7"))
+            .and(query_param_is_missing("pagetoken"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [{
+                    "id": 1,
+                    "userName": "jdoe",
+                    "fullName": "John Doe",
+                    "email": "jdoe@example.com",
+                    "shortId": "4821",
+                    "cards": ["1111"],
+                    "groupIds": [3, 4]
+                }],
+                "nextPageToken": "page-2"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/users/all"))
+            .and(query_param("pagetoken", "page-2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "items": [{ "id": 2, "userName": "asmith", "otp": "X7K2" }]
+            })))
+            .mount(&server)
+            .await;
+
+        let fields = projection_fields(&[
+            "userName".to_string(),
+            " email".to_string(),
+            "fullName".to_string(),
+        ])
+        .unwrap();
+        let users = client_for(&server)
+            .list_users_projected(7, &fields)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            users,
+            vec![
+                json!({ "userName": "jdoe", "fullName": "John Doe", "email": "jdoe@example.com" }),
+                json!({ "userName": "asmith" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_projection_rejects_unknown_fields() {
+        assert_eq!(
+            projection_fields(&[
+                "userName".to_string(),
+                "apiKey".to_string(),
+                "Email".to_string()
+            ]),
+            Err(vec!["apiKey".to_string(), "Email".to_string()])
+        );
+        assert_eq!(
+            projection_fields(&["cards".to_string(), "cards".to_string()]),
+            Ok(vec!["cards"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auth_header_style_selects_the_api_key_header() {
+        use wiremock::matchers::header;
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .and(header("X-Api-Key", "test-api-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 1 })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .and(header("Authorization", "Bearer test-api-key"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client_for(&server).get_account().await.unwrap();
+
+        let bearer = SafeQClient::from_settings(SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "test-api-key".to_string(),
+            auth_header_style: AuthHeaderStyle::Bearer,
+            ..SafeQSettings::default()
+        })
+        .unwrap();
+        bearer
+            .update_user_detail("jdoe", None, UserDetailType::Email, Some("j@example.com"))
+            .await
+            .unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests[0].headers.get("Authorization").is_none());
+        assert!(requests[1].headers.get("X-Api-Key").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_update_of_unknown_user_maps_to_user_not_found() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/ghost"))
+            .respond_with(ResponseTemplate::new(404).set_body_string("Not Found"))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/phantom"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "message": "No such user"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/users/jdoe"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "errorCode": "INVALID_EMAIL"
+            })))
+            .mount(&server)
+            .await;
+
+        let client = client_for(&server);
+        let error = client
+            .update_user_detail(
+                "ghost",
+                Some(1),
+                UserDetailType::Email,
+                Some("g@example.com"),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(&error, SafeQApiError::UserNotFound(name) if name == "ghost"));
+        assert_eq!(error.to_string(), "user ghost does not exist");
+        assert_eq!(error.message_key(), "safeq.user_not_found");
+
+        let error = client
+            .update_user_details("phantom", None, &[(UserDetailType::Pin, "1234")])
+            .await
+            .unwrap_err();
+        assert!(matches!(error, SafeQApiError::UserNotFound(name) if name == "phantom"));
+
+        let error = client
+            .update_user_detail("jdoe", None, UserDetailType::Email, Some("bad"))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SafeQApiError::HttpStatus {
+                status: StatusCode::BAD_REQUEST,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_paced_by_the_safeq_rate_limit() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v1/account"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "id": 1 })))
+            .expect(10)
+            .mount(&server)
+            .await;
+
+        let client = SafeQClient::from_settings(SafeQSettings {
+            tenant_url: server.uri(),
+            api_key: "test-api-key".to_string(),
+            safeq_requests_per_second: Some(5),
+            ..SafeQSettings::default()
+        })
+        .unwrap();
+
+        // Real time, as wiremock needs real IO; a burst of 5 leaves 5 requests
+        // to be paced at 5 per second
+        let started = Instant::now();
+        let results = futures::future::join_all((0..10).map(|_| client.get_account())).await;
+        let elapsed = started.elapsed();
+
+        assert!(results.iter().all(Result::is_ok));
+        assert!(elapsed >= Duration::from_millis(950), "{elapsed:?}");
+    }
 }
@@ -0,0 +1,174 @@
+// The keychain backend is unused when the in-memory mock replaces it
+#![cfg_attr(feature = "mock-keychain", allow(dead_code))]
+
+use std::fmt;
+
+use tracing::debug;
+
+/// Service name the secrets are filed under in the OS credential manager
+const KEYCHAIN_SERVICE: &str = "sqc-user-manager";
+
+/// Secrets kept in the OS keychain rather than `safeq-settings.json`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SecretKey {
+    ApiKey,
+    GraphClientSecret,
+    /// Refresh token of the user signed in for delegated Graph sends
+    GraphRefreshToken,
+    ProxyPassword,
+}
+
+impl SecretKey {
+    pub const ALL: [SecretKey; 4] = [
+        SecretKey::ApiKey,
+        SecretKey::GraphClientSecret,
+        SecretKey::GraphRefreshToken,
+        SecretKey::ProxyPassword,
+    ];
+
+    fn account(self) -> &'static str {
+        match self {
+            Self::ApiKey => "apiKey",
+            Self::GraphClientSecret => "graphClientSecret",
+            Self::GraphRefreshToken => "graphRefreshToken",
+            Self::ProxyPassword => "proxyPassword",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SecretStoreError {
+    Keychain(keyring::Error),
+}
+
+impl fmt::Display for SecretStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Keychain(error) => write!(f, "failed to access the OS keychain: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for SecretStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Keychain(error) => Some(error),
+        }
+    }
+}
+
+impl From<keyring::Error> for SecretStoreError {
+    fn from(error: keyring::Error) -> Self {
+        Self::Keychain(error)
+    }
+}
+
+/// Backend that holds secret settings values
+pub trait SecretStore: Send + Sync {
+    /// Read a secret, returning `None` when no entry exists
+    fn get(&self, key: SecretKey) -> Result<Option<String>, SecretStoreError>;
+
+    /// Store a secret; an empty value removes the entry
+    fn set(&self, key: SecretKey, value: &str) -> Result<(), SecretStoreError>;
+
+    fn delete(&self, key: SecretKey) -> Result<(), SecretStoreError>;
+}
+
+/// Secret store backed by the OS credential manager
+pub struct KeychainSecretStore;
+
+impl KeychainSecretStore {
+    fn entry(key: SecretKey) -> Result<keyring::Entry, SecretStoreError> {
+        Ok(keyring::Entry::new(KEYCHAIN_SERVICE, key.account())?)
+    }
+}
+
+impl SecretStore for KeychainSecretStore {
+    fn get(&self, key: SecretKey) -> Result<Option<String>, SecretStoreError> {
+        match Self::entry(key)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => {
+                debug!(account = key.account(), "no keychain entry");
+                Ok(None)
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn set(&self, key: SecretKey, value: &str) -> Result<(), SecretStoreError> {
+        if value.is_empty() {
+            return self.delete(key);
+        }
+        Ok(Self::entry(key)?.set_password(value)?)
+    }
+
+    fn delete(&self, key: SecretKey) -> Result<(), SecretStoreError> {
+        match Self::entry(key)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+/// Secret store used by the app
+///
+/// Builds with the `mock-keychain` feature keep secrets in memory instead, for
+/// CI machines and test runs that have no OS keychain.
+#[cfg(not(feature = "mock-keychain"))]
+pub fn default_store() -> &'static dyn SecretStore {
+    static KEYCHAIN: KeychainSecretStore = KeychainSecretStore;
+    &KEYCHAIN
+}
+
+#[cfg(feature = "mock-keychain")]
+pub fn default_store() -> &'static dyn SecretStore {
+    static MOCK: std::sync::OnceLock<MockSecretStore> = std::sync::OnceLock::new();
+    MOCK.get_or_init(MockSecretStore::default)
+}
+
+/// In-memory secret store for tests that must not touch the real keychain
+#[cfg(any(test, feature = "mock-keychain"))]
+#[derive(Debug, Default)]
+pub struct MockSecretStore {
+    values: std::sync::Mutex<std::collections::HashMap<SecretKey, String>>,
+}
+
+#[cfg(any(test, feature = "mock-keychain"))]
+impl SecretStore for MockSecretStore {
+    fn get(&self, key: SecretKey) -> Result<Option<String>, SecretStoreError> {
+        Ok(self.values.lock().unwrap().get(&key).cloned())
+    }
+
+    fn set(&self, key: SecretKey, value: &str) -> Result<(), SecretStoreError> {
+        if value.is_empty() {
+            return self.delete(key);
+        }
+        self.values.lock().unwrap().insert(key, value.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, key: SecretKey) -> Result<(), SecretStoreError> {
+        self.values.lock().unwrap().remove(&key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_store_round_trip() {
+        let store = MockSecretStore::default();
+        assert_eq!(store.get(SecretKey::ApiKey).unwrap(), None);
+
+        store.set(SecretKey::ApiKey, "key").unwrap();
+        assert_eq!(
+            store.get(SecretKey::ApiKey).unwrap().as_deref(),
+            Some("key")
+        );
+
+        store.set(SecretKey::ApiKey, "").unwrap();
+        assert_eq!(store.get(SecretKey::ApiKey).unwrap(), None);
+    }
+}
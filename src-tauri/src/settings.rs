@@ -1,21 +1,155 @@
+use std::collections::BTreeMap;
 use std::fmt;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Mutex;
 
 use serde::{Deserialize, Serialize};
-use tauri::AppHandle;
+use tauri::{AppHandle, Manager};
 use tauri_plugin_store::StoreExt;
+use tracing::info;
 
-use crate::url_utils::UrlUtils;
+use crate::email::EmailContentType;
+use crate::generator::{
+    CharsetWarning, PinSettings, ShortIdSettings, AMBIGUOUS_CHARACTERS, DEFAULT_OTP_LENGTH,
+    DEFAULT_SHORT_ID_LENGTH,
+};
+use crate::secrets::{default_store, SecretKey, SecretStore, SecretStoreError};
+use crate::url_utils::{BaseUrlError, UrlUtils};
 
 const SETTINGS_FILE: &str = "safeq-settings.json";
 const SETTINGS_KEY: &str = "safeqCredentials";
+const SCHEMA_VERSION_KEY: &str = "schemaVersion";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Version written with every saved settings payload; bump it and add a
+/// migration step whenever the stored shape changes
+pub const SETTINGS_SCHEMA_VERSION: u64 = 1;
+
+/// SAFEQ API port used when neither the tenant URL nor the settings name one
+pub const DEFAULT_API_PORT: u16 = 7300;
+
+/// Requests a bulk operation keeps in flight when not configured
+pub const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+/// Accepted values for `max_concurrency`
+pub const MAX_CONCURRENCY_RANGE: RangeInclusive<usize> = 1..=64;
+
+/// Shortest and longest PIN the app will generate
+pub const PIN_LENGTH_RANGE: RangeInclusive<usize> = 3..=12;
+
+/// PIN length used when `pin_length` is not set
+pub const DEFAULT_PIN_LENGTH: usize = 4;
+
+/// Minimum TLS version for outgoing connections, stored as `"1.2"` or `"1.3"`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MinTlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// How the API key is sent to SAFEQ, stored as `"apiKeyHeader"` or `"bearer"`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuthHeaderStyle {
+    /// `X-Api-Key: <key>`
+    #[default]
+    ApiKeyHeader,
+    /// `Authorization: Bearer <key>`
+    Bearer,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SafeQSettings {
     pub tenant_url: String,
     pub api_key: String,
+    /// API port used when the tenant URL has no explicit port (defaults to 7300)
+    #[serde(default)]
+    pub api_port: Option<u16>,
+    /// SAFEQ account id; when set, the `api/v1/account` lookup is skipped
+    #[serde(default)]
+    pub account_id: Option<i64>,
+    /// Answer SAFEQ calls with canned demo data instead of contacting a tenant
+    #[serde(default)]
+    pub mock_mode: bool,
+    /// Skip TLS certificate verification for on-prem tenants with self-signed certs
+    #[serde(default)]
+    pub allow_invalid_certs: bool,
+    /// Accept an `http://` tenant URL for on-prem or test servers; the API key is sent in plaintext
+    #[serde(default)]
+    pub allow_insecure_http: bool,
+    /// Path to a PEM-encoded root CA to trust in addition to the system roots
+    #[serde(default)]
+    pub ca_cert_path: Option<String>,
+    /// Oldest TLS version the SAFEQ and Graph clients will negotiate
+    #[serde(default)]
+    pub min_tls_version: MinTlsVersion,
+    /// Header carrying the API key; some deployments expect a Bearer token
+    #[serde(default)]
+    pub auth_header_style: AuthHeaderStyle,
+    /// HTTP/HTTPS proxy used for both SAFEQ and Microsoft Graph requests
+    #[serde(default)]
+    pub proxy_url: Option<String>,
+    #[serde(default)]
+    pub proxy_username: Option<String>,
+    /// Kept in the OS keychain; the settings file never holds it
+    #[serde(default)]
+    pub proxy_password: Option<String>,
+    /// Operator or workstation name appended to the User-Agent for access logs
+    #[serde(default)]
+    pub operator_id: Option<String>,
+    /// Lowercase usernames before creating users; they are always trimmed
+    #[serde(default)]
+    pub lowercase_usernames: bool,
+    /// Idle keep-alive connections kept per host; reqwest's default when unset
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds an idle pooled connection is kept open; reqwest's default when unset
+    #[serde(default)]
+    pub pool_idle_timeout_secs: Option<u64>,
+    /// Rows per chunk when creating users in bulk; 200 when unset
+    #[serde(default)]
+    pub batch_chunk_size: Option<usize>,
+    /// Pause between bulk creation chunks in milliseconds
+    #[serde(default)]
+    pub batch_chunk_delay_ms: Option<u64>,
+    /// Milliseconds one SAFEQ request may take before it fails as timed out;
+    /// 30 seconds when unset
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+    /// Requests in flight at once for bulk create, PIN/OTP generation and
+    /// email sends; 8 when unset, 1 sends one at a time
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+    /// Cap on SAFEQ requests per second across all commands; unlimited when
+    /// unset or 0
+    #[serde(default)]
+    pub safeq_requests_per_second: Option<u32>,
+    /// Cap on Microsoft Graph requests per second, independent of the SAFEQ one
+    #[serde(default)]
+    pub graph_requests_per_second: Option<u32>,
+    /// Write every SAFEQ request and response, secrets redacted, to a rotating
+    /// trace file in the app log directory
+    #[serde(default)]
+    pub debug_trace: bool,
+    /// Keep the main window closed at startup while `validate_settings` fails
+    #[serde(default)]
+    pub require_valid_settings_on_startup: bool,
+    /// Seconds a fetched auth provider list is reused; 300 when unset
+    #[serde(default)]
+    pub provider_cache_ttl_secs: Option<u64>,
+    /// Layout for credentials copied to the clipboard, using email template tokens
+    #[serde(default)]
+    pub clipboard_template: Option<String>,
     #[serde(default)]
     pub pin_length: Option<usize>,
+    /// Shortest `pin_length` allowed by policy, within 3..=12; 3 when unset
+    #[serde(default)]
+    pub min_pin_length: Option<usize>,
     #[serde(default)]
     pub otp_length: Option<usize>,
     #[serde(default)]
@@ -28,6 +162,10 @@ pub struct SafeQSettings {
     pub otp_use_special: Option<bool>,
     #[serde(default)]
     pub otp_exclude_characters: Option<String>,
+    /// Also exclude `AMBIGUOUS_CHARACTERS` from OTPs and short IDs, on top of
+    /// the custom exclusions
+    #[serde(default)]
+    pub exclude_ambiguous: bool,
     #[serde(default)]
     pub short_id_length: Option<usize>,
     #[serde(default)]
@@ -39,29 +177,41 @@ pub struct SafeQSettings {
     #[serde(default)]
     pub short_id_use_special: Option<bool>,
     #[serde(default)]
+    pub short_id_exclude_characters: Option<String>,
+    #[serde(default)]
     pub email_settings: EmailSettings,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub enum EmailDeliveryMethod {
+    #[default]
     Desktop,
     Graph,
 }
 
-impl Default for EmailDeliveryMethod {
-    fn default() -> Self {
-        Self::Desktop
-    }
+/// How the app signs in to Microsoft Graph
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum GraphAuthFlow {
+    /// Application permissions through the client credentials grant
+    #[default]
+    ClientCredentials,
+    /// Delegated permissions: a user signs in once with a device code and
+    /// mail is sent as that user
+    DeviceCode,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EmailTemplateSettings {
     #[serde(default)]
     pub subject: String,
     #[serde(default)]
     pub body: String,
+    /// Declared body format; when unset it is detected from the body's tags
+    #[serde(default)]
+    pub content_type: Option<EmailContentType>,
 }
 
 impl EmailTemplateSettings {
@@ -69,6 +219,7 @@ impl EmailTemplateSettings {
         Self {
             subject: "Your SAFEQ PIN".to_string(),
             body: "Hello {{fullName || userName}},\n\nYour new SAFEQ PIN is {{pin}}.\nUse this code to access printers that require a numeric PIN.\n\nThanks,\nSAFEQ Cloud Administrator".to_string(),
+            content_type: None,
         }
     }
 
@@ -76,17 +227,21 @@ impl EmailTemplateSettings {
         Self {
             subject: "Your SAFEQ OTP".to_string(),
             body: "Hello {{fullName || userName}},\n\nYour one-time password is {{otp}}.\nEnter this code when the portal or device asks for an OTP.\n\nThanks,\nSAFEQ Cloud Administrator".to_string(),
+            content_type: None,
         }
     }
 }
 
-impl Default for EmailTemplateSettings {
-    fn default() -> Self {
-        Self {
-            subject: String::new(),
-            body: String::new(),
-        }
-    }
+/// Alternative wording picked per user through their `templateKey`
+///
+/// A missing PIN or OTP template falls back to the default one.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedEmailTemplates {
+    #[serde(default)]
+    pub pin_template: Option<EmailTemplateSettings>,
+    #[serde(default)]
+    pub otp_template: Option<EmailTemplateSettings>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,6 +249,9 @@ impl Default for EmailTemplateSettings {
 pub struct EmailSettings {
     #[serde(default)]
     pub method: EmailDeliveryMethod,
+    /// Client credentials need `graph_client_secret`; device code needs a sign-in
+    #[serde(default)]
+    pub auth_flow: GraphAuthFlow,
     #[serde(default)]
     pub graph_tenant_id: Option<String>,
     #[serde(default)]
@@ -102,22 +260,44 @@ pub struct EmailSettings {
     pub graph_client_secret: Option<String>,
     #[serde(default)]
     pub graph_sender_address: Option<String>,
+    /// Keep a copy of each credential email in the sender's Sent Items
+    #[serde(default)]
+    pub graph_save_to_sent_items: Option<bool>,
+    /// Address replies should go to, e.g. a shared helpdesk mailbox
+    #[serde(default)]
+    pub graph_reply_to: Option<String>,
+    /// Friendly sender name shown instead of the raw sender address
+    #[serde(default)]
+    pub graph_from_display_name: Option<String>,
+    /// `From` written into desktop drafts, e.g. `IT Desk <it@example.com>`;
+    /// when unset the mail client uses its default account
+    #[serde(default)]
+    pub draft_from: Option<String>,
     #[serde(default = "EmailTemplateSettings::default_pin_template")]
     pub pin_template: EmailTemplateSettings,
     #[serde(default = "EmailTemplateSettings::default_otp_template")]
     pub otp_template: EmailTemplateSettings,
+    /// Named overrides, e.g. per department
+    #[serde(default)]
+    pub templates: BTreeMap<String, NamedEmailTemplates>,
 }
 
 impl Default for EmailSettings {
     fn default() -> Self {
         Self {
             method: EmailDeliveryMethod::Desktop,
+            auth_flow: GraphAuthFlow::ClientCredentials,
             graph_tenant_id: None,
             graph_client_id: None,
             graph_client_secret: None,
             graph_sender_address: None,
+            graph_save_to_sent_items: None,
+            graph_reply_to: None,
+            graph_from_display_name: None,
+            draft_from: None,
             pin_template: EmailTemplateSettings::default_pin_template(),
             otp_template: EmailTemplateSettings::default_otp_template(),
+            templates: BTreeMap::new(),
         }
     }
 }
@@ -130,8 +310,60 @@ struct StoredSafeQSettings {
     #[serde(default)]
     api_key: String,
     #[serde(default)]
+    api_port: Option<u16>,
+    #[serde(default)]
+    account_id: Option<i64>,
+    #[serde(default)]
+    mock_mode: bool,
+    #[serde(default)]
+    allow_invalid_certs: bool,
+    #[serde(default)]
+    allow_insecure_http: bool,
+    #[serde(default)]
+    ca_cert_path: Option<String>,
+    #[serde(default)]
+    min_tls_version: MinTlsVersion,
+    #[serde(default)]
+    auth_header_style: AuthHeaderStyle,
+    #[serde(default)]
+    proxy_url: Option<String>,
+    #[serde(default)]
+    proxy_username: Option<String>,
+    #[serde(default)]
+    proxy_password: Option<String>,
+    #[serde(default)]
+    operator_id: Option<String>,
+    #[serde(default)]
+    lowercase_usernames: bool,
+    #[serde(default)]
+    pool_max_idle_per_host: Option<usize>,
+    #[serde(default)]
+    pool_idle_timeout_secs: Option<u64>,
+    #[serde(default)]
+    batch_chunk_size: Option<usize>,
+    #[serde(default)]
+    batch_chunk_delay_ms: Option<u64>,
+    #[serde(default)]
+    request_timeout_ms: Option<u64>,
+    #[serde(default)]
+    max_concurrency: Option<usize>,
+    #[serde(default)]
+    safeq_requests_per_second: Option<u32>,
+    #[serde(default)]
+    graph_requests_per_second: Option<u32>,
+    #[serde(default)]
+    debug_trace: bool,
+    #[serde(default)]
+    require_valid_settings_on_startup: bool,
+    #[serde(default)]
+    provider_cache_ttl_secs: Option<u64>,
+    #[serde(default)]
+    clipboard_template: Option<String>,
+    #[serde(default)]
     pin_length: Option<usize>,
     #[serde(default)]
+    min_pin_length: Option<usize>,
+    #[serde(default)]
     otp_length: Option<usize>,
     #[serde(default)]
     otp_use_uppercase: Option<bool>,
@@ -144,6 +376,8 @@ struct StoredSafeQSettings {
     #[serde(default)]
     otp_exclude_characters: Option<String>,
     #[serde(default)]
+    exclude_ambiguous: bool,
+    #[serde(default)]
     short_id_length: Option<usize>,
     #[serde(default)]
     short_id_use_uppercase: Option<bool>,
@@ -154,6 +388,8 @@ struct StoredSafeQSettings {
     #[serde(default)]
     short_id_use_special: Option<bool>,
     #[serde(default)]
+    short_id_exclude_characters: Option<String>,
+    #[serde(default)]
     email_settings: EmailSettings,
 }
 
@@ -161,8 +397,17 @@ struct StoredSafeQSettings {
 pub enum SettingsLoadError {
     Store(tauri_plugin_store::Error),
     Deserialize(serde_json::Error),
+    Secrets(SecretStoreError),
     MissingTenantUrl,
     MissingApiKey,
+    InvalidTenantUrl(BaseUrlError),
+    InsecureTenantUrl,
+    InvalidMaxConcurrency(usize),
+    InvalidPinLength {
+        length: usize,
+        min: usize,
+        max: usize,
+    },
 }
 
 impl fmt::Display for SettingsLoadError {
@@ -170,8 +415,43 @@ impl fmt::Display for SettingsLoadError {
         match self {
             Self::Store(error) => write!(f, "failed to access SAFEQ settings store: {error}"),
             Self::Deserialize(error) => write!(f, "failed to parse SAFEQ settings: {error}"),
+            Self::Secrets(error) => write!(f, "{error}"),
             Self::MissingTenantUrl => write!(f, "tenant URL is not configured"),
             Self::MissingApiKey => write!(f, "API key is not configured"),
+            Self::InvalidTenantUrl(error) => write!(f, "tenant URL is not valid: {error}"),
+            Self::InsecureTenantUrl => write!(
+                f,
+                "tenant URL uses http://, which would send the API key unencrypted; use https:// or enable insecure HTTP"
+            ),
+            Self::InvalidMaxConcurrency(value) => write!(
+                f,
+                "max concurrency must be between {} and {}, got {value}",
+                MAX_CONCURRENCY_RANGE.start(),
+                MAX_CONCURRENCY_RANGE.end()
+            ),
+            Self::InvalidPinLength { length, min, max } => {
+                write!(
+                    f,
+                    "PIN length must be between {min} and {max} digits, got {length}"
+                )
+            }
+        }
+    }
+}
+
+impl SettingsLoadError {
+    /// Key of this error's message in the `i18n` tables
+    pub fn message_key(&self) -> &'static str {
+        match self {
+            Self::Store(_) => "settings.store",
+            Self::Deserialize(_) => "settings.parse",
+            Self::Secrets(_) => "secrets.unavailable",
+            Self::MissingTenantUrl => "settings.missing_tenant_url",
+            Self::MissingApiKey => "settings.missing_api_key",
+            Self::InvalidTenantUrl(_) => "settings.invalid_tenant_url",
+            Self::InsecureTenantUrl => "settings.insecure_tenant_url",
+            Self::InvalidMaxConcurrency(_) => "settings.invalid_max_concurrency",
+            Self::InvalidPinLength { .. } => "settings.invalid_pin_length",
         }
     }
 }
@@ -181,51 +461,1233 @@ impl std::error::Error for SettingsLoadError {
         match self {
             Self::Store(error) => Some(error),
             Self::Deserialize(error) => Some(error),
-            Self::MissingTenantUrl | Self::MissingApiKey => None,
+            Self::Secrets(error) => Some(error),
+            Self::InvalidTenantUrl(error) => Some(error),
+            Self::MissingTenantUrl
+            | Self::MissingApiKey
+            | Self::InsecureTenantUrl
+            | Self::InvalidMaxConcurrency(_)
+            | Self::InvalidPinLength { .. } => None,
         }
     }
 }
 
+impl From<SecretStoreError> for SettingsLoadError {
+    fn from(error: SecretStoreError) -> Self {
+        Self::Secrets(error)
+    }
+}
+
 pub fn load_safeq_settings(app: &AppHandle) -> Result<Option<SafeQSettings>, SettingsLoadError> {
     let store = app.store(SETTINGS_FILE).map_err(SettingsLoadError::Store)?;
 
-    if let Some(raw_value) = store.get(SETTINGS_KEY) {
-        let stored: StoredSafeQSettings =
-            serde_json::from_str(&raw_value.to_string()).map_err(SettingsLoadError::Deserialize)?;
+    let secrets = default_store();
+    let mut raw_value = store.get(SETTINGS_KEY);
+
+    if let Some(value) = raw_value.as_mut() {
+        if prepare_stored_value(value, secrets)? {
+            info!(
+                version = SETTINGS_SCHEMA_VERSION,
+                "migrated stored settings to the current schema"
+            );
+            store.set(SETTINGS_KEY, value.clone());
+            store.save().map_err(SettingsLoadError::Store)?;
+        }
+    }
+
+    settings_from_value(raw_value, secrets)
+}
+
+/// `require_valid_settings_on_startup` read straight from the store
+///
+/// Read apart from `load_safeq_settings` so the gate still holds when the
+/// stored settings fail to load.
+pub fn requires_valid_settings_on_startup(app: &AppHandle) -> bool {
+    app.store(SETTINGS_FILE)
+        .ok()
+        .and_then(|store| store.get(SETTINGS_KEY))
+        .is_some_and(|raw| stored_requires_valid_settings(&raw))
+}
+
+pub(crate) fn stored_requires_valid_settings(raw: &serde_json::Value) -> bool {
+    raw["requireValidSettingsOnStartup"]
+        .as_bool()
+        .unwrap_or(false)
+}
+
+/// Last loaded settings, so commands called in a loop skip the store and keychain
+///
+/// Only configured settings are kept; commands that change the settings must
+/// call `invalidate`.
+#[derive(Default)]
+pub struct SettingsCache {
+    cached: Mutex<Option<SafeQSettings>>,
+}
+
+impl SettingsCache {
+    pub fn get_or_load<E>(
+        &self,
+        load: impl FnOnce() -> Result<Option<SafeQSettings>, E>,
+    ) -> Result<Option<SafeQSettings>, E> {
+        let mut cached = self.cached.lock().unwrap_or_else(|err| err.into_inner());
+        if let Some(settings) = cached.as_ref() {
+            return Ok(Some(settings.clone()));
+        }
+
+        let loaded = load()?;
+        cached.clone_from(&loaded);
+        Ok(loaded)
+    }
+
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap_or_else(|err| err.into_inner()) = None;
+    }
+}
+
+/// `load_safeq_settings` through the app's `SettingsCache`
+pub fn load_cached_safeq_settings(
+    app: &AppHandle,
+) -> Result<Option<SafeQSettings>, SettingsLoadError> {
+    app.state::<SettingsCache>()
+        .get_or_load(|| load_safeq_settings(app))
+}
+
+/// Upgrade a raw settings payload to the current schema version
+///
+/// Returns `true` when the payload was changed and should be written back.
+/// Payloads without a `schemaVersion` are treated as version 0.
+fn migrate_settings(raw_value: &mut serde_json::Value) -> bool {
+    let Some(object) = raw_value.as_object_mut() else {
+        return false;
+    };
+
+    let version = object
+        .get(SCHEMA_VERSION_KEY)
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(0);
+    if version >= SETTINGS_SCHEMA_VERSION {
+        return false;
+    }
+
+    if version < 1 {
+        migrate_v0_to_v1(object);
+    }
+
+    object.insert(
+        SCHEMA_VERSION_KEY.to_string(),
+        serde_json::Value::from(SETTINGS_SCHEMA_VERSION),
+    );
+    true
+}
+
+/// Version 0 payloads may carry a tenant URL exactly as typed and no email
+/// settings block
+fn migrate_v0_to_v1(object: &mut serde_json::Map<String, serde_json::Value>) {
+    if let Some(tenant_url) = object.get("tenantUrl").and_then(|url| url.as_str()) {
+        let normalized = UrlUtils::normalize_tenant_url(tenant_url);
+        object.insert("tenantUrl".to_string(), serde_json::Value::from(normalized));
+    }
+
+    if object
+        .get("emailSettings")
+        .is_none_or(|email| email.is_null())
+    {
+        let defaults = serde_json::to_value(EmailSettings::default()).unwrap_or_default();
+        object.insert("emailSettings".to_string(), defaults);
+    }
+}
+
+/// Serialize settings for storage, stamped with the current schema version
+fn to_stored_value(settings: &SafeQSettings) -> Result<serde_json::Value, serde_json::Error> {
+    let mut value = serde_json::to_value(settings)?;
+    if let Some(object) = value.as_object_mut() {
+        object.insert(
+            SCHEMA_VERSION_KEY.to_string(),
+            serde_json::Value::from(SETTINGS_SCHEMA_VERSION),
+        );
+    }
+    Ok(value)
+}
+
+/// Remove the stored settings so the next load behaves like a fresh install
+pub fn reset_safeq_settings(app: &AppHandle) -> Result<SafeQSettings, SettingsLoadError> {
+    let store = app.store(SETTINGS_FILE).map_err(SettingsLoadError::Store)?;
+
+    store.delete(SETTINGS_KEY);
+    store.save().map_err(SettingsLoadError::Store)?;
+
+    for key in SecretKey::ALL {
+        default_store().delete(key)?;
+    }
+
+    Ok(SafeQSettings::default())
+}
+
+/// Secret values as shown in the settings form
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretSettings {
+    pub api_key: Option<String>,
+    pub graph_client_secret: Option<String>,
+    #[serde(default)]
+    pub proxy_password: Option<String>,
+}
+
+pub fn load_secret_settings() -> Result<SecretSettings, SettingsLoadError> {
+    let secrets = default_store();
+    Ok(SecretSettings {
+        api_key: secrets.get(SecretKey::ApiKey)?,
+        graph_client_secret: secrets.get(SecretKey::GraphClientSecret)?,
+        proxy_password: secrets.get(SecretKey::ProxyPassword)?,
+    })
+}
+
+/// Store secrets in the keychain; empty or missing values remove the entry
+pub fn save_secret_settings(settings: &SecretSettings) -> Result<(), SettingsLoadError> {
+    let secrets = default_store();
+    secrets.set(
+        SecretKey::ApiKey,
+        settings.api_key.as_deref().unwrap_or("").trim(),
+    )?;
+    secrets.set(
+        SecretKey::GraphClientSecret,
+        settings.graph_client_secret.as_deref().unwrap_or("").trim(),
+    )?;
+    secrets.set(
+        SecretKey::ProxyPassword,
+        settings.proxy_password.as_deref().unwrap_or(""),
+    )?;
+    Ok(())
+}
+
+/// Migrate a raw payload and move any secrets it carries into `secrets`
+///
+/// Returns `true` when the payload changed and should be written back.
+fn prepare_stored_value(
+    raw_value: &mut serde_json::Value,
+    secrets: &dyn SecretStore,
+) -> Result<bool, SecretStoreError> {
+    let migrated = migrate_settings(raw_value);
+    let moved = move_secrets_to_store(raw_value, secrets)?;
+    Ok(migrated || moved)
+}
+
+/// Copy plain-text secrets out of a settings payload into the secret store
+///
+/// Older builds wrote the API key, Graph client secret and proxy password into
+/// `safeq-settings.json`; this strips them so only the keychain holds them.
+fn move_secrets_to_store(
+    raw_value: &mut serde_json::Value,
+    secrets: &dyn SecretStore,
+) -> Result<bool, SecretStoreError> {
+    let mut moved = false;
+
+    if let Some(value) = raw_value.get_mut("apiKey") {
+        if let Some(api_key) = value.as_str().map(str::trim).filter(|key| !key.is_empty()) {
+            secrets.set(SecretKey::ApiKey, api_key)?;
+            *value = serde_json::Value::from("");
+            moved = true;
+        }
+    }
+
+    let client_secret = raw_value
+        .get_mut("emailSettings")
+        .and_then(|email| email.get_mut("graphClientSecret"));
+    if let Some(value) = client_secret {
+        if let Some(secret) = value.as_str().map(str::trim).filter(|s| !s.is_empty()) {
+            secrets.set(SecretKey::GraphClientSecret, secret)?;
+            *value = serde_json::Value::Null;
+            moved = true;
+        }
+    }
+
+    if let Some(value) = raw_value.get_mut("proxyPassword") {
+        if let Some(password) = value.as_str().filter(|password| !password.is_empty()) {
+            secrets.set(SecretKey::ProxyPassword, password)?;
+            *value = serde_json::Value::Null;
+            moved = true;
+        }
+    }
+
+    Ok(moved)
+}
+
+/// Persist settings to the store, replacing whatever was saved before
+///
+/// The tenant URL is normalized first and the save is rejected if it cannot
+/// produce a SAFEQ base URL. Returns the settings as stored.
+pub fn save_safeq_settings(
+    app: &AppHandle,
+    settings: &SafeQSettings,
+) -> Result<SafeQSettings, SettingsLoadError> {
+    let settings = normalize_for_save(settings.clone())?;
+    let store = app.store(SETTINGS_FILE).map_err(SettingsLoadError::Store)?;
+
+    let mut value = to_stored_value(&settings).map_err(SettingsLoadError::Deserialize)?;
+    move_secrets_to_store(&mut value, default_store())?;
+    store.set(SETTINGS_KEY, value);
+    store.save().map_err(SettingsLoadError::Store)?;
+    Ok(settings)
+}
+
+/// Run the checks a save would, without saving
+pub fn validate_settings(settings: &SafeQSettings) -> Result<(), SettingsLoadError> {
+    normalize_for_save(settings.clone()).map(|_| ())
+}
+
+/// Normalize the tenant URL and check it builds a base URL
+fn normalize_for_save(mut settings: SafeQSettings) -> Result<SafeQSettings, SettingsLoadError> {
+    settings.tenant_url = UrlUtils::normalize_tenant_url(&settings.tenant_url);
+    check_limits(&settings)?;
+    check_scheme(&settings)?;
+    if settings.mock_mode && settings.tenant_url.is_empty() {
+        return Ok(settings);
+    }
+    if settings.tenant_url.is_empty() {
+        return Err(SettingsLoadError::MissingTenantUrl);
+    }
+
+    UrlUtils::build_base_url(
+        &settings.tenant_url,
+        settings.api_port.unwrap_or(DEFAULT_API_PORT),
+    )
+    .map_err(SettingsLoadError::InvalidTenantUrl)?;
+    Ok(settings)
+}
+
+/// Reject a plain `http://` tenant unless `allow_insecure_http` is set
+fn check_scheme(settings: &SafeQSettings) -> Result<(), SettingsLoadError> {
+    let insecure = settings
+        .tenant_url
+        .get(..7)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("http://"));
+    if insecure && !settings.allow_insecure_http && !settings.mock_mode {
+        return Err(SettingsLoadError::InsecureTenantUrl);
+    }
+    Ok(())
+}
+
+/// Reject numeric settings outside the ranges the app supports
+fn check_limits(settings: &SafeQSettings) -> Result<(), SettingsLoadError> {
+    if let Some(value) = settings.max_concurrency {
+        if !MAX_CONCURRENCY_RANGE.contains(&value) {
+            return Err(SettingsLoadError::InvalidMaxConcurrency(value));
+        }
+    }
+
+    let max = *PIN_LENGTH_RANGE.end();
+    if let Some(min) = settings.min_pin_length {
+        if !PIN_LENGTH_RANGE.contains(&min) {
+            return Err(SettingsLoadError::InvalidPinLength {
+                length: min,
+                min: *PIN_LENGTH_RANGE.start(),
+                max,
+            });
+        }
+    }
+    if let Some(length) = settings.pin_length {
+        let min = min_pin_length(settings);
+        if !(min..=max).contains(&length) {
+            return Err(SettingsLoadError::InvalidPinLength { length, min, max });
+        }
+    }
+
+    Ok(())
+}
+
+/// Shortest PIN policy allows, never below the supported minimum
+pub fn min_pin_length(settings: &SafeQSettings) -> usize {
+    settings
+        .min_pin_length
+        .unwrap_or(*PIN_LENGTH_RANGE.start())
+        .clamp(*PIN_LENGTH_RANGE.start(), *PIN_LENGTH_RANGE.end())
+}
+
+/// PIN, OTP and short ID rules with every unset setting given its default
+///
+/// This is the one place generation defaults are applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationSettings {
+    /// Clamped to `min_pin_length` and `PIN_LENGTH_RANGE`, so a setting that
+    /// skipped validation still never yields a too-short PIN
+    pub pin_length: usize,
+    pub min_pin_length: usize,
+    pub otp: ShortIdSettings,
+    pub short_id: ShortIdSettings,
+}
+
+impl GenerationSettings {
+    pub fn resolve(settings: &SafeQSettings) -> Self {
+        let min_pin_length = min_pin_length(settings);
+        Self {
+            pin_length: settings
+                .pin_length
+                .unwrap_or(DEFAULT_PIN_LENGTH)
+                .clamp(min_pin_length, *PIN_LENGTH_RANGE.end()),
+            min_pin_length,
+            otp: charset_settings(
+                settings.otp_length.unwrap_or(DEFAULT_OTP_LENGTH),
+                [
+                    settings.otp_use_uppercase,
+                    settings.otp_use_lowercase,
+                    settings.otp_use_numbers,
+                    settings.otp_use_special,
+                ],
+                &settings.otp_exclude_characters,
+                settings.exclude_ambiguous,
+            ),
+            short_id: charset_settings(
+                settings.short_id_length.unwrap_or(DEFAULT_SHORT_ID_LENGTH),
+                [
+                    settings.short_id_use_uppercase,
+                    settings.short_id_use_lowercase,
+                    settings.short_id_use_numbers,
+                    settings.short_id_use_special,
+                ],
+                &settings.short_id_exclude_characters,
+                settings.exclude_ambiguous,
+            ),
+        }
+    }
+
+    pub fn pin(&self) -> PinSettings {
+        PinSettings {
+            length: self.pin_length,
+        }
+    }
+
+    /// Fallbacks the OTP and short ID generators would apply without saying so
+    pub fn warnings(&self) -> Vec<GenerationWarning> {
+        [("otp", &self.otp), ("shortId", &self.short_id)]
+            .into_iter()
+            .filter_map(|(setting, charset)| {
+                charset.charset_warning().map(|code| GenerationWarning {
+                    setting,
+                    code,
+                    message: format!("{setting}: {code}"),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A generation setting that doesn't produce the characters it asks for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationWarning {
+    /// `otp` or `shortId`
+    pub setting: &'static str,
+    pub code: CharsetWarning,
+    pub message: String,
+}
+
+/// Character rules from `[uppercase, lowercase, numbers, special]` flags
+///
+/// `exclude_ambiguous` adds `AMBIGUOUS_CHARACTERS` to the exclusions.
+fn charset_settings(
+    length: usize,
+    [upper, lower, numbers, special]: [Option<bool>; 4],
+    exclude_characters: &Option<String>,
+    exclude_ambiguous: bool,
+) -> ShortIdSettings {
+    let defaults = ShortIdSettings::default();
+    let mut exclude_characters = exclude_characters
+        .clone()
+        .unwrap_or(defaults.exclude_characters);
+    if exclude_ambiguous {
+        for c in AMBIGUOUS_CHARACTERS.chars() {
+            if !exclude_characters.contains(c) {
+                exclude_characters.push(c);
+            }
+        }
+    }
+
+    ShortIdSettings {
+        length,
+        use_uppercase: upper.unwrap_or(defaults.use_uppercase),
+        use_lowercase: lower.unwrap_or(defaults.use_lowercase),
+        use_numbers: numbers.unwrap_or(defaults.use_numbers),
+        use_special: special.unwrap_or(defaults.use_special),
+        exclude_characters,
+    }
+}
+
+/// Errors raised while exporting or importing a settings file
+#[derive(Debug)]
+pub enum SettingsFileError {
+    Load(SettingsLoadError),
+    NotConfigured,
+    Read { path: String, source: io::Error },
+    Write { path: String, source: io::Error },
+    Invalid(serde_json::Error),
+}
+
+impl fmt::Display for SettingsFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Load(error) => write!(f, "{error}"),
+            Self::NotConfigured => write!(f, "there are no saved settings to export"),
+            Self::Read { path, source } => {
+                write!(f, "failed to read settings file '{path}': {source}")
+            }
+            Self::Write { path, source } => {
+                write!(f, "failed to write settings file '{path}': {source}")
+            }
+            Self::Invalid(error) => write!(f, "settings file is not valid: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for SettingsFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Load(error) => Some(error),
+            Self::Read { source, .. } | Self::Write { source, .. } => Some(source),
+            Self::Invalid(error) => Some(error),
+            Self::NotConfigured => None,
+        }
+    }
+}
 
-        let tenant_url = UrlUtils::normalize_tenant_url(&stored.tenant_url);
-        let api_key = stored.api_key.trim().to_owned();
+impl From<SettingsLoadError> for SettingsFileError {
+    fn from(error: SettingsLoadError) -> Self {
+        Self::Load(error)
+    }
+}
 
-        if tenant_url.is_empty() && api_key.is_empty() {
-            return Ok(None);
+/// Drop the API key, proxy password and Graph secret from settings JSON
+fn remove_secret_keys(value: &mut serde_json::Value) {
+    if let Some(object) = value.as_object_mut() {
+        object.remove("apiKey");
+        object.remove("proxyPassword");
+        if let Some(email) = object
+            .get_mut("emailSettings")
+            .and_then(serde_json::Value::as_object_mut)
+        {
+            email.remove("graphClientSecret");
         }
+    }
+}
 
-        if tenant_url.is_empty() {
-            return Err(SettingsLoadError::MissingTenantUrl);
+impl SafeQSettings {
+    /// Overlay the non-secret, non-empty fields set in `imported` onto these settings
+    ///
+    /// `imported` is a settings file's JSON as written, so a field the file
+    /// leaves out keeps its current value rather than taking its default.
+    /// Nested objects merge field by field; empty strings, lists and `null`
+    /// keep the current value, and secrets are never taken from `imported`.
+    pub fn merged_with(&self, imported: &serde_json::Value) -> Result<Self, serde_json::Error> {
+        let mut imported = imported.clone();
+        remove_secret_keys(&mut imported);
+        if let Some(object) = imported.as_object_mut() {
+            object.remove(SCHEMA_VERSION_KEY);
         }
 
-        if api_key.is_empty() {
-            return Err(SettingsLoadError::MissingApiKey);
+        let mut current = serde_json::to_value(self)?;
+        merge_value(&mut current, imported);
+        serde_json::from_value(current)
+    }
+}
+
+fn merge_value(current: &mut serde_json::Value, imported: serde_json::Value) {
+    use serde_json::Value;
+
+    match (current, imported) {
+        (Value::Object(current), Value::Object(imported)) => {
+            for (key, value) in imported {
+                match current.get_mut(&key) {
+                    Some(existing) => merge_value(existing, value),
+                    None => {
+                        current.insert(key, value);
+                    }
+                }
+            }
         }
+        (_, Value::Null) => {}
+        (_, Value::String(value)) if value.is_empty() => {}
+        (_, Value::Array(values)) if values.is_empty() => {}
+        (current, imported) => *current = imported,
+    }
+}
+
+/// Write settings to a portable JSON file
+///
+/// Without `include_secrets` the secret fields are left out entirely, so the
+/// file can be shared safely but the API key and other secrets have to be
+/// re-entered after importing it.
+pub fn export_settings_file(
+    settings: &SafeQSettings,
+    path: &Path,
+    include_secrets: bool,
+) -> Result<(), SettingsFileError> {
+    let mut exported = to_stored_value(settings).map_err(SettingsFileError::Invalid)?;
+    if !include_secrets {
+        remove_secret_keys(&mut exported);
+    }
+
+    let contents = serde_json::to_string_pretty(&exported).map_err(SettingsFileError::Invalid)?;
+    std::fs::write(path, contents).map_err(|source| SettingsFileError::Write {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+/// Read and validate a settings file produced by `export_settings_file`
+pub fn import_settings_file(path: &Path) -> Result<SafeQSettings, SettingsFileError> {
+    parse_settings_file(&read_settings_file(path)?)
+}
+
+/// The JSON of a settings file as written, once it validates like an import
+///
+/// Used for merging, so keys the file leaves out stay absent instead of
+/// coming back as defaults.
+pub fn import_settings_keys(path: &Path) -> Result<serde_json::Value, SettingsFileError> {
+    let contents = read_settings_file(path)?;
+    parse_settings_file(&contents)?;
+    serde_json::from_str(&contents).map_err(SettingsFileError::Invalid)
+}
+
+fn read_settings_file(path: &Path) -> Result<String, SettingsFileError> {
+    std::fs::read_to_string(path).map_err(|source| SettingsFileError::Read {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+fn parse_settings_file(contents: &str) -> Result<SafeQSettings, SettingsFileError> {
+    let mut raw_value: serde_json::Value =
+        serde_json::from_str(contents).map_err(SettingsFileError::Invalid)?;
+    migrate_settings(&mut raw_value);
+
+    let stored: StoredSafeQSettings =
+        serde_json::from_value(raw_value).map_err(SettingsFileError::Invalid)?;
+
+    Ok(SafeQSettings::from(stored))
+}
+
+/// Parse the raw store value; `None` or empty credentials mean "not configured"
+///
+/// Secrets are read from `secrets`; a missing keychain entry leaves the value
+/// empty, so the settings count as unconfigured.
+pub(crate) fn settings_from_value(
+    raw_value: Option<serde_json::Value>,
+    secrets: &dyn SecretStore,
+) -> Result<Option<SafeQSettings>, SettingsLoadError> {
+    let Some(raw_value) = raw_value else {
+        return Ok(None);
+    };
+
+    let stored: StoredSafeQSettings =
+        serde_json::from_value(raw_value).map_err(SettingsLoadError::Deserialize)?;
+    let mut settings = SafeQSettings::from(stored);
+    check_limits(&settings)?;
+    check_scheme(&settings)?;
+
+    if let Some(api_key) = secrets.get(SecretKey::ApiKey)? {
+        settings.api_key = api_key.trim().to_owned();
+    }
+    if let Some(client_secret) = secrets.get(SecretKey::GraphClientSecret)? {
+        settings.email_settings.graph_client_secret = Some(client_secret);
+    }
+    if let Some(proxy_password) = secrets.get(SecretKey::ProxyPassword)? {
+        settings.proxy_password = Some(proxy_password);
+    }
 
-        Ok(Some(SafeQSettings {
-            tenant_url,
-            api_key,
+    // Demos run without a tenant, so mock mode counts as configured
+    if settings.mock_mode {
+        return Ok(Some(settings));
+    }
+
+    if settings.tenant_url.is_empty() && settings.api_key.is_empty() {
+        return Ok(None);
+    }
+
+    if settings.tenant_url.is_empty() {
+        return Err(SettingsLoadError::MissingTenantUrl);
+    }
+
+    if settings.api_key.is_empty() {
+        return Err(SettingsLoadError::MissingApiKey);
+    }
+
+    Ok(Some(settings))
+}
+
+impl From<StoredSafeQSettings> for SafeQSettings {
+    fn from(stored: StoredSafeQSettings) -> Self {
+        Self {
+            tenant_url: UrlUtils::normalize_tenant_url(&stored.tenant_url),
+            api_key: stored.api_key.trim().to_owned(),
+            api_port: stored.api_port,
+            account_id: stored.account_id,
+            mock_mode: stored.mock_mode,
+            allow_invalid_certs: stored.allow_invalid_certs,
+            allow_insecure_http: stored.allow_insecure_http,
+            ca_cert_path: stored
+                .ca_cert_path
+                .map(|path| path.trim().to_owned())
+                .filter(|path| !path.is_empty()),
+            min_tls_version: stored.min_tls_version,
+            auth_header_style: stored.auth_header_style,
+            proxy_url: stored
+                .proxy_url
+                .map(|url| url.trim().to_owned())
+                .filter(|url| !url.is_empty()),
+            proxy_username: stored.proxy_username,
+            proxy_password: stored.proxy_password,
+            operator_id: stored
+                .operator_id
+                .map(|id| id.trim().to_owned())
+                .filter(|id| !id.is_empty()),
+            lowercase_usernames: stored.lowercase_usernames,
+            pool_max_idle_per_host: stored.pool_max_idle_per_host,
+            pool_idle_timeout_secs: stored.pool_idle_timeout_secs,
+            batch_chunk_size: stored.batch_chunk_size,
+            batch_chunk_delay_ms: stored.batch_chunk_delay_ms,
+            request_timeout_ms: stored.request_timeout_ms,
+            max_concurrency: stored.max_concurrency,
+            safeq_requests_per_second: stored.safeq_requests_per_second,
+            graph_requests_per_second: stored.graph_requests_per_second,
+            debug_trace: stored.debug_trace,
+            require_valid_settings_on_startup: stored.require_valid_settings_on_startup,
+            provider_cache_ttl_secs: stored.provider_cache_ttl_secs,
+            clipboard_template: stored
+                .clipboard_template
+                .filter(|template| !template.trim().is_empty()),
             pin_length: stored.pin_length,
+            min_pin_length: stored.min_pin_length,
             otp_length: stored.otp_length,
             otp_use_uppercase: stored.otp_use_uppercase,
             otp_use_lowercase: stored.otp_use_lowercase,
             otp_use_numbers: stored.otp_use_numbers,
             otp_use_special: stored.otp_use_special,
             otp_exclude_characters: stored.otp_exclude_characters,
+            exclude_ambiguous: stored.exclude_ambiguous,
             short_id_length: stored.short_id_length,
             short_id_use_uppercase: stored.short_id_use_uppercase,
             short_id_use_lowercase: stored.short_id_use_lowercase,
             short_id_use_numbers: stored.short_id_use_numbers,
             short_id_use_special: stored.short_id_use_special,
+            short_id_exclude_characters: stored.short_id_exclude_characters,
             email_settings: stored.email_settings,
-        }))
-    } else {
-        Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::MockSecretStore;
+    use serde_json::json;
+
+    #[test]
+    fn test_missing_settings_load_as_none() {
+        assert!(settings_from_value(None, &MockSecretStore::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_reset_defaults_load_as_none() {
+        let defaults = serde_json::to_value(SafeQSettings::default()).unwrap();
+        assert!(
+            settings_from_value(Some(defaults), &MockSecretStore::default())
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_configured_settings_load() {
+        let raw = json!({ "tenantUrl": "tenant.example.com", "apiKey": " key " });
+        let settings = settings_from_value(Some(raw), &MockSecretStore::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(settings.tenant_url, "https://tenant.example.com");
+        assert_eq!(settings.api_key, "key");
+    }
+
+    #[test]
+    fn test_settings_cache_loads_once() {
+        let cache = SettingsCache::default();
+        let loads = std::cell::Cell::new(0);
+        let load = || {
+            loads.set(loads.get() + 1);
+            Ok::<_, SettingsLoadError>(Some(SafeQSettings {
+                tenant_url: "https://tenant.example.com".to_string(),
+                ..SafeQSettings::default()
+            }))
+        };
+
+        for _ in 0..3 {
+            let settings = cache.get_or_load(load).unwrap().unwrap();
+            assert_eq!(settings.tenant_url, "https://tenant.example.com");
+        }
+        assert_eq!(loads.get(), 1);
+
+        cache.invalidate();
+        cache.get_or_load(load).unwrap();
+        assert_eq!(loads.get(), 2);
+    }
+
+    #[test]
+    fn test_settings_cache_does_not_keep_missing_settings() {
+        let cache = SettingsCache::default();
+        let loads = std::cell::Cell::new(0);
+        let load = || {
+            loads.set(loads.get() + 1);
+            Ok::<_, SettingsLoadError>(None)
+        };
+
+        assert!(cache.get_or_load(load).unwrap().is_none());
+        assert!(cache.get_or_load(load).unwrap().is_none());
+        assert_eq!(loads.get(), 2);
+    }
+
+    #[test]
+    fn test_mock_mode_loads_without_tenant() {
+        let raw = json!({ "mockMode": true });
+        let settings = settings_from_value(Some(raw), &MockSecretStore::default())
+            .unwrap()
+            .unwrap();
+        assert!(settings.mock_mode);
+        assert!(normalize_for_save(settings).is_ok());
+    }
+
+    #[test]
+    fn test_max_concurrency_must_be_in_range() {
+        for value in [0, 65] {
+            let raw = json!({ "tenantUrl": "tenant.example.com", "apiKey": "key", "maxConcurrency": value });
+            assert!(
+                matches!(
+                    settings_from_value(Some(raw), &MockSecretStore::default()),
+                    Err(SettingsLoadError::InvalidMaxConcurrency(v)) if v == value
+                ),
+                "{value} should be rejected"
+            );
+        }
+
+        for value in [1, 64] {
+            let raw = json!({ "tenantUrl": "tenant.example.com", "apiKey": "key", "maxConcurrency": value });
+            let settings = settings_from_value(Some(raw), &MockSecretStore::default())
+                .unwrap()
+                .unwrap();
+            assert_eq!(settings.max_concurrency, Some(value));
+        }
+    }
+
+    #[test]
+    fn test_pin_length_bounds() {
+        let load = |extra: serde_json::Value| {
+            let mut raw = json!({ "tenantUrl": "tenant.example.com", "apiKey": "key" });
+            raw.as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            settings_from_value(Some(raw), &MockSecretStore::default())
+        };
+
+        assert!(load(json!({ "pinLength": 3 })).is_ok());
+        assert!(load(json!({ "pinLength": 12 })).is_ok());
+        for length in [1, 2, 13] {
+            assert!(
+                matches!(
+                    load(json!({ "pinLength": length })),
+                    Err(SettingsLoadError::InvalidPinLength {
+                        min: 3,
+                        max: 12,
+                        ..
+                    })
+                ),
+                "{length} should be rejected"
+            );
+        }
+
+        assert!(load(json!({ "pinLength": 4, "minPinLength": 4 })).is_ok());
+        assert!(matches!(
+            load(json!({ "pinLength": 4, "minPinLength": 6 })),
+            Err(SettingsLoadError::InvalidPinLength {
+                length: 4,
+                min: 6,
+                ..
+            })
+        ));
+        assert!(load(json!({ "minPinLength": 2 })).is_err());
+    }
+
+    #[test]
+    fn test_save_normalizes_tenant_url() {
+        let settings = SafeQSettings {
+            tenant_url: "  tenant.example.com/ ".to_string(),
+            ..SafeQSettings::default()
+        };
+        let saved = normalize_for_save(settings).unwrap();
+        assert_eq!(saved.tenant_url, "https://tenant.example.com");
+    }
+
+    #[test]
+    fn test_save_rejects_invalid_tenant_url() {
+        for tenant_url in ["https://tenant.example.com:99999", "https://"] {
+            let settings = SafeQSettings {
+                tenant_url: tenant_url.to_string(),
+                ..SafeQSettings::default()
+            };
+            assert!(
+                matches!(
+                    normalize_for_save(settings),
+                    Err(SettingsLoadError::InvalidTenantUrl(_))
+                ),
+                "{tenant_url} should be rejected"
+            );
+        }
+
+        assert!(matches!(
+            normalize_for_save(SafeQSettings::default()),
+            Err(SettingsLoadError::MissingTenantUrl)
+        ));
+    }
+
+    #[test]
+    fn test_settings_file_round_trip() {
+        let settings = SafeQSettings {
+            tenant_url: "https://tenant.example.com".to_string(),
+            api_key: "secret-key".to_string(),
+            api_port: Some(8443),
+            proxy_password: Some("proxy-secret".to_string()),
+            pin_length: Some(6),
+            ..SafeQSettings::default()
+        };
+        let path = std::env::temp_dir().join(format!("sqc-settings-{}.json", std::process::id()));
+
+        export_settings_file(&settings, &path, true).unwrap();
+        let imported = import_settings_file(&path).unwrap();
+        assert_eq!(imported.tenant_url, settings.tenant_url);
+        assert_eq!(imported.api_key, "secret-key");
+        assert_eq!(imported.api_port, Some(8443));
+        assert_eq!(imported.pin_length, Some(6));
+
+        export_settings_file(&settings, &path, false).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let imported = import_settings_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let written: serde_json::Value = serde_json::from_str(&contents).unwrap();
+        assert!(written.get("apiKey").is_none());
+        assert!(written.get("proxyPassword").is_none());
+        assert!(written["emailSettings"].get("graphClientSecret").is_none());
+        assert!(!contents.contains("secret"));
+        assert_eq!(imported.api_key, "");
+        assert_eq!(imported.proxy_password, None);
+        assert_eq!(imported.api_port, Some(8443));
+    }
+
+    #[test]
+    fn test_settings_file_rejects_garbage() {
+        assert!(matches!(
+            parse_settings_file("not json at all"),
+            Err(SettingsFileError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse_settings_file("[1, 2, 3]"),
+            Err(SettingsFileError::Invalid(_))
+        ));
+
+        let error =
+            parse_settings_file(r#"{ "tenantUrl": "x", "apiPort": "not-a-port" }"#).unwrap_err();
+        assert!(error.to_string().starts_with("settings file is not valid"));
+    }
+
+    #[test]
+    fn test_v0_payload_is_migrated() {
+        let mut raw = json!({
+            "tenantUrl": "tenant.example.com/",
+            "apiKey": "key",
+            "pinLength": 6,
+        });
+
+        assert!(migrate_settings(&mut raw));
+        assert_eq!(raw["schemaVersion"], SETTINGS_SCHEMA_VERSION);
+        assert_eq!(raw["tenantUrl"], "https://tenant.example.com");
+        assert_eq!(raw["emailSettings"]["method"], "desktop");
+
+        let settings = settings_from_value(Some(raw.clone()), &MockSecretStore::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(settings.pin_length, Some(6));
+
+        // A second load finds nothing to do, so the store is not rewritten
+        assert!(!migrate_settings(&mut raw));
+    }
+
+    #[test]
+    fn test_current_payload_is_left_alone() {
+        let settings = SafeQSettings {
+            tenant_url: "https://tenant.example.com".to_string(),
+            api_key: "key".to_string(),
+            ..SafeQSettings::default()
+        };
+        let mut raw = to_stored_value(&settings).unwrap();
+        let before = raw.clone();
+
+        assert!(!migrate_settings(&mut raw));
+        assert_eq!(raw, before);
+    }
+
+    #[test]
+    fn test_plain_text_secrets_move_to_secret_store() {
+        let secrets = MockSecretStore::default();
+        let mut raw = json!({
+            "schemaVersion": SETTINGS_SCHEMA_VERSION,
+            "tenantUrl": "https://tenant.example.com",
+            "apiKey": "file-key",
+            "proxyPassword": "proxy-secret",
+            "emailSettings": { "graphClientSecret": "graph-secret" },
+        });
+
+        assert!(prepare_stored_value(&mut raw, &secrets).unwrap());
+        assert_eq!(raw["apiKey"], "");
+        assert!(raw["proxyPassword"].is_null());
+        assert!(raw["emailSettings"]["graphClientSecret"].is_null());
+        assert!(!prepare_stored_value(&mut raw, &secrets).unwrap());
+
+        let settings = settings_from_value(Some(raw), &secrets).unwrap().unwrap();
+        assert_eq!(settings.api_key, "file-key");
+        assert_eq!(
+            settings.email_settings.graph_client_secret.as_deref(),
+            Some("graph-secret")
+        );
+        assert_eq!(settings.proxy_password.as_deref(), Some("proxy-secret"));
+    }
+
+    #[test]
+    fn test_missing_keychain_entry_is_unconfigured() {
+        let secrets = MockSecretStore::default();
+        let raw = json!({
+            "schemaVersion": SETTINGS_SCHEMA_VERSION,
+            "tenantUrl": "https://tenant.example.com",
+            "apiKey": "",
+        });
+
+        assert!(matches!(
+            settings_from_value(Some(raw.clone()), &secrets),
+            Err(SettingsLoadError::MissingApiKey)
+        ));
+
+        secrets.set(SecretKey::ApiKey, "keychain-key").unwrap();
+        let settings = settings_from_value(Some(raw), &secrets).unwrap().unwrap();
+        assert_eq!(settings.api_key, "keychain-key");
+    }
+
+    #[test]
+    fn test_http_tenant_requires_opt_in() {
+        let http = |allow_insecure_http: bool| SafeQSettings {
+            tenant_url: "http://onprem.example.local:8080".to_string(),
+            allow_insecure_http,
+            ..SafeQSettings::default()
+        };
+
+        assert!(matches!(
+            normalize_for_save(http(false)),
+            Err(SettingsLoadError::InsecureTenantUrl)
+        ));
+        let raw = json!({ "tenantUrl": "http://onprem.example.local", "apiKey": "key" });
+        assert!(matches!(
+            settings_from_value(Some(raw), &MockSecretStore::default()),
+            Err(SettingsLoadError::InsecureTenantUrl)
+        ));
+
+        let saved = normalize_for_save(http(true)).unwrap();
+        assert_eq!(saved.tenant_url, "http://onprem.example.local:8080");
+        let raw = json!({
+            "tenantUrl": "http://onprem.example.local",
+            "apiKey": "key",
+            "allowInsecureHttp": true,
+        });
+        assert!(settings_from_value(Some(raw), &MockSecretStore::default())
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_generation_defaults_are_resolved() {
+        let resolved = GenerationSettings::resolve(&SafeQSettings::default());
+
+        assert_eq!(resolved.pin_length, DEFAULT_PIN_LENGTH);
+        assert_eq!(resolved.min_pin_length, 3);
+        assert_eq!(
+            resolved.otp,
+            ShortIdSettings {
+                length: DEFAULT_OTP_LENGTH,
+                ..ShortIdSettings::default()
+            }
+        );
+        assert_eq!(resolved.short_id, ShortIdSettings::default());
+    }
+
+    #[test]
+    fn test_generation_settings_override_defaults() {
+        let resolved = GenerationSettings::resolve(&SafeQSettings {
+            pin_length: Some(2),
+            min_pin_length: Some(5),
+            otp_length: Some(10),
+            otp_use_special: Some(true),
+            otp_exclude_characters: Some(String::new()),
+            short_id_use_lowercase: Some(false),
+            ..SafeQSettings::default()
+        });
+
+        assert_eq!(resolved.pin_length, 5);
+        assert_eq!(resolved.pin().length, 5);
+        assert_eq!(resolved.otp.length, 10);
+        assert!(resolved.otp.use_special && resolved.otp.use_uppercase);
+        assert_eq!(resolved.otp.exclude_characters, "");
+        assert!(!resolved.short_id.use_lowercase);
+
+        let value = serde_json::to_value(&resolved).unwrap();
+        assert_eq!(value["pinLength"], 5);
+        assert_eq!(value["otp"]["useSpecial"], true);
+        assert_eq!(value["shortId"]["excludeCharacters"], "1lI0Oo");
+    }
+
+    #[test]
+    fn test_generation_warnings_for_disabled_classes_and_over_exclusion() {
+        assert!(GenerationSettings::resolve(&SafeQSettings::default())
+            .warnings()
+            .is_empty());
+
+        let disabled = GenerationSettings::resolve(&SafeQSettings {
+            otp_use_uppercase: Some(false),
+            otp_use_lowercase: Some(false),
+            otp_use_numbers: Some(false),
+            otp_use_special: Some(false),
+            ..SafeQSettings::default()
+        });
+        assert_eq!(
+            disabled.warnings(),
+            vec![GenerationWarning {
+                setting: "otp",
+                code: CharsetWarning::NoCharacterClass,
+                message: "otp: no character classes enabled; falling back to numeric".to_string(),
+            }]
+        );
+
+        let excluded = GenerationSettings::resolve(&SafeQSettings {
+            short_id_use_uppercase: Some(false),
+            short_id_use_lowercase: Some(false),
+            short_id_exclude_characters: Some("0123456789".to_string()),
+            ..SafeQSettings::default()
+        });
+        let warnings = excluded.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].setting, "shortId");
+        assert_eq!(warnings[0].code, CharsetWarning::EverythingExcluded);
+        assert_eq!(
+            warnings[0].message,
+            "shortId: exclusion removes entire charset; using numeric fallback"
+        );
+        assert_eq!(
+            serde_json::to_value(&warnings[0]).unwrap()["code"],
+            "everythingExcluded"
+        );
+    }
+
+    #[test]
+    fn test_settings_import_overwrite_and_merge() {
+        let existing = SafeQSettings {
+            tenant_url: "https://old.example.com".to_string(),
+            api_key: "local-key".to_string(),
+            api_port: Some(7300),
+            proxy_url: Some("http://proxy.local:8080".to_string()),
+            proxy_password: Some("local-proxy".to_string()),
+            operator_id: Some("helpdesk".to_string()),
+            ..SafeQSettings::default()
+        };
+        let mut imported = SafeQSettings {
+            tenant_url: "https://new.example.com".to_string(),
+            api_port: Some(8443),
+            pin_length: Some(6),
+            operator_id: Some(String::new()),
+            ..SafeQSettings::default()
+        };
+        imported.email_settings.graph_sender_address = Some("print@example.com".to_string());
+
+        // Overwrite takes the file as-is, wiping the local secrets
+        let path = std::env::temp_dir().join(format!("sqc-merge-{}.json", std::process::id()));
+        export_settings_file(&imported, &path, false).unwrap();
+        let overwritten = import_settings_file(&path).unwrap();
+        let mut keys = import_settings_keys(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(overwritten.api_key, "");
+        assert_eq!(overwritten.proxy_url, None);
+
+        let merged = existing.merged_with(&keys).unwrap();
+        assert_eq!(merged.tenant_url, "https://new.example.com");
+        assert_eq!(merged.api_port, Some(8443));
+        assert_eq!(merged.pin_length, Some(6));
+        assert_eq!(merged.api_key, "local-key");
+        assert_eq!(merged.proxy_password.as_deref(), Some("local-proxy"));
+        assert_eq!(merged.proxy_url.as_deref(), Some("http://proxy.local:8080"));
+        assert_eq!(merged.operator_id.as_deref(), Some("helpdesk"));
+        assert_eq!(
+            merged.email_settings.graph_sender_address.as_deref(),
+            Some("print@example.com")
+        );
+
+        // Secrets in the file are ignored when merging
+        keys["apiKey"] = json!("imported-key");
+        assert_eq!(existing.merged_with(&keys).unwrap().api_key, "local-key");
+    }
+
+    #[test]
+    fn test_settings_merge_keeps_fields_the_file_omits() {
+        let existing = SafeQSettings {
+            tenant_url: "https://old.example.com".to_string(),
+            allow_insecure_http: true,
+            debug_trace: true,
+            exclude_ambiguous: true,
+            auth_header_style: AuthHeaderStyle::Bearer,
+            ..SafeQSettings::default()
+        };
+
+        let path = std::env::temp_dir().join(format!("sqc-partial-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{ "tenantUrl": "https://new.example.com", "debugTrace": false }"#,
+        )
+        .unwrap();
+        let keys = import_settings_keys(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let merged = existing.merged_with(&keys).unwrap();
+        assert_eq!(merged.tenant_url, "https://new.example.com");
+        assert!(!merged.debug_trace);
+        assert!(merged.allow_insecure_http);
+        assert!(merged.exclude_ambiguous);
+        assert_eq!(merged.auth_header_style, AuthHeaderStyle::Bearer);
+    }
+
+    #[test]
+    fn test_exclude_ambiguous_unions_with_custom_exclusions() {
+        let curated = GenerationSettings::resolve(&SafeQSettings {
+            otp_exclude_characters: Some(String::new()),
+            exclude_ambiguous: true,
+            ..SafeQSettings::default()
+        });
+        assert_eq!(curated.otp.exclude_characters, AMBIGUOUS_CHARACTERS);
+
+        let unioned = GenerationSettings::resolve(&SafeQSettings {
+            otp_exclude_characters: Some("xO!".to_string()),
+            exclude_ambiguous: true,
+            ..SafeQSettings::default()
+        });
+        assert_eq!(unioned.otp.exclude_characters, "xO!0o1lI|5S");
+        // The default exclusions already cover most of the curated set
+        assert_eq!(unioned.short_id.exclude_characters, "1lI0Oo|5S");
+
+        let untouched = GenerationSettings::resolve(&SafeQSettings {
+            otp_exclude_characters: Some("xO!".to_string()),
+            ..SafeQSettings::default()
+        });
+        assert_eq!(untouched.otp.exclude_characters, "xO!");
     }
 }
@@ -0,0 +1,278 @@
+use std::borrow::Cow;
+
+use serde_json::Value;
+
+/// Tags that mark a template body as HTML, mirroring the frontend detection
+const HTML_TAGS: &[&str] = &[
+    "p", "div", "br", "span", "strong", "em", "h1", "h2", "h3", "h4", "h5", "h6", "ul", "ol", "li",
+    "table", "tr", "td", "th", "a", "img", "html", "body", "head",
+];
+
+/// Clipboard layout used when no `clipboardTemplate` is configured
+pub const DEFAULT_CLIPBOARD_TEMPLATE: &str =
+    "{{fullName || userName}}\nUsername: {{userName}}\nPIN: {{pin}}\nOTP: {{otp}}";
+
+/// Values available to `{{token}}` placeholders in email templates
+#[derive(Debug, Clone, Default)]
+pub struct TemplateTokens {
+    pub user_name: String,
+    pub full_name: String,
+    pub email: String,
+    pub pin: String,
+    pub otp: String,
+}
+
+impl TemplateTokens {
+    /// Build tokens from a user JSON object plus any freshly generated credentials
+    pub fn from_user(user: &Value, pin: Option<&str>, otp: Option<&str>) -> Self {
+        let field = |name: &str| user[name].as_str().unwrap_or("").trim().to_string();
+
+        Self {
+            user_name: field("userName"),
+            full_name: field("fullName"),
+            email: field("email"),
+            pin: pin.map(str::to_string).unwrap_or_else(|| field("shortId")),
+            otp: otp.map(str::to_string).unwrap_or_else(|| field("otp")),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        match key {
+            "userName" => Some(&self.user_name),
+            "fullName" => Some(&self.full_name),
+            "email" => Some(&self.email),
+            "pin" => Some(&self.pin),
+            "otp" => Some(&self.otp),
+            _ => None,
+        }
+    }
+}
+
+/// Replace `{{token}}` placeholders, supporting `{{fullName || userName}}` fallbacks
+///
+/// Unknown or empty tokens render as an empty string, matching the frontend.
+pub fn render_template(template: &str, tokens: &TemplateTokens) -> String {
+    render_with(template, tokens, Cow::Borrowed).0
+}
+
+/// Like [`render_template`], but HTML-escapes every substituted value
+///
+/// The template's own markup is kept as written; only user data is escaped,
+/// so a name like `O'Brien & <Co>` cannot break the layout or inject tags.
+pub fn render_html_template(template: &str, tokens: &TemplateTokens) -> String {
+    render_with(template, tokens, escape_html).0
+}
+
+/// Escape the characters that are significant in HTML text and attributes
+pub fn escape_html(value: &str) -> Cow<'_, str> {
+    if !value.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(value);
+    }
+
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Render `template`, also reporting whether any placeholder resolved to nothing
+fn render_with<'a>(
+    template: &str,
+    tokens: &'a TemplateTokens,
+    encode: impl Fn(&'a str) -> Cow<'a, str>,
+) -> (String, bool) {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    let mut has_empty_token = false;
+
+    while let Some(start) = rest.find("{{") {
+        let after_open = &rest[start + 2..];
+        let Some(end) = after_open.find("}}") else {
+            break;
+        };
+
+        let expression = &after_open[..end];
+        if expression.is_empty() || expression.contains('}') {
+            // Not a placeholder; keep the braces and continue scanning after them
+            rendered.push_str(&rest[..start + 2]);
+            rest = after_open;
+            continue;
+        }
+
+        rendered.push_str(&rest[..start]);
+        let value = expression
+            .split("||")
+            .map(str::trim)
+            .filter_map(|key| tokens.get(key))
+            .find(|value| !value.is_empty())
+            .unwrap_or("");
+        has_empty_token |= value.is_empty();
+        rendered.push_str(&encode(value));
+
+        rest = &after_open[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    (rendered, has_empty_token)
+}
+
+/// Render a user's credentials for the clipboard
+///
+/// Lines with a placeholder that renders empty (e.g. `OTP: {{otp}}` when no OTP
+/// was generated) are dropped so the copied text only shows what the user
+/// actually has. Static text, even a label ending in `:`, is kept.
+pub fn format_credentials(template: &str, tokens: &TemplateTokens) -> String {
+    template
+        .lines()
+        .filter_map(|line| match render_with(line, tokens, Cow::Borrowed) {
+            (_, true) => None,
+            (rendered, false) => Some(rendered.trim_end().to_string()),
+        })
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Detect whether a rendered body contains HTML markup
+pub fn is_html_content(content: &str) -> bool {
+    let lowered = content.to_lowercase();
+
+    lowered.match_indices('<').any(|(index, _)| {
+        let tag_start = &lowered[index + 1..];
+        HTML_TAGS.iter().any(|tag| {
+            tag_start.strip_prefix(tag).is_some_and(|after| {
+                after
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c == '>' || c == '/' || c.is_whitespace())
+            })
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tokens() -> TemplateTokens {
+        TemplateTokens {
+            user_name: "jdoe".to_string(),
+            full_name: String::new(),
+            email: "jdoe@example.com".to_string(),
+            pin: "1234".to_string(),
+            otp: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_render_template_replaces_tokens() {
+        assert_eq!(
+            render_template("PIN for {{ userName }}: {{pin}}", &tokens()),
+            "PIN for jdoe: 1234"
+        );
+    }
+
+    #[test]
+    fn test_render_template_uses_fallbacks() {
+        assert_eq!(
+            render_template("Hello {{fullName || userName}},", &tokens()),
+            "Hello jdoe,"
+        );
+        assert_eq!(render_template("OTP: {{otp}}", &tokens()), "OTP: ");
+        assert_eq!(render_template("{{unknown}}!", &tokens()), "!");
+    }
+
+    #[test]
+    fn test_render_template_leaves_unclosed_braces() {
+        assert_eq!(render_template("{{pin", &tokens()), "{{pin");
+    }
+
+    #[test]
+    fn test_tokens_from_user_prefer_generated_values() {
+        let user = json!({ "userName": "jdoe", "shortId": "0000", "otp": "old" });
+        let tokens = TemplateTokens::from_user(&user, Some("1234"), None);
+        assert_eq!(tokens.pin, "1234");
+        assert_eq!(tokens.otp, "old");
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("O'Brien & <Co> \"Ltd\""),
+            "O&#39;Brien &amp; &lt;Co&gt; &quot;Ltd&quot;"
+        );
+        assert!(matches!(escape_html("plain"), Cow::Borrowed("plain")));
+    }
+
+    #[test]
+    fn test_render_html_template_escapes_values_only() {
+        let tokens = TemplateTokens {
+            full_name: "O'Brien & <Co>".to_string(),
+            pin: "<script>alert(1)</script>".to_string(),
+            ..tokens()
+        };
+        assert_eq!(
+            render_html_template(
+                "<p>Hello {{fullName || userName}}</p><div class=\"pin\">{{pin}}</div>",
+                &tokens
+            ),
+            "<p>Hello O&#39;Brien &amp; &lt;Co&gt;</p><div class=\"pin\">&lt;script&gt;alert(1)&lt;/script&gt;</div>"
+        );
+        // Plain-text rendering keeps values verbatim
+        assert_eq!(
+            render_template("Hello {{fullName}}", &tokens),
+            "Hello O'Brien & <Co>"
+        );
+    }
+
+    #[test]
+    fn test_format_credentials_default_template() {
+        let tokens = TemplateTokens {
+            full_name: "John Doe".to_string(),
+            ..tokens()
+        };
+        assert_eq!(
+            format_credentials(DEFAULT_CLIPBOARD_TEMPLATE, &tokens),
+            "John Doe\nUsername: jdoe\nPIN: 1234"
+        );
+    }
+
+    #[test]
+    fn test_format_credentials_custom_template() {
+        let tokens = TemplateTokens {
+            otp: "X7K2".to_string(),
+            ..tokens()
+        };
+        assert_eq!(
+            format_credentials("{{userName}} / {{pin}} / {{otp}}", &tokens),
+            "jdoe / 1234 / X7K2"
+        );
+    }
+
+    #[test]
+    fn test_format_credentials_keeps_static_labels() {
+        let template = "Your login details:\nUsername: {{userName}}\nOTP: {{otp}}";
+        assert_eq!(
+            format_credentials(template, &tokens()),
+            "Your login details:\nUsername: jdoe"
+        );
+    }
+
+    #[test]
+    fn test_is_html_content() {
+        assert!(is_html_content("<p>Hello</p>"));
+        assert!(is_html_content("Line<br/>break"));
+        assert!(is_html_content("<DIV class=\"x\">"));
+        assert!(!is_html_content("PIN < 5 digits"));
+        assert!(!is_html_content("<pin>"));
+    }
+}
@@ -0,0 +1,85 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Overrides every error-body truncation limit, e.g. `SQC_ERROR_BODY_LIMIT=4000`
+const ERROR_BODY_LIMIT_ENV_VAR: &str = "SQC_ERROR_BODY_LIMIT";
+
+/// Marker appended by [`truncate`] when text was cut
+const TRUNCATION_MARKER: &str = "…";
+
+/// Shorten `input` to at most `limit` user-perceived characters
+///
+/// Counts grapheme clusters so emoji sequences and combining accents stay
+/// intact, and only ever slices on a cluster boundary, so multibyte UTF-8 can
+/// never cause a panic. `marker` is appended when anything was cut.
+pub fn truncate_graphemes(input: &str, limit: usize, marker: &str) -> String {
+    match input.grapheme_indices(true).nth(limit) {
+        Some((index, _)) => format!("{}{marker}", &input[..index]),
+        None => input.to_string(),
+    }
+}
+
+/// Shorten `input` to `limit` characters, marking the cut with `…`
+pub fn truncate(input: &str, limit: usize) -> String {
+    truncate_graphemes(input, limit, TRUNCATION_MARKER)
+}
+
+/// Length error bodies are cut to in logs and error messages
+///
+/// Uses `SQC_ERROR_BODY_LIMIT` when set, so a longer body can be captured
+/// while debugging, and `default` otherwise.
+pub fn error_body_limit(default: usize) -> usize {
+    parse_limit(
+        std::env::var(ERROR_BODY_LIMIT_ENV_VAR).ok().as_deref(),
+        default,
+    )
+}
+
+fn parse_limit(value: Option<&str>, default: usize) -> usize {
+    value
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|limit| *limit > 0)
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_input_is_unchanged() {
+        assert_eq!(truncate_graphemes("hello", 5, "…"), "hello");
+        assert_eq!(truncate_graphemes("", 5, "…"), "");
+    }
+
+    #[test]
+    fn test_truncates_ascii() {
+        assert_eq!(truncate_graphemes("hello world", 5, "..."), "hello...");
+    }
+
+    #[test]
+    fn test_keeps_grapheme_clusters_whole() {
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let input = family.repeat(3);
+        assert_eq!(truncate_graphemes(&input, 2, ""), family.repeat(2));
+
+        let accented = "e\u{301}".repeat(4);
+        assert_eq!(truncate_graphemes(&accented, 1, "…"), "e\u{301}…");
+    }
+
+    #[test]
+    fn test_truncate_around_limit() {
+        assert_eq!(truncate("abcd", 5), "abcd");
+        assert_eq!(truncate("abcde", 5), "abcde");
+        assert_eq!(truncate("abcdef", 5), "abcde…");
+        assert_eq!(truncate("ééééé", 5), "ééééé");
+        assert_eq!(truncate("éééééé", 5), "ééééé…");
+    }
+
+    #[test]
+    fn test_parse_limit() {
+        assert_eq!(parse_limit(None, 400), 400);
+        assert_eq!(parse_limit(Some(" 2000 "), 400), 2000);
+        assert_eq!(parse_limit(Some("0"), 400), 400);
+        assert_eq!(parse_limit(Some("lots"), 400), 400);
+    }
+}
@@ -0,0 +1,312 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
+
+use crate::safeq_api::UserDetailType;
+use crate::text::truncate;
+
+/// Trace file name inside the app log directory
+pub const TRACE_FILE: &str = "safeq-trace.log";
+
+/// Size at which the trace file is rotated
+const MAX_TRACE_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Rotated files kept next to the current one (`.1` is the newest)
+const TRACE_BACKUPS: usize = 3;
+
+/// Longest response body kept per entry
+const MAX_TRACE_BODY_CHARS: usize = 4000;
+
+/// Replacement for every redacted value
+const REDACTED: &str = "[redacted]";
+
+/// Form fields whose value is always redacted
+const SECRET_FIELDS: [&str; 5] = ["apikey", "api_key", "password", "pin", "otp"];
+
+/// JSON keys whose value is redacted in response bodies, compared case-insensitively
+const SECRET_BODY_KEYS: [&str; 5] = ["shortid", "pin", "otp", "password", "apikey"];
+
+/// Detail types whose `detaildata` is redacted
+const SECRET_DETAILS: [UserDetailType; 3] = [
+    UserDetailType::Password,
+    UserDetailType::Pin,
+    UserDetailType::Otp,
+];
+
+/// One SAFEQ request and what came back, with secrets already removed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceEntry {
+    pub timestamp: DateTime<Utc>,
+    pub method: String,
+    pub url: String,
+    pub form: Vec<(String, String)>,
+    pub status: Option<u16>,
+    pub body: Option<String>,
+    /// Set when no response was received
+    pub error: Option<String>,
+}
+
+impl TraceEntry {
+    /// Build a redacted entry
+    ///
+    /// Secret form values and `api_key` are also scrubbed from the URL, body
+    /// and error text in case the server echoes them back. JSON bodies also
+    /// lose the values of `SECRET_BODY_KEYS`, e.g. every user's PIN and OTP in
+    /// a user listing.
+    pub fn new(
+        method: &str,
+        url: &str,
+        form: Option<&[(&str, String)]>,
+        api_key: &str,
+        status: Option<u16>,
+        body: Option<&str>,
+        error: Option<&str>,
+    ) -> Self {
+        let (form, mut secrets) = redact_form(form.unwrap_or_default());
+        secrets.push(api_key.trim().to_string());
+        secrets.retain(|secret| !secret.is_empty());
+        // Longest first, so a secret containing another is masked whole
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+
+        let scrub = |text: &str| redact_text(text, &secrets);
+        Self {
+            timestamp: Utc::now(),
+            method: method.to_string(),
+            url: scrub(url),
+            form,
+            status,
+            body: body.map(|body| truncate(&scrub(&redact_body(body)), MAX_TRACE_BODY_CHARS)),
+            error: error.map(scrub),
+        }
+    }
+}
+
+/// Copy of `form` with secret values masked, plus the values that were masked
+///
+/// SAFEQ sends details as `detailtype`/`detaildata` pairs, so a `detaildata`
+/// is masked when the `detailtype` before it is a password, PIN or OTP.
+pub fn redact_form(form: &[(&str, String)]) -> (Vec<(String, String)>, Vec<String>) {
+    let mut secrets = Vec::new();
+    let mut secret_detail = false;
+
+    let redacted = form
+        .iter()
+        .map(|(key, value)| {
+            let key_lower = key.to_ascii_lowercase();
+            let secret = match key_lower.as_str() {
+                "detailtype" => {
+                    secret_detail = value
+                        .trim()
+                        .parse()
+                        .ok()
+                        .and_then(UserDetailType::from_id)
+                        .is_some_and(|detail| SECRET_DETAILS.contains(&detail));
+                    false
+                }
+                "detaildata" => secret_detail,
+                other => SECRET_FIELDS.contains(&other),
+            };
+
+            if secret {
+                secrets.push(value.clone());
+                (key.to_string(), REDACTED.to_string())
+            } else {
+                (key.to_string(), value.clone())
+            }
+        })
+        .collect();
+
+    (redacted, secrets)
+}
+
+/// `body` with secret JSON values masked; non-JSON bodies are returned as-is
+fn redact_body(body: &str) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(mut value) => {
+            redact_json(&mut value);
+            value.to_string()
+        }
+        Err(_) => body.to_string(),
+    }
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if SECRET_BODY_KEYS.contains(&key.to_ascii_lowercase().as_str()) {
+                    if !value.is_null() {
+                        *value = Value::from(REDACTED);
+                    }
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+/// `text` with every occurrence of `secrets` masked
+pub fn redact_text(text: &str, secrets: &[String]) -> String {
+    secrets.iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret.as_str(), REDACTED)
+    })
+}
+
+/// Append-only JSON-lines trace file that rotates at `MAX_TRACE_BYTES`
+#[derive(Debug)]
+pub struct TraceLog {
+    path: PathBuf,
+    /// Serialises writes so concurrent requests don't interleave lines
+    lock: Mutex<()>,
+}
+
+impl TraceLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Append `entry`; failures are logged and otherwise ignored
+    pub fn record(&self, entry: &TraceEntry) {
+        let _guard = self.lock.lock().unwrap_or_else(|err| err.into_inner());
+        if let Err(err) = self.append(entry) {
+            warn!(path = %self.path.display(), error = %err, "failed to write SAFEQ trace");
+        }
+    }
+
+    fn append(&self, entry: &TraceEntry) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if fs::metadata(&self.path).is_ok_and(|meta| meta.len() >= MAX_TRACE_BYTES) {
+            rotate(&self.path)?;
+        }
+
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+}
+
+/// Shift `trace.log` to `trace.log.1`, `.1` to `.2` and so on, dropping the oldest
+fn rotate(path: &Path) -> io::Result<()> {
+    let backup = |index: usize| PathBuf::from(format!("{}.{index}", path.display()));
+
+    for index in (1..TRACE_BACKUPS).rev() {
+        if backup(index).exists() {
+            fs::rename(backup(index), backup(index + 1))?;
+        }
+    }
+    fs::rename(path, backup(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction_masks_api_key_and_credentials() {
+        let form = vec![
+            ("username", "jdoe".to_string()),
+            ("detailtype", "1".to_string()),
+            ("detaildata", "jdoe@example.com".to_string()),
+            ("detailtype", "5".to_string()),
+            ("detaildata", "4821".to_string()),
+            ("detailtype", "10".to_string()),
+            ("detaildata", "X7K2P9".to_string()),
+            ("detailtype", "3".to_string()),
+            ("detaildata", "hunter2".to_string()),
+        ];
+        let entry = TraceEntry::new(
+            "PUT",
+            "https://tenant.example.com:7300/api/v1/users?key=sk-live-123",
+            Some(&form),
+            "sk-live-123",
+            Some(400),
+            Some(r#"{"error":"PIN 4821 rejected for key sk-live-123"}"#),
+            None,
+        );
+
+        let serialized = serde_json::to_string(&entry).unwrap();
+        for secret in ["4821", "X7K2P9", "hunter2", "sk-live-123"] {
+            assert!(
+                !serialized.contains(secret),
+                "{secret} leaked: {serialized}"
+            );
+        }
+        assert_eq!(entry.form[2].1, "jdoe@example.com");
+        assert_eq!(entry.form[4].1, REDACTED);
+        assert_eq!(entry.form[6].1, REDACTED);
+        assert_eq!(entry.form[8].1, REDACTED);
+        assert_eq!(
+            entry.body.as_deref(),
+            Some(r#"{"error":"PIN [redacted] rejected for key [redacted]"}"#)
+        );
+    }
+
+    #[test]
+    fn test_user_listing_bodies_hide_credentials() {
+        let body = serde_json::json!({
+            "items": [
+                { "userName": "jdoe", "shortId": "4821", "otp": "X7K2P9", "cards": ["C-1"] },
+                { "userName": "asmith", "pin": "9931", "shortId": null },
+            ],
+            "nextPageToken": "page-2",
+        })
+        .to_string();
+        let entry = TraceEntry::new(
+            "GET",
+            "https://tenant.example.com:7300/api/v1/users/all",
+            None,
+            "key",
+            Some(200),
+            Some(&body),
+            None,
+        );
+
+        let traced: Value = serde_json::from_str(entry.body.as_deref().unwrap()).unwrap();
+        assert_eq!(traced["items"][0]["shortId"], REDACTED);
+        assert_eq!(traced["items"][0]["otp"], REDACTED);
+        assert_eq!(traced["items"][1]["pin"], REDACTED);
+        assert_eq!(traced["items"][1]["shortId"], Value::Null);
+        assert_eq!(traced["items"][0]["userName"], "jdoe");
+        assert_eq!(traced["items"][0]["cards"][0], "C-1");
+        assert_eq!(traced["nextPageToken"], "page-2");
+    }
+
+    #[test]
+    fn test_trace_log_rotates_full_files() {
+        let dir = std::env::temp_dir().join(format!("sqc-trace-{}", std::process::id()));
+        let path = dir.join(TRACE_FILE);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, vec![b'x'; MAX_TRACE_BYTES as usize]).unwrap();
+
+        let log = TraceLog::new(path.clone());
+        let entry = TraceEntry::new("GET", "/api/v1/account", None, "key", Some(200), None, None);
+        log.record(&entry);
+
+        let current = fs::read_to_string(&path).unwrap();
+        let rotated = fs::metadata(format!("{}.1", path.display())).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(current.lines().count(), 1);
+        assert!(current.contains("/api/v1/account"));
+        assert_eq!(rotated.len(), MAX_TRACE_BYTES);
+    }
+}
@@ -0,0 +1,407 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tauri::{AppHandle, Manager, WebviewUrl, WebviewWindowBuilder};
+use tracing::{debug, info, warn};
+
+use crate::settings::{
+    load_safeq_settings, requires_valid_settings_on_startup, validate_settings, SafeQSettings,
+    SettingsLoadError,
+};
+
+const MAIN_LABEL: &str = "main";
+const SPLASH_LABEL: &str = "splashscreen";
+
+/// Seconds to wait for the frontend before the splash is closed anyway
+const SPLASH_TIMEOUT_ENV_VAR: &str = "SQC_SPLASH_TIMEOUT_SECS";
+/// Set to `1`/`true` to open the main window directly, e.g. when debugging;
+/// this also skips a startup blocker
+const SKIP_SPLASH_ENV_VAR: &str = "SQC_SKIP_SPLASH";
+const DEFAULT_SPLASH_TIMEOUT: Duration = Duration::from_secs(15);
+/// Optional file in the bundle's resource directory that overrides `WindowConfig`
+const BRANDING_FILE: &str = "branding.json";
+
+/// Window title and pages, overridable for white-label builds
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WindowConfig {
+    pub title: String,
+    pub main_page: String,
+    pub splash_page: String,
+    /// Frontend dev server used instead of bundled pages in `tauri dev`
+    pub dev_server_url: String,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "SAFEQ Cloud User Manager".to_string(),
+            main_page: "index.html".to_string(),
+            splash_page: "splash.html".to_string(),
+            dev_server_url: "http://localhost:1420".to_string(),
+        }
+    }
+}
+
+impl WindowConfig {
+    /// Read `branding.json` from the resource directory, falling back to defaults
+    pub fn load(app: &AppHandle) -> Self {
+        let contents = app
+            .path()
+            .resource_dir()
+            .ok()
+            .map(|dir| dir.join(BRANDING_FILE))
+            .filter(|path| path.exists())
+            .and_then(|path| match std::fs::read_to_string(&path) {
+                Ok(contents) => Some(contents),
+                Err(error) => {
+                    warn!(%error, path = %path.display(), "failed to read branding file");
+                    None
+                }
+            });
+
+        Self::from_json(contents.as_deref())
+    }
+
+    /// Apply a (possibly partial) JSON override on top of the defaults
+    fn from_json(contents: Option<&str>) -> Self {
+        let Some(contents) = contents else {
+            return Self::default();
+        };
+
+        match serde_json::from_str(contents) {
+            Ok(config) => {
+                info!("loaded window branding overrides");
+                config
+            }
+            Err(error) => {
+                warn!(%error, "ignoring invalid branding file");
+                Self::default()
+            }
+        }
+    }
+
+    /// URL for a page: the dev server during `tauri dev`, bundled assets otherwise
+    fn page_url(&self, page: &str, dev: bool) -> WebviewUrl {
+        if dev {
+            let url = format!("{}/{}", self.dev_server_url.trim_end_matches('/'), page);
+            match url.parse() {
+                Ok(url) => return WebviewUrl::External(url),
+                Err(error) => warn!(%error, url, "invalid dev server URL; using bundled page"),
+            }
+        }
+        WebviewUrl::App(page.into())
+    }
+
+    fn main_url(&self) -> WebviewUrl {
+        // The dev server serves the main page at its root
+        let page = if cfg!(dev) {
+            ""
+        } else {
+            self.main_page.as_str()
+        };
+        self.page_url(page, cfg!(dev))
+    }
+
+    fn splash_url(&self) -> WebviewUrl {
+        self.page_url(&self.splash_page, cfg!(dev))
+    }
+}
+
+/// Makes sure only one caller ever builds the main window
+///
+/// Both the frontend's `close_splashscreen` call and the splash timeout may
+/// try to open the main window at nearly the same moment.
+#[derive(Debug, Default)]
+pub struct MainWindowGuard {
+    created: AtomicBool,
+}
+
+impl MainWindowGuard {
+    /// Returns `true` for the first caller only
+    fn claim(&self) -> bool {
+        !self.created.swap(true, Ordering::SeqCst)
+    }
+
+    /// Run `build` for the first caller only; `Ok(None)` when another caller won
+    ///
+    /// A failed build releases the claim so a later call can try again.
+    pub fn create_once<T, E>(&self, build: impl FnOnce() -> Result<T, E>) -> Result<Option<T>, E> {
+        if !self.claim() {
+            return Ok(None);
+        }
+        build()
+            .map(Some)
+            .inspect_err(|_| self.created.store(false, Ordering::SeqCst))
+    }
+}
+
+/// Whether the splash screen should be skipped, from `SQC_SKIP_SPLASH`
+pub fn skip_splash() -> bool {
+    parse_flag(std::env::var(SKIP_SPLASH_ENV_VAR).ok().as_deref())
+}
+
+/// Splash timeout from `SQC_SPLASH_TIMEOUT_SECS`; `0` disables the timeout
+pub fn splash_timeout() -> Option<Duration> {
+    parse_timeout(std::env::var(SPLASH_TIMEOUT_ENV_VAR).ok().as_deref())
+}
+
+fn parse_flag(value: Option<&str>) -> bool {
+    value.is_some_and(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+fn parse_timeout(value: Option<&str>) -> Option<Duration> {
+    let Some(value) = value else {
+        return Some(DEFAULT_SPLASH_TIMEOUT);
+    };
+
+    match value.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            warn!(value, "ignoring invalid {SPLASH_TIMEOUT_ENV_VAR}");
+            Some(DEFAULT_SPLASH_TIMEOUT)
+        }
+    }
+}
+
+pub fn create_splashscreen(app: &AppHandle) -> Result<(), tauri::Error> {
+    let config = app.state::<WindowConfig>();
+
+    WebviewWindowBuilder::new(app, SPLASH_LABEL, config.splash_url())
+        .title(&config.title)
+        .inner_size(600.0, 400.0)
+        .resizable(false)
+        .decorations(false)
+        .always_on_top(true)
+        .skip_taskbar(true)
+        .center()
+        .build()?;
+
+    Ok(())
+}
+
+/// Why startup is blocked, if `require_valid_settings_on_startup` is set
+///
+/// A blocked start keeps the splash up with the error until the user opens the
+/// main window, which then only shows the settings page. The flag is read from the raw store, so settings that fail to load still
+/// block when it is on. Loading runs first to migrate the stored value.
+pub fn startup_blocker(app: &AppHandle) -> Option<SettingsLoadError> {
+    let loaded = load_safeq_settings(app);
+    startup_blocker_for(requires_valid_settings_on_startup(app), loaded)
+}
+
+fn startup_blocker_for(
+    required: bool,
+    loaded: Result<Option<SafeQSettings>, SettingsLoadError>,
+) -> Option<SettingsLoadError> {
+    if !required {
+        return None;
+    }
+    match loaded {
+        Ok(Some(settings)) => validate_settings(&settings).err(),
+        Ok(None) => Some(SettingsLoadError::MissingTenantUrl),
+        Err(error) => Some(error),
+    }
+}
+
+/// Close the splash after the timeout unless the frontend already did
+pub fn schedule_splash_timeout(app: &AppHandle, timeout: Duration) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(timeout);
+        if app.get_webview_window(SPLASH_LABEL).is_none() {
+            return;
+        }
+
+        if let Some(error) = startup_blocker(&app) {
+            warn!(%error, "settings are invalid; keeping the splash screen open");
+            return;
+        }
+        warn!(
+            seconds = timeout.as_secs(),
+            "frontend did not close the splash screen in time; showing the main window"
+        );
+        if let Err(error) = show_main_window(&app) {
+            warn!(%error, "failed to show the main window after the splash timeout");
+        }
+    });
+}
+
+/// Show (creating it once) the main window and close the splash screen
+pub fn show_main_window(app: &AppHandle) -> Result<(), tauri::Error> {
+    let guard = app.state::<MainWindowGuard>();
+    let config = app.state::<WindowConfig>();
+
+    let main_window = match app.get_webview_window(MAIN_LABEL) {
+        Some(window) => {
+            debug!("main window already exists, showing it");
+            window
+        }
+        None => {
+            let created = guard.create_once(|| {
+                WebviewWindowBuilder::new(app, MAIN_LABEL, config.main_url())
+                    .title(&config.title)
+                    .inner_size(1200.0, 800.0)
+                    .center()
+                    .build()
+            })?;
+            let Some(window) = created else {
+                // Another caller is building the window right now
+                debug!("main window is already being created");
+                return Ok(());
+            };
+            window
+        }
+    };
+
+    main_window.show()?;
+    main_window.set_focus()?;
+
+    // Close the splashscreen window AFTER main window is shown
+    if let Some(splashscreen) = app.get_webview_window(SPLASH_LABEL) {
+        splashscreen.close()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::MockSecretStore;
+    use crate::settings::{settings_from_value, stored_requires_valid_settings};
+    use serde_json::json;
+
+    #[test]
+    fn test_guard_allows_single_creation() {
+        let guard = MainWindowGuard::default();
+        assert!(guard.claim());
+        assert!(!guard.claim());
+        assert!(!guard.claim());
+    }
+
+    #[test]
+    fn test_failed_creation_releases_the_guard() {
+        let guard = MainWindowGuard::default();
+
+        let failed = guard.create_once(|| Err::<(), _>("no display"));
+        assert_eq!(failed, Err("no display"));
+
+        assert_eq!(
+            guard.create_once(|| Ok::<_, ()>("window")),
+            Ok(Some("window"))
+        );
+        assert_eq!(guard.create_once(|| Ok::<_, ()>("second")), Ok(None));
+    }
+
+    #[test]
+    fn test_guard_across_threads() {
+        let guard = std::sync::Arc::new(MainWindowGuard::default());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let guard = guard.clone();
+                std::thread::spawn(move || guard.claim())
+            })
+            .collect();
+
+        let winners = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|claimed| *claimed)
+            .count();
+        assert_eq!(winners, 1);
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout(None), Some(DEFAULT_SPLASH_TIMEOUT));
+        assert_eq!(parse_timeout(Some("5")), Some(Duration::from_secs(5)));
+        assert_eq!(parse_timeout(Some("0")), None);
+        assert_eq!(parse_timeout(Some("soon")), Some(DEFAULT_SPLASH_TIMEOUT));
+    }
+
+    #[test]
+    fn test_parse_flag() {
+        assert!(parse_flag(Some("1")));
+        assert!(parse_flag(Some("TRUE")));
+        assert!(!parse_flag(Some("0")));
+        assert!(!parse_flag(None));
+    }
+
+    #[test]
+    fn test_window_config_defaults_without_override() {
+        assert_eq!(WindowConfig::from_json(None), WindowConfig::default());
+        assert_eq!(
+            WindowConfig::from_json(Some("not json")),
+            WindowConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_window_config_partial_override() {
+        let config = WindowConfig::from_json(Some(r#"{ "title": "Acme Print Admin" }"#));
+        assert_eq!(config.title, "Acme Print Admin");
+        assert_eq!(config.main_page, "index.html");
+        assert_eq!(config.splash_page, "splash.html");
+    }
+
+    #[test]
+    fn test_window_config_page_urls() {
+        let config = WindowConfig::default();
+        assert_eq!(
+            config.page_url("splash.html", false),
+            WebviewUrl::App("splash.html".into())
+        );
+        assert_eq!(
+            config.page_url("splash.html", true),
+            WebviewUrl::External("http://localhost:1420/splash.html".parse().unwrap())
+        );
+
+        let broken = WindowConfig {
+            dev_server_url: "not a url".to_string(),
+            ..WindowConfig::default()
+        };
+        assert_eq!(
+            broken.page_url("splash.html", true),
+            WebviewUrl::App("splash.html".into())
+        );
+    }
+
+    #[test]
+    fn test_startup_is_blocked_only_for_invalid_gated_settings() {
+        let blocker = |raw: serde_json::Value| {
+            let loaded = settings_from_value(Some(raw.clone()), &MockSecretStore::default());
+            startup_blocker_for(stored_requires_valid_settings(&raw), loaded)
+        };
+
+        let insecure = json!({ "tenantUrl": "http://tenant.example.com", "apiKey": "key" });
+        assert!(blocker(insecure.clone()).is_none());
+
+        let mut gated = insecure;
+        gated["requireValidSettingsOnStartup"] = json!(true);
+        assert!(matches!(
+            blocker(gated.clone()),
+            Some(SettingsLoadError::InsecureTenantUrl)
+        ));
+
+        let mut out_of_range = gated.clone();
+        out_of_range["tenantUrl"] = json!("https://tenant.example.com");
+        out_of_range["maxConcurrency"] = json!(0);
+        assert!(matches!(
+            blocker(out_of_range.clone()),
+            Some(SettingsLoadError::InvalidMaxConcurrency(0))
+        ));
+
+        let mut valid = out_of_range;
+        valid["maxConcurrency"] = json!(4);
+        assert!(blocker(valid).is_none());
+
+        let empty = json!({ "requireValidSettingsOnStartup": true });
+        assert!(matches!(
+            blocker(empty),
+            Some(SettingsLoadError::MissingTenantUrl)
+        ));
+    }
+}
@@ -1,4 +1,5 @@
 import { HashRouter, NavLink, Route, Routes } from "react-router-dom";
+import StartupBlockerNotice from "./components/StartupBlockerNotice";
 import UsersPage from "./pages/UsersPage";
 import SettingsPage from "./pages/SettingsPage";
 import ImportPage from "./pages/ImportPage";
@@ -75,6 +76,7 @@ function App() {
           </div>
         </header>
         <main className="flex-1 overflow-auto">
+          <StartupBlockerNotice />
           <Routes>
             <Route path="/" element={<UsersPage />} />
             <Route path="/import" element={<ImportPage />} />
@@ -2,10 +2,11 @@ import { useEffect, useState, useRef } from "react";
 import "./SplashScreen.css";
 
 interface SplashScreenProps {
+  title: string;
   onComplete: () => void;
 }
 
-export default function SplashScreen({ onComplete }: SplashScreenProps) {
+export default function SplashScreen({ title, onComplete }: SplashScreenProps) {
   const [loadingStep, setLoadingStep] = useState("Initializing...");
   const [progress, setProgress] = useState(0);
   const [fadeOut, setFadeOut] = useState(false);
@@ -61,7 +62,7 @@ export default function SplashScreen({ onComplete }: SplashScreenProps) {
           <img src="/splash-logo.svg" alt="SAFEQ Cloud" className="splash-logo" />
         </div>
 
-        <h1 className="splash-title">SAFEQ Cloud User Manager</h1>
+        <h1 className="splash-title">{title}</h1>
         <p className="splash-subtitle">Desktop Tool for User Management</p>
 
         <div className="splash-loading">
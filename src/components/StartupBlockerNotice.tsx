@@ -0,0 +1,46 @@
+import { useEffect, useState } from "react";
+import { useLocation, useNavigate } from "react-router-dom";
+import { AlertCircle } from "lucide-react";
+import { getStartupBlocker } from "../services/safeqClient";
+
+/**
+ * Keeps the app on the settings page while invalid settings block startup.
+ *
+ * Re-checked on every navigation, so saving valid settings unlocks the other pages.
+ */
+function StartupBlockerNotice() {
+  const location = useLocation();
+  const navigate = useNavigate();
+  const [blocker, setBlocker] = useState<string | null>(null);
+
+  useEffect(() => {
+    let cancelled = false;
+    getStartupBlocker()
+      .then((error) => {
+        if (cancelled) return;
+        setBlocker(error?.message ?? null);
+        if (error && location.pathname !== "/settings") {
+          navigate("/settings", { replace: true });
+        }
+      })
+      .catch((error) => console.error("Failed to check startup settings:", error));
+    return () => {
+      cancelled = true;
+    };
+  }, [location.pathname, navigate]);
+
+  if (!blocker) {
+    return null;
+  }
+
+  return (
+    <div className="container mx-auto px-6 pt-6">
+      <div className="flex items-center gap-2 rounded-md border border-red-200 bg-red-50 p-4 text-red-900 dark:border-red-900 dark:bg-red-950 dark:text-red-100">
+        <AlertCircle className="h-5 w-5" />
+        <span>Settings are invalid: {blocker}. Fix and save them to use the rest of the app.</span>
+      </div>
+    </div>
+  );
+}
+
+export default StartupBlockerNotice;
@@ -86,7 +86,7 @@ function ImportPage() {
 
       try {
         const content = await readFileAsText(file);
-        const result = parseCsv(content);
+        const result = await parseCsv(content);
 
         // Validate provider requirement based on current default provider selection
         const validatedUsers = result.users.map((user) => {
@@ -11,6 +11,7 @@ import {
   DEFAULT_PIN_TEMPLATE,
   DEFAULT_OTP_TEMPLATE,
 } from "../services/settingsStore";
+import { isAppError } from "../services/safeqClient";
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from "@/components/ui/card";
 import { Input } from "@/components/ui/input";
 import { Label } from "@/components/ui/label";
@@ -609,6 +610,10 @@ function toErrorMessage(error: unknown): string {
     return error.message;
   }
 
+  if (isAppError(error)) {
+    return error.message;
+  }
+
   return "Unknown error while saving settings.";
 }
 
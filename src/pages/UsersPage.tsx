@@ -1,5 +1,5 @@
 import { useCallback, useEffect, useState, useMemo } from "react";
-import { listAuthProviders, listUsersForProvider, generateBulkPins, generateBulkOtps, type BulkGenerationResult } from "../services/safeqClient";
+import { listAuthProviders, listUsersForProvider, generateBulkPins, generateBulkOtps, isAppError, type BulkGenerationResult } from "../services/safeqClient";
 import { type CredentialType } from "../services/emailDelivery";
 import { extractUsers, type SafeQAuthProvider, type SafeQUser } from "../types/safeq";
 import UserTable from "../components/UserTable";
@@ -473,6 +473,10 @@ function toErrorMessage(error: unknown): string {
     return error.message;
   }
 
+  if (isAppError(error)) {
+    return error.message;
+  }
+
   if (typeof error === "string") {
     return error;
   }
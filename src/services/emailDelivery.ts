@@ -121,9 +121,10 @@ export async function sendCredentialEmails(requests: EmailDeliveryRequest[], typ
     }
 
     const tokens = context.tokens;
+    // Decide on the template itself so user data can never switch the format
+    const isHtml = isHtmlContent(template.body);
     const subject = renderTemplate(template.subject, tokens).trim();
-    const body = renderTemplate(template.body, tokens).trim();
-    const isHtml = isHtmlContent(body);
+    const body = renderTemplate(template.body, tokens, isHtml ? escapeHtml : undefined).trim();
 
     if (!subject || !body) {
       templateErrors.push(`${tokens.userName}: template subject or body is empty after rendering.`);
@@ -206,7 +207,17 @@ function createContext(request: EmailDeliveryRequest, type: CredentialType): Tem
 
 const TOKEN_PATTERN = /{{\s*([^}]+)\s*}}/g;
 
-function renderTemplate(template: string, tokens: TemplateTokens) {
+// Keep in sync with escape_html in src-tauri/src/template.rs
+function escapeHtml(value: string): string {
+  return value
+    .replace(/&/g, "&amp;")
+    .replace(/</g, "&lt;")
+    .replace(/>/g, "&gt;")
+    .replace(/"/g, "&quot;")
+    .replace(/'/g, "&#39;");
+}
+
+function renderTemplate(template: string, tokens: TemplateTokens, encode: (value: string) => string = (value) => value) {
   return template.replace(TOKEN_PATTERN, (_, expression: string) => {
     const fallbacks = expression.split("||").map((chunk) => chunk.trim());
 
@@ -214,7 +225,7 @@ function renderTemplate(template: string, tokens: TemplateTokens) {
       const key = candidate as keyof typeof tokens;
       const value = tokens[key];
       if (value && value.length > 0) {
-        return value;
+        return encode(value);
       }
     }
 
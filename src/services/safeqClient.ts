@@ -1,4 +1,34 @@
 import { invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+import type { SafeQSettings } from "./settingsStore";
+
+/**
+ * Structured error returned by every backend command.
+ */
+export type AppError = {
+  kind: string;
+  message: string;
+  status?: number | null;
+  /** SAFEQ error code from a structured error body, e.g. "USER_EXISTS" */
+  code?: string | null;
+  /** Key into the message catalog; `message` itself is always English */
+  messageKey?: string | null;
+};
+
+export type MessageCatalog = Record<string, string>;
+
+/** Translated error messages for `locale` (e.g. "fi-FI"); untranslated keys are English. */
+export async function getMessageCatalog(locale: string): Promise<MessageCatalog> {
+  return invoke<MessageCatalog>("get_message_catalog", { locale });
+}
+
+export function localizeError(error: AppError, catalog: MessageCatalog): string {
+  return (error.messageKey && catalog[error.messageKey]) || error.message;
+}
+
+export function isAppError(value: unknown): value is AppError {
+  return typeof value === "object" && value !== null && "kind" in value && "message" in value;
+}
 
 export type SafeQUsersPayload = unknown;
 export type SafeQProvidersPayload = unknown;
@@ -7,16 +37,135 @@ export async function listSafeQUsers(): Promise<SafeQUsersPayload> {
   return invoke<SafeQUsersPayload>("list_safeq_users");
 }
 
-export async function listAuthProviders(): Promise<SafeQProvidersPayload> {
-  return invoke<SafeQProvidersPayload>("list_auth_providers");
+export type SafeQAccountInfo = {
+  id: number;
+  name?: string;
+  [key: string]: unknown;
+};
+
+export async function getAccountInfo(): Promise<SafeQAccountInfo> {
+  return invoke<SafeQAccountInfo>("get_account_info");
+}
+
+/** Served from a short-lived backend cache; pass `forceRefresh` to refetch. */
+export async function listAuthProviders(forceRefresh: boolean = false): Promise<SafeQProvidersPayload> {
+  return invoke<SafeQProvidersPayload>("list_auth_providers", { forceRefresh });
 }
 
 export async function listUsersForProvider(providerId: number): Promise<SafeQUsersPayload> {
   return invoke<SafeQUsersPayload>("list_users_for_provider", { providerId });
 }
 
-export async function updateUserCard(username: string, providerId: number | null, cardId: string | null): Promise<unknown> {
-  return invoke("update_user_card", { username, providerId, cardId });
+/** A provider's users with only `fields` (e.g. `["userName", "email"]`) kept */
+export async function listUsersProjected(
+  providerId: number,
+  fields: string[]
+): Promise<Array<Record<string, unknown>>> {
+  return invoke("list_users_projected", { providerId, fields });
+}
+
+/** Streams the provider's users to a CSV file at `path`; resolves to the row count. */
+export async function exportUsersCsv(providerId: number, path: string): Promise<number> {
+  return invoke("export_users_csv", { providerId, path });
+}
+
+export interface ProviderUserCount {
+  providerId: number;
+  providerName: string;
+  count: number;
+}
+
+export async function countUsersByProvider(): Promise<ProviderUserCount[]> {
+  return invoke("count_users_by_provider");
+}
+
+export async function searchUsers(providerId: number | null, query: string): Promise<SafeQUsersPayload> {
+  return invoke<SafeQUsersPayload>("search_users", { providerId, query });
+}
+
+export interface UserDetailTypeInfo {
+  id: number;
+  name: string;
+}
+
+export async function listUserDetailTypes(): Promise<UserDetailTypeInfo[]> {
+  return invoke("list_user_detail_types");
+}
+
+/** Pass `null` or "" as the value to remove the detail from the user. */
+export async function updateUserCard(
+  username: string,
+  providerId: number | null,
+  cardId: string | null,
+  checkCardUniqueness: boolean = false
+): Promise<unknown> {
+  return invoke("update_user_card", { username, providerId, cardId, checkCardUniqueness });
+}
+
+export interface CardAssignment {
+  userName: string;
+  providerId?: number | null;
+  cardId: string;
+}
+
+export async function bulkUpdateCards(rows: CardAssignment[]): Promise<BulkGenerationResult> {
+  return invoke("bulk_update_cards", { rows });
+}
+
+/**
+ * Removes one detail type (an id from `listUserDetailTypes`) from every user.
+ * Full name and email are refused.
+ */
+export async function bulkClearDetail(users: unknown[], detailType: number): Promise<BulkGenerationResult> {
+  return invoke("bulk_clear_detail", { users, detailType });
+}
+
+export interface CredentialStatus {
+  userName: string;
+  hasFullName: boolean;
+  hasEmail: boolean;
+  hasCard: boolean;
+  hasPin: boolean;
+  hasOtp: boolean;
+}
+
+/** Which credentials a user has, without their values; `null` if the user is not found */
+export async function getCredentialStatus(username: string, providerId: number | null): Promise<CredentialStatus | null> {
+  return invoke("get_credential_status", { username, providerId });
+}
+
+export interface DetailVerification {
+  userName: string;
+  detail: string;
+  sent: string | null;
+  /** Always `null` for PIN and OTP */
+  stored: string | null;
+  /** `null` for details SAFEQ never returns (password, home folder, expiration, external ID) */
+  verified: boolean | null;
+}
+
+/** Set one detail and read the user back to confirm SAFEQ kept the value */
+export async function setAndVerifyDetail(
+  username: string,
+  providerId: number | null,
+  detailType: number,
+  detailData: string | null
+): Promise<DetailVerification> {
+  return invoke("set_and_verify_detail", { username, providerId, detailType, detailData });
+}
+
+/** Why invalid settings block startup, or `null` when they don't */
+export async function getStartupBlocker(): Promise<AppError | null> {
+  return invoke<AppError | null>("get_startup_blocker");
+}
+
+/** Opens the main window despite a startup blocker so the settings can be fixed */
+export async function openSettingsDespiteBlocker(): Promise<void> {
+  return invoke("open_settings_despite_blocker");
+}
+
+export async function quitApp(): Promise<void> {
+  return invoke("quit_app");
 }
 
 export async function updateUserShortId(username: string, providerId: number | null, shortId: string | null): Promise<unknown> {
@@ -27,6 +176,14 @@ export async function updateUserPin(username: string, providerId: number | null,
   return invoke("update_user_pin", { username, providerId, pin });
 }
 
+/**
+ * Suspends or re-enables a user. SAFEQ has no enabled flag, so disabling sets an
+ * expiration in the past and enabling clears the user's expiration.
+ */
+export async function setUserEnabled(username: string, providerId: number | null, enabled: boolean): Promise<unknown> {
+  return invoke("set_user_enabled", { username, providerId, enabled });
+}
+
 export async function generateUserPin(username: string, providerId: number | null): Promise<{ pin: string }> {
   return invoke("generate_user_pin", { username, providerId });
 }
@@ -35,12 +192,128 @@ export async function generateUserOtp(username: string, providerId: number | nul
   return invoke("generate_user_otp", { username, providerId });
 }
 
+/** Generates distinct PINs for printing; nothing is sent to SAFEQ. */
+export async function previewGeneratePins(count: number): Promise<string[]> {
+  return invoke("preview_generate_pins", { count });
+}
+
+/** Generates distinct OTPs for printing; nothing is sent to SAFEQ. */
+export async function previewGenerateOtps(count: number): Promise<string[]> {
+  return invoke("preview_generate_otps", { count });
+}
+
+/** Generates distinct short IDs for printing; nothing is sent to SAFEQ. */
+export async function previewGenerateShortIds(count: number): Promise<string[]> {
+  return invoke("preview_generate_short_ids", { count });
+}
+
+export interface GeneratorConfig {
+  length: number;
+  useUppercase?: boolean;
+  useLowercase?: boolean;
+  useNumbers?: boolean;
+  useSpecial?: boolean;
+  excludeCharacters?: string;
+}
+
+/** Generates a one-off value from `config`; stored settings are not used. */
+export async function generateValue(config: GeneratorConfig): Promise<string> {
+  return invoke("generate_value", { config });
+}
+
 export async function createUsers(
   users: unknown[],
   autoGeneratePin: boolean = false,
-  autoGenerateOtp: boolean = false
+  autoGenerateOtp: boolean = false,
+  rollbackOnPartial: boolean = false,
+  validateProviders: boolean = false
 ): Promise<BulkGenerationResult> {
-  return invoke("create_users", { users, autoGeneratePin, autoGenerateOtp });
+  return invoke("create_users", { users, autoGeneratePin, autoGenerateOtp, rollbackOnPartial, validateProviders });
+}
+
+/** Emitted after each chunk of a `createUsers` batch. */
+export interface CreateUsersProgress {
+  chunk: number;
+  chunks: number;
+  processed: number;
+  total: number;
+}
+
+export async function onCreateUsersProgress(handler: (progress: CreateUsersProgress) => void): Promise<UnlistenFn> {
+  return listen<CreateUsersProgress>("create-users-progress", (event) => handler(event.payload));
+}
+
+export interface UserValidationReport {
+  valid: number;
+  invalid: number;
+  results: Array<{
+    index: number;
+    user: unknown;
+    valid: boolean;
+    errors: string[];
+  }>;
+}
+
+export interface UserFieldChange {
+  field: "fullName" | "email" | "cardId";
+  current: string | null;
+  desired: string;
+}
+
+export interface UserDiff {
+  toCreate: unknown[];
+  toUpdate: Array<{ userName: string; user: unknown; changes: UserFieldChange[] }>;
+  toDelete: unknown[];
+}
+
+/** Matches users on `userName`; blank fields in `desired` are not compared. */
+export async function diffUsers(current: SafeQUsersPayload, desired: unknown[]): Promise<UserDiff> {
+  return invoke("diff_users", { current, desired });
+}
+
+export type ImportField =
+  | "userName"
+  | "fullName"
+  | "email"
+  | "homeFolder"
+  | "password"
+  | "cardId"
+  | "shortId"
+  | "otp"
+  | "providerId"
+  | "templateKey";
+
+export interface ImportInspection {
+  delimiter: string;
+  columns: Array<{ index: number; name: string; field: ImportField | null }>;
+  /** Best-guess `{ field: column }` mapping */
+  mapping: Partial<Record<ImportField, string>>;
+  unmappedColumns: string[];
+  missingRequired: ImportField[];
+  sampleRows: string[][];
+}
+
+export async function inspectImportFile(path: string): Promise<ImportInspection> {
+  return invoke("inspect_import_file", { path });
+}
+
+/** Parse an import file into user rows; `mapping` is `{ field: csvColumn }` */
+export async function importUsersCsv(
+  path: string,
+  mapping?: Partial<Record<ImportField, string>>
+): Promise<Array<Partial<Record<ImportField, string | number | null>>>> {
+  return invoke("import_users_csv", { path, mapping: mapping ?? null });
+}
+
+/** Parse CSV text into user rows using the header aliases; a non-numeric `providerId` stays text */
+export async function parseImportCsv(
+  contents: string
+): Promise<Array<Partial<Record<ImportField, string | number | null>>>> {
+  return invoke("parse_import_csv", { contents });
+}
+
+export async function validateUsers(users: unknown[]): Promise<UserValidationReport> {
+  return invoke("validate_users", { users });
 }
 
 export interface BulkGenerationResult {
@@ -53,15 +326,36 @@ export interface BulkGenerationResult {
     pin?: string;
     otp?: string;
     error?: string;
+    /** Set with `reason` when the row failed local validation rather than in SAFEQ */
+    field?: string;
+    reason?: string;
+    rollback?: {
+      status: "deleted" | "notCreated" | "failed";
+      error?: string;
+    };
   }>;
 }
 
-export async function generateBulkPins(users: unknown[]): Promise<BulkGenerationResult> {
-  return invoke("generate_bulk_pins", { users });
+/** With `uniquePins`, no two users of the same provider get the same PIN in this batch. */
+export async function generateBulkPins(
+  users: unknown[],
+  validateProviders: boolean = false,
+  uniquePins: boolean = false
+): Promise<BulkGenerationResult> {
+  return invoke("generate_bulk_pins", { users, validateProviders, uniquePins });
 }
 
-export async function generateBulkOtps(users: unknown[]): Promise<BulkGenerationResult> {
-  return invoke("generate_bulk_otps", { users });
+export async function generateBulkOtps(users: unknown[], validateProviders: boolean = false): Promise<BulkGenerationResult> {
+  return invoke("generate_bulk_otps", { users, validateProviders });
+}
+
+/** New PIN and OTP for every user, set in one request per user; rows carry `pin` and `otp` */
+export async function generateBulkCredentials(
+  users: unknown[],
+  validateProviders: boolean = false,
+  uniquePins: boolean = false
+): Promise<BulkGenerationResult> {
+  return invoke("generate_bulk_credentials", { users, validateProviders, uniquePins });
 }
 
 export type PreparedEmailMessage = {
@@ -69,8 +363,215 @@ export type PreparedEmailMessage = {
   subject: string;
   body: string;
   contentType?: "text" | "html";
+  importance?: "low" | "normal" | "high";
+  attachments?: EmailAttachment[];
 };
 
-export async function sendGraphEmails(messages: PreparedEmailMessage[]): Promise<{ success: number; failed: number; errors: string[] }> {
+export type EmailAttachment = {
+  name: string;
+  contentType?: string;
+  /** File contents, base64 encoded */
+  contentBytes: string;
+};
+
+export interface CreateAndNotifyResult {
+  created: number;
+  failed: number;
+  emailed: number;
+  emailFailed: number;
+  drafts: number;
+  results: Array<{
+    user: unknown;
+    success: boolean;
+    pin?: string;
+    otp?: string;
+    error?: string;
+    field?: string;
+    reason?: string;
+    email?: {
+      status: "sent" | "draft" | "failed";
+      error?: string;
+      message?: PreparedEmailMessage;
+    };
+  }>;
+}
+
+export async function createAndNotifyUsers(
+  users: unknown[],
+  credential: "pin" | "otp",
+  autoGeneratePin: boolean = false,
+  autoGenerateOtp: boolean = false
+): Promise<CreateAndNotifyResult> {
+  return invoke("create_and_notify_users", { users, autoGeneratePin, autoGenerateOtp, credential });
+}
+
+export interface GenerateAndEmailResult {
+  generated: number;
+  generateFailed: number;
+  emailed: number;
+  emailFailed: number;
+  drafts: number;
+  /** OTPs that are live in SAFEQ but whose email failed */
+  undelivered: number;
+  results: Array<{
+    user: unknown;
+    success: boolean;
+    value?: string;
+    error?: string;
+    undelivered?: boolean;
+    email?: {
+      status: "sent" | "draft" | "failed";
+      error?: string;
+      message?: PreparedEmailMessage;
+    };
+  }>;
+}
+
+export async function generateAndEmailOtps(users: unknown[]): Promise<GenerateAndEmailResult> {
+  return invoke("generate_and_email_otps", { users });
+}
+
+export interface SendEmailsResult {
+  success: number;
+  failed: number;
+  /** One per failure, each starting with the recipient address */
+  errors: string[];
+  failedIndices: number[];
+  /** Pass to `resendFailed` to retry only these */
+  failedMessages: PreparedEmailMessage[];
+}
+
+export async function sendGraphEmails(messages: PreparedEmailMessage[]): Promise<SendEmailsResult> {
   return invoke("send_graph_emails", { messages });
 }
+
+/** Writes one .eml draft per message into `directory`; resolves to the file paths. */
+export async function generateEmailDrafts(messages: PreparedEmailMessage[], directory: string): Promise<string[]> {
+  return invoke("generate_email_drafts", { messages, directory });
+}
+
+export async function resendFailed(failedMessages: PreparedEmailMessage[]): Promise<SendEmailsResult> {
+  return invoke("resend_failed", { messages: failedMessages });
+}
+
+/** Formats credentials for the clipboard and records the copy in the audit log. */
+export async function formatCredentials(user: unknown, pin?: string | null, otp?: string | null): Promise<string> {
+  return invoke("format_credentials", { user, pin, otp });
+}
+
+export interface EmailPreview {
+  subject: string;
+  body: string;
+  contentType: "text" | "html";
+}
+
+export interface EmailPreviewSample {
+  userName?: string;
+  fullName?: string;
+  email?: string;
+  pin?: string;
+  otp?: string;
+}
+
+export async function previewEmail(template: { subject: string; body: string }, sample: EmailPreviewSample): Promise<EmailPreview> {
+  return invoke("preview_email", { template, sample });
+}
+
+export async function testGraphToken(): Promise<void> {
+  return invoke("test_graph_token");
+}
+
+export interface DeviceCodePrompt {
+  deviceCode: string;
+  userCode: string;
+  verificationUri: string;
+  expiresIn: number;
+  /** Seconds to wait between `pollGraphDeviceLogin` calls */
+  interval: number;
+  message: string;
+}
+
+export type DeviceLoginStatus = { status: "pending" } | { status: "slowDown" } | { status: "signedIn" };
+
+/** Starts a delegated Graph sign-in; show `userCode` and `verificationUri` to the user. */
+export async function startGraphDeviceLogin(): Promise<DeviceCodePrompt> {
+  return invoke("start_graph_device_login");
+}
+
+export async function pollGraphDeviceLogin(deviceCode: string): Promise<DeviceLoginStatus> {
+  return invoke("poll_graph_device_login", { deviceCode });
+}
+
+export type SenderMailboxStatus =
+  | { status: "found" }
+  | { status: "notFound" }
+  | { status: "forbidden"; message: string }
+  | { status: "unexpected"; httpStatus: number; message: string };
+
+export async function verifySenderMailbox(): Promise<SenderMailboxStatus> {
+  return invoke("verify_sender_mailbox");
+}
+
+export interface CredentialStrength {
+  thresholdBits: number;
+  pin: { bits: number; weak: boolean };
+  otp: { bits: number; weak: boolean };
+}
+
+export interface CharsetSettings {
+  length: number;
+  useUppercase: boolean;
+  useLowercase: boolean;
+  useNumbers: boolean;
+  useSpecial: boolean;
+  excludeCharacters: string;
+}
+
+/** Generation rules with every unset setting replaced by its default. */
+export interface EffectiveGenerationSettings {
+  pinLength: number;
+  minPinLength: number;
+  otp: CharsetSettings;
+  shortId: CharsetSettings;
+}
+
+export async function getEffectiveGenerationSettings(): Promise<EffectiveGenerationSettings> {
+  return invoke<EffectiveGenerationSettings>("get_effective_generation_settings");
+}
+
+/** Strength of the saved settings, or of `settings` when given (e.g. unsaved edits) */
+export async function getCredentialStrength(settings?: SafeQSettings): Promise<CredentialStrength> {
+  return invoke<CredentialStrength>("get_credential_strength", { settings });
+}
+
+export interface AuditEntry {
+  timestamp: string;
+  operation:
+    | "userCreated"
+    | "pinGenerated"
+    | "otpGenerated"
+    | "emailSent"
+    | "credentialsCopied"
+    | "cardUpdated"
+    | "detailCleared";
+  operator?: string | null;
+  username: string;
+  providerId?: number | null;
+  success: boolean;
+  error?: string | null;
+}
+
+export async function readAuditLog(): Promise<AuditEntry[]> {
+  return invoke<AuditEntry[]>("read_audit_log");
+}
+
+export interface AuditCsvExport {
+  rows: number;
+  /** Malformed or outdated log lines left out of the export */
+  skipped: number;
+}
+
+/** `from` and `to` are inclusive ISO 8601 timestamps; omit either for an open range */
+export async function exportAuditCsv(path: string, from?: string, to?: string): Promise<AuditCsvExport> {
+  return invoke<AuditCsvExport>("export_audit_csv", { path, from: from ?? null, to: to ?? null });
+}
@@ -1,3 +1,4 @@
+import { invoke } from "@tauri-apps/api/core";
 import { LazyStore } from "@tauri-apps/plugin-store";
 
 export type EmailDeliveryMethod = "desktop" | "graph";
@@ -5,33 +6,93 @@ export type EmailDeliveryMethod = "desktop" | "graph";
 export type EmailTemplate = {
   subject: string;
   body: string;
+  /** Detected from the body's tags when unset */
+  contentType?: "text" | "html";
 };
 
+/** Per-user wording selected through a user's `templateKey`. */
+export type NamedEmailTemplates = {
+  pinTemplate?: EmailTemplate;
+  otpTemplate?: EmailTemplate;
+};
+
+/** Client credentials use the app's own permissions; device code sends as a signed-in user. */
+export type GraphAuthFlow = "clientCredentials" | "deviceCode";
+
 export type EmailSettings = {
   method: EmailDeliveryMethod;
+  authFlow?: GraphAuthFlow;
   graphTenantId?: string;
   graphClientId?: string;
   graphClientSecret?: string;
   graphSenderAddress?: string;
+  graphSaveToSentItems?: boolean;
+  graphReplyTo?: string;
+  graphFromDisplayName?: string;
+  /** From header for desktop drafts, e.g. "IT Desk <it@example.com>" */
+  draftFrom?: string;
   pinTemplate: EmailTemplate;
   otpTemplate: EmailTemplate;
+  templates?: Record<string, NamedEmailTemplates>;
 };
 
 export type SafeQSettings = {
+  schemaVersion?: number;
   tenantUrl: string;
   apiKey: string;
+  apiPort?: number;
+  /** SAFEQ account id; skips the account lookup when set */
+  accountId?: number;
+  /** Serve canned demo data instead of contacting a SAFEQ tenant */
+  mockMode?: boolean;
+  allowInvalidCerts?: boolean;
+  /** Accept an http:// tenant URL; the API key is then sent unencrypted */
+  allowInsecureHttp?: boolean;
+  caCertPath?: string;
+  /** Oldest TLS version to negotiate; defaults to "1.2" */
+  minTlsVersion?: "1.2" | "1.3";
+  /** `X-Api-Key` header (default) or `Authorization: Bearer` */
+  authHeaderStyle?: "apiKeyHeader" | "bearer";
+  proxyUrl?: string;
+  proxyUsername?: string;
+  proxyPassword?: string;
+  operatorId?: string;
+  lowercaseUsernames?: boolean;
+  poolMaxIdlePerHost?: number;
+  poolIdleTimeoutSecs?: number;
+  batchChunkSize?: number;
+  batchChunkDelayMs?: number;
+  /** Milliseconds before one SAFEQ request fails as timed out (default 30000) */
+  requestTimeoutMs?: number;
+  /** Requests in flight at once for bulk operations and email sends (1-64, default 8) */
+  maxConcurrency?: number;
+  /** SAFEQ requests per second across all operations; unlimited when unset or 0 */
+  safeqRequestsPerSecond?: number;
+  /** Microsoft Graph requests per second, limited separately from SAFEQ */
+  graphRequestsPerSecond?: number;
+  /** Log raw SAFEQ requests/responses (secrets redacted) to a rotating trace file */
+  debugTrace?: boolean;
+  /** Keep the main window closed at startup while the saved settings are invalid */
+  requireValidSettingsOnStartup?: boolean;
+  providerCacheTtlSecs?: number;
+  clipboardTemplate?: string;
   pinLength?: number;
+  /** Shortest PIN length allowed by policy (3-12) */
+  minPinLength?: number;
   otpLength?: number;
   otpUseUppercase?: boolean;
   otpUseLowercase?: boolean;
   otpUseNumbers?: boolean;
   otpUseSpecial?: boolean;
   otpExcludeCharacters?: string;
+  /** Also exclude look-alike characters (0, O, 1, l, I, ...) from OTPs and short IDs */
+  excludeAmbiguous?: boolean;
   shortIdLength?: number;
   shortIdUseUppercase?: boolean;
   shortIdUseLowercase?: boolean;
   shortIdUseNumbers?: boolean;
   shortIdUseSpecial?: boolean;
+  shortIdExcludeCharacters?: string;
   emailSettings?: EmailSettings;
 };
 
@@ -168,6 +229,15 @@ export function getDefaultEmailSettings(): EmailSettings {
   };
 }
 
+// Keep in sync with SETTINGS_SCHEMA_VERSION in src-tauri/src/settings.rs
+export const SETTINGS_SCHEMA_VERSION = 1;
+
+type SecretSettings = {
+  apiKey?: string | null;
+  graphClientSecret?: string | null;
+  proxyPassword?: string | null;
+};
+
 const SETTINGS_FILE = "safeq-settings.json";
 const SETTINGS_KEY = "safeqCredentials";
 
@@ -189,35 +259,181 @@ export async function loadSettings(): Promise<SafeQSettings | null> {
     return null;
   }
 
+  // Secrets live in the OS keychain; older files may still carry them inline
+  const secrets = await invoke<SecretSettings>("get_secret_settings");
+  const emailSettings = normalizeEmailSettings(raw.emailSettings);
+  emailSettings.graphClientSecret = normalizeOptional(secrets.graphClientSecret) ?? emailSettings.graphClientSecret;
+
   return {
     tenantUrl: raw.tenantUrl?.trim() ?? "",
-    apiKey: raw.apiKey?.trim() ?? "",
+    apiKey: secrets.apiKey?.trim() || raw.apiKey?.trim() || "",
+    apiPort: raw.apiPort,
+    accountId: raw.accountId,
+    mockMode: raw.mockMode,
+    allowInvalidCerts: raw.allowInvalidCerts,
+    allowInsecureHttp: raw.allowInsecureHttp,
+    caCertPath: normalizeOptional(raw.caCertPath),
+    minTlsVersion: raw.minTlsVersion,
+    authHeaderStyle: raw.authHeaderStyle,
+    proxyUrl: normalizeOptional(raw.proxyUrl),
+    proxyUsername: normalizeOptional(raw.proxyUsername),
+    proxyPassword: secrets.proxyPassword || raw.proxyPassword,
+    operatorId: normalizeOptional(raw.operatorId),
+    lowercaseUsernames: raw.lowercaseUsernames,
+    poolMaxIdlePerHost: raw.poolMaxIdlePerHost,
+    poolIdleTimeoutSecs: raw.poolIdleTimeoutSecs,
+    batchChunkSize: raw.batchChunkSize,
+    batchChunkDelayMs: raw.batchChunkDelayMs,
+    requestTimeoutMs: raw.requestTimeoutMs,
+    maxConcurrency: raw.maxConcurrency,
+    safeqRequestsPerSecond: raw.safeqRequestsPerSecond,
+    graphRequestsPerSecond: raw.graphRequestsPerSecond,
+    debugTrace: raw.debugTrace,
+    requireValidSettingsOnStartup: raw.requireValidSettingsOnStartup,
+    providerCacheTtlSecs: raw.providerCacheTtlSecs,
+    clipboardTemplate: normalizeOptional(raw.clipboardTemplate),
     pinLength: raw.pinLength,
+    minPinLength: raw.minPinLength,
+    otpLength: raw.otpLength,
+    otpUseUppercase: raw.otpUseUppercase,
+    otpUseLowercase: raw.otpUseLowercase,
+    otpUseNumbers: raw.otpUseNumbers,
+    otpUseSpecial: raw.otpUseSpecial,
+    otpExcludeCharacters: raw.otpExcludeCharacters,
+    excludeAmbiguous: raw.excludeAmbiguous,
     shortIdLength: raw.shortIdLength,
     shortIdUseUppercase: raw.shortIdUseUppercase,
     shortIdUseLowercase: raw.shortIdUseLowercase,
     shortIdUseNumbers: raw.shortIdUseNumbers,
     shortIdUseSpecial: raw.shortIdUseSpecial,
-    emailSettings: normalizeEmailSettings(raw.emailSettings),
+    shortIdExcludeCharacters: raw.shortIdExcludeCharacters,
+    emailSettings,
   };
 }
 
-export async function saveSettings(settings: SafeQSettings) {
+/** Saves through the backend, which normalizes the tenant URL and rejects invalid ones. */
+export async function saveSettings(settings: SafeQSettings): Promise<SafeQSettings> {
+  const emailSettings = settings.emailSettings ? sanitizeEmailSettings(settings.emailSettings) : getDefaultEmailSettings();
+
+  await invoke("save_secret_settings", {
+    secrets: {
+      apiKey: settings.apiKey.trim(),
+      graphClientSecret: emailSettings.graphClientSecret,
+      proxyPassword: settings.proxyPassword,
+    },
+  });
+
   const payload: SafeQSettings = {
+    schemaVersion: SETTINGS_SCHEMA_VERSION,
     tenantUrl: settings.tenantUrl.trim(),
-    apiKey: settings.apiKey.trim(),
+    apiKey: "",
+    apiPort: settings.apiPort,
+    accountId: settings.accountId,
+    mockMode: settings.mockMode,
+    allowInvalidCerts: settings.allowInvalidCerts,
+    allowInsecureHttp: settings.allowInsecureHttp,
+    caCertPath: normalizeOptional(settings.caCertPath),
+    minTlsVersion: settings.minTlsVersion,
+    authHeaderStyle: settings.authHeaderStyle,
+    proxyUrl: normalizeOptional(settings.proxyUrl),
+    proxyUsername: normalizeOptional(settings.proxyUsername),
+    proxyPassword: undefined,
+    operatorId: normalizeOptional(settings.operatorId),
+    lowercaseUsernames: settings.lowercaseUsernames,
+    poolMaxIdlePerHost: settings.poolMaxIdlePerHost,
+    poolIdleTimeoutSecs: settings.poolIdleTimeoutSecs,
+    batchChunkSize: settings.batchChunkSize,
+    batchChunkDelayMs: settings.batchChunkDelayMs,
+    requestTimeoutMs: settings.requestTimeoutMs,
+    maxConcurrency: settings.maxConcurrency,
+    safeqRequestsPerSecond: settings.safeqRequestsPerSecond,
+    graphRequestsPerSecond: settings.graphRequestsPerSecond,
+    debugTrace: settings.debugTrace,
+    requireValidSettingsOnStartup: settings.requireValidSettingsOnStartup,
+    providerCacheTtlSecs: settings.providerCacheTtlSecs,
+    clipboardTemplate: normalizeOptional(settings.clipboardTemplate),
     pinLength: settings.pinLength,
+    minPinLength: settings.minPinLength,
+    otpLength: settings.otpLength,
+    otpUseUppercase: settings.otpUseUppercase,
+    otpUseLowercase: settings.otpUseLowercase,
+    otpUseNumbers: settings.otpUseNumbers,
+    otpUseSpecial: settings.otpUseSpecial,
+    otpExcludeCharacters: settings.otpExcludeCharacters,
+    excludeAmbiguous: settings.excludeAmbiguous,
     shortIdLength: settings.shortIdLength,
     shortIdUseUppercase: settings.shortIdUseUppercase,
     shortIdUseLowercase: settings.shortIdUseLowercase,
     shortIdUseNumbers: settings.shortIdUseNumbers,
     shortIdUseSpecial: settings.shortIdUseSpecial,
-    emailSettings: settings.emailSettings ? sanitizeEmailSettings(settings.emailSettings) : getDefaultEmailSettings(),
+    shortIdExcludeCharacters: settings.shortIdExcludeCharacters,
+    emailSettings: { ...emailSettings, graphClientSecret: undefined },
   };
 
-  const storage = await getStore();
-  await storage.set(SETTINGS_KEY, payload);
-  await storage.save();
+  return invoke<SafeQSettings>("save_safeq_settings", { settings: payload });
+}
+
+/** Validates the new key against SAFEQ and stores it only when accepted. */
+export async function rotateApiKey(newKey: string): Promise<unknown> {
+  return invoke("rotate_api_key", { newKey });
+}
+
+export async function resetSettings(): Promise<SafeQSettings> {
+  return invoke<SafeQSettings>("reset_settings");
+}
+
+export async function exportSettings(path: string, includeSecrets: boolean = false): Promise<void> {
+  return invoke("export_settings", { path, includeSecrets });
+}
+
+/** Run the checks a save would (URL, scheme, limits) without saving */
+export async function validateSettings(settings: SafeQSettings): Promise<void> {
+  return invoke("validate_settings", { settings });
+}
+
+export interface GenerationWarning {
+  setting: "otp" | "shortId";
+  code: "noCharacterClass" | "everythingExcluded";
+  message: string;
+}
+
+/** OTP and short ID rules that would silently fall back to digits */
+export async function validateGenerationSettings(settings: SafeQSettings): Promise<GenerationWarning[]> {
+  return invoke<GenerationWarning[]>("validate_generation_settings", { settings });
+}
+
+/** With `merge`, only non-secret, non-empty fields from the file replace current values */
+
+export async function importSettings(path: string, merge = false): Promise<SafeQSettings> {
+  return invoke<SafeQSettings>("import_settings", { path, merge });
+}
+
+export interface DiagnosticCheck {
+  name: "settings" | "tenantUrl" | "baseUrl" | "account" | "providers" | "graphToken";
+  status: "pass" | "fail" | "skipped";
+  message: string;
+}
+
+export interface DiagnosticsReport {
+  passed: boolean;
+  checks: DiagnosticCheck[];
+}
+
+/** Run the settings, connectivity and auth checks; messages never contain secrets */
+export async function runDiagnostics(): Promise<DiagnosticsReport> {
+  return invoke<DiagnosticsReport>("run_diagnostics");
+}
+
+export interface UrlPreview {
+  normalized: string;
+  baseUrl: string | null;
+  port: number | null;
+  error: string | null;
+}
+
+/** Normalizes `input` in the backend and shows the base URL requests would target. */
+export async function normalizeAndPreviewUrl(input: string, apiPort?: number | null): Promise<UrlPreview> {
+  return invoke<UrlPreview>("normalize_and_preview_url", { input, apiPort });
 }
 
 export function normalizeTenantUrl(input: string): string {
@@ -232,7 +448,7 @@ export function normalizeTenantUrl(input: string): string {
   try {
     const url = new URL(urlWithScheme);
     const normalized = `${url.protocol}//${url.host}${url.pathname}`.replace(/\/$/, "");
-    return normalized;
+    return `${normalized}${url.search}${url.hash}`;
   } catch (error) {
     return trimmed;
   }
@@ -245,12 +461,18 @@ function normalizeEmailSettings(raw?: SafeQSettings["emailSettings"]): EmailSett
 
   return {
     method: raw.method ?? "desktop",
+    authFlow: raw.authFlow ?? "clientCredentials",
     graphTenantId: normalizeOptional(raw.graphTenantId),
     graphClientId: normalizeOptional(raw.graphClientId),
     graphClientSecret: normalizeOptional(raw.graphClientSecret),
     graphSenderAddress: normalizeOptional(raw.graphSenderAddress),
+    graphSaveToSentItems: raw.graphSaveToSentItems,
+    graphReplyTo: normalizeOptional(raw.graphReplyTo),
+    graphFromDisplayName: normalizeOptional(raw.graphFromDisplayName),
+    draftFrom: normalizeOptional(raw.draftFrom),
     pinTemplate: normalizeTemplate(raw.pinTemplate, DEFAULT_PIN_TEMPLATE),
     otpTemplate: normalizeTemplate(raw.otpTemplate, DEFAULT_OTP_TEMPLATE),
+    templates: raw.templates,
   };
 }
 
@@ -262,24 +484,33 @@ function normalizeTemplate(candidate: EmailTemplate | undefined, fallback: Email
   return {
     subject: candidate.subject?.trim() || fallback.subject,
     body: candidate.body?.trim() || fallback.body,
+    contentType: candidate.contentType,
   };
 }
 
 function sanitizeEmailSettings(settings: EmailSettings): EmailSettings {
   return {
     method: settings.method,
+    authFlow: settings.authFlow,
     graphTenantId: normalizeOptional(settings.graphTenantId),
     graphClientId: normalizeOptional(settings.graphClientId),
     graphClientSecret: normalizeOptional(settings.graphClientSecret),
     graphSenderAddress: normalizeOptional(settings.graphSenderAddress),
+    graphSaveToSentItems: settings.graphSaveToSentItems,
+    graphReplyTo: normalizeOptional(settings.graphReplyTo),
+    graphFromDisplayName: normalizeOptional(settings.graphFromDisplayName),
+    draftFrom: normalizeOptional(settings.draftFrom),
     pinTemplate: {
       subject: settings.pinTemplate.subject.trim(),
       body: settings.pinTemplate.body.trim(),
+      contentType: settings.pinTemplate.contentType,
     },
     otpTemplate: {
       subject: settings.otpTemplate.subject.trim(),
       body: settings.otpTemplate.body.trim(),
+      contentType: settings.otpTemplate.contentType,
     },
+    templates: settings.templates,
   };
 }
 
@@ -2,17 +2,54 @@ import ReactDOM from "react-dom/client";
 import SplashScreen from "./components/SplashScreen";
 import "./index.css";
 import { invoke } from "@tauri-apps/api/core";
+import { getCurrentWindow } from "@tauri-apps/api/window";
+import { useEffect, useState } from "react";
+import { openSettingsDespiteBlocker, quitApp, type AppError } from "./services/safeqClient";
+
+const DEFAULT_TITLE = "SAFEQ Cloud User Manager";
 
 function SplashApp() {
+  const [startupError, setStartupError] = useState<string | null>(null);
+  // The backend titles the splash window from the branding config
+  const [title, setTitle] = useState(DEFAULT_TITLE);
+
+  useEffect(() => {
+    getCurrentWindow()
+      .title()
+      .then((windowTitle) => setTitle(windowTitle || DEFAULT_TITLE))
+      .catch((error) => console.error("Failed to read the window title:", error));
+  }, []);
+
   const handleComplete = async () => {
     try {
       await invoke("close_splashscreen");
     } catch (error) {
       console.error("Failed to close splashscreen:", error);
+      // Invalid settings with `requireValidSettingsOnStartup` keep the main window closed
+      setStartupError((error as AppError)?.message ?? String(error));
     }
   };
 
-  return <SplashScreen onComplete={handleComplete} />;
+  if (startupError) {
+    return (
+      <div className="splash-screen">
+        <div className="splash-content">
+          <h1 className="splash-title">{title}</h1>
+          <p className="splash-subtitle">Settings are invalid: {startupError}</p>
+          <div className="splash-actions">
+            <button type="button" onClick={() => openSettingsDespiteBlocker().catch(console.error)}>
+              Open settings
+            </button>
+            <button type="button" onClick={() => quitApp().catch(console.error)}>
+              Quit
+            </button>
+          </div>
+        </div>
+      </div>
+    );
+  }
+
+  return <SplashScreen title={title} onComplete={handleComplete} />;
 }
 
 ReactDOM.createRoot(document.getElementById("splash-root") as HTMLElement).render(<SplashApp />);
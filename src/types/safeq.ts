@@ -78,10 +78,13 @@ export interface ImportUser {
   userName: string;
   fullName?: string;
   email?: string;
+  homeFolder?: string;
+  password?: string; // Initial password; sent to SAFEQ only, never shown in results
   cardId?: string;
   shortId?: string;
   otp?: string;
   providerId?: number;
+  templateKey?: string; // Named email template, see EmailSettings.templates
   errors: string[]; // Validation errors
   isValid: boolean;
 }